@@ -68,6 +68,18 @@ fn main() {
         "sd_card_save_key_source".to_string(),
         "|keys, key| {keys.sd_card_key_sources[0]= hex_to_array(key)?;Ok(())}".to_string(),
     );
+    map.entry(
+        "sd_card_save_key".to_string(),
+        "|keys, key| {keys.console.sd_card_keys[0]= hex_to_array(key)?;Ok(())}".to_string(),
+    );
+    map.entry(
+        "sd_card_nca_key".to_string(),
+        "|keys, key| {keys.console.sd_card_keys[1]= hex_to_array(key)?;Ok(())}".to_string(),
+    );
+    map.entry(
+        "sd_seed".to_string(),
+        "|keys, key| {keys.console.sd_seed= hex_to_array(key)?;Ok(())}".to_string(),
+    );
     map.entry(
         "save_mac_kek_source".to_string(),
         "|keys, key| {keys.save_mac_kek_source= hex_to_array(key)?;Ok(())}".to_string(),
@@ -86,11 +98,11 @@ fn main() {
     );
     map.entry(
         "secure_boot_key".to_string(),
-        "|keys, key| {keys.secure_boot_key= hex_to_array(key)?;Ok(())}".to_string(),
+        "|keys, key| {keys.console.secure_boot_key= hex_to_array(key)?;Ok(())}".to_string(),
     );
     map.entry(
         "tsec_key".to_string(),
-        "|keys, key| {keys.tsec_key= hex_to_array(key)?;Ok(())}".to_string(),
+        "|keys, key| {keys.console.tsec_key= hex_to_array(key)?;Ok(())}".to_string(),
     );
     map.entry(
         "mariko_kek".to_string(),
@@ -114,11 +126,31 @@ fn main() {
     );
     map.entry(
         "device_key".to_string(),
-        "|keys, key| {keys.device_key = hex_to_array(key)?;Ok(())}".to_string(),
+        "|keys, key| {keys.console.device_key = hex_to_array(key)?;Ok(())}".to_string(),
     );
     map.entry(
         "save_mac_key".to_string(),
-        "|keys, key| {keys.save_mac_key = hex_to_array(key)?;Ok(())}".to_string(),
+        "|keys, key| {keys.console.save_mac_key = hex_to_array(key)?;Ok(())}".to_string(),
+    );
+    map.entry(
+        "bis_kek_source".to_string(),
+        "|keys, key| {keys.bis_kek_source= hex_to_array(key)?;Ok(())}".to_string(),
+    );
+    map.entry(
+        "retail_specific_aes_key_source".to_string(),
+        "|keys, key| {keys.retail_specific_aes_key_source= hex_to_array(key)?;Ok(())}".to_string(),
+    );
+    map.entry(
+        "eticket_rsa_kek_source".to_string(),
+        "|keys, key| {keys.eticket_rsa_kek_source= hex_to_array(key)?;Ok(())}".to_string(),
+    );
+    map.entry(
+        "eticket_rsa_kekek_source".to_string(),
+        "|keys, key| {keys.eticket_rsa_kekek_source= hex_to_array(key)?;Ok(())}".to_string(),
+    );
+    map.entry(
+        "eticket_rsa_kek".to_string(),
+        "|keys, key| {keys.eticket_rsa_kek= hex_to_array(key)?;Ok(())}".to_string(),
     );
 
     map.entry(
@@ -140,11 +172,11 @@ fn main() {
         );
         map.entry(
             format!("keyblob_key_{:02x}", index),
-            format!("|keys, key| {{keys.keyblob_keys[{index}] = hex_to_array(key)?;Ok(())}}"),
+            format!("|keys, key| {{keys.console.keyblob_keys[{index}] = hex_to_array(key)?;Ok(())}}"),
         );
         map.entry(
             format!("encrypted_keyblob_{:02x}", index),
-            format!("|keys, key| {{keys.encrypted_keyblobs[{index}] = hex_to_array(key)?;Ok(())}}"),
+            format!("|keys, key| {{keys.console.encrypted_keyblobs[{index}] = hex_to_array(key)?;Ok(())}}"),
         );
         map.entry(
             format!("mariko_master_kek_source_{:02x}", index),
@@ -157,7 +189,7 @@ fn main() {
 
         map.entry(
             format!("keyblob_mac_key_{:02x}", index),
-            format!("|keys, key| {{keys.keyblob_mac_keys[{index}] = hex_to_array(key)?;Ok(())}}"),
+            format!("|keys, key| {{keys.console.keyblob_mac_keys[{index}] = hex_to_array(key)?;Ok(())}}"),
         );
     }
 
@@ -237,6 +269,17 @@ fn main() {
         );
     }
 
+    for index in 0..4u8 {
+        map.entry(
+            format!("bis_key_{:02x}", index),
+            format!("|keys, key| {{keys.console.bis_keys[{index}] = hex_to_array(key)?;Ok(())}}"),
+        );
+        map.entry(
+            format!("bis_key_source_{:02x}", index),
+            format!("|keys, key| {{keys.bis_key_sources[{index}] = hex_to_array(key)?;Ok(())}}"),
+        );
+    }
+
     write!(
         &mut file,
         "static KEY_HANDLERS: phf::Map<&'static str, fn(&mut NcaKeys, &str) -> anyhow::Result<()>> = {}",
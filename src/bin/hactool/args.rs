@@ -6,15 +6,15 @@ use hactool_rs::keys::KeysetType;
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 pub struct Args {
-   /// The base file type to work on.
+   /// The base file type to work on. Detected from the input file when omitted.
    #[clap(value_enum, subcommand)]
-   pub file_type: SupportedFileTypes,
+   pub file_type: Option<SupportedFileTypes>,
 
    /// The action required on the input file/folder
    #[clap(short, long, value_parser, value_delimiter = ',', global = true)]
    pub action: Vec<Action>,
 
-   /// Input file/folder
+   /// Input file/folder, or a file inside nested containers such as `game.xci:/secure/abc.nca/romfs/x.bin`
    #[clap(short, long, value_parser, global = true)]
    pub input: Option<String>,
 
@@ -24,7 +24,96 @@ pub struct Args {
 
     /// Key set
     #[clap(short, long, value_parser, global = true, default_value = "retail")]
-    pub keyset: KeysetType
+    pub keyset: KeysetType,
+
+    /// Output format for the info and verify actions
+    #[clap(long, value_enum, global = true, default_value = "text")]
+    pub format: OutputFormat,
+
+    /// Save the decrypted NCA header to a file
+    #[clap(long, value_parser, global = true)]
+    pub header: Option<String>,
+
+    /// Save a fully decrypted copy of the NCA to a file
+    #[clap(long, value_parser, global = true)]
+    pub plaintext: Option<String>,
+
+    /// Save the decrypted NCA section 0 to a file
+    #[clap(long, value_parser, global = true)]
+    pub section0: Option<String>,
+
+    /// Save the decrypted NCA section 1 to a file
+    #[clap(long, value_parser, global = true)]
+    pub section1: Option<String>,
+
+    /// Save the decrypted NCA section 2 to a file
+    #[clap(long, value_parser, global = true)]
+    pub section2: Option<String>,
+
+    /// Save the decrypted NCA section 3 to a file
+    #[clap(long, value_parser, global = true)]
+    pub section3: Option<String>,
+
+    /// Extract the NCA ExeFS to a folder
+    #[clap(long, value_parser, global = true)]
+    pub exefsdir: Option<String>,
+
+    /// Extract the NCA RomFS to a folder
+    #[clap(long, value_parser, global = true)]
+    pub romfsdir: Option<String>,
+
+    /// List the files in the NCA ExeFS
+    #[clap(long, value_parser, global = true)]
+    pub listexefs: bool,
+
+    /// List the files in the NCA RomFS
+    #[clap(long, value_parser, global = true)]
+    pub listromfs: bool,
+
+    /// List the ExeFS modules of an NCA with their build IDs and load addresses
+    #[clap(long, value_parser, global = true)]
+    pub list_modules: bool,
+
+    /// SD seed (hex) of the console an SD card belongs to, for NAX0 key derivation
+    #[clap(long, value_parser, global = true)]
+    pub sdseed: Option<String>,
+
+    /// The `private` file of system save 8000000000000043, to read the SD seed from instead of --sdseed
+    #[clap(long, value_parser, global = true)]
+    pub sdprivate: Option<String>,
+
+    /// Path of a NAX0 file relative to Nintendo/Contents or Nintendo/save, e.g.
+    /// /registered/000000AB/0123456789abcdef0123456789abcdef.nca. Taken from the input path when omitted.
+    #[clap(long, value_parser, global = true)]
+    pub sdpath: Option<String>,
+
+    /// Files to replace in a save with the create action, as save_path=local_file
+    #[clap(long, value_parser, value_delimiter = ',', global = true)]
+    pub savefile: Vec<String>,
+
+    /// NAND partitions to decrypt with the extract action, by GPT name (e.g. SYSTEM,USER). All when omitted.
+    #[clap(long, value_parser, value_delimiter = ',', global = true)]
+    pub partition: Vec<String>,
+
+    /// Title key (hex) for NCAs with a rights ID, encrypted with the titlekek as in a ticket
+    #[clap(long, value_parser, global = true)]
+    pub titlekey: Option<String>,
+
+    /// Already decrypted title key (hex) for NCAs with a rights ID
+    #[clap(long, value_parser, global = true, conflicts_with = "titlekey")]
+    pub contentkey: Option<String>,
+
+    /// RSA-2048 private key (PEM or DER) used to sign the ACID when creating an NPDM
+    #[clap(long, value_parser, global = true)]
+    pub acidkey: Option<String>,
+
+    /// RSA public key (PEM or DER) to verify the ACID signature against
+    #[clap(long, value_parser, global = true)]
+    pub acidpubkey: Option<String>,
+
+    /// Convert the NSO or NRO executable to an ELF file
+    #[clap(long, value_parser, global = true)]
+    pub elf: Option<String>,
 }
 
 
@@ -32,7 +121,26 @@ pub struct Args {
 pub enum SupportedFileTypes {
     Npdm,
     Pfs0,
-    Nca
+    Hfs0,
+    Romfs,
+    Nca,
+    Nax0,
+    Save,
+    /// The `Nintendo` folder of an SD card: every NAX0 file in it is checked, or decrypted to the output folder
+    Sd,
+    /// A raw NAND dump (rawnand.bin): its partitions are checked against the BIS keys, or decrypted to the output folder
+    Nand,
+    Nso,
+    Nro,
+    /// Mount the input (any container, or a virtual path into one) read-only at the output folder
+    #[cfg(feature = "fuse")]
+    Mount,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
 }
 
 #[derive(Debug,ValueEnum, Clone, PartialEq, PartialOrd, Eq, Ord)]
@@ -1,5 +1,11 @@
-use std::{fs::File, path::PathBuf};
+use std::{
+    fs::File,
+    io::{BufWriter, Cursor, Write},
+    path::{Component, PathBuf},
+    rc::Rc,
+};
 
+use binrw::BinReaderExt;
 use clap::Parser;
 use dirs::home_dir;
 
@@ -7,15 +13,30 @@ mod args;
 
 use anyhow::anyhow;
 use args::Args;
-use hactool_rs::file_formats::Validity;
+use hactool_rs::{
+    file_formats::{
+        container::{ContainerNode, FileType, VirtualPath},
+        Validity,
+        elf::{ElfModule, ModuleImage},
+        nand::NandReader,
+        nax0::Nax0Reader,
+        nca::NcaFileReader,
+        sd_card::{decrypt_sd_card, sd_seed_from_private},
+        nso::{DEFAULT_LOAD_BASE, exefs_modules},
+        npdm::descriptor::NpdmDescriptor,
+    },
+    info::{JsonReport, PrettyInfo, VerificationResult, Verify},
+    keys::NcaKeys,
+};
+use serde::Serialize;
 
-use crate::args::Action;
+use crate::args::{Action, OutputFormat};
 
 fn main() -> anyhow::Result<()> {
     //let pubkey_test()
     //let pubkey = rsa::RsaPublicKey::new(BigUint::from_bytes_le(pubkey_test.as_slice()), BigUint::from_bytes_le([1u8,0, 1].as_slice())).unwrap();
     //let key: VerifyingKey<Sha256> = rsa::pss::VerifyingKey::new(pubkey);
-    let keys = match home_dir() {
+    let mut keys = match home_dir() {
         Some(ref mut path) => {
             path.push(".switch");
             path.push("prod.keys");
@@ -37,8 +58,43 @@ fn main() -> anyhow::Result<()> {
 
     let mut args = Args::parse();
     args.action.sort();
+    if let Some(sd_seed) = &args.sdseed {
+        keys.set_sd_seed(parse_hex_key(sd_seed)?);
+    }
+    if let Some(private) = &args.sdprivate {
+        // The SD card's copy of the ID is only checked when the Nintendo folder is the input
+        let sd_private = args.input.as_ref().and_then(|input| std::fs::read(PathBuf::from(input).join("Contents/private")).ok());
+        keys.set_sd_seed(sd_seed_from_private(&std::fs::read(private)?, sd_private.as_deref())?);
+    }
 
-    match args.file_type {
+    let file_type = match args.file_type.clone() {
+        Some(file_type) => file_type,
+        None => {
+            let file_name = args
+                .input
+                .clone()
+                .ok_or(anyhow!("Input file must be provided when no file type is given"))?;
+            if !PathBuf::from(&file_name).is_file() && VirtualPath::parse(&file_name).is_some() {
+                let node = ContainerNode::open_virtual(&file_name, Rc::new(keys))?;
+                return print_container(&args, node.file_type, node);
+            }
+            match FileType::detect_file(&file_name, Some(&keys))? {
+                FileType::Npdm => args::SupportedFileTypes::Npdm,
+                FileType::Pfs0 => args::SupportedFileTypes::Pfs0,
+                FileType::Hfs0 => args::SupportedFileTypes::Hfs0,
+                FileType::RomFs => args::SupportedFileTypes::Romfs,
+                FileType::Nca => args::SupportedFileTypes::Nca,
+                FileType::Nax0 => args::SupportedFileTypes::Nax0,
+                FileType::Save => args::SupportedFileTypes::Save,
+                FileType::Nand => args::SupportedFileTypes::Nand,
+                FileType::Nso => args::SupportedFileTypes::Nso,
+                FileType::Nro => args::SupportedFileTypes::Nro,
+                file_type => return print_container(&args, file_type, ContainerNode::open(&file_name, Rc::new(keys))?),
+            }
+        }
+    };
+
+    match file_type {
         args::SupportedFileTypes::Npdm => {
             for action in args.action.iter() {
                 match action {
@@ -49,26 +105,54 @@ fn main() -> anyhow::Result<()> {
                             .ok_or(anyhow!("Input file must be provided for info action"))?;
                         let npdm = hactool_rs::file_formats::npdm::NpdmFile::parse(&file_name)?;
 
-                        println!("Npdm file: {}, content: {:#?}", file_name, npdm);
+                        print_info(args.format, "npdm", &npdm)?;
                     }
                     Action::Verify => {
                         let file_name = args
                             .input
                             .clone()
                             .ok_or(anyhow!("Input file must be provided for verify action"))?;
-                        let npdm = hactool_rs::file_formats::npdm::NpdmFile::parse(&file_name)?;
-                        println!(
-                            "Npdm parsed successfully. Valid: {}, content: {:?}",
-                            npdm.verify_acid(hactool_rs::keys::KeysetType::Dev) == Ok(Validity::Valid)
-                                || npdm.verify_acid(hactool_rs::keys::KeysetType::Retail) == Ok(Validity::Valid),
-                            npdm
-                        );
+                        let mut npdm = hactool_rs::file_formats::npdm::NpdmFile::parse(&file_name)?;
+                        let mut results = npdm.verification_results();
+                        if let Some(key_file) = args.acidpubkey.as_ref() {
+                            let key = hactool_rs::keys::rsa_public_key_from_bytes(&std::fs::read(key_file)?)?;
+                            results.push(VerificationResult::new("acid_signature_with_key", None, npdm.verify_acid_with_key(&key)));
+                        }
+                        print_verification(args.format, "npdm", &results)?;
                     }
                     Action::Extract => {
-                        eprintln!("Npdm files have no files to extract.");
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for extract action"))?;
+                        let npdm = hactool_rs::file_formats::npdm::NpdmFile::parse(&file_name)?;
+                        let json = NpdmDescriptor::from_npdm(&npdm).to_json()?;
+                        match args.output.as_ref() {
+                            Some(output) => std::fs::write(output, json)?,
+                            None => println!("{json}"),
+                        }
                     }
                     Action::Create => {
-                        eprintln!("Creating npdm files not supported.")
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input JSON descriptor must be provided for create action"))?;
+                        let output = args
+                            .output
+                            .clone()
+                            .ok_or(anyhow!("Output file must be provided for create action"))?;
+                        // An existing NPDM is re-signed, anything else is read as a JSON descriptor
+                        let input = std::fs::read(&file_name)?;
+                        let mut npdm = if input.starts_with(b"META") {
+                            Cursor::new(input).read_le()?
+                        } else {
+                            NpdmDescriptor::from_json(std::str::from_utf8(&input)?)?.to_npdm()?
+                        };
+                        if let Some(key_file) = args.acidkey.as_ref() {
+                            let key = hactool_rs::keys::rsa_private_key_from_bytes(&std::fs::read(key_file)?)?;
+                            npdm.sign_acid(&key)?;
+                        }
+                        npdm.write(&mut BufWriter::new(File::create(&output)?))?;
                     }
                 }
             }
@@ -83,21 +167,16 @@ fn main() -> anyhow::Result<()> {
                             .ok_or(anyhow!("Input file must be provided for info action"))?;
                         let pfs =
                             hactool_rs::file_formats::pfs0::Pfs0Reader::parse_file(&file_name)?;
-                        println!(
-                            "Pfs0 file: {}, files: {:?}",
-                            file_name,
-                            pfs.list_files().as_slice()
-                        );
+                        print_info(args.format, "pfs0", &pfs.pfs)?;
                     }
                     Action::Verify => {
-                        eprintln!("Pfs0 files have no verification metadata.");
                         let file_name = args
                             .input
                             .clone()
                             .ok_or(anyhow!("Input file must be provided for verify action"))?;
-                        let pfs =
+                        let mut pfs =
                             hactool_rs::file_formats::pfs0::Pfs0Reader::parse_file(&file_name)?;
-                        println!("Pfs0 parsed successfully: {:?}", pfs);
+                        print_verification(args.format, "pfs0", &pfs.verification_results())?;
                     }
                     Action::Extract => {
                         let file_name = args
@@ -121,12 +200,397 @@ fn main() -> anyhow::Result<()> {
                         }
                     }
                     Action::Create => {
-                        todo!()
+                        let input_folder = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input folder must be provided for create action"))?;
+                        let output_file = args
+                            .output
+                            .clone()
+                            .ok_or(anyhow!("Output file must be provided for create action"))?;
+                        let pfs =
+                            hactool_rs::file_formats::pfs0::Pfs0Builder::from_dir(&input_folder)?;
+
+                        println!("Creating {}...", output_file);
+
+                        let mut writer = BufWriter::new(File::create(&output_file)?);
+                        pfs.write(&mut writer)?;
                     }
                 }
             }
         },
+        args::SupportedFileTypes::Hfs0 => {
+            for action in args.action.iter() {
+                match action {
+                    Action::Info => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for info action"))?;
+                        let hfs = hactool_rs::file_formats::hfs0::Hfs0::parse(&file_name)?;
+                        print_info(args.format, "hfs0", &hfs)?;
+                    }
+                    Action::Verify => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for verify action"))?;
+                        let mut hfs = hactool_rs::file_formats::hfs0::Hfs0::parse(&file_name)?;
+                        print_verification(args.format, "hfs0", &hfs.verification_results())?;
+                    }
+                    Action::Extract => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for extract action"))?;
+                        let output_folder =
+                            PathBuf::from(args.output.as_ref().ok_or(anyhow!(
+                                "Output folder must be provided for extract action"
+                            ))?);
+                        let hfs = hactool_rs::file_formats::hfs0::Hfs0::parse(&file_name)?;
+
+                        for file in hfs.files.iter() {
+                            let mut output_file = output_folder.clone();
+                            output_file.push(file.file_name.to_string());
+
+                            println!("Extracting {}...", output_file.display());
+
+                            std::fs::write(output_file.as_path(), &file.file_data)?;
+                        }
+                    }
+                    Action::Create => {
+                        let input_folder = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input folder must be provided for create action"))?;
+                        let output_file = args
+                            .output
+                            .clone()
+                            .ok_or(anyhow!("Output file must be provided for create action"))?;
+                        let hfs =
+                            hactool_rs::file_formats::hfs0::Hfs0Builder::from_dir(&input_folder)?;
+
+                        println!("Creating {}...", output_file);
+
+                        let mut writer = BufWriter::new(File::create(&output_file)?);
+                        hfs.write(&mut writer)?;
+                    }
+                }
+            }
+        }
+        args::SupportedFileTypes::Romfs => {
+            for action in args.action.iter() {
+                match action {
+                    Action::Info => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for info action"))?;
+                        let romfs =
+                            hactool_rs::file_formats::romfs::RomFsReader::parse_file(&file_name)?;
+                        let files = romfs.romfs.files()?;
+                        match args.format {
+                            OutputFormat::Text => {
+                                println!("RomFs file: {}", file_name);
+                                for file in files {
+                                    println!("romfs:/{} ({:#x} bytes)", file.path, file.file_size);
+                                }
+                            }
+                            OutputFormat::Json => println!("{}", JsonReport::new("romfs").with_info(&files).to_json()?),
+                        }
+                    }
+                    Action::Verify => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for verify action"))?;
+                        let mut romfs =
+                            hactool_rs::file_formats::romfs::RomFsReader::parse_file(&file_name)?;
+                        print_verification(args.format, "romfs", &romfs.verification_results())?;
+                    }
+                    Action::Extract => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for extract action"))?;
+                        let output_folder = args.output.as_ref().ok_or(anyhow!(
+                            "Output folder must be provided for extract action"
+                        ))?;
+                        let mut romfs =
+                            hactool_rs::file_formats::romfs::RomFsReader::parse_file(&file_name)?;
+
+                        println!("Extracting {}...", output_folder);
+
+                        romfs.extract_to(output_folder)?;
+                    }
+                    Action::Create => {
+                        let input_folder = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input folder must be provided for create action"))?;
+                        let output_file = args
+                            .output
+                            .clone()
+                            .ok_or(anyhow!("Output file must be provided for create action"))?;
+                        let romfs =
+                            hactool_rs::file_formats::romfs::RomFsBuilder::from_dir(&input_folder)?;
+
+                        println!("Creating {}...", output_file);
+
+                        let mut writer = BufWriter::new(File::create(&output_file)?);
+                        romfs.write(&mut writer)?;
+                    }
+                }
+            }
+        }
+        args::SupportedFileTypes::Nax0 => {
+            for action in args.action.iter() {
+                let file_name = args
+                    .input
+                    .clone()
+                    .ok_or(anyhow!("Input file must be provided for {:?} action", action))?;
+                match action {
+                    Action::Info => {
+                        let nax0 = Nax0Reader::open(&file_name, args.sdpath.as_deref(), &keys)?;
+                        print_info(args.format, "nax0", &nax0)?;
+                    }
+                    Action::Verify => {
+                        // The keys are only known to be right once the header MAC matches
+                        let result = match Nax0Reader::open(&file_name, args.sdpath.as_deref(), &keys) {
+                            Ok(_) => Validity::Valid,
+                            Err(binrw::Error::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData => Validity::Invalid,
+                            Err(_) => Validity::CheckError,
+                        };
+                        print_verification(args.format, "nax0", &[VerificationResult::new("header_mac", None, result)])?;
+                    }
+                    Action::Extract => {
+                        let output_file = args.output.as_ref().ok_or(anyhow!(
+                            "Output file must be provided for extract action"
+                        ))?;
+                        let mut nax0 = Nax0Reader::open(&file_name, args.sdpath.as_deref(), &keys)?;
+
+                        println!("Decrypting {}...", output_file);
+
+                        std::io::copy(&mut nax0, &mut BufWriter::new(File::create(output_file)?))?;
+                    }
+                    Action::Create => {
+                        return Err(anyhow!("Creating NAX0 files is not supported"));
+                    }
+                }
+            }
+        }
+        args::SupportedFileTypes::Save => {
+            for action in args.action.iter() {
+                let file_name = args
+                    .input
+                    .clone()
+                    .ok_or(anyhow!("Input file must be provided for {:?} action", action))?;
+                match action {
+                    Action::Info => {
+                        let save = hactool_rs::file_formats::save::SaveReader::parse_file(&file_name)?;
+                        print_info(args.format, "save", &save)?;
+                    }
+                    Action::Verify => {
+                        let mut save = hactool_rs::file_formats::save::SaveReader::parse_file(&file_name)?;
+                        let mut results = save.verification_results();
+                        results.push(VerificationResult::new("header_cmac", None, save.verify_header_cmac(&keys)));
+                        print_verification(args.format, "save", &results)?;
+                    }
+                    Action::Extract => {
+                        let output_folder = args.output.as_ref().ok_or(anyhow!(
+                            "Output folder must be provided for extract action"
+                        ))?;
+                        let mut save = hactool_rs::file_formats::save::SaveReader::parse_file(&file_name)?;
+
+                        println!("Extracting {}...", output_folder);
+
+                        save.extract_to(output_folder)?;
+                    }
+                    Action::Create => {
+                        if args.savefile.is_empty() {
+                            return Err(anyhow!("Files to replace must be provided with --savefile for create action"));
+                        }
+                        let mut save = hactool_rs::file_formats::save::SaveReader::parse_file_writable(&file_name, &keys)?;
+                        for replacement in args.savefile.iter() {
+                            let (save_path, local_file) = replacement
+                                .split_once('=')
+                                .ok_or(anyhow!("--savefile takes save_path=local_file, got {}", replacement))?;
+                            println!("Replacing save:/{}...", save_path.trim_start_matches('/'));
+                            save.write_file(save_path, &std::fs::read(local_file)?)?;
+                        }
+                        save.commit()?;
+                    }
+                }
+            }
+        }
+        args::SupportedFileTypes::Nand => {
+            for action in args.action.iter() {
+                let file_name = args
+                    .input
+                    .clone()
+                    .ok_or(anyhow!("Input file must be provided for {:?} action", action))?;
+                match action {
+                    Action::Info => {
+                        let nand = NandReader::open(&file_name, &keys)?;
+                        print_info(args.format, "nand", &nand)?;
+                    }
+                    Action::Verify => {
+                        let mut nand = NandReader::open(&file_name, &keys)?;
+                        print_verification(args.format, "nand", &nand.verification_results())?;
+                    }
+                    Action::Extract => {
+                        let output_folder = PathBuf::from(args.output.as_ref().ok_or(anyhow!(
+                            "Output folder must be provided for extract action"
+                        ))?);
+                        let nand = NandReader::open(&file_name, &keys)?;
+                        std::fs::create_dir_all(&output_folder)?;
+                        for partition in nand.partitions.iter() {
+                            if !args.partition.is_empty() && !args.partition.iter().any(|name| name.eq_ignore_ascii_case(&partition.name)) {
+                                continue;
+                            }
+                            println!("Decrypting {}...", partition.name);
+
+                            // Partition names come from the GPT, so they must not leave the output folder
+                            let mut components = std::path::Path::new(&partition.name).components();
+                            if partition.name.contains(['/', '\\']) || !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+                                return Err(anyhow!("Refusing to extract partition {:?} outside of the output folder", partition.name));
+                            }
+
+                            let mut reader = nand.open_partition(partition)?;
+                            let mut writer = BufWriter::new(File::create(output_folder.join(&partition.name))?);
+                            std::io::copy(&mut reader, &mut writer)?;
+                            writer.flush()?;
+                        }
+                    }
+                    Action::Create => {
+                        return Err(anyhow!("Creating NAND dumps is not supported"));
+                    }
+                }
+            }
+        }
+        args::SupportedFileTypes::Sd => {
+            for action in args.action.iter() {
+                let nintendo_folder = args
+                    .input
+                    .clone()
+                    .ok_or(anyhow!("Input Nintendo folder must be provided for {:?} action", action))?;
+                match action {
+                    Action::Info => {
+                        let files = decrypt_sd_card(&nintendo_folder, None, &keys)?;
+                        print_info(args.format, "sd", files.as_slice())?;
+                    }
+                    Action::Verify | Action::Extract => {
+                        let output_folder = match action {
+                            Action::Extract => Some(PathBuf::from(args.output.as_ref().ok_or(anyhow!(
+                                "Output folder must be provided for extract action"
+                            ))?)),
+                            _ => None,
+                        };
+                        let results: Vec<_> = decrypt_sd_card(&nintendo_folder, output_folder.as_deref(), &keys)?
+                            .into_iter()
+                            .map(|file| VerificationResult::new("header_mac", Some(file.path), file.header_mac))
+                            .collect();
+                        print_verification(args.format, "sd", &results)?;
+                    }
+                    Action::Create => {
+                        return Err(anyhow!("Creating SD card folders is not supported"));
+                    }
+                }
+            }
+        }
+        args::SupportedFileTypes::Nso => {
+            for action in args.action.iter() {
+                match action {
+                    Action::Info => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for info action"))?;
+                        let nso = hactool_rs::file_formats::nso::NsoReader::parse_file(&file_name)?;
+                        print_info(args.format, "nso", &nso.header)?;
+                    }
+                    Action::Verify => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for verify action"))?;
+                        let mut nso = hactool_rs::file_formats::nso::NsoReader::parse_file(&file_name)?;
+                        print_verification(args.format, "nso", &nso.verification_results())?;
+                    }
+                    Action::Extract => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for extract action"))?;
+                        let output_file = args.elf.as_ref().or(args.output.as_ref()).ok_or(anyhow!(
+                            "Output ELF file must be provided for extract action"
+                        ))?;
+                        let mut nso = hactool_rs::file_formats::nso::NsoReader::parse_file(&file_name)?;
+                        write_elf(nso.module_image()?, output_file)?;
+                    }
+                    Action::Create => {
+                        return Err(anyhow!("Creating NSO files is not supported"));
+                    }
+                }
+            }
+        }
+        args::SupportedFileTypes::Nro => {
+            for action in args.action.iter() {
+                match action {
+                    Action::Info => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for info action"))?;
+                        let nro = hactool_rs::file_formats::nro::NroReader::parse_file(&file_name)?;
+                        print_info(args.format, "nro", &nro)?;
+                    }
+                    Action::Verify => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for verify action"))?;
+                        let mut nro = hactool_rs::file_formats::nro::NroReader::parse_file(&file_name)?;
+                        print_verification(args.format, "nro", &nro.verification_results())?;
+                    }
+                    Action::Extract => {
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for extract action"))?;
+                        if args.output.is_none() && args.elf.is_none() {
+                            return Err(anyhow!("Output folder or --elf must be provided for extract action"));
+                        }
+                        let mut nro = hactool_rs::file_formats::nro::NroReader::parse_file(&file_name)?;
+
+                        if let Some(elf) = &args.elf {
+                            write_elf(nro.module_image()?, elf)?;
+                        }
+                        if let Some(output_folder) = &args.output {
+                            println!("Extracting {}...", output_folder);
+
+                            nro.extract_assets(output_folder)?;
+                        }
+                    }
+                    Action::Create => {
+                        return Err(anyhow!("Creating NRO files is not supported"));
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "fuse")]
+        args::SupportedFileTypes::Mount => mount(&args, keys)?,
         args::SupportedFileTypes::Nca => {
+            if args.list_modules {
+                let file_name = args
+                    .input
+                    .clone()
+                    .ok_or(anyhow!("Input file must be provided to list modules"))?;
+                let mut nca_reader = hactool_rs::file_formats::nca::NcaFileReader::parse_file(&file_name, &keys)?;
+                set_nca_title_key(&args, &mut nca_reader, &keys)?;
+                let modules = exefs_modules(&mut nca_reader.into_exefs()?, DEFAULT_LOAD_BASE)?;
+                print_info(args.format, "modules", modules.as_slice())?;
+            }
             for action in args.action.iter() {
                 match action {
                     Action::Info => {
@@ -136,19 +600,76 @@ fn main() -> anyhow::Result<()> {
                             .ok_or(anyhow!("Input file must be provided for info action"))?;
                         let nca_reader =
                             hactool_rs::file_formats::nca::NcaFileReader::parse_file(&file_name, &keys)?;
-                        println!(
-                            "Nca file: {:X?}",
-                            nca_reader.nca_ctx
-                        );
+                        print_info(args.format, "nca", &nca_reader)?;
                     }
                     Action::Verify => {
-                        todo!()
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for verify action"))?;
+                        let mut nca_reader =
+                            hactool_rs::file_formats::nca::NcaFileReader::parse_file(&file_name, &keys)?;
+                        set_nca_title_key(&args, &mut nca_reader, &keys)?;
+                        print_verification(args.format, "nca", &nca_reader.verification_results())?;
                     }
                     Action::Extract => {
-                        todo!()
+                        let file_name = args
+                            .input
+                            .clone()
+                            .ok_or(anyhow!("Input file must be provided for extract action"))?;
+                        let mut nca_reader =
+                            hactool_rs::file_formats::nca::NcaFileReader::parse_file(&file_name, &keys)?;
+                        set_nca_title_key(&args, &mut nca_reader, &keys)?;
+
+                        if let Some(header_path) = &args.header {
+                            std::fs::write(header_path, nca_reader.decrypted_header())?;
+                        }
+                        if let Some(plaintext_path) = &args.plaintext {
+                            nca_reader.write_plaintext(&mut BufWriter::new(File::create(plaintext_path)?))?;
+                        }
+                        let section_paths = [&args.section0, &args.section1, &args.section2, &args.section3];
+                        for (index, section_path) in section_paths.into_iter().enumerate() {
+                            if let Some(section_path) = section_path {
+                                nca_reader.write_section(index, &mut BufWriter::new(File::create(section_path)?))?;
+                            }
+                        }
+
+                        let (mut exefs_dir, mut romfs_dir) = (args.exefsdir.clone().map(PathBuf::from), args.romfsdir.clone().map(PathBuf::from));
+                        if let Some(output) = &args.output {
+                            exefs_dir.get_or_insert(PathBuf::from(output).join("exefs"));
+                            romfs_dir.get_or_insert(PathBuf::from(output).join("romfs"));
+                        }
+
+                        let exefs_index = nca_reader.exefs_section_index();
+                        if let Some(exefs_index) = exefs_index {
+                            if args.listexefs {
+                                for file in nca_reader.section_pfs0(exefs_index)?.files.iter() {
+                                    println!("exefs:/{}", file.file_name);
+                                }
+                            }
+                            if let Some(exefs_dir) = &exefs_dir {
+                                nca_reader.extract_pfs0_section(exefs_index, exefs_dir)?;
+                            }
+                        } else if args.listexefs || args.exefsdir.is_some() {
+                            println!("NCA has no ExeFS section.");
+                        }
+
+                        let romfs_index = nca_reader.romfs_section_index();
+                        if let Some(romfs_index) = romfs_index {
+                            if args.listromfs {
+                                for file in nca_reader.section_romfs(romfs_index)?.files()? {
+                                    println!("romfs:/{}", file.path);
+                                }
+                            }
+                            if let Some(romfs_dir) = &romfs_dir {
+                                nca_reader.extract_romfs_section(romfs_index, romfs_dir)?;
+                            }
+                        } else if args.listromfs || args.romfsdir.is_some() {
+                            println!("NCA has no RomFS section.");
+                        }
                     }
                     Action::Create => {
-                        todo!()
+                        return Err(anyhow!("Creating NCAs is not supported"));
                     }
                 }
             }
@@ -156,3 +677,109 @@ fn main() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+/// Mount the input, or the file a virtual input path points at, until it is unmounted
+#[cfg(feature = "fuse")]
+fn mount(args: &Args, keys: hactool_rs::keys::NcaKeys) -> anyhow::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let file_name = args.input.clone().ok_or(anyhow!("Input file must be provided for mount"))?;
+    let mountpoint = args.output.clone().ok_or(anyhow!("Output folder must be provided as the mount point"))?;
+    let (host_path, mut root) = match PathBuf::from(&file_name) {
+        path if path.is_file() => (path, ContainerNode::open(&file_name, Rc::new(keys))?),
+        _ => {
+            let path = VirtualPath::parse(&file_name).ok_or(anyhow!("Input file {} does not exist", file_name))?;
+            (path.host_path, ContainerNode::open_virtual(&file_name, Rc::new(keys))?)
+        }
+    };
+    root = with_title_key(args, root)?;
+
+    let metadata = std::fs::metadata(host_path)?;
+    let attributes = hactool_rs::fuse::MountAttributes {
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        time: metadata.modified()?,
+    };
+    hactool_rs::fuse::mount(root, mountpoint, attributes)?;
+    Ok(())
+}
+
+/// Handle formats without a dedicated subcommand, and files addressed by a virtual path: `info`
+/// lists the tree of nested files and `extract` writes out the file itself
+fn print_container(args: &Args, file_type: FileType, mut node: ContainerNode) -> anyhow::Result<()> {
+    node = with_title_key(args, node)?;
+    for action in args.action.iter() {
+        match action {
+            Action::Info => print_info(args.format, "container", &node.tree())?,
+            Action::Extract => {
+                // Stream the file straight out of its containers, to stdout if no output is given
+                match args.output.as_ref() {
+                    Some(output) => node.write_to(&mut BufWriter::new(File::create(output)?))?,
+                    None => node.write_to(&mut std::io::stdout().lock())?,
+                };
+            }
+            _ => return Err(anyhow!("{:?} is not supported for {} files", action, file_type)),
+        }
+    }
+    Ok(())
+}
+
+/// Convert a decompressed NSO or NRO to an ELF with rebuilt section headers
+fn write_elf(module: ModuleImage, output_file: &str) -> anyhow::Result<()> {
+    let elf = ElfModule::parse(module)?;
+
+    println!("Writing {} ({} dynamic symbols)...", output_file, elf.symbols.len());
+
+    elf.write(&mut BufWriter::new(File::create(output_file)?))?;
+    Ok(())
+}
+
+fn parse_hex_key(hex: &str) -> anyhow::Result<[u8; 0x10]> {
+    let mut key = [0u8; 0x10];
+    if hex.len() != key.len() * 2 {
+        return Err(anyhow!("Key must be {} hex characters", key.len() * 2));
+    }
+    for (byte, chunk) in key.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(chunk)?, 16)?;
+    }
+    Ok(key)
+}
+
+/// Apply `--titlekey` (titlekek encrypted) or `--contentkey` (decrypted) to an NCA. NCAs with a
+/// rights ID can't be decrypted without one of them.
+fn set_nca_title_key(args: &Args, nca: &mut NcaFileReader, keys: &NcaKeys) -> anyhow::Result<()> {
+    match (args.titlekey.as_deref(), args.contentkey.as_deref()) {
+        (Some(title_key), _) => nca.set_title_key(parse_hex_key(title_key)?, keys),
+        (None, Some(content_key)) => nca.set_decrypted_title_key(parse_hex_key(content_key)?),
+        (None, None) if nca.nca_ctx.has_rights_id() => {
+            return Err(anyhow!("The NCA uses titlekey crypto, --titlekey or --contentkey must be provided"));
+        }
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// Apply `--titlekey` or `--contentkey` to the NCAs with a rights ID in a container tree
+fn with_title_key(args: &Args, node: ContainerNode) -> anyhow::Result<ContainerNode> {
+    Ok(match (args.titlekey.as_deref(), args.contentkey.as_deref()) {
+        (Some(title_key), _) => node.with_title_key(parse_hex_key(title_key)?),
+        (None, Some(content_key)) => node.with_decrypted_title_key(parse_hex_key(content_key)?),
+        (None, None) => node,
+    })
+}
+
+fn print_info<T: PrettyInfo + Serialize + ?Sized>(format: OutputFormat, file_type: &'static str, file: &T) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Text => print!("{}", file.info_string()),
+        OutputFormat::Json => println!("{}", JsonReport::new(file_type).with_info(file).to_json()?),
+    }
+    Ok(())
+}
+
+fn print_verification(format: OutputFormat, file_type: &'static str, results: &[VerificationResult]) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Text => print!("{}", results.info_string()),
+        OutputFormat::Json => println!("{}", JsonReport::<()>::new(file_type).with_verification(results).to_json()?),
+    }
+    Ok(())
+}
@@ -0,0 +1,547 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use binrw::prelude::*;
+use serde::Serialize;
+
+use super::hfs0::Hfs0Index;
+use super::kip1::{Ini1Header, Kip1Header, INI1_HEADER_SIZE};
+use super::nca::{self, NcaFileReader};
+use super::nro::{NroReader, NRO_HEADER_OFFSET};
+use super::pfs0::Pfs0Reader;
+use super::romfs::RomFs;
+use super::xci::{Xci, XCI_MAGIC_OFFSET};
+use crate::keys::NcaKeys;
+use crate::utils::{read_up_to, ReadSeek};
+
+/// Number of bytes read from the start of a file to detect its type
+const DETECT_LEN: usize = 0x400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FileType {
+    Xci,
+    Hfs0,
+    Pfs0,
+    Nca,
+    RomFs,
+    Npdm,
+    Nso,
+    Nro,
+    Kip1,
+    Ini1,
+    Nax0,
+    Save,
+    Nand,
+    Unknown,
+}
+
+impl std::fmt::Display for FileType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FileType::Xci => "XCI",
+            FileType::Hfs0 => "HFS0",
+            FileType::Pfs0 => "PFS0",
+            FileType::Nca => "NCA",
+            FileType::RomFs => "RomFS",
+            FileType::Npdm => "NPDM",
+            FileType::Nso => "NSO",
+            FileType::Nro => "NRO",
+            FileType::Kip1 => "KIP1",
+            FileType::Ini1 => "INI1",
+            FileType::Nax0 => "NAX0",
+            FileType::Save => "SAVE",
+            FileType::Nand => "NAND",
+            FileType::Unknown => "Unknown",
+        })
+    }
+}
+
+impl FileType {
+    /// Detect the type of the file starting at the current position of `reader` from its magic.
+    /// The position is restored afterwards.
+    ///
+    /// Encrypted NCA headers can only be recognised with the header key, so NCAs are reported as
+    /// [`FileType::Unknown`] when no keys are given.
+    pub fn detect<R: Read + Seek + ?Sized>(reader: &mut R, key_set: Option<&NcaKeys>) -> Result<FileType> {
+        let position = reader.stream_position()?;
+        let mut header = [0u8; DETECT_LEN];
+        let len = read_up_to(reader, &mut header)?;
+        reader.seek(SeekFrom::Start(position))?;
+
+        Ok(Self::detect_bytes(&header[..len], key_set))
+    }
+
+    pub fn detect_file<P: AsRef<Path>>(path: P, key_set: Option<&NcaKeys>) -> Result<FileType> {
+        Self::detect(&mut File::open(path.as_ref())?, key_set)
+    }
+
+    /// Detect the type of a file from its first bytes (ideally at least 0x400)
+    pub fn detect_bytes(header: &[u8], key_set: Option<&NcaKeys>) -> FileType {
+        let magic_at = |offset: usize, magic: &[u8; 4]| header.get(offset..offset + 4) == Some(magic);
+        let u64_at = |offset: usize| header.get(offset..offset + 8).map(|b| u64::from_le_bytes(b.try_into().unwrap()));
+
+        if magic_at(0, b"PFS0") {
+            FileType::Pfs0
+        } else if magic_at(0, b"HFS0") {
+            FileType::Hfs0
+        } else if magic_at(0, b"META") {
+            FileType::Npdm
+        } else if magic_at(0, b"NSO0") {
+            FileType::Nso
+        } else if magic_at(0, b"KIP1") {
+            FileType::Kip1
+        } else if magic_at(0, b"INI1") {
+            FileType::Ini1
+        } else if magic_at(0x20, b"NAX0") {
+            FileType::Nax0
+        } else if magic_at(0x100, b"DISF") {
+            FileType::Save
+        } else if header.get(0x200..0x208) == Some(b"EFI PART") {
+            // The GPT header follows the protective MBR
+            FileType::Nand
+        } else if magic_at(NRO_HEADER_OFFSET as usize, b"NRO0") {
+            FileType::Nro
+        } else if magic_at(XCI_MAGIC_OFFSET as usize, b"HEAD") {
+            FileType::Xci
+        } else if key_set.is_some_and(|keys| nca::is_nca_header(header, keys)) {
+            FileType::Nca
+        } else if u64_at(0) == Some(0x50) && u64_at(0x8).is_some_and(|o| o >= 0x50) && u64_at(0x48).is_some_and(|o| o >= 0x50) {
+            // RomFS has no magic, but its header size is always 0x50 and the tables follow it
+            FileType::RomFs
+        } else {
+            FileType::Unknown
+        }
+    }
+
+    /// Whether nodes of this type have children
+    pub fn is_container(self) -> bool {
+        matches!(
+            self,
+            FileType::Xci | FileType::Hfs0 | FileType::Pfs0 | FileType::Nca | FileType::RomFs | FileType::Ini1 | FileType::Nro
+        )
+    }
+}
+
+/// Seekable view of a region of a shared reader.
+///
+/// Clones share the underlying reader but keep their own position, so the children of a
+/// container can all be read from the one open file without copying any data.
+#[derive(Clone)]
+pub struct NodeReader {
+    inner: Rc<RefCell<Box<dyn ReadSeek>>>,
+    /// Offset of the region in the underlying reader
+    offset: u64,
+    size: u64,
+    position: u64,
+}
+
+impl NodeReader {
+    /// View the whole of `reader`
+    pub fn new<R: ReadSeek + 'static>(mut reader: R) -> Result<Self> {
+        let size = reader.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            inner: Rc::new(RefCell::new(Box::new(reader))),
+            offset: 0,
+            size,
+            position: 0,
+        })
+    }
+
+    /// View `size` bytes starting at `offset` within this view
+    pub fn slice(&self, offset: u64, size: u64) -> Self {
+        let offset = offset.min(self.size);
+        Self {
+            inner: self.inner.clone(),
+            offset: self.offset + offset,
+            size: size.min(self.size - offset),
+            position: 0,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl std::fmt::Debug for NodeReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NodeReader")
+            .field("offset", &self.offset)
+            .field("size", &self.size)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl Read for NodeReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = (buf.len() as u64).min(self.size.saturating_sub(self.position)) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let mut inner = self.inner.borrow_mut();
+        inner.seek(SeekFrom::Start(self.offset + self.position))?;
+        let read = inner.read(&mut buf[..len])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for NodeReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Invalid seek to a negative or overflowing position",
+        ))?;
+        Ok(self.position)
+    }
+}
+
+/// Path to a file inside nested containers: a file on disk, followed by `:/` and the path
+/// within it, e.g. `game.xci:/secure/abc.nca/romfs/data/x.bin`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualPath {
+    pub host_path: PathBuf,
+    pub inner_path: String,
+}
+
+impl VirtualPath {
+    /// Split a virtual path at the first `:/` which follows an existing file, falling back to the
+    /// last `:/` so that paths to files which do not exist yet can still be parsed
+    pub fn parse(path: &str) -> Option<VirtualPath> {
+        let separators: Vec<usize> = path.match_indices(":/").map(|(index, _)| index).collect();
+        let index = separators
+            .iter()
+            .copied()
+            .find(|&index| Path::new(&path[..index]).is_file())
+            .or(separators.last().copied())?;
+
+        Some(VirtualPath {
+            host_path: PathBuf::from(&path[..index]),
+            inner_path: path[index + 2..].to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for VirtualPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:/{}", self.host_path.display(), self.inner_path)
+    }
+}
+
+/// A file in a tree of nested containers, e.g. XCI → HFS0 → NCA → section → PFS0 → NSO.
+///
+/// Nodes only hold a view of their data. Containers are parsed when [`ContainerNode::children`]
+/// is called, so descending into one branch never reads the others.
+#[derive(Clone)]
+pub struct ContainerNode {
+    pub name: String,
+    pub file_type: FileType,
+    reader: NodeReader,
+    key_set: Rc<NcaKeys>,
+    /// Title key used for NCAs with a rights ID
+    title_key: Option<TitleKey>,
+}
+
+/// A title key given for NCAs with a rights ID
+#[derive(Clone, Copy)]
+enum TitleKey {
+    /// Encrypted with the titlekek of each NCA's key generation
+    Encrypted([u8; 0x10]),
+    /// Already decrypted, used as the content key as is
+    Decrypted([u8; 0x10]),
+}
+
+impl std::fmt::Debug for ContainerNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContainerNode")
+            .field("name", &self.name)
+            .field("file_type", &self.file_type)
+            .field("size", &self.size())
+            .finish()
+    }
+}
+
+impl ContainerNode {
+    /// Open a file on disk, detecting its type
+    pub fn open<P: AsRef<Path>>(path: P, key_set: Rc<NcaKeys>) -> BinResult<ContainerNode> {
+        let path = path.as_ref();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        Self::from_reader(name, File::open(path)?, key_set)
+    }
+
+    /// Open a file inside nested containers addressed by a [`VirtualPath`], e.g.
+    /// `game.xci:/secure/abc.nca/romfs/data/x.bin`
+    pub fn open_virtual(path: &str, key_set: Rc<NcaKeys>) -> BinResult<ContainerNode> {
+        let path = VirtualPath::parse(path).ok_or_else(|| {
+            binrw::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not a virtual path of the form <file>:/<path>", path),
+            ))
+        })?;
+        Self::open(&path.host_path, key_set)?.resolve(&path.inner_path)
+    }
+
+    /// Open any reader, detecting its type
+    pub fn from_reader<S: Into<String>, R: ReadSeek + 'static>(name: S, reader: R, key_set: Rc<NcaKeys>) -> BinResult<ContainerNode> {
+        let mut reader = NodeReader::new(reader)?;
+        let file_type = FileType::detect(&mut reader, Some(&key_set))?;
+        Ok(ContainerNode { name: name.into(), file_type, reader, key_set, title_key: None })
+    }
+
+    /// Use a (titlekek encrypted) title key for NCAs with a rights ID in this node and its descendants
+    pub fn with_title_key(mut self, encrypted_title_key: [u8; 0x10]) -> Self {
+        self.title_key = Some(TitleKey::Encrypted(encrypted_title_key));
+        self
+    }
+
+    /// Use an already decrypted title key for NCAs with a rights ID in this node and its descendants
+    pub fn with_decrypted_title_key(mut self, title_key: [u8; 0x10]) -> Self {
+        self.title_key = Some(TitleKey::Decrypted(title_key));
+        self
+    }
+
+    pub fn size(&self) -> u64 {
+        self.reader.size()
+    }
+
+    pub fn is_container(&self) -> bool {
+        self.file_type.is_container()
+    }
+
+    /// A new reader over the data of this node, positioned at its start
+    pub fn reader(&self) -> NodeReader {
+        let mut reader = self.reader.clone();
+        reader.position = 0;
+        reader
+    }
+
+    /// Copy the data of this node into `writer`, returning the number of bytes written
+    pub fn write_to(&self, writer: &mut dyn Write) -> Result<u64> {
+        std::io::copy(&mut self.reader(), writer)
+    }
+
+    /// Parse this node and return its direct children. Leaf files have no children.
+    ///
+    /// NCA sections are named `exefs` and `romfs` where applicable and `section<n>` otherwise,
+    /// and skip the section hash layers. RomFS children are named by their full path. NROs
+    /// contain the `icon.jpg`, `control.nacp` and `romfs` of their asset section.
+    pub fn children(&self) -> BinResult<Vec<ContainerNode>> {
+        match self.file_type {
+            FileType::Xci => {
+                let xci: Xci = self.reader().read_le()?;
+                self.hfs0_children(&xci.root_partition)
+            }
+            FileType::Hfs0 => {
+                let hfs0: Hfs0Index = self.reader().read_le()?;
+                self.hfs0_children(&hfs0)
+            }
+            FileType::Pfs0 => {
+                let pfs0 = Pfs0Reader::parse_reader(self.reader())?;
+                pfs0.pfs
+                    .files
+                    .iter()
+                    .map(|file| self.child(file.file_name.to_string(), self.reader.slice(file.file_offset, file.file_size), None))
+                    .collect()
+            }
+            FileType::Nca => self.nca_children(),
+            FileType::RomFs => {
+                let romfs: RomFs = self.reader().read_le()?;
+                romfs
+                    .files()?
+                    .into_iter()
+                    .map(|file| self.child(file.path, self.reader.slice(file.file_offset, file.file_size), None))
+                    .collect()
+            }
+            FileType::Ini1 => self.ini1_children(),
+            FileType::Nro => {
+                let nro = NroReader::parse_reader(self.reader())?;
+                let Some(assets) = nro.nro.assets else {
+                    return Ok(Vec::new());
+                };
+                [("icon.jpg", assets.icon, None), ("control.nacp", assets.nacp, None), ("romfs", assets.romfs, Some(FileType::RomFs))]
+                    .into_iter()
+                    .filter(|(_, section, _)| section.is_present())
+                    .map(|(name, section, file_type)| self.child(name.to_string(), self.reader.slice(section.offset, section.size), file_type))
+                    .collect()
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Find a direct child by name
+    pub fn find_child(&self, name: &str) -> BinResult<Option<ContainerNode>> {
+        let components: Vec<&str> = name.split('/').filter(|c| !c.is_empty()).collect();
+        Ok(self
+            .child_for_path(&components)?
+            .filter(|(_, consumed)| *consumed == components.len())
+            .map(|(child, _)| child))
+    }
+
+    /// Follow a `/` separated path through nested containers, e.g.
+    /// `secure/abc.nca/romfs/data/x.bin` from an XCI. Only the containers along the path are
+    /// parsed and no data is copied.
+    pub fn resolve(&self, path: &str) -> BinResult<ContainerNode> {
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let mut node = self.clone();
+        let mut remaining = &components[..];
+        while !remaining.is_empty() {
+            let (child, consumed) = node.child_for_path(remaining)?.ok_or_else(|| {
+                binrw::Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{} ({}) has no entry {}", node.name, node.file_type, remaining.join("/")),
+                ))
+            })?;
+            node = child;
+            remaining = &remaining[consumed..];
+        }
+        Ok(node)
+    }
+
+    /// Find the child matching the longest prefix of `components`, as child names may contain
+    /// `/` (RomFS paths). Returns the child and the number of components it consumed.
+    fn child_for_path(&self, components: &[&str]) -> BinResult<Option<(ContainerNode, usize)>> {
+        if self.file_type == FileType::RomFs {
+            // Use the RomFS hash tables rather than listing every file
+            let romfs: RomFs = self.reader().read_le()?;
+            for len in (1..=components.len()).rev() {
+                if let Some(file) = romfs.find_file(components[..len].join("/"))? {
+                    let reader = self.reader.slice(file.file_offset, file.file_size);
+                    return Ok(Some((self.child(file.path, reader, None)?, len)));
+                }
+            }
+            return Ok(None);
+        }
+
+        let mut children = self.children()?;
+        for len in (1..=components.len()).rev() {
+            let name = components[..len].join("/");
+            if let Some(index) = children.iter().position(|child| child.name == name) {
+                return Ok(Some((children.swap_remove(index), len)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn child(&self, name: String, mut reader: NodeReader, file_type: Option<FileType>) -> BinResult<ContainerNode> {
+        let file_type = match file_type {
+            Some(file_type) => file_type,
+            None => FileType::detect(&mut reader, Some(&self.key_set))?,
+        };
+        Ok(ContainerNode {
+            name,
+            file_type,
+            reader,
+            key_set: self.key_set.clone(),
+            title_key: self.title_key,
+        })
+    }
+
+    fn hfs0_children(&self, hfs0: &Hfs0Index) -> BinResult<Vec<ContainerNode>> {
+        hfs0.files
+            .iter()
+            .map(|file| self.child(file.file_name.to_string(), self.reader.slice(file.file_offset, file.file_size), None))
+            .collect()
+    }
+
+    fn open_nca(&self) -> BinResult<NcaFileReader> {
+        let mut nca = NcaFileReader::parse_reader(self.reader(), &self.key_set)?;
+        match (nca.nca_ctx.has_rights_id(), self.title_key) {
+            (true, Some(TitleKey::Encrypted(title_key))) => nca.set_title_key(title_key, &self.key_set),
+            (true, Some(TitleKey::Decrypted(title_key))) => nca.set_decrypted_title_key(title_key),
+            _ => {}
+        }
+        Ok(nca)
+    }
+
+    fn nca_children(&self) -> BinResult<Vec<ContainerNode>> {
+        let nca = self.open_nca()?;
+        let exefs_index = nca.exefs_section_index();
+        let romfs_index = nca.romfs_section_index();
+
+        let mut children = Vec::new();
+        for index in 0..4 {
+            let entry = nca.nca_ctx.section_entries[index];
+            let Some(data_offset) = nca.section_data_offset(index) else {
+                continue;
+            };
+            if entry.end_block_offset <= entry.start_block_offset {
+                continue;
+            }
+
+            let name = if Some(index) == exefs_index {
+                "exefs".to_string()
+            } else if Some(index) == romfs_index {
+                "romfs".to_string()
+            } else {
+                format!("section{}", index)
+            };
+            let file_type = match nca.nca_ctx.fs_headers[index].fs_type {
+                nca::fs::FsType::Pfs0 => FileType::Pfs0,
+                nca::fs::FsType::RomFs => FileType::RomFs,
+                _ => continue,
+            };
+
+            let section = NodeReader::new(self.open_nca()?.into_verified_section(index)?)?;
+            let size = section.size().saturating_sub(data_offset);
+            children.push(self.child(name, section.slice(data_offset, size), Some(file_type))?);
+        }
+        Ok(children)
+    }
+
+    fn ini1_children(&self) -> BinResult<Vec<ContainerNode>> {
+        let mut reader = self.reader();
+        let ini1: Ini1Header = reader.read_le()?;
+
+        let mut children = Vec::with_capacity(ini1.process_count as usize);
+        let mut offset = INI1_HEADER_SIZE;
+        for _ in 0..ini1.process_count {
+            reader.seek(SeekFrom::Start(offset))?;
+            let kip: Kip1Header = reader.read_le()?;
+            let size = kip.file_size();
+            children.push(self.child(format!("{}.kip1", kip.name()), self.reader.slice(offset, size), Some(FileType::Kip1))?);
+            offset += size;
+        }
+        Ok(children)
+    }
+}
+
+/// A node and all of its descendants, fully expanded for listing
+#[derive(Debug, Serialize)]
+pub struct ContainerTree {
+    pub name: String,
+    pub file_type: FileType,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<ContainerTree>,
+    /// Why the children could not be listed, e.g. because of missing keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ContainerNode {
+    /// Descend into every nested container. Errors are recorded in the tree rather than
+    /// returned, so one unreadable NCA does not hide the rest of an XCI.
+    pub fn tree(&self) -> ContainerTree {
+        let (children, error) = match self.children() {
+            Ok(children) => (children.iter().map(ContainerNode::tree).collect(), None),
+            Err(e) => (Vec::new(), Some(e.to_string())),
+        };
+        ContainerTree {
+            name: self.name.clone(),
+            file_type: self.file_type,
+            size: self.size(),
+            children,
+            error,
+        }
+    }
+}
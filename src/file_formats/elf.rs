@@ -0,0 +1,476 @@
+//! Conversion of NSO and NRO modules into ELF files for disassemblers.
+//!
+//! Modules are position independent AArch64 shared objects with their section headers stripped.
+//! The MOD0 header locates `.dynamic`, `.bss` and `.eh_frame_hdr`, and the dynamic table locates
+//! the symbol, string and relocation tables, which is enough to rebuild the section headers. The
+//! image is copied verbatim, so every address in the ELF matches the address in the module.
+
+use std::io::{Cursor, Error, ErrorKind, Write};
+use std::ops::Range;
+
+use binrw::prelude::*;
+use binrw::BinWriterExt;
+use serde::Serialize;
+
+use crate::utils::CurPos;
+
+/// Offset of the module image in the ELF file, leaving room for the ELF and program headers
+const IMAGE_FILE_OFFSET: u64 = 0x1000;
+const PAGE_SIZE: u64 = 0x1000;
+
+const ELF_HEADER_SIZE: u16 = 0x40;
+const PROGRAM_HEADER_SIZE: u16 = 0x38;
+const SECTION_HEADER_SIZE: u16 = 0x40;
+const DYNAMIC_ENTRY_SIZE: u64 = 0x10;
+const SYMBOL_SIZE: u64 = 0x18;
+const RELA_SIZE: u64 = 0x18;
+
+const ET_DYN: u16 = 3;
+const EM_AARCH64: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_GNU_EH_FRAME: u32 = 0x6474_E550;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_HASH: u32 = 5;
+const SHT_DYNAMIC: u32 = 6;
+const SHT_NOBITS: u32 = 8;
+const SHT_DYNSYM: u32 = 11;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+/// Sections covering the module segments, which defined symbols are assigned to
+const SEGMENT_SECTIONS: [&str; 4] = [".text", ".rodata", ".data", ".bss"];
+
+const SHN_UNDEF: u16 = 0;
+const SHN_LORESERVE: u16 = 0xFF00;
+const SHN_ABS: u16 = 0xFFF1;
+
+const DT_NULL: i64 = 0;
+const DT_PLTRELSZ: i64 = 2;
+const DT_HASH: i64 = 4;
+const DT_STRTAB: i64 = 5;
+const DT_SYMTAB: i64 = 6;
+const DT_RELA: i64 = 7;
+const DT_RELASZ: i64 = 8;
+const DT_STRSZ: i64 = 10;
+const DT_JMPREL: i64 = 23;
+
+/// A module as laid out in memory, starting at address 0
+#[derive(Debug)]
+pub struct ModuleImage {
+    /// .text, .rodata and .data at their memory offsets. .bss is not included.
+    pub image: Vec<u8>,
+    pub text: Range<u64>,
+    pub rodata: Range<u64>,
+    pub data: Range<u64>,
+    pub bss_size: u64,
+}
+
+/// Module header pointed to by the word at offset 4 of .text. Offsets are converted from
+/// relative to the header into addresses in the module.
+#[binread]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[br(little, magic = b"MOD0")]
+pub struct Mod0Header {
+    #[br(temp)] cursor_position: CurPos,
+    #[br(temp)] relative_offsets: [i32; 6],
+    #[br(calc = relative_address(cursor_position, relative_offsets[0]))]
+    pub dynamic: u64,
+    #[br(calc = relative_address(cursor_position, relative_offsets[1]))]
+    pub bss_start: u64,
+    #[br(calc = relative_address(cursor_position, relative_offsets[2]))]
+    pub bss_end: u64,
+    #[br(calc = relative_address(cursor_position, relative_offsets[3]))]
+    pub eh_frame_hdr_start: u64,
+    #[br(calc = relative_address(cursor_position, relative_offsets[4]))]
+    pub eh_frame_hdr_end: u64,
+    /// Runtime module object, filled in by rtld
+    #[br(calc = relative_address(cursor_position, relative_offsets[5]))]
+    pub module_object: u64,
+}
+
+/// Address of an offset relative to the MOD0 magic, which precedes `cursor_position`
+fn relative_address(cursor_position: CurPos, offset: i32) -> u64 {
+    (cursor_position.0 - 4).wrapping_add_signed(offset.into())
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[br(little)]
+pub struct Elf64Dyn {
+    pub tag: i64,
+    pub value: u64,
+}
+
+#[binrw]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[brw(little)]
+pub struct Elf64Symbol {
+    /// Offset of the name in .dynstr
+    pub name: u32,
+    /// Binding in the high nibble, type in the low nibble
+    pub info: u8,
+    pub other: u8,
+    pub section_index: u16,
+    pub value: u64,
+    pub size: u64,
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[br(little)]
+pub struct Elf64Rela {
+    pub offset: u64,
+    /// Symbol index in the high word, relocation type in the low word
+    pub info: u64,
+    pub addend: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DynamicSymbol {
+    pub name: String,
+    #[serde(flatten)]
+    pub symbol: Elf64Symbol,
+}
+
+impl DynamicSymbol {
+    pub fn is_defined(&self) -> bool {
+        self.symbol.section_index != SHN_UNDEF
+    }
+}
+
+#[binwrite]
+#[bw(little)]
+struct Elf64Header {
+    #[bw(calc = *b"\x7FELF\x02\x01\x01\0\0\0\0\0\0\0\0\0")]
+    ident: [u8; 0x10],
+    #[bw(calc = ET_DYN)]
+    file_type: u16,
+    #[bw(calc = EM_AARCH64)]
+    machine: u16,
+    #[bw(calc = 1)]
+    version: u32,
+    entry: u64,
+    program_header_offset: u64,
+    section_header_offset: u64,
+    #[bw(calc = 0)]
+    flags: u32,
+    #[bw(calc = ELF_HEADER_SIZE)]
+    header_size: u16,
+    #[bw(calc = PROGRAM_HEADER_SIZE)]
+    program_header_size: u16,
+    program_header_count: u16,
+    #[bw(calc = SECTION_HEADER_SIZE)]
+    section_header_size: u16,
+    section_header_count: u16,
+    section_name_index: u16,
+}
+
+#[binwrite]
+#[bw(little)]
+struct Elf64ProgramHeader {
+    segment_type: u32,
+    flags: u32,
+    offset: u64,
+    virtual_address: u64,
+    physical_address: u64,
+    file_size: u64,
+    memory_size: u64,
+    align: u64,
+}
+
+#[binwrite]
+#[bw(little)]
+#[derive(Default)]
+struct Elf64SectionHeader {
+    name: u32,
+    section_type: u32,
+    flags: u64,
+    address: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+/// A section rebuilt from the module layout, linked to other sections by name
+#[derive(Debug)]
+struct Section {
+    name: &'static str,
+    section_type: u32,
+    flags: u64,
+    address: Range<u64>,
+    link: Option<&'static str>,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+impl Section {
+    fn new(name: &'static str, section_type: u32, flags: u64, address: Range<u64>) -> Self {
+        Section { name, section_type, flags, address, link: None, info: 0, align: 8, entry_size: 0 }
+    }
+}
+
+/// A module with its dynamic tables parsed, ready to be written out as an ELF
+#[derive(Debug)]
+pub struct ElfModule {
+    module: ModuleImage,
+    pub mod0: Mod0Header,
+    /// The dynamic table, without the terminating `DT_NULL`
+    pub dynamic: Vec<Elf64Dyn>,
+    /// Dynamic symbols, with section indices pointing at the rebuilt sections
+    pub symbols: Vec<DynamicSymbol>,
+    pub relocations: Vec<Elf64Rela>,
+    pub plt_relocations: Vec<Elf64Rela>,
+    sections: Vec<Section>,
+}
+
+fn invalid_module(message: String) -> binrw::Error {
+    binrw::Error::Io(Error::new(ErrorKind::InvalidData, message))
+}
+
+impl ElfModule {
+    pub fn parse(module: ModuleImage) -> BinResult<ElfModule> {
+        let mut image = Cursor::new(module.image.as_slice());
+        let mod0_offset = u32::from_le_bytes(
+            module.image.get(4..8).ok_or_else(|| invalid_module("Module is too small to hold a MOD0 offset".to_string()))?.try_into().unwrap(),
+        );
+        image.set_position(mod0_offset.into());
+        let mod0: Mod0Header = image.read_le()?;
+
+        image.set_position(mod0.dynamic);
+        let mut dynamic = Vec::new();
+        loop {
+            let entry: Elf64Dyn = image.read_le()?;
+            if entry.tag == DT_NULL {
+                break;
+            }
+            dynamic.push(entry);
+        }
+        let tag = |tag: i64| dynamic.iter().find(|entry| entry.tag == tag).map(|entry| entry.value);
+
+        let symtab = tag(DT_SYMTAB).ok_or_else(|| invalid_module("Module has no DT_SYMTAB".to_string()))?;
+        let strtab = tag(DT_STRTAB).ok_or_else(|| invalid_module("Module has no DT_STRTAB".to_string()))?;
+        let strsz = tag(DT_STRSZ).unwrap_or(0);
+        let overflow = |name: &str| invalid_module(format!("The {} of the module ends past the address space", name));
+        let strtab_end = strtab.checked_add(strsz).ok_or_else(|| overflow(".dynstr"))?;
+        // The symbol count is only recorded in the hash table. Without one, .dynstr is assumed to
+        // directly follow .dynsym, which is how the official linker lays them out.
+        let symbol_count = match tag(DT_HASH) {
+            Some(hash) => {
+                image.set_position(hash.checked_add(4).ok_or_else(|| overflow(".hash"))?);
+                u64::from(image.read_le::<u32>()?)
+            }
+            None if strtab > symtab => (strtab - symtab) / SYMBOL_SIZE,
+            None => return Err(invalid_module("Unable to determine the dynamic symbol count".to_string())),
+        };
+
+        image.set_position(symtab);
+        let mut raw_symbols: Vec<Elf64Symbol> = (0..symbol_count).map(|_| image.read_le()).collect::<BinResult<_>>()?;
+        let symbols_end = image.position();
+        let names = module.image.get(strtab as usize..strtab_end as usize).unwrap_or_default();
+
+        let read_relocations = |address: Option<u64>, size: Option<u64>| -> BinResult<Vec<Elf64Rela>> {
+            let (Some(address), Some(size)) = (address, size) else {
+                return Ok(Vec::new());
+            };
+            let mut image = Cursor::new(module.image.as_slice());
+            image.set_position(address);
+            (0..size / RELA_SIZE).map(|_| image.read_le()).collect()
+        };
+        let relocations = read_relocations(tag(DT_RELA), tag(DT_RELASZ))?;
+        let plt_relocations = read_relocations(tag(DT_JMPREL), tag(DT_PLTRELSZ))?;
+
+        let dynamic_end = (dynamic.len() as u64 + 1)
+            .checked_mul(DYNAMIC_ENTRY_SIZE)
+            .and_then(|size| mod0.dynamic.checked_add(size))
+            .ok_or_else(|| overflow(".dynamic"))?;
+        let bss = match mod0.bss_end > mod0.bss_start {
+            true => mod0.bss_start..mod0.bss_end,
+            false => module.data.end..module.data.end + module.bss_size,
+        };
+        let mut sections = vec![
+            Section { flags: SHF_ALLOC | SHF_EXECINSTR, align: 0x10, ..Section::new(".text", SHT_PROGBITS, 0, module.text.clone()) },
+            Section { align: 0x10, ..Section::new(".rodata", SHT_PROGBITS, SHF_ALLOC, module.rodata.clone()) },
+            Section { align: 0x10, ..Section::new(".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, module.data.clone()) },
+            Section { align: 0x10, ..Section::new(".bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, bss) },
+            Section {
+                link: Some(".dynstr"),
+                entry_size: DYNAMIC_ENTRY_SIZE,
+                ..Section::new(".dynamic", SHT_DYNAMIC, SHF_ALLOC | SHF_WRITE, mod0.dynamic..dynamic_end)
+            },
+            Section {
+                link: Some(".dynstr"),
+                // Index of the first non-local symbol
+                info: raw_symbols.iter().position(|symbol| symbol.info >> 4 != 0).unwrap_or(raw_symbols.len()) as u32,
+                entry_size: SYMBOL_SIZE,
+                ..Section::new(".dynsym", SHT_DYNSYM, SHF_ALLOC, symtab..symbols_end)
+            },
+            Section { align: 1, ..Section::new(".dynstr", SHT_STRTAB, SHF_ALLOC, strtab..strtab_end) },
+        ];
+        if let Some(hash) = tag(DT_HASH) {
+            // nbucket and nchain words, followed by the buckets and the chain
+            let mut image = Cursor::new(module.image.as_slice());
+            image.set_position(hash);
+            let bucket_count = u64::from(image.read_le::<u32>()?);
+            let hash_end = (2 + bucket_count)
+                .checked_add(symbol_count)
+                .and_then(|count| count.checked_mul(4))
+                .and_then(|size| hash.checked_add(size))
+                .ok_or_else(|| overflow(".hash"))?;
+            sections.push(Section {
+                link: Some(".dynsym"),
+                entry_size: 4,
+                ..Section::new(".hash", SHT_HASH, SHF_ALLOC, hash..hash_end)
+            });
+        }
+        for (name, flags, address, count) in [
+            (".rela.dyn", SHF_ALLOC, tag(DT_RELA), relocations.len()),
+            (".rela.plt", SHF_ALLOC | SHF_INFO_LINK, tag(DT_JMPREL), plt_relocations.len()),
+        ] {
+            if let Some(address) = address.filter(|_| count > 0) {
+                sections.push(Section {
+                    link: Some(".dynsym"),
+                    entry_size: RELA_SIZE,
+                    ..Section::new(name, SHT_RELA, flags, address..address + count as u64 * RELA_SIZE)
+                });
+            }
+        }
+        if mod0.eh_frame_hdr_end > mod0.eh_frame_hdr_start {
+            sections.push(Section {
+                align: 4,
+                ..Section::new(".eh_frame_hdr", SHT_PROGBITS, SHF_ALLOC, mod0.eh_frame_hdr_start..mod0.eh_frame_hdr_end)
+            });
+        }
+        sections.retain(|section| !section.address.is_empty());
+
+        // The section indices in the module refer to the stripped sections of the original link,
+        // so point defined symbols at whichever rebuilt section holds their address
+        for symbol in raw_symbols.iter_mut().filter(|s| s.section_index != SHN_UNDEF && s.section_index < SHN_LORESERVE) {
+            symbol.section_index = sections
+                .iter()
+                .position(|section| SEGMENT_SECTIONS.contains(&section.name) && section.address.contains(&symbol.value))
+                .map_or(SHN_ABS, |index| index as u16 + 1);
+        }
+
+        let symbols = raw_symbols
+            .into_iter()
+            .map(|symbol| {
+                let name = names.get(symbol.name as usize..).unwrap_or_default();
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                DynamicSymbol { name: String::from_utf8_lossy(&name[..end]).into_owned(), symbol }
+            })
+            .collect();
+
+        Ok(ElfModule { module, mod0, dynamic, symbols, relocations, plt_relocations, sections })
+    }
+
+    /// Write the module as an ELF shared object, returning the number of bytes written
+    pub fn write<W: Write>(&self, writer: &mut W) -> BinResult<u64> {
+        let module = &self.module;
+        let bss_end = self.sections.iter().find(|s| s.section_type == SHT_NOBITS).map_or(module.data.end, |s| s.address.end);
+        let load = |flags: u32, address: Range<u64>, memory_end: u64| Elf64ProgramHeader {
+            segment_type: PT_LOAD,
+            flags,
+            offset: IMAGE_FILE_OFFSET + address.start,
+            virtual_address: address.start,
+            physical_address: address.start,
+            file_size: address.end - address.start,
+            memory_size: memory_end.max(address.end) - address.start,
+            align: PAGE_SIZE,
+        };
+        let mut program_headers = vec![
+            load(PF_R | PF_X, module.text.clone(), 0),
+            load(PF_R, module.rodata.clone(), 0),
+            load(PF_R | PF_W, module.data.clone(), bss_end),
+        ];
+        for (segment_type, flags, name) in [(PT_DYNAMIC, PF_R | PF_W, ".dynamic"), (PT_GNU_EH_FRAME, PF_R, ".eh_frame_hdr")] {
+            if let Some(section) = self.sections.iter().find(|s| s.name == name) {
+                program_headers.push(Elf64ProgramHeader {
+                    segment_type,
+                    flags,
+                    align: section.align,
+                    ..load(flags, section.address.clone(), 0)
+                });
+            }
+        }
+
+        let mut section_names = vec![0u8];
+        let mut name_offset = |name: &str| {
+            let offset = section_names.len() as u32;
+            section_names.extend_from_slice(name.as_bytes());
+            section_names.push(0);
+            offset
+        };
+        let mut section_headers = vec![Elf64SectionHeader::default()];
+        for section in &self.sections {
+            section_headers.push(Elf64SectionHeader {
+                name: name_offset(section.name),
+                section_type: section.section_type,
+                flags: section.flags,
+                address: section.address.start,
+                offset: IMAGE_FILE_OFFSET + section.address.start,
+                size: section.address.end - section.address.start,
+                link: section.link.and_then(|link| self.sections.iter().position(|s| s.name == link)).map_or(0, |index| index as u32 + 1),
+                info: section.info,
+                align: section.align,
+                entry_size: section.entry_size,
+            });
+        }
+        let section_names_name = name_offset(".shstrtab");
+        let section_names_offset = IMAGE_FILE_OFFSET + module.image.len() as u64;
+        section_headers.push(Elf64SectionHeader {
+            name: section_names_name,
+            section_type: SHT_STRTAB,
+            offset: section_names_offset,
+            size: section_names.len() as u64,
+            align: 1,
+            ..Default::default()
+        });
+        let section_headers_offset = (section_names_offset + section_names.len() as u64).next_multiple_of(8);
+
+        let mut headers = Cursor::new(Vec::with_capacity(IMAGE_FILE_OFFSET as usize));
+        headers.write_le(&Elf64Header {
+            entry: module.text.start,
+            program_header_offset: ELF_HEADER_SIZE.into(),
+            section_header_offset: section_headers_offset,
+            program_header_count: program_headers.len() as u16,
+            section_header_count: section_headers.len() as u16,
+            section_name_index: section_headers.len() as u16 - 1,
+        })?;
+        headers.write_le(&program_headers)?;
+        let mut headers = headers.into_inner();
+        headers.resize(IMAGE_FILE_OFFSET as usize, 0);
+
+        // Defined symbols are patched to reference the rebuilt sections
+        let mut image = Cursor::new(module.image.clone());
+        if let Some(dynsym) = self.sections.iter().find(|s| s.section_type == SHT_DYNSYM) {
+            image.set_position(dynsym.address.start);
+            for symbol in &self.symbols {
+                image.write_le(&symbol.symbol)?;
+            }
+        }
+
+        writer.write_all(&headers)?;
+        writer.write_all(image.get_ref())?;
+        writer.write_all(&section_names)?;
+        let padding = section_headers_offset - section_names_offset - section_names.len() as u64;
+        writer.write_all(&vec![0u8; padding as usize])?;
+        let mut section_table = Cursor::new(Vec::new());
+        section_table.write_le(&section_headers)?;
+        writer.write_all(section_table.get_ref())?;
+        Ok(section_headers_offset + section_table.get_ref().len() as u64)
+    }
+}
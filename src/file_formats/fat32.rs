@@ -0,0 +1,383 @@
+//! FAT32, the file system of the SAFE, SYSTEM and USER partitions of the NAND.
+//!
+//! Files larger than FAT32 allows are stored as concatenation files: a directory with the
+//! archive attribute set, holding the parts `00`, `01`, ... These are listed and read as a single
+//! file.
+
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+
+use binrw::prelude::*;
+use serde::Serialize;
+
+use super::container::NodeReader;
+use super::romfs::join_path;
+use crate::utils::ReadSeek;
+
+/// Size of a directory entry
+pub const FAT_DIRECTORY_ENTRY_SIZE: usize = 0x20;
+
+pub const ATTRIBUTE_VOLUME_ID: u8 = 0x08;
+pub const ATTRIBUTE_DIRECTORY: u8 = 0x10;
+pub const ATTRIBUTE_ARCHIVE: u8 = 0x20;
+/// Attributes of a long file name entry
+pub const ATTRIBUTE_LONG_NAME: u8 = 0x0F;
+
+/// FAT entries at or above this value end a cluster chain
+const END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct Fat32BootSector {
+    #[br(pad_before = 0xB, assert(bytes_per_sector.is_power_of_two() && bytes_per_sector >= 0x200))]
+    pub bytes_per_sector: u16,
+    #[br(assert(sectors_per_cluster.is_power_of_two()))]
+    pub sectors_per_cluster: u8,
+    pub reserved_sector_count: u16,
+    pub fat_count: u8,
+    /// Always 0 for FAT32, which keeps the root directory in a cluster chain
+    pub root_entry_count: u16,
+    pub total_sectors_16: u16,
+    pub media: u8,
+    /// Always 0 for FAT32, see `fat_size_32`
+    pub fat_size_16: u16,
+    pub sectors_per_track: u16,
+    pub head_count: u16,
+    pub hidden_sectors: u32,
+    pub total_sectors_32: u32,
+    /// Size of each FAT, in sectors
+    pub fat_size_32: u32,
+    pub ext_flags: u16,
+    pub fs_version: u16,
+    pub root_cluster: u32,
+    #[br(pad_before = 0x13)]
+    pub volume_id: u32,
+    #[br(map = |label: [u8; 11]| String::from_utf8_lossy(&label).trim_end().to_string())]
+    pub volume_label: String,
+    #[br(temp, assert(&fs_type == b"FAT32   " && fat_size_16 == 0, "Not a FAT32 file system"))]
+    fs_type: [u8; 8],
+    #[br(temp, pad_before = 0x1A4, assert(signature == 0xAA55, "Missing boot sector signature"))]
+    signature: u16,
+}
+
+/// A file or directory, with its path relative to the root
+#[derive(Debug, Clone, Serialize)]
+pub struct FatEntry {
+    /// Path using `/` as the separator
+    pub path: String,
+    pub is_directory: bool,
+    pub attributes: u8,
+    pub size: u64,
+    /// Whether this is a concatenation file, read from the parts in its directory
+    pub split: bool,
+    /// First cluster and size of each part of the data
+    #[serde(skip)]
+    parts: Vec<(u32, u64)>,
+}
+
+/// An entry of a directory, with its long file name resolved
+#[derive(Debug)]
+struct DirectoryRecord {
+    name: String,
+    attributes: u8,
+    first_cluster: u32,
+    size: u32,
+}
+
+pub struct Fat32 {
+    reader: NodeReader,
+    pub boot_sector: Fat32BootSector,
+    /// The first FAT, with the reserved high bits masked off
+    fat: Vec<u32>,
+    cluster_size: u64,
+    /// Offset of cluster 2, the first data cluster
+    data_offset: u64,
+}
+
+impl std::fmt::Debug for Fat32 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Fat32").field("boot_sector", &self.boot_sector).finish_non_exhaustive()
+    }
+}
+
+impl Fat32 {
+    /// Parse a FAT32 file system starting at the start of `reader`
+    pub fn parse_reader<R: ReadSeek + 'static>(reader: R) -> BinResult<Fat32> {
+        Self::from_node_reader(NodeReader::new(reader)?)
+    }
+
+    pub(crate) fn from_node_reader(mut reader: NodeReader) -> BinResult<Fat32> {
+        reader.seek(SeekFrom::Start(0))?;
+        let boot_sector: Fat32BootSector = reader.read_le()?;
+        let bytes_per_sector = u64::from(boot_sector.bytes_per_sector);
+        let fat_offset = u64::from(boot_sector.reserved_sector_count) * bytes_per_sector;
+        let fat_size = u64::from(boot_sector.fat_size_32) * bytes_per_sector;
+        if fat_offset + fat_size > reader.size() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("The FAT of 0x{:X} bytes at 0x{:X} ends past the partition", fat_size, fat_offset)).into());
+        }
+
+        let mut fat = vec![0u8; fat_size as usize];
+        reader.seek(SeekFrom::Start(fat_offset))?;
+        reader.read_exact(&mut fat)?;
+        let fat = fat.chunks_exact(4).map(|entry| u32::from_le_bytes(entry.try_into().unwrap()) & 0x0FFF_FFFF).collect();
+
+        Ok(Fat32 {
+            reader,
+            cluster_size: u64::from(boot_sector.sectors_per_cluster) * bytes_per_sector,
+            data_offset: fat_offset + u64::from(boot_sector.fat_count) * fat_size,
+            boot_sector,
+            fat,
+        })
+    }
+
+    /// Clusters making up the chain starting at `first_cluster`
+    fn chain(&self, first_cluster: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first_cluster;
+        while cluster < END_OF_CHAIN {
+            let next = self
+                .fat
+                .get(cluster as usize)
+                .copied()
+                .filter(|_| cluster >= 2)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Cluster {} is out of range", cluster)))?;
+            clusters.push(cluster);
+            if clusters.len() > self.fat.len() {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Cluster chain starting at {} loops", first_cluster)));
+            }
+            cluster = next;
+        }
+        Ok(clusters)
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + u64::from(cluster - 2) * self.cluster_size
+    }
+
+    /// Extents of the image holding `size` bytes from the chain starting at `first_cluster`, as
+    /// (offset, length), with consecutive clusters merged
+    fn extents(&self, first_cluster: u32, size: u64) -> Result<Vec<(u64, u64)>> {
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let mut extents: Vec<(u64, u64)> = Vec::new();
+        let mut remaining = size;
+        for cluster in self.chain(first_cluster)? {
+            let (offset, len) = (self.cluster_offset(cluster), remaining.min(self.cluster_size));
+            match extents.last_mut() {
+                Some((start, extent_len)) if *start + *extent_len == offset => *extent_len += len,
+                _ => extents.push((offset, len)),
+            }
+            remaining -= len;
+            if remaining == 0 {
+                return Ok(extents);
+            }
+        }
+        Err(Error::new(ErrorKind::InvalidData, format!("Cluster chain starting at {} is shorter than 0x{:X} bytes", first_cluster, size)))
+    }
+
+    fn read_directory(&self, first_cluster: u32) -> Result<Vec<DirectoryRecord>> {
+        let mut reader = self.reader.clone();
+        let mut data = Vec::new();
+        for cluster in self.chain(first_cluster)? {
+            let start = data.len();
+            data.resize(start + self.cluster_size as usize, 0);
+            reader.seek(SeekFrom::Start(self.cluster_offset(cluster)))?;
+            reader.read_exact(&mut data[start..])?;
+        }
+        Ok(parse_directory(&data))
+    }
+
+    /// All files and directories, parents before children. Concatenation files are listed as
+    /// files rather than as directories of parts.
+    pub fn entries(&self) -> BinResult<Vec<FatEntry>> {
+        let mut entries = Vec::new();
+        let mut visited = HashSet::new();
+        self.walk(self.boot_sector.root_cluster, "", &mut visited, &mut entries)?;
+        Ok(entries)
+    }
+
+    fn walk(&self, cluster: u32, dir_path: &str, visited: &mut HashSet<u32>, entries: &mut Vec<FatEntry>) -> Result<()> {
+        if !visited.insert(cluster) {
+            return Err(Error::new(ErrorKind::InvalidData, "The directory tree loops"));
+        }
+        for record in self.read_directory(cluster)? {
+            let path = join_path(dir_path, &record.name);
+            let is_directory = record.attributes & ATTRIBUTE_DIRECTORY != 0;
+            if !is_directory {
+                let size = u64::from(record.size);
+                entries.push(FatEntry { path, is_directory, attributes: record.attributes, size, split: false, parts: vec![(record.first_cluster, size)] });
+            } else if record.attributes & ATTRIBUTE_ARCHIVE != 0 {
+                let parts = self.concatenation_parts(record.first_cluster)?;
+                let size = parts.iter().map(|(_, size)| size).sum();
+                entries.push(FatEntry { path, is_directory: false, attributes: record.attributes, size, split: true, parts });
+            } else {
+                entries.push(FatEntry { path: path.clone(), is_directory, attributes: record.attributes, size: 0, split: false, parts: Vec::new() });
+                self.walk(record.first_cluster, &path, visited, entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parts `00`, `01`, ... of a concatenation file, as (first cluster, size)
+    fn concatenation_parts(&self, cluster: u32) -> Result<Vec<(u32, u64)>> {
+        let records = self.read_directory(cluster)?;
+        let mut parts = Vec::new();
+        for index in 0.. {
+            let name = format!("{:02}", index);
+            match records.iter().find(|record| record.name == name && record.attributes & ATTRIBUTE_DIRECTORY == 0) {
+                Some(record) => parts.push((record.first_cluster, u64::from(record.size))),
+                None => return Ok(parts),
+            }
+        }
+        unreachable!("the part index is unbounded")
+    }
+
+    /// Find a file or directory by its path, ignoring case as FAT does
+    pub fn find<S: AsRef<str>>(&self, path: S) -> BinResult<Option<FatEntry>> {
+        let path = path.as_ref().trim_matches('/');
+        Ok(self.entries()?.into_iter().find(|entry| entry.path.eq_ignore_ascii_case(path)))
+    }
+
+    /// Open the data of a file, joining the parts of concatenation files
+    pub fn open_file(&self, entry: &FatEntry) -> BinResult<FatFile> {
+        if entry.is_directory {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{} is a directory", entry.path)).into());
+        }
+        let mut extents = Vec::new();
+        for &(first_cluster, size) in &entry.parts {
+            extents.extend(self.extents(first_cluster, size)?);
+        }
+        Ok(FatFile { reader: self.reader.clone(), extents, size: entry.size, position: 0 })
+    }
+}
+
+/// Parse the entries of a directory, skipping deleted entries, volume labels and `.`/`..`
+fn parse_directory(data: &[u8]) -> Vec<DirectoryRecord> {
+    let mut records = Vec::new();
+    // Long name parts in entry order, with the checksum of the short name they belong to
+    let mut long_name: Vec<[u16; 13]> = Vec::new();
+    let mut long_name_checksum = None;
+    for entry in data.chunks_exact(FAT_DIRECTORY_ENTRY_SIZE) {
+        let attributes = entry[0x0B];
+        match entry[0] {
+            0x00 => break,
+            0xE5 => {
+                long_name.clear();
+                continue;
+            }
+            _ => {}
+        }
+        if attributes == ATTRIBUTE_LONG_NAME {
+            if entry[0] & 0x40 != 0 {
+                long_name.clear();
+                long_name_checksum = Some(entry[0x0D]);
+            }
+            let mut chars = [0u16; 13];
+            for (index, offset) in (1..11).step_by(2).chain((14..26).step_by(2)).chain((28..32).step_by(2)).enumerate() {
+                chars[index] = u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+            }
+            long_name.push(chars);
+            continue;
+        }
+        if attributes & ATTRIBUTE_VOLUME_ID != 0 {
+            long_name.clear();
+            continue;
+        }
+
+        let short_name: [u8; 11] = entry[..11].try_into().unwrap();
+        let name = match !long_name.is_empty() && long_name_checksum == Some(short_name_checksum(&short_name)) {
+            // Long name entries are stored last part first
+            true => {
+                let chars: Vec<u16> = long_name.iter().rev().flatten().copied().take_while(|&c| c != 0).collect();
+                String::from_utf16_lossy(&chars)
+            }
+            false => format_short_name(&short_name, entry[0x0C]),
+        };
+        long_name.clear();
+        if name == "." || name == ".." {
+            continue;
+        }
+        records.push(DirectoryRecord {
+            name,
+            attributes,
+            first_cluster: u32::from(u16::from_le_bytes([entry[0x14], entry[0x15]])) << 16 | u32::from(u16::from_le_bytes([entry[0x1A], entry[0x1B]])),
+            size: u32::from_le_bytes(entry[0x1C..0x20].try_into().unwrap()),
+        });
+    }
+    records
+}
+
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+/// An 8.3 name, lowercased where the case flags ask for it
+fn format_short_name(short_name: &[u8; 11], case_flags: u8) -> String {
+    let mut base = short_name[..8].to_vec();
+    // 0xE5 marks deleted entries, so a name starting with it is stored as 0x05
+    if base[0] == 0x05 {
+        base[0] = 0xE5;
+    }
+    let part = |bytes: &[u8], lowercase: bool| {
+        let text = String::from_utf8_lossy(bytes).trim_end().to_string();
+        match lowercase {
+            true => text.to_ascii_lowercase(),
+            false => text,
+        }
+    };
+    let base = part(&base, case_flags & 0x08 != 0);
+    let extension = part(&short_name[8..], case_flags & 0x10 != 0);
+    match extension.is_empty() {
+        true => base,
+        false => format!("{}.{}", base, extension),
+    }
+}
+
+/// Data of a file, read from its clusters
+#[derive(Debug, Clone)]
+pub struct FatFile {
+    reader: NodeReader,
+    /// Regions of the file system holding the data, as (offset, length)
+    extents: Vec<(u64, u64)>,
+    size: u64,
+    position: u64,
+}
+
+impl FatFile {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Read for FatFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut extent_start = 0;
+        for &(offset, len) in &self.extents {
+            if self.position < extent_start + len {
+                let extent_offset = self.position - extent_start;
+                let len = buf.len().min((len - extent_offset) as usize);
+                self.reader.seek(SeekFrom::Start(offset + extent_offset))?;
+                let read = self.reader.read(&mut buf[..len])?;
+                self.position += read as u64;
+                return Ok(read);
+            }
+            extent_start += len;
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for FatFile {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(Error::new(ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
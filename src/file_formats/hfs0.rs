@@ -1,48 +1,364 @@
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
 
 use binrw::FilePtr32;
 use binrw::prelude::*;
+use binrw::BinWriterExt;
 use binrw::NullString;
 use binrw::VecArgs;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 
-use crate::utils::Placement;
+use super::{Validity, MEDIA_UNIT_SIZE};
+use crate::utils::{serde_helpers, Placement};
 
 const MAGIC_HFS0:u32 = 0x30534648;
 
 #[binread]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 #[br(little, assert(magic == MAGIC_HFS0))]
 pub struct Hfs0 {
     /// Embed current cursor position since the HFS0 structure is embedded in a file
     #[br(temp)] _cursor_position: crate::utils::CurPos,
-    /// "PFS0" magic value
+    /// "HFS0" magic value
     #[br(temp)] magic: u32,
     /// number of embedded files
     #[br(temp)] file_count: u32,
     /// size of the file name string buffer in bytes
     #[br(temp, pad_after = 4)] string_table_byte_size: u32,
+    // Calculated fields
+    #[br(temp, calc = _cursor_position.0 /* HFS0 start position */ + 0x10 /* Offset of file entries */ + u64::from(file_count)*0x40 /* Size of file entry table */)]
+    string_table_absolute_start: u64,
     /// Embedded file entries
-    #[br(count = file_count, args { inner: (_cursor_position.0 /* HFS0 start position */ + 0x10 /* Offset of file entries */ + u64::from(file_count)*0x40 /* Size of file entry table */,)})]
+    #[br(count = file_count, args { inner: (string_table_absolute_start, string_table_absolute_start + u64::from(string_table_byte_size))})]
     pub files: Vec<Hfs0FileEntry>,
 }
 
 #[binread]
-#[derive(Debug)]
-#[br(little, import(string_table_offset: u64))]
+#[derive(Debug, Serialize)]
+#[br(little, import(string_table_offset: u64, file_data_offset: u64))]
 pub struct Hfs0FileEntry {
-    /// Offset of file from HFS0 header start
+    /// Offset of file from the end of the HFS0 header
     #[br(temp)] file_offset: u64,
     /// Size of the file in bytes
     #[br(temp)] file_size: u64,
     /// Embedded file path
     #[br(parse_with = FilePtr32::parse, offset = string_table_offset)]
+    #[serde(serialize_with = "serde_helpers::display")]
     pub file_name: NullString,
     /// Length of the hashed region at the start of the embedded file
     pub hashed_prefix_len: u32,
     /// Padding
-    #[br(temp)] _0x18: u32,
+    #[br(temp)] _0x18: u64,
     /// SHA256 hash of the first `hashed_prefix_len` bytes of the embedded file
+    #[serde(serialize_with = "serde_helpers::hex")]
     pub file_prefix_hash: super::SHA256Hash,
     /// File Data
-    #[br(parse_with = Placement::parse, args {offset: file_offset, inner: VecArgs {count: file_size as usize, inner: ()}})]
+    #[br(parse_with = Placement::parse, args {offset: file_data_offset + file_offset, inner: VecArgs {count: file_size as usize, inner: ()}})]
+    #[serde(rename = "file_size", serialize_with = "serde_helpers::len")]
     pub file_data: Vec<u8>,
-}
\ No newline at end of file
+}
+
+/// HFS0 header and file entry table without the file data, for partitions which are too large
+/// to read into memory (e.g. the secure partition of an XCI)
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, assert(magic == MAGIC_HFS0))]
+pub struct Hfs0Index {
+    /// Embed current cursor position since the HFS0 structure is embedded in a file
+    #[br(temp)] _cursor_position: crate::utils::CurPos,
+    /// "HFS0" magic value
+    #[br(temp)] magic: u32,
+    /// number of embedded files
+    #[br(temp)] file_count: u32,
+    /// size of the file name string buffer in bytes
+    #[br(temp, pad_after = 4)] string_table_byte_size: u32,
+    // Calculated fields
+    #[br(temp, calc = _cursor_position.0 + 0x10 + u64::from(file_count)*0x40)]
+    string_table_absolute_start: u64,
+    /// Embedded file entries
+    #[br(count = file_count, args { inner: (string_table_absolute_start, string_table_absolute_start + u64::from(string_table_byte_size))})]
+    pub files: Vec<Hfs0FileRecord>,
+}
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, import(string_table_offset: u64, file_data_offset: u64))]
+pub struct Hfs0FileRecord {
+    /// Offset of file from the end of the HFS0 header
+    #[br(temp)] file_table_offset: u64,
+    /// Absolute offset of the file in the underlying reader
+    #[br(calc = file_table_offset + file_data_offset)]
+    pub file_offset: u64,
+    /// Size of the file in bytes
+    pub file_size: u64,
+    /// Embedded file path
+    #[br(parse_with = FilePtr32::parse, offset = string_table_offset)]
+    #[serde(serialize_with = "serde_helpers::display")]
+    pub file_name: NullString,
+    /// Length of the hashed region at the start of the embedded file
+    pub hashed_prefix_len: u32,
+    /// Padding
+    #[br(temp)] _0x18: u64,
+    /// SHA256 hash of the first `hashed_prefix_len` bytes of the embedded file
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub file_prefix_hash: super::SHA256Hash,
+}
+
+impl Hfs0 {
+    pub fn parse<P: AsRef<Path>>(hfs0_file: P) -> BinResult<Hfs0> {
+        let mut file = File::open(hfs0_file.as_ref())?;
+
+        file.read_le()
+    }
+}
+
+impl Hfs0FileEntry {
+    /// Check `file_prefix_hash` against the first `hashed_prefix_len` bytes of the file data
+    pub fn verify_prefix_hash(&self) -> Validity {
+        match self.file_data.get(..self.hashed_prefix_len as usize) {
+            Some(prefix) if Sha256::digest(prefix).as_slice() == self.file_prefix_hash => Validity::Valid,
+            Some(_) => Validity::Invalid,
+            None => Validity::CheckError,
+        }
+    }
+}
+
+/// On-disk layout of a file entry, used when writing partitions
+#[binwrite]
+#[bw(little)]
+struct Hfs0RawFileEntry {
+    /// Offset of the file data from the end of the header
+    data_offset: u64,
+    data_size: u64,
+    /// Offset of the file name in the string table
+    string_table_offset: u32,
+    #[bw(pad_after = 8)]
+    hashed_prefix_len: u32,
+    file_prefix_hash: super::SHA256Hash,
+}
+
+/// Builds HFS0 partitions, such as the root and sub-partitions of an XCI.
+///
+/// Only the hashed prefix of each entry is read up front (to fill in the header); the rest of
+/// the data is streamed from the added readers when the partition is written. Entries are
+/// aligned to the media unit size.
+#[derive(Default)]
+pub struct Hfs0Builder<'a> {
+    entries: Vec<Hfs0BuilderEntry<'a>>,
+}
+
+struct Hfs0BuilderEntry<'a> {
+    name: String,
+    source: Hfs0Source<'a>,
+}
+
+enum Hfs0Source<'a> {
+    Reader {
+        size: u64,
+        hashed_prefix_len: u32,
+        reader: Box<dyn Read + 'a>,
+    },
+    /// A nested partition, whose hashed prefix is its header
+    Partition(Hfs0Builder<'a>),
+}
+
+impl Hfs0Source<'_> {
+    fn size(&self) -> u64 {
+        match self {
+            Hfs0Source::Reader { size, .. } => *size,
+            Hfs0Source::Partition(builder) => builder.total_size(),
+        }
+    }
+}
+
+/// A partition whose header has been generated and which is ready to be streamed out
+struct PreparedHfs0<'a> {
+    header: Vec<u8>,
+    entries: Vec<PreparedHfs0Entry<'a>>,
+}
+
+enum PreparedHfs0Entry<'a> {
+    Reader {
+        name: String,
+        size: u64,
+        prefix: Vec<u8>,
+        reader: Box<dyn Read + 'a>,
+    },
+    Partition(PreparedHfs0<'a>),
+}
+
+impl<'a> Hfs0Builder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a builder from the contents of `dir`, sorted by name.
+    ///
+    /// Regular files are added as files and sub-directories are added as nested partitions, so a
+    /// directory containing `normal`, `secure`, `update` folders produces an XCI root partition.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> std::io::Result<Self> {
+        let mut paths = std::fs::read_dir(dir.as_ref())?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        paths.sort();
+
+        let mut builder = Self::new();
+        for path in paths {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            if path.is_dir() {
+                builder.add_partition(name, Self::from_dir(&path)?);
+            } else if path.is_file() {
+                builder.add_named_file(name, &path)?;
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Add a file from disk under the given name, hashing the first media unit.
+    pub fn add_named_file<S: Into<String>, P: AsRef<Path>>(&mut self, name: S, path: P) -> std::io::Result<&mut Self> {
+        let file = File::open(path.as_ref())?;
+        let size = file.metadata()?.len();
+        Ok(self.add_reader(name, size, file))
+    }
+
+    /// Add `size` bytes read from `reader` under the given name, hashing the first media unit
+    /// (which covers the header of an NCA).
+    pub fn add_reader<S: Into<String>, R: Read + 'a>(&mut self, name: S, size: u64, reader: R) -> &mut Self {
+        let hashed_prefix_len = size.min(MEDIA_UNIT_SIZE) as u32;
+        self.add_reader_with_hashed_prefix(name, size, hashed_prefix_len, reader)
+    }
+
+    /// Add `size` bytes read from `reader` under the given name, hashing the first
+    /// `hashed_prefix_len` bytes.
+    pub fn add_reader_with_hashed_prefix<S: Into<String>, R: Read + 'a>(
+        &mut self,
+        name: S,
+        size: u64,
+        hashed_prefix_len: u32,
+        reader: R,
+    ) -> &mut Self {
+        self.entries.push(Hfs0BuilderEntry {
+            name: name.into(),
+            source: Hfs0Source::Reader {
+                size,
+                hashed_prefix_len: u64::from(hashed_prefix_len).min(size) as u32,
+                reader: Box::new(reader),
+            },
+        });
+        self
+    }
+
+    /// Add a nested partition. Its hashed prefix is the nested partition's header.
+    pub fn add_partition<S: Into<String>>(&mut self, name: S, partition: Hfs0Builder<'a>) -> &mut Self {
+        self.entries.push(Hfs0BuilderEntry {
+            name: name.into(),
+            source: Hfs0Source::Partition(partition),
+        });
+        self
+    }
+
+    /// Size of the header, file entry table and padded string table in bytes
+    pub fn header_size(&self) -> u64 {
+        let string_table_size: u64 = self.entries.iter().map(|e| e.name.len() as u64 + 1).sum();
+        (0x10 + self.entries.len() as u64 * 0x40 + string_table_size).next_multiple_of(MEDIA_UNIT_SIZE)
+    }
+
+    /// Total size of the partition in bytes, including the padding after each entry
+    pub fn total_size(&self) -> u64 {
+        self.header_size()
+            + self
+                .entries
+                .iter()
+                .map(|e| e.source.size().next_multiple_of(MEDIA_UNIT_SIZE))
+                .sum::<u64>()
+    }
+
+    /// Write the partition to `writer`, returning the number of bytes written.
+    pub fn write<W: Write>(self, writer: &mut W) -> BinResult<u64> {
+        self.prepare()?.write(writer)
+    }
+
+    /// Read the hashed prefix of every entry and generate the header.
+    fn prepare(self) -> BinResult<PreparedHfs0<'a>> {
+        let header_size = self.header_size();
+        let entry_table_end = 0x10 + self.entries.len() as u64 * 0x40;
+
+        let mut header = Cursor::new(Vec::with_capacity(header_size as usize));
+        header.write_le(&MAGIC_HFS0)?;
+        header.write_le(&(self.entries.len() as u32))?;
+        header.write_le(&((header_size - entry_table_end) as u32))?;
+        header.write_le(&0u32)?;
+
+        let mut string_table = Vec::new();
+        let mut data_offset = 0u64;
+        let mut prepared_entries = Vec::with_capacity(self.entries.len());
+        for entry in self.entries {
+            let data_size = entry.source.size();
+            let (prepared, hashed_prefix) = match entry.source {
+                Hfs0Source::Reader { size, hashed_prefix_len, mut reader } => {
+                    let mut prefix = vec![0u8; hashed_prefix_len as usize];
+                    reader.read_exact(&mut prefix)?;
+                    let hashed_prefix = (hashed_prefix_len, Sha256::digest(&prefix).into());
+                    (PreparedHfs0Entry::Reader { name: entry.name.clone(), size, prefix, reader }, hashed_prefix)
+                }
+                Hfs0Source::Partition(partition) => {
+                    let partition = partition.prepare()?;
+                    let hashed_prefix = (partition.header.len() as u32, Sha256::digest(&partition.header).into());
+                    (PreparedHfs0Entry::Partition(partition), hashed_prefix)
+                }
+            };
+
+            header.write_le(&Hfs0RawFileEntry {
+                data_offset,
+                data_size,
+                string_table_offset: string_table.len() as u32,
+                hashed_prefix_len: hashed_prefix.0,
+                file_prefix_hash: hashed_prefix.1,
+            })?;
+            string_table.extend_from_slice(entry.name.as_bytes());
+            string_table.push(0);
+            data_offset += data_size.next_multiple_of(MEDIA_UNIT_SIZE);
+            prepared_entries.push(prepared);
+        }
+        string_table.resize((header_size - entry_table_end) as usize, 0);
+        header.write_all(&string_table)?;
+
+        Ok(PreparedHfs0 {
+            header: header.into_inner(),
+            entries: prepared_entries,
+        })
+    }
+}
+
+impl PreparedHfs0<'_> {
+    fn write<W: Write>(self, writer: &mut W) -> BinResult<u64> {
+        writer.write_all(&self.header)?;
+        let mut written = self.header.len() as u64;
+
+        for entry in self.entries {
+            let entry_size = match entry {
+                PreparedHfs0Entry::Reader { name, size, prefix, reader } => {
+                    writer.write_all(&prefix)?;
+                    let remaining = size - prefix.len() as u64;
+                    let copied = std::io::copy(&mut reader.take(remaining), writer)?;
+                    if copied != remaining {
+                        return Err(binrw::Error::Io(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            format!("Expected {} bytes for Hfs0 entry \"{}\", only read {}", size, name, prefix.len() as u64 + copied),
+                        )));
+                    }
+                    size
+                }
+                PreparedHfs0Entry::Partition(partition) => partition.write(writer)?,
+            };
+
+            let padding = entry_size.next_multiple_of(MEDIA_UNIT_SIZE) - entry_size;
+            std::io::copy(&mut std::io::repeat(0).take(padding), writer)?;
+            written += entry_size + padding;
+        }
+
+        Ok(written)
+    }
+}
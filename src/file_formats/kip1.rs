@@ -0,0 +1,61 @@
+use binrw::prelude::*;
+use serde::Serialize;
+
+use crate::utils::serde_helpers;
+
+/// Size of the KIP1 header, which is followed by the compressed segments
+pub const KIP1_HEADER_SIZE: u64 = 0x100;
+/// Size of the INI1 header, which is followed by the embedded KIP1 processes
+pub const INI1_HEADER_SIZE: u64 = 0x10;
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"KIP1")]
+pub struct Kip1Header {
+    /// Process name, NUL padded
+    #[serde(serialize_with = "serde_helpers::c_string")]
+    pub name: [u8; 0xC],
+    pub program_id: u64,
+    pub version: u32,
+    pub main_thread_priority: u8,
+    pub default_cpu_id: u8,
+    #[br(pad_before = 1)]
+    pub flags: u8,
+    /// .text, .rodata, .data and .bss, followed by two reserved entries
+    pub segments: [Kip1SegmentHeader; 6],
+    pub kernel_capabilities: [u32; 0x20],
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[br(little)]
+pub struct Kip1SegmentHeader {
+    /// Offset of the segment in memory, relative to the start of the process image
+    pub memory_offset: u32,
+    pub decompressed_size: u32,
+    pub compressed_size: u32,
+    pub attribute: u32,
+}
+
+impl Kip1Header {
+    pub fn name(&self) -> String {
+        let end = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+        String::from_utf8_lossy(&self.name[..end]).into_owned()
+    }
+
+    /// Size of the KIP1 file: the header and the stored .text, .rodata and .data segments
+    pub fn file_size(&self) -> u64 {
+        KIP1_HEADER_SIZE + self.segments[..3].iter().map(|s| u64::from(s.compressed_size)).sum::<u64>()
+    }
+}
+
+/// Initial process bundle, a list of KIP1 files packed back to back after the header
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"INI1")]
+pub struct Ini1Header {
+    /// Size of the whole INI1, including this header
+    pub size: u32,
+    #[br(pad_after = 4)]
+    pub process_count: u32,
+}
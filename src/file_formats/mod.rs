@@ -1,14 +1,41 @@
+pub mod container;
+pub mod elf;
+pub mod fat32;
 pub mod hfs0;
+pub mod kip1;
+pub mod nax0;
+pub mod nacp;
+pub mod nand;
 pub mod nca;
 pub mod npdm;
+pub mod nro;
+pub mod nso;
 pub mod pfs0;
+pub mod romfs;
+pub mod save;
+pub mod sd_card;
+pub mod xci;
 
 pub type SHA256Hash = [u8;0x20];
 
-#[derive(Debug,PartialEq, Eq)]
+/// Size of a media unit (sector) in bytes. Offsets within XCI and NCA files are in media units.
+pub const MEDIA_UNIT_SIZE: u64 = 0x200;
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
 pub enum Validity {
     Unchecked,
     Invalid,
     Valid,
     CheckError
-}
\ No newline at end of file
+}
+
+impl std::fmt::Display for Validity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Validity::Unchecked => "Unchecked",
+            Validity::Invalid => "Invalid",
+            Validity::Valid => "Valid",
+            Validity::CheckError => "Check Error",
+        })
+    }
+}
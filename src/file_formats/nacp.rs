@@ -0,0 +1,100 @@
+use std::fs::File;
+use std::path::Path;
+
+use binrw::prelude::*;
+use serde::Serialize;
+
+use crate::utils::serde_helpers;
+
+/// Size of the application control property (`control.nacp`)
+pub const NACP_SIZE: u64 = 0x4000;
+
+/// Languages of the title entries, in table order
+pub const NACP_LANGUAGES: [&str; 16] = [
+    "AmericanEnglish",
+    "BritishEnglish",
+    "Japanese",
+    "French",
+    "German",
+    "LatinAmericanSpanish",
+    "Spanish",
+    "Italian",
+    "Dutch",
+    "CanadianFrench",
+    "Portuguese",
+    "Russian",
+    "Korean",
+    "TraditionalChinese",
+    "SimplifiedChinese",
+    "BrazilianPortuguese",
+];
+
+/// Application control property, the metadata shown by the home menu. Only the commonly used
+/// fields at the start of the structure are parsed.
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct Nacp {
+    /// Name and publisher for each of [`NACP_LANGUAGES`]
+    pub titles: [NacpTitle; 16],
+    #[serde(serialize_with = "serde_helpers::c_string")]
+    pub isbn: [u8; 0x25],
+    pub startup_user_account: u8,
+    pub user_account_switch_lock: u8,
+    pub add_on_content_registration_type: u8,
+    pub attribute_flag: u32,
+    pub supported_language_flag: u32,
+    pub parental_control_flag: u32,
+    pub screenshot: u8,
+    pub video_capture: u8,
+    pub data_loss_confirmation: u8,
+    pub play_log_policy: u8,
+    pub presence_group_id: u64,
+    /// Minimum age for each rating organisation, 0xFF if unrated
+    pub rating_age: [u8; 0x20],
+    #[serde(serialize_with = "serde_helpers::c_string")]
+    pub display_version: [u8; 0x10],
+    pub add_on_content_base_id: u64,
+    pub save_data_owner_id: u64,
+    pub user_account_save_data_size: u64,
+    pub user_account_save_data_journal_size: u64,
+    pub device_save_data_size: u64,
+    pub device_save_data_journal_size: u64,
+    pub bcat_delivery_cache_storage_size: u64,
+}
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct NacpTitle {
+    #[serde(serialize_with = "serde_helpers::c_string")]
+    pub name: [u8; 0x200],
+    #[serde(serialize_with = "serde_helpers::c_string")]
+    pub publisher: [u8; 0x100],
+}
+
+impl Nacp {
+    pub fn parse<P: AsRef<Path>>(nacp_file: P) -> BinResult<Nacp> {
+        let mut file = File::open(nacp_file.as_ref())?;
+
+        file.read_le()
+    }
+
+    pub fn display_version(&self) -> String {
+        c_str(&self.display_version)
+    }
+
+    /// Languages with a title entry, with their name and publisher
+    pub fn titles(&self) -> impl Iterator<Item = (&'static str, String, String)> + '_ {
+        NACP_LANGUAGES
+            .iter()
+            .zip(self.titles.iter())
+            .filter(|(_, title)| title.name[0] != 0)
+            .map(|(language, title)| (*language, c_str(&title.name), c_str(&title.publisher)))
+    }
+}
+
+fn c_str(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
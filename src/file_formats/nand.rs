@@ -0,0 +1,399 @@
+//! Raw NAND dumps (`rawnand.bin`), the user area of the eMMC.
+//!
+//! The dump starts with a GPT listing the BIS partitions. PRODINFO holds the console's
+//! calibration data (CAL0), and PRODINFOF, SAFE, SYSTEM and USER are FAT32 file systems. These
+//! are encrypted with AES-XTS using the BIS keys, while the package2 partitions (BCPKG2-*) are
+//! stored in the clear.
+//!
+//! BOOT0 and BOOT1 are separate hardware partitions of the eMMC, so they are dumped to their own
+//! files rather than being part of `rawnand.bin`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::Path;
+
+use aes::{Aes128, cipher::{KeyInit, generic_array::GenericArray}};
+use binrw::prelude::*;
+use serde::Serialize;
+use xts_mode::Xts128;
+
+use super::Validity;
+use super::container::NodeReader;
+use super::fat32::{Fat32, FatEntry};
+use super::nca::get_nintendo_tweak;
+use crate::keys::NcaKeys;
+use crate::utils::{read_up_to, serde_helpers, ReadSeek};
+
+/// Size of a logical block of the GPT
+pub const NAND_SECTOR_SIZE: u64 = 0x200;
+/// AES-XTS sector size of the BIS partitions
+pub const BIS_SECTOR_SIZE: u64 = 0x4000;
+/// Largest partition table accepted. The GPT standard reserves 128 entries, which is also what
+/// the Switch uses.
+pub const MAX_GPT_ENTRIES: u32 = 128;
+/// Folder of the SYSTEM and USER partitions holding installed NCAs
+pub const REGISTERED_CONTENTS_PATH: &str = "Contents/registered";
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"EFI PART")]
+pub struct GptHeader {
+    pub revision: u32,
+    pub header_size: u32,
+    #[br(pad_after = 4)]
+    pub header_crc32: u32,
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub disk_guid: [u8; 0x10],
+    pub partition_entry_lba: u64,
+    pub partition_entry_count: u32,
+    pub partition_entry_size: u32,
+    pub partition_entry_crc32: u32,
+}
+
+#[binread]
+#[derive(Debug)]
+#[br(little)]
+struct GptEntry {
+    type_guid: [u8; 0x10],
+    #[br(pad_before = 0x10)]
+    first_lba: u64,
+    /// Last block of the partition, inclusive
+    last_lba: u64,
+    #[br(pad_before = 8, map = |name: [u16; 36]| String::from_utf16_lossy(&name).trim_end_matches('\0').to_string())]
+    name: String,
+}
+
+/// Partitions of the eMMC, by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BisPartition {
+    Boot0,
+    Boot1,
+    Prodinfo,
+    Prodinfof,
+    /// One of the BCPKG2-* partitions holding package2
+    Package2,
+    Safe,
+    System,
+    User,
+    Unknown,
+}
+
+impl BisPartition {
+    /// Identify a partition from its GPT name, or a BOOT0/BOOT1 dump from its file name
+    pub fn from_name(name: &str) -> BisPartition {
+        match name.to_ascii_uppercase().as_str() {
+            "BOOT0" => BisPartition::Boot0,
+            "BOOT1" => BisPartition::Boot1,
+            "PRODINFO" => BisPartition::Prodinfo,
+            "PRODINFOF" => BisPartition::Prodinfof,
+            "SAFE" => BisPartition::Safe,
+            "SYSTEM" => BisPartition::System,
+            "USER" => BisPartition::User,
+            name if name.starts_with("BCPKG2-") => BisPartition::Package2,
+            _ => BisPartition::Unknown,
+        }
+    }
+
+    /// Index of the BIS key the partition is encrypted with, if it is encrypted
+    pub fn bis_key_index(self) -> Option<usize> {
+        match self {
+            BisPartition::Prodinfo | BisPartition::Prodinfof => Some(0),
+            BisPartition::Safe => Some(1),
+            BisPartition::System => Some(2),
+            BisPartition::User => Some(3),
+            _ => None,
+        }
+    }
+
+    /// Whether the partition holds a FAT32 file system
+    pub fn is_fat32(self) -> bool {
+        matches!(self, BisPartition::Prodinfof | BisPartition::Safe | BisPartition::System | BisPartition::User)
+    }
+}
+
+impl std::fmt::Display for BisPartition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BisPartition::Boot0 => "BOOT0",
+            BisPartition::Boot1 => "BOOT1",
+            BisPartition::Prodinfo => "PRODINFO",
+            BisPartition::Prodinfof => "PRODINFOF",
+            BisPartition::Package2 => "Package2",
+            BisPartition::Safe => "SAFE",
+            BisPartition::System => "SYSTEM",
+            BisPartition::User => "USER",
+            BisPartition::Unknown => "Unknown",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NandPartition {
+    pub name: String,
+    pub kind: BisPartition,
+    /// Offset of the partition in the dump
+    pub offset: u64,
+    pub size: u64,
+}
+
+pub struct NandReader {
+    reader: NodeReader,
+    pub gpt_header: GptHeader,
+    pub partitions: Vec<NandPartition>,
+    bis_keys: [[u8; 0x20]; 4],
+}
+
+impl std::fmt::Debug for NandReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NandReader")
+            .field("gpt_header", &self.gpt_header)
+            .field("partitions", &self.partitions)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Serialize for NandReader {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        /// NCAs of a partition, or why they could not be listed
+        #[derive(Serialize)]
+        #[serde(untagged)]
+        enum PartitionNcas {
+            Listed(Vec<FatEntry>),
+            Unreadable { error: String },
+        }
+
+        let ncas: BTreeMap<&str, PartitionNcas> = self
+            .partitions
+            .iter()
+            .filter(|partition| matches!(partition.kind, BisPartition::System | BisPartition::User))
+            .map(|partition| {
+                let ncas = match self.registered_ncas(partition) {
+                    Ok(ncas) => PartitionNcas::Listed(ncas),
+                    Err(e) => PartitionNcas::Unreadable { error: e.to_string() },
+                };
+                (partition.name.as_str(), ncas)
+            })
+            .collect();
+
+        let mut state = serializer.serialize_struct("NandReader", 3)?;
+        state.serialize_field("gpt_header", &self.gpt_header)?;
+        state.serialize_field("partitions", &self.partitions)?;
+        state.serialize_field("ncas", &ncas)?;
+        state.end()
+    }
+}
+
+impl NandReader {
+    pub fn open<P: AsRef<Path>>(nand_file: P, key_set: &NcaKeys) -> BinResult<NandReader> {
+        Self::parse_reader(File::open(nand_file.as_ref())?, key_set)
+    }
+
+    /// Parse a NAND dump starting at the start of `reader`
+    pub fn parse_reader<R: ReadSeek + 'static>(reader: R, key_set: &NcaKeys) -> BinResult<NandReader> {
+        let mut reader = NodeReader::new(reader)?;
+        reader.seek(SeekFrom::Start(NAND_SECTOR_SIZE))?;
+        let gpt_header: GptHeader = reader.read_le()?;
+
+        // The header CRC covers `header_size` bytes with the CRC field itself zeroed
+        if !(0x5C..=NAND_SECTOR_SIZE as u32).contains(&gpt_header.header_size) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("GPT header size 0x{:X} is invalid", gpt_header.header_size)).into());
+        }
+        let mut header = vec![0u8; gpt_header.header_size as usize];
+        reader.seek(SeekFrom::Start(NAND_SECTOR_SIZE))?;
+        reader.read_exact(&mut header)?;
+        header[0x10..0x14].fill(0);
+        if crc32fast::hash(&header) != gpt_header.header_crc32 {
+            return Err(Error::new(ErrorKind::InvalidData, "GPT header CRC32 does not match").into());
+        }
+
+        let entry_size = gpt_header.partition_entry_size as usize;
+        if !(0x80..=NAND_SECTOR_SIZE as usize).contains(&entry_size) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("GPT entries of 0x{:X} bytes are not supported", entry_size)).into());
+        }
+        if gpt_header.partition_entry_count > MAX_GPT_ENTRIES {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("GPT lists {} partition entries, at most {} are supported", gpt_header.partition_entry_count, MAX_GPT_ENTRIES),
+            )
+            .into());
+        }
+        let mut entries = vec![0u8; entry_size * gpt_header.partition_entry_count as usize];
+        let entries_offset = gpt_header.partition_entry_lba.checked_mul(NAND_SECTOR_SIZE).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, format!("GPT partition entry LBA 0x{:X} is out of range", gpt_header.partition_entry_lba))
+        })?;
+        reader.seek(SeekFrom::Start(entries_offset))?;
+        reader.read_exact(&mut entries)?;
+        if crc32fast::hash(&entries) != gpt_header.partition_entry_crc32 {
+            return Err(Error::new(ErrorKind::InvalidData, "GPT partition entry CRC32 does not match").into());
+        }
+
+        let mut partitions = Vec::new();
+        for entry in entries.chunks_exact(entry_size) {
+            let entry: GptEntry = Cursor::new(entry).read_le()?;
+            if entry.type_guid == [0; 0x10] {
+                continue;
+            }
+            let size = entry.last_lba.checked_add(1)
+                .and_then(|end| end.checked_sub(entry.first_lba))
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Partition {} ends before it starts", entry.name)))?;
+            let (Some(offset), Some(size)) = (entry.first_lba.checked_mul(NAND_SECTOR_SIZE), size.checked_mul(NAND_SECTOR_SIZE)) else {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Partition {} is out of range", entry.name)).into());
+            };
+            partitions.push(NandPartition {
+                kind: BisPartition::from_name(&entry.name),
+                name: entry.name,
+                offset,
+                size,
+            });
+        }
+
+        Ok(NandReader { reader, gpt_header, partitions, bis_keys: key_set.console.bis_keys })
+    }
+
+    pub fn partition(&self, kind: BisPartition) -> Option<&NandPartition> {
+        self.partitions.iter().find(|partition| partition.kind == kind)
+    }
+
+    /// Decrypted view of a partition. Fails if the BIS key it needs is missing.
+    pub fn open_partition(&self, partition: &NandPartition) -> BinResult<BisReader> {
+        let cipher = match partition.kind.bis_key_index() {
+            Some(index) if self.bis_keys[index] == [0; 0x20] => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("bis_key_{:02x} is needed to decrypt {}", index, partition.name)).into());
+            }
+            Some(index) => {
+                let (crypt_key, tweak_key) = self.bis_keys[index].split_at(0x10);
+                Some(Xts128::new(Aes128::new(GenericArray::from_slice(crypt_key)), Aes128::new(GenericArray::from_slice(tweak_key))))
+            }
+            None => None,
+        };
+        Ok(BisReader {
+            reader: self.reader.slice(partition.offset, partition.size),
+            size: partition.size,
+            cipher,
+            position: 0,
+            sector_cache: None,
+        })
+    }
+
+    /// Check that a partition decrypts to what it should hold: CAL0 for PRODINFO and a FAT boot
+    /// sector for the file systems. Other partitions are not checked.
+    pub fn verify_partition(&self, partition: &NandPartition) -> Validity {
+        let Some(index) = partition.kind.bis_key_index() else {
+            return Validity::Unchecked;
+        };
+        if self.bis_keys[index] == [0; 0x20] {
+            return Validity::Unchecked;
+        }
+        let mut start = [0u8; NAND_SECTOR_SIZE as usize];
+        match self.open_partition(partition).map(|mut reader| reader.read_exact(&mut start)) {
+            Ok(Ok(())) => {}
+            _ => return Validity::CheckError,
+        }
+        let valid = match partition.kind {
+            BisPartition::Prodinfo => &start[..4] == b"CAL0",
+            _ => start[0x1FE..] == [0x55, 0xAA],
+        };
+        match valid {
+            true => Validity::Valid,
+            false => Validity::Invalid,
+        }
+    }
+
+    /// The FAT32 file system of PRODINFOF, SAFE, SYSTEM or USER
+    pub fn open_fat32(&self, partition: &NandPartition) -> BinResult<Fat32> {
+        if !partition.kind.is_fat32() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{} does not hold a FAT32 file system", partition.name)).into());
+        }
+        Fat32::from_node_reader(NodeReader::new(self.open_partition(partition)?)?)
+    }
+
+    /// NCAs installed in the `Contents/registered` folder of SYSTEM or USER
+    pub fn registered_ncas(&self, partition: &NandPartition) -> BinResult<Vec<FatEntry>> {
+        let prefix = format!("{}/", REGISTERED_CONTENTS_PATH);
+        Ok(self
+            .open_fat32(partition)?
+            .entries()?
+            .into_iter()
+            .filter(|entry| !entry.is_directory && entry.path.starts_with(&prefix) && entry.path.to_ascii_lowercase().ends_with(".nca"))
+            .collect())
+    }
+}
+
+/// Decrypted view of a BIS partition
+pub struct BisReader {
+    reader: NodeReader,
+    size: u64,
+    /// Cipher of encrypted partitions, whose sectors are numbered from the partition start
+    cipher: Option<Xts128<Aes128>>,
+    position: u64,
+    /// Most recently decrypted sector, as sequential reads are usually smaller than a sector
+    sector_cache: Option<(u64, Vec<u8>)>,
+}
+
+impl std::fmt::Debug for BisReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BisReader")
+            .field("size", &self.size)
+            .field("encrypted", &self.cipher.is_some())
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BisReader {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn decrypted_sector(&mut self, sector: u64) -> Result<&[u8]> {
+        if self.sector_cache.as_ref().is_none_or(|(cached, _)| *cached != sector) {
+            let mut data = vec![0u8; BIS_SECTOR_SIZE as usize];
+            self.reader.seek(SeekFrom::Start(sector * BIS_SECTOR_SIZE))?;
+            let read = read_up_to(&mut self.reader, &mut data)?;
+            data.truncate(read - read % 0x10);
+            if let Some(cipher) = &self.cipher {
+                cipher.decrypt_area(&mut data, BIS_SECTOR_SIZE as usize, sector.into(), get_nintendo_tweak);
+            }
+            self.sector_cache = Some((sector, data));
+        }
+        Ok(&self.sector_cache.as_ref().expect("sector was just cached").1)
+    }
+}
+
+impl Read for BisReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let remaining = self.size.saturating_sub(self.position);
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let (sector, offset) = (self.position / BIS_SECTOR_SIZE, (self.position % BIS_SECTOR_SIZE) as usize);
+        let sector_data = self.decrypted_sector(sector)?;
+        let len = buf.len().min(remaining as usize).min(sector_data.len().saturating_sub(offset));
+        if len == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "The partition is cut short in the dump"));
+        }
+        buf[..len].copy_from_slice(&sector_data[offset..offset + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for BisReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(Error::new(ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,310 @@
+//! NAX0, the encryption container for content stored on the SD card.
+//!
+//! Every file under `Nintendo/Contents` and `Nintendo/save` is wrapped in a NAX0 header holding
+//! a pair of AES-XTS keys. Those keys are wrapped with keys derived from the console's SD card
+//! keys and the path of the file relative to the `Contents` or `save` directory, so the same
+//! content has a different header on every console and at every location.
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use aes::{Aes128, cipher::{BlockDecrypt, KeyInit, generic_array::GenericArray}};
+use binrw::prelude::*;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use xts_mode::Xts128;
+
+use super::nca::get_nintendo_tweak;
+use crate::keys::NcaKeys;
+use crate::utils::{read_up_to, serde_helpers, ReadSeek};
+
+/// Size of the NAX0 header. The encrypted data starts after it.
+pub const NAX0_HEADER_SIZE: u64 = 0x4000;
+/// AES-XTS sector size of the encrypted data
+pub const NAX0_SECTOR_SIZE: u64 = 0x4000;
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct Nax0Header {
+    /// HMAC-SHA256 of the rest of the header (0x20..0x80) with the keys decrypted, keyed with the
+    /// second half of the SD card key
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub hmac: [u8; 0x20],
+    /// AES-XTS data and tweak keys, each encrypted with a key derived from the file path
+    #[br(magic = b"NAX0\0\0\0\0")]
+    #[serde(serialize_with = "serde_helpers::hex_list")]
+    pub encrypted_keys: [[u8; 0x10]; 2],
+    /// Size of the decrypted data
+    pub size: u64,
+}
+
+/// Which of the SD card keys a NAX0 file is encrypted with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Nax0KeyType {
+    Save,
+    Nca,
+}
+
+impl Nax0KeyType {
+    fn index(self) -> usize {
+        match self {
+            Nax0KeyType::Save => 0,
+            Nax0KeyType::Nca => 1,
+        }
+    }
+}
+
+/// Decrypted view of the data in a NAX0 file
+pub struct Nax0Reader {
+    reader: Box<dyn ReadSeek>,
+    pub header: Nax0Header,
+    pub key_type: Nax0KeyType,
+    keys: [[u8; 0x10]; 2],
+    cipher: Xts128<Aes128>,
+    position: u64,
+    /// Most recently decrypted sector, as sequential reads are usually smaller than a sector
+    sector_cache: Option<(u64, Vec<u8>)>,
+}
+
+impl std::fmt::Debug for Nax0Reader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Nax0Reader")
+            .field("header", &self.header)
+            .field("key_type", &self.key_type)
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Serialize for Nax0Reader {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Nax0Reader", 3)?;
+        state.serialize_field("header", &self.header)?;
+        state.serialize_field("key_type", &self.key_type)?;
+        state.serialize_field("keys", &self.keys.map(hex::encode_upper))?;
+        state.end()
+    }
+}
+
+impl Nax0Reader {
+    /// Open a NAX0 file, or a directory of split parts. `sd_path` is the path used for key
+    /// derivation, e.g. `/registered/000000AB/0123456789abcdef0123456789abcdef.nca`; when `None`
+    /// it is taken from the part of `path` below `Nintendo/Contents` or `Nintendo/save`.
+    pub fn open<P: AsRef<Path>>(path: P, sd_path: Option<&str>, key_set: &NcaKeys) -> BinResult<Nax0Reader> {
+        let path = path.as_ref();
+        let sd_path = match sd_path {
+            Some(sd_path) => sd_path.to_string(),
+            None => sd_relative_path(path).ok_or_else(|| {
+                binrw::Error::Io(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is not below Nintendo/Contents or Nintendo/save, the SD path must be given", path.display()),
+                ))
+            })?,
+        };
+        let reader: Box<dyn ReadSeek> = match path.is_dir() {
+            true => Box::new(SplitFile::open(path)?),
+            false => Box::new(File::open(path)?),
+        };
+        Self::from_boxed_reader(reader, &sd_path, key_set)
+    }
+
+    /// Parse a NAX0 starting at the start of `reader`
+    pub fn parse_reader<R: ReadSeek + 'static>(reader: R, sd_path: &str, key_set: &NcaKeys) -> BinResult<Nax0Reader> {
+        Self::from_boxed_reader(Box::new(reader), sd_path, key_set)
+    }
+
+    fn from_boxed_reader(mut reader: Box<dyn ReadSeek>, sd_path: &str, key_set: &NcaKeys) -> BinResult<Nax0Reader> {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header_data = [0u8; 0x80];
+        reader.read_exact(&mut header_data)?;
+        let header: Nax0Header = std::io::Cursor::new(&header_data).read_le()?;
+
+        // The header does not record which SD card key was used, so try both against the HMAC
+        let (key_type, keys) = [Nax0KeyType::Save, Nax0KeyType::Nca]
+            .into_iter()
+            .map(|key_type| {
+                let sd_card_key = &key_set.console.sd_card_keys[key_type.index()];
+                (key_type, sd_card_key, decrypt_keys(&header, sd_card_key, sd_path))
+            })
+            .find(|(_, sd_card_key, keys)| header_mac(sd_card_key, keys, &header_data).verify_slice(&header.hmac).is_ok())
+            .map(|(key_type, _, keys)| (key_type, keys))
+            .ok_or_else(|| {
+                binrw::Error::Io(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("NAX0 header MAC mismatch for {}, check the SD seed and path", sd_path),
+                ))
+            })?;
+
+        let cipher = Xts128::new(
+            Aes128::new(GenericArray::from_slice(&keys[0])),
+            Aes128::new(GenericArray::from_slice(&keys[1])),
+        );
+        Ok(Nax0Reader { reader, header, key_type, keys, cipher, position: 0, sector_cache: None })
+    }
+
+    /// The decrypted AES-XTS data and tweak keys
+    pub fn keys(&self) -> [[u8; 0x10]; 2] {
+        self.keys
+    }
+
+    pub fn size(&self) -> u64 {
+        self.header.size
+    }
+
+    fn decrypted_sector(&mut self, sector: u64) -> Result<&[u8]> {
+        if self.sector_cache.as_ref().is_none_or(|(cached, _)| *cached != sector) {
+            let mut data = vec![0u8; NAX0_SECTOR_SIZE as usize];
+            self.reader.seek(SeekFrom::Start(NAX0_HEADER_SIZE + sector * NAX0_SECTOR_SIZE))?;
+            let read = read_up_to(&mut self.reader, &mut data)?;
+            // Sectors are padded to whole AES blocks
+            data.truncate(read - read % 0x10);
+            self.cipher.decrypt_area(&mut data, NAX0_SECTOR_SIZE as usize, sector.into(), get_nintendo_tweak);
+            self.sector_cache = Some((sector, data));
+        }
+        Ok(&self.sector_cache.as_ref().expect("sector was just cached").1)
+    }
+}
+
+impl Read for Nax0Reader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let remaining = self.header.size.saturating_sub(self.position);
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let (sector, offset) = (self.position / NAX0_SECTOR_SIZE, (self.position % NAX0_SECTOR_SIZE) as usize);
+        let sector_data = self.decrypted_sector(sector)?;
+        let len = buf.len().min(remaining as usize).min(sector_data.len().saturating_sub(offset));
+        if len == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "NAX0 data is shorter than its header says"));
+        }
+        buf[..len].copy_from_slice(&sector_data[offset..offset + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for Nax0Reader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.header.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(Error::new(ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
+/// Unwrap the XTS keys with the HMAC-SHA256 of the path, keyed with the first half of an SD
+/// card key. Each half of the digest is the AES-ECB key for one of the XTS keys.
+fn decrypt_keys(header: &Nax0Header, sd_card_key: &[u8; 0x20], sd_path: &str) -> [[u8; 0x10]; 2] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&sd_card_key[..0x10]).expect("HMAC accepts keys of any size");
+    mac.update(sd_path.as_bytes());
+    let path_keys = mac.finalize().into_bytes();
+
+    let mut keys = header.encrypted_keys;
+    for (key, path_key) in keys.iter_mut().zip(path_keys.chunks_exact(0x10)) {
+        Aes128::new(GenericArray::from_slice(path_key)).decrypt_block(GenericArray::from_mut_slice(key));
+    }
+    keys
+}
+
+/// HMAC over the header from the magic onwards with the decrypted keys in place of the
+/// encrypted ones, keyed with the second half of the SD card key
+fn header_mac(sd_card_key: &[u8; 0x20], keys: &[[u8; 0x10]; 2], header: &[u8; 0x80]) -> Hmac<Sha256> {
+    let mut data = header[0x20..].to_vec();
+    data[0x8..0x28].copy_from_slice(keys.as_flattened());
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&sd_card_key[0x10..]).expect("HMAC accepts keys of any size");
+    mac.update(&data);
+    mac
+}
+
+/// The path used for key derivation: the part of `path` below `Nintendo/Contents` or
+/// `Nintendo/save`, with a leading `/`. Split file parts (`00`, `01`, ...) share the path of
+/// their directory.
+pub fn sd_relative_path(path: &Path) -> Option<String> {
+    let components: Vec<_> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    let start = components.windows(2).rposition(|pair| {
+        pair[0].eq_ignore_ascii_case("Nintendo") && (pair[1].eq_ignore_ascii_case("Contents") || pair[1].eq_ignore_ascii_case("save"))
+    })? + 2;
+
+    let mut relative = &components[start..];
+    if relative.len() > 1 && relative.last().is_some_and(|name| is_split_part_name(name)) {
+        relative = &relative[..relative.len() - 1];
+    }
+    (!relative.is_empty()).then(|| relative.iter().map(|name| format!("/{}", name)).collect())
+}
+
+fn is_split_part_name(name: &str) -> bool {
+    name.len() == 2 && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// A file split into a directory of numbered parts, read as one. Content larger than FAT32
+/// allows is stored this way.
+struct SplitFile {
+    parts: Vec<(File, u64)>,
+    size: u64,
+    position: u64,
+}
+
+impl SplitFile {
+    fn open(directory: &Path) -> Result<SplitFile> {
+        let mut parts = Vec::new();
+        for index in 0.. {
+            let part_path: PathBuf = directory.join(format!("{:02}", index));
+            if !part_path.is_file() {
+                break;
+            }
+            let file = File::open(&part_path)?;
+            let size = file.metadata()?.len();
+            parts.push((file, size));
+        }
+        if parts.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, format!("{} has no split file parts", directory.display())));
+        }
+        let size = parts.iter().map(|(_, size)| size).sum();
+        Ok(SplitFile { parts, size, position: 0 })
+    }
+}
+
+impl Read for SplitFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut part_start = 0;
+        for (file, size) in self.parts.iter_mut() {
+            if self.position < part_start + *size {
+                let offset = self.position - part_start;
+                let len = buf.len().min((*size - offset) as usize);
+                file.seek(SeekFrom::Start(offset))?;
+                let read = file.read(&mut buf[..len])?;
+                self.position += read as u64;
+                return Ok(read);
+            }
+            part_start += *size;
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for SplitFile {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(Error::new(ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
+    collections::HashSet,
     fs::File,
-    io::{Cursor, Read},
+    io::{Cursor, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
@@ -9,18 +10,24 @@ use log::info;
 use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
 
 use proc_bitfield::bitfield;
+use serde::Serialize;
 
-use crate::{keys::NcaKeys, utils::ReaderType};
+use crate::{keys::{KeysetType, NcaKeys}, utils::{read_restore_into, read_up_to, serde_helpers, ReadSeek, ReaderType}};
 
-use aes::{Aes128, cipher::KeyInit, cipher::generic_array::GenericArray};
+use aes::{Aes128, cipher::{BlockDecrypt, BlockEncrypt, KeyInit, KeyIvInit, StreamCipher, StreamCipherSeek}, cipher::generic_array::GenericArray};
+use ctr::Ctr128BE;
 use xts_mode::Xts128;
 
-use super::SHA256Hash;
+use rsa::{pss::{BlindedSigningKey, Signature, VerifyingKey}, rand_core::{OsRng, RngCore}, RsaPrivateKey};
+use sha2::{Digest, Sha256};
+use signature::{RandomizedSigner, SignatureEncoding, Verifier};
+
+use super::{pfs0::{Pfs0, Pfs0Reader}, romfs::RomFs, MEDIA_UNIT_SIZE, SHA256Hash, Validity};
 
 #[repr(u32)]
-#[binread]
-#[br(little, repr = u32)]
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
+#[binrw]
+#[brw(little, repr = u32)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
 pub enum NcaVersion {
     Nca0 = 0x3041434E,
     Nca1 = 0x3141434E,
@@ -28,19 +35,26 @@ pub enum NcaVersion {
     Nca3 = 0x3341434E,
 }
 
+impl std::fmt::Display for NcaVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let magic = u32::from(*self).to_le_bytes();
+        f.write_str(&String::from_utf8_lossy(&magic))
+    }
+}
+
 #[repr(u8)]
-#[binread]
-#[br(little, repr = u8)]
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
+#[binrw]
+#[brw(little, repr = u8)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
 pub enum DistributionType {
     Download,
     GameCard,
 }
 
 #[repr(u8)]
-#[binread]
-#[br(little, repr = u8)]
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
+#[binrw]
+#[brw(little, repr = u8)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
 pub enum ContentType {
     Program,
     Meta,
@@ -51,9 +65,9 @@ pub enum ContentType {
 }
 
 #[repr(u8)]
-#[binread]
-#[br(little, repr = u8)]
-#[derive(Debug, Default, FromPrimitive, IntoPrimitive)]
+#[binrw]
+#[brw(little, repr = u8)]
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, FromPrimitive, IntoPrimitive)]
 pub enum KeyGeneration {
     OldOne = 0,
     Unused = 1,
@@ -76,10 +90,35 @@ pub enum KeyGeneration {
     Invalid = 0xFF,
 }
 
+impl KeyGeneration {
+    /// Earliest system firmware version using this key generation
+    pub fn firmware_version(&self) -> &'static str {
+        match self {
+            Self::OldOne => "1.0.0",
+            Self::Unused => "Unused",
+            Self::OldThree => "3.0.0",
+            Self::ThreeZeroOne => "3.0.1",
+            Self::Four => "4.0.0",
+            Self::Five => "5.0.0",
+            Self::Six => "6.0.0",
+            Self::SixTwo => "6.2.0",
+            Self::Seven => "7.0.0",
+            Self::EightOne => "8.1.0",
+            Self::Nine => "9.0.0",
+            Self::NineOne => "9.1.0",
+            Self::TwelveOne => "12.1.0",
+            Self::Thirteen => "13.0.0",
+            Self::Fourteen => "14.0.0",
+            Self::Fifteen => "15.0.0",
+            Self::Invalid => "Unknown",
+        }
+    }
+}
+
 #[repr(u8)]
-#[binread]
-#[br(little, repr = u8)]
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
+#[binrw]
+#[brw(little, repr = u8)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
 pub enum KeyAreaIndex {
     Application,
     Ocean,
@@ -87,7 +126,8 @@ pub enum KeyAreaIndex {
 }
 
 bitfield! {
-    #[binread]
+    #[binrw]
+    #[derive(Clone, Copy)]
     pub struct SdkAddonVersion(u32): Debug {
         pub raw: u32 @ ..,
 
@@ -98,11 +138,26 @@ bitfield! {
     }
 }
 
-#[binread]
-#[derive(Debug)]
+impl Serialize for SdkAddonVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl std::fmt::Display for SdkAddonVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.major(), self.minor(), self.micro(), self.revision())
+    }
+}
+
+#[binrw]
+#[derive(Serialize, Debug)]
+#[brw(little)]
 #[repr(C)]
 pub struct NcaFileCtx {
+    #[serde(serialize_with = "serde_helpers::hex")]
     pub signature: [u8; 0x100],
+    #[serde(serialize_with = "serde_helpers::hex")]
     pub modulus: [u8; 0x100],
     pub file_version: NcaVersion,
     pub distribution_type: DistributionType,
@@ -114,15 +169,16 @@ pub struct NcaFileCtx {
     pub content_index: u32,
     pub sdk_addon_version: SdkAddonVersion,
     pub key_generation: KeyGeneration,
+    #[brw(pad_after = 0xE)]
     pub signature_key_generation: u8, // TODO: need enum?
-    #[br(temp)]
-    _0x222: [u8; 0xE],
+    #[serde(serialize_with = "serde_helpers::hex")]
     pub rights_id: [u8; 16],
     pub section_entries: [fs::FsEntry; 4],
+    #[serde(serialize_with = "serde_helpers::hex_list")]
     pub section_hashes: [SHA256Hash; 4],
+    #[brw(pad_after = 0xC0)]
+    #[serde(serialize_with = "serde_helpers::hex")]
     pub encrypted_key_area: [u8; 64],
-    #[br(temp)]
-    _0x340: [u8;0xC0],
     pub fs_headers: [fs::FsHeader;4],
 }
 
@@ -130,14 +186,50 @@ pub struct NcaFileCtx {
 pub struct NcaFileReader {
     reader: ReaderType,
     pub nca_ctx: NcaFileCtx,
+    header: Vec<u8>,
+    /// Key area decrypted with the key area key for the NCA's key generation
+    pub decrypted_key_area: [[u8; 0x10]; 4],
+    /// Decrypted title key, used instead of the key area for NCAs with a rights ID
+    title_key: Option<[u8; 0x10]>,
+}
+
+impl Serialize for NcaFileReader {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("NcaFileReader", 2)?;
+        state.serialize_field("header", &self.nca_ctx)?;
+        state.serialize_field("decrypted_key_area", &self.decrypted_key_area.map(hex::encode_upper))?;
+        state.end()
+    }
 }
 
 impl NcaFileReader {
     pub fn parse_file(nca_file: impl AsRef<Path>, key_set: &NcaKeys) -> BinResult<NcaFileReader> {
-        let mut file: File = File::open(nca_file.as_ref())?;
+        Self::from_reader_type(ReaderType::Raw(File::open(nca_file.as_ref())?), key_set)
+    }
+
+    pub fn parse_file_mmap(pfs0_file: impl AsRef<Path>, key_set: &NcaKeys) -> BinResult<Self> {
+        let memmap = unsafe { memmap::MmapOptions::new().map(&File::open(pfs0_file.as_ref())?)? };
+        Self::from_reader_type(ReaderType::Mapped(memmap), key_set)
+    }
+
+    /// Parse an NCA from any reader, e.g. one nested inside an NSP or XCI partition. Offsets are
+    /// relative to the start of the reader.
+    pub fn parse_reader<R: ReadSeek + 'static>(reader: R, key_set: &NcaKeys) -> BinResult<Self> {
+        Self::from_reader_type(ReaderType::Stream(Box::new(reader)), key_set)
+    }
 
+    fn from_reader_type(mut reader: ReaderType, key_set: &NcaKeys) -> BinResult<Self> {
         let mut maybe_encrypted_header = [0u8; 0xC00];
-        let read_len = file.read(maybe_encrypted_header.as_mut_slice())?;
+        let read_len = match reader {
+            ReaderType::Raw(ref mut f) => read_up_to(f, &mut maybe_encrypted_header)?,
+            ReaderType::Mapped(ref map) => read_up_to(&mut Cursor::new(&map[..]), &mut maybe_encrypted_header)?,
+            ReaderType::Stream(ref mut r) => {
+                r.seek(SeekFrom::Start(0))?;
+                read_up_to(r, &mut maybe_encrypted_header)?
+            }
+        };
         if read_len != 0xC00 && read_len != 0xA00 {
             return Err(binrw::Error::Io(std::io::Error::new(
                 std::io::ErrorKind::UnexpectedEof,
@@ -145,111 +237,984 @@ impl NcaFileReader {
             )));
         }
 
-        let maybe_magic =
-            u32::from_le_bytes(maybe_encrypted_header[0x200..0x204].try_into().unwrap());
-        if !((maybe_magic == NcaVersion::Nca3.into() || maybe_magic == NcaVersion::Nca2.into())
-            && maybe_encrypted_header[0x340..(0x340 + 0xC0)]
-                .into_iter()
-                .all(|&b| b == 0))
-        {
+        if !is_plaintext_header(&maybe_encrypted_header) {
             info!("Decrypting NCA Header");
             // the header is encrypted
-            fn get_nintendo_tweak(sector_index: u128) -> [u8; 0x10] {
-                sector_index.to_be_bytes()
-            }
-            let xts_context: Xts128<Aes128> = Xts128::new(
-                Aes128::new(GenericArray::from_slice(&key_set.header_key[..0x10])),
-                Aes128::new(GenericArray::from_slice(&key_set.header_key[0x10..])),
-            );
-
-            xts_context.decrypt_area(&mut maybe_encrypted_header, 0x200, 0, get_nintendo_tweak);
+            header_xts_context(key_set).decrypt_area(&mut maybe_encrypted_header, 0x200, 0, get_nintendo_tweak);
         }
-        
-        let nca_ctx = Cursor::new(maybe_encrypted_header.as_slice()).read_le()?;
+
+        let nca_ctx: NcaFileCtx = Cursor::new(maybe_encrypted_header.as_slice()).read_le()?;
+        let decrypted_key_area = nca_ctx.decrypt_key_area(key_set);
 
         Ok(Self {
-            reader: ReaderType::Raw(file),
+            reader,
             nca_ctx,
+            header: maybe_encrypted_header.to_vec(),
+            decrypted_key_area,
+            title_key: None,
         })
     }
 
-    pub fn parse_file_mmap(pfs0_file: impl AsRef<Path>, key_set: &NcaKeys) -> BinResult<Self> {
-        let memmap = unsafe { memmap::MmapOptions::new().map(&File::open(pfs0_file.as_ref())?)? };
-        let mut cursor = Cursor::new(&memmap[..]);
+    /// The decrypted 0xC00 byte header
+    pub fn decrypted_header(&self) -> &[u8] {
+        &self.header
+    }
 
-        let mut maybe_encrypted_header = [0u8; 0xC00];
-        let read_len = cursor.read(maybe_encrypted_header.as_mut_slice())?;
-        if read_len != 0xC00 && read_len != 0xA00 {
-            return Err(binrw::Error::Io(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "Failed to read NCA header",
-            )));
+    /// Set the (titlekek encrypted) title key used to decrypt an NCA with a rights ID
+    pub fn set_title_key(&mut self, encrypted_title_key: [u8; 0x10], key_set: &NcaKeys) {
+        let mut title_key = encrypted_title_key;
+        let titlekek = &key_set.titlekeks[self.nca_ctx.master_key_revision()];
+        Aes128::new(GenericArray::from_slice(titlekek)).decrypt_block(GenericArray::from_mut_slice(&mut title_key));
+        self.title_key = Some(title_key);
+    }
+
+    /// Set an already decrypted title key used to decrypt an NCA with a rights ID
+    pub fn set_decrypted_title_key(&mut self, title_key: [u8; 0x10]) {
+        self.title_key = Some(title_key);
+    }
+
+    /// Key used to decrypt AES-CTR sections. NCAs with a rights ID need their title key to be set,
+    /// while the title key is ignored for NCAs using the key area.
+    pub fn content_key(&self) -> std::io::Result<[u8; 0x10]> {
+        match (self.title_key, self.nca_ctx.has_rights_id()) {
+            (Some(title_key), true) => Ok(title_key),
+            (None, true) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "The NCA uses titlekey crypto, but no title key was given.",
+            )),
+            (_, false) => Ok(self.decrypted_key_area[2]),
         }
+    }
 
-        let maybe_magic =
-            u32::from_le_bytes(maybe_encrypted_header[0x200..0x204].try_into().unwrap());
-        if !((maybe_magic == NcaVersion::Nca3.into() || maybe_magic == NcaVersion::Nca2.into())
-            && maybe_encrypted_header[0x340..(0x340 + 0xC0)]
-                .into_iter()
-                .all(|&b| b == 0))
-        {
-            // the header is encrypted
-            let tweak = move |sector: u128| sector.to_be_bytes();
-            let xts_context: Xts128<Aes128> = Xts128::new(
-                Aes128::new(GenericArray::from_slice(&key_set.header_key[..0x10])),
-                Aes128::new(GenericArray::from_slice(&key_set.header_key[0x10..])),
-            );
+    /// Index of the ExeFS section, if this is a program NCA
+    pub fn exefs_section_index(&self) -> Option<usize> {
+        (self.nca_ctx.content_type == ContentType::Program
+            && self.nca_ctx.fs_headers[0].fs_type == fs::FsType::Pfs0
+            && self.nca_ctx.section_entries[0].end_block_offset != 0)
+            .then_some(0)
+    }
+
+    /// Index of the first RomFS section
+    pub fn romfs_section_index(&self) -> Option<usize> {
+        (0..4).find(|&i| {
+            self.nca_ctx.fs_headers[i].fs_type == fs::FsType::RomFs && self.nca_ctx.section_entries[i].end_block_offset != 0
+        })
+    }
 
-            xts_context.decrypt_area(maybe_encrypted_header.as_mut_slice(), 0x200, 0, tweak);
+    fn raw_reader(&mut self) -> Box<dyn ReadSeek + '_> {
+        match self.reader {
+            ReaderType::Mapped(ref map) => Box::new(Cursor::new(&map[..])),
+            ReaderType::Raw(ref mut f) => Box::new(f),
+            ReaderType::Stream(ref mut r) => Box::new(r),
         }
+    }
 
-        let nca_ctx = Cursor::new(maybe_encrypted_header.as_slice()).read_le()?;
+    /// Open a decrypted view of a section
+    pub fn open_section(&mut self, index: usize) -> std::io::Result<NcaSectionReader<'_>> {
+        let (section_offset, section_size, crypto) = self.section_layout(index)?;
 
-        Ok(Self {
-            reader: ReaderType::Mapped(memmap),
-            nca_ctx,
+        Ok(NcaSectionReader {
+            reader: self.raw_reader(),
+            section_offset,
+            section_size,
+            crypto,
+            position: 0,
         })
     }
+
+    /// Open a decrypted view of a section which owns the underlying reader, so it can outlive
+    /// this `NcaFileReader`
+    pub fn into_section(self, index: usize) -> std::io::Result<NcaSectionReader<'static>> {
+        let (section_offset, section_size, crypto) = self.section_layout(index)?;
+        let reader: Box<dyn ReadSeek> = match self.reader {
+            ReaderType::Mapped(map) => Box::new(Cursor::new(map)),
+            ReaderType::Raw(f) => Box::new(f),
+            ReaderType::Stream(r) => r,
+        };
+
+        Ok(NcaSectionReader {
+            reader,
+            section_offset,
+            section_size,
+            crypto,
+            position: 0,
+        })
+    }
+
+    /// Like [`NcaFileReader::into_section`], but every read is checked against the section's hash
+    /// layers first
+    pub fn into_verified_section(self, index: usize) -> std::io::Result<VerifiedSectionReader> {
+        let layers = self.section_hash_layers(index).unwrap_or_default();
+        let section = self.into_section(index)?;
+        Ok(VerifiedSectionReader {
+            verified: vec![HashSet::new(); layers.len()],
+            section,
+            layers,
+            position: 0,
+        })
+    }
+
+    /// Open the ExeFS as a PFS0 reader which owns the underlying reader
+    pub fn into_exefs(self) -> BinResult<Pfs0Reader> {
+        let index = self.exefs_section_index().ok_or_else(|| {
+            binrw::Error::Io(std::io::Error::new(std::io::ErrorKind::NotFound, "NCA has no ExeFS section."))
+        })?;
+        let data_offset = self.pfs0_data_offset(index)?;
+        let mut section = self.into_section(index)?;
+        section.seek(SeekFrom::Start(data_offset))?;
+        Pfs0Reader::parse_reader(section)
+    }
+
+    /// Absolute offset, size and crypto of a section
+    fn section_layout(&self, index: usize) -> std::io::Result<(u64, u64, SectionCrypto)> {
+        let entry = self.nca_ctx.section_entries.get(index).copied().unwrap_or_default();
+        if entry.end_block_offset <= entry.start_block_offset {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("NCA section {} is not present.", index),
+            ));
+        }
+        let fs_header = &self.nca_ctx.fs_headers[index];
+        let crypto = match fs_header.encryption_type {
+            fs::EncryptionType::None => SectionCrypto::None,
+            fs::EncryptionType::AesCtr | fs::EncryptionType::AesCtrSkipLayerHash => {
+                SectionCrypto::Ctr(self.content_key()?, fs_header.section_ctr)
+            }
+            encryption_type => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("NCA section {} uses unsupported encryption {:?}.", index, encryption_type),
+                ))
+            }
+        };
+
+        Ok((
+            u64::from(entry.start_block_offset) * MEDIA_UNIT_SIZE,
+            u64::from(entry.end_block_offset - entry.start_block_offset) * MEDIA_UNIT_SIZE,
+            crypto,
+        ))
+    }
+
+    /// Offset of the filesystem data (PFS0 or RomFS) within a section, skipping the hash layers
+    pub fn section_data_offset(&self, index: usize) -> Option<u64> {
+        match &self.nca_ctx.fs_headers.get(index)?.superblock {
+            fs::SuperBlock::None => None,
+            fs::SuperBlock::Pfs0(superblock) => Some(superblock.pfs0_offset),
+            fs::SuperBlock::RomFs(superblock) => Some(superblock.levels[5].logical_offset),
+        }
+    }
+
+    /// Parse the PFS0 in a section. File offsets are relative to the start of the section.
+    pub fn section_pfs0(&mut self, index: usize) -> BinResult<Pfs0> {
+        let data_offset = self.pfs0_data_offset(index)?;
+        let mut section = self.open_section(index)?;
+        section.seek(SeekFrom::Start(data_offset))?;
+        section.read_le()
+    }
+
+    /// Parse the RomFS in a section. File offsets are relative to the start of the section.
+    pub fn section_romfs(&mut self, index: usize) -> BinResult<RomFs> {
+        let data_offset = match &self.nca_ctx.fs_headers[index].superblock {
+            fs::SuperBlock::RomFs(superblock) => superblock.levels[5].logical_offset,
+            _ => return Err(not_a_section_of_type(index, "RomFs")),
+        };
+        let mut section = self.open_section(index)?;
+        section.seek(SeekFrom::Start(data_offset))?;
+        section.read_le()
+    }
+
+    fn pfs0_data_offset(&self, index: usize) -> BinResult<u64> {
+        match &self.nca_ctx.fs_headers.get(index).map(|h| &h.superblock) {
+            Some(fs::SuperBlock::Pfs0(superblock)) => Ok(superblock.pfs0_offset),
+            _ => Err(not_a_section_of_type(index, "Pfs0")),
+        }
+    }
+
+    /// Extract the files of a PFS0 section (e.g. the ExeFS) into `output_folder`
+    pub fn extract_pfs0_section<P: AsRef<Path>>(&mut self, index: usize, output_folder: P) -> BinResult<()> {
+        let pfs0 = self.section_pfs0(index)?;
+        let output_folder = output_folder.as_ref();
+        std::fs::create_dir_all(output_folder)?;
+
+        let mut section = self.open_section(index)?;
+        for file in pfs0.files.iter() {
+            let mut output_file = File::create(output_folder.join(file.file_name.to_string()))?;
+            read_restore_into(&mut section, &mut output_file, file.file_offset, file.file_size as usize)?;
+        }
+        Ok(())
+    }
+
+    /// Extract the files of a RomFS section into `output_folder`
+    pub fn extract_romfs_section<P: AsRef<Path>>(&mut self, index: usize, output_folder: P) -> BinResult<()> {
+        let romfs = self.section_romfs(index)?;
+        let mut section = self.open_section(index)?;
+        romfs.extract_to(&mut section, output_folder)
+    }
+
+    /// Write the whole decrypted section, including its hash layers
+    pub fn write_section<W: Write>(&mut self, index: usize, writer: &mut W) -> std::io::Result<u64> {
+        std::io::copy(&mut self.open_section(index)?, writer)
+    }
+
+    /// Write a fully decrypted copy of the NCA: the decrypted header followed by the decrypted
+    /// sections. Anything outside the sections is copied as-is.
+    pub fn write_plaintext<W: Write>(&mut self, writer: &mut W) -> std::io::Result<u64> {
+        writer.write_all(&self.header)?;
+        let mut position = self.header.len() as u64;
+
+        let mut sections: Vec<usize> = (0..4)
+            .filter(|&i| self.nca_ctx.section_entries[i].end_block_offset > self.nca_ctx.section_entries[i].start_block_offset)
+            .collect();
+        sections.sort_by_key(|&i| self.nca_ctx.section_entries[i].start_block_offset);
+
+        for index in sections {
+            let section_offset = u64::from(self.nca_ctx.section_entries[index].start_block_offset) * MEDIA_UNIT_SIZE;
+            if section_offset > position {
+                read_restore_into(&mut self.raw_reader(), writer, position, (section_offset - position) as usize)?;
+            }
+            position = section_offset + self.write_section(index, writer)?;
+        }
+
+        let mut reader = self.raw_reader();
+        let end = reader.seek(SeekFrom::End(0))?;
+        if end > position {
+            read_restore_into(&mut reader, writer, position, (end - position) as usize)?;
+        }
+        Ok(end.max(position))
+    }
+
+    /// Verify the header signature against the fixed key selected by `signature_key_generation`
+    pub fn verify_header_signature(&self, key_type: KeysetType) -> Result<Validity, Validity> {
+        let moduli = match key_type {
+            KeysetType::Retail => &crate::keys::constants::retail_keys::NCA_HDR_FIXED_KEY_MODULI,
+            KeysetType::Dev => &crate::keys::constants::development_keys::NCA_HDR_FIXED_KEY_MODULI,
+        };
+        let modulus = moduli
+            .get(self.nca_ctx.signature_key_generation as usize)
+            .ok_or(Validity::Invalid)?;
+        let rsa_pubkey = rsa::RsaPublicKey::new(
+            rsa::BigUint::from_bytes_be(modulus.as_slice()),
+            rsa::BigUint::from_bytes_be([1, 0, 1].as_slice()),
+        )
+        .map_err(|_| Validity::CheckError)?;
+
+        Ok(self.verify_header_signature_with_key(&rsa_pubkey))
+    }
+
+    /// Check the SHA256 hash of a section's FS header against the hash stored in the NCA header
+    pub fn verify_fs_header_hash(&self, index: usize) -> Validity {
+        let fs_header = &self.header[0x400 + index * 0x200..0x600 + index * 0x200];
+        if Sha256::digest(fs_header).as_slice() == self.nca_ctx.section_hashes[index] {
+            Validity::Valid
+        } else {
+            Validity::Invalid
+        }
+    }
+
+    /// Check the hash tree of a section: HierarchicalSha256 for PFS0 sections and IVFC for RomFS
+    /// sections. Sections without a superblock are `Unchecked`.
+    pub fn verify_section_hashes(&mut self, index: usize) -> BinResult<Validity> {
+        let Some(layers) = self.section_hash_layers(index) else {
+            return Ok(Validity::Unchecked);
+        };
+
+        let mut section = self.open_section(index)?;
+        for layer in layers {
+            let expected = match layer.expected {
+                HashSource::Master(hash) => hash.to_vec(),
+                HashSource::Table { offset, size } => {
+                    let mut table = vec![0u8; size as usize];
+                    section.seek(SeekFrom::Start(offset))?;
+                    section.read_exact(&mut table)?;
+                    table
+                }
+            };
+            section.seek(SeekFrom::Start(layer.offset))?;
+            let actual = hash_blocks(&mut section, layer.size, layer.block_size, layer.pad_last_block)?;
+            if expected.get(..actual.len()) != Some(actual.as_slice()) {
+                return Ok(Validity::Invalid);
+            }
+        }
+        Ok(Validity::Valid)
+    }
+
+    /// Hash layers of a section from the master hash down to the data, or `None` for sections
+    /// without a superblock
+    fn section_hash_layers(&self, index: usize) -> Option<Vec<HashLayer>> {
+        let layers = match &self.nca_ctx.fs_headers[index].superblock {
+            fs::SuperBlock::None => return None,
+            fs::SuperBlock::Pfs0(superblock) => vec![
+                HashLayer {
+                    expected: HashSource::Master(superblock.master_hash),
+                    offset: superblock.hash_table_offset,
+                    size: superblock.hash_table_size,
+                    block_size: superblock.hash_table_size.max(1) as usize,
+                    pad_last_block: false,
+                },
+                HashLayer {
+                    expected: HashSource::Table { offset: superblock.hash_table_offset, size: superblock.hash_table_size },
+                    offset: superblock.pfs0_offset,
+                    size: superblock.pfs0_size,
+                    block_size: superblock.block_size_bytes as usize,
+                    pad_last_block: false,
+                },
+            ],
+            fs::SuperBlock::RomFs(superblock) => {
+                let level_count = (superblock.level_count as usize).saturating_sub(1).min(superblock.levels.len());
+                let levels = &superblock.levels[..level_count];
+                levels
+                    .iter()
+                    .enumerate()
+                    .map(|(level_index, level)| HashLayer {
+                        expected: match level_index.checked_sub(1) {
+                            None => HashSource::Master(superblock.master_hash),
+                            Some(parent) => HashSource::Table {
+                                offset: levels[parent].logical_offset,
+                                size: levels[parent].hash_data_size,
+                            },
+                        },
+                        offset: level.logical_offset,
+                        size: level.hash_data_size,
+                        block_size: 1 << level.block_size_log2,
+                        pad_last_block: true,
+                    })
+                    .collect()
+            }
+        };
+        Some(layers)
+    }
+
+    /// Verify the header signature against an arbitrary RSA-2048 public key
+    pub fn verify_header_signature_with_key(&self, public_key: &rsa::RsaPublicKey) -> Validity {
+        let verifying_key: VerifyingKey<Sha256> = VerifyingKey::new(public_key.clone());
+        let signature = Signature::try_from(self.nca_ctx.signature.as_slice()).expect("try_from with a slice always succeeds.");
+
+        if verifying_key.verify(&self.header[0x200..0x400], &signature).is_ok() {
+            Validity::Valid
+        } else {
+            Validity::Invalid
+        }
+    }
+}
+
+impl NcaFileCtx {
+    /// Effective key generation, taking the larger of the old and new header fields
+    pub fn effective_key_generation(&self) -> KeyGeneration {
+        KeyGeneration::from(u8::from(self.key_generation_old).max(u8::from(self.key_generation)))
+    }
+
+    /// Index into the master key (and derived key) tables for this NCA
+    pub fn master_key_revision(&self) -> usize {
+        u8::from(self.effective_key_generation()).saturating_sub(1) as usize
+    }
+
+    /// Whether the NCA uses titlekey crypto rather than its key area
+    pub fn has_rights_id(&self) -> bool {
+        self.rights_id.iter().any(|&b| b != 0)
+    }
+
+    /// Decrypt the key area with the matching key area encryption key
+    pub fn decrypt_key_area(&self, key_set: &NcaKeys) -> [[u8; 0x10]; 4] {
+        let key_area_key = key_set.key_area_keys[u8::from(self.key_area_index) as usize][self.master_key_revision()];
+        let cipher = Aes128::new(GenericArray::from_slice(&key_area_key));
+
+        let mut key_area = [[0u8; 0x10]; 4];
+        for (key, encrypted) in key_area.iter_mut().zip(self.encrypted_key_area.chunks_exact(0x10)) {
+            key.copy_from_slice(encrypted);
+            cipher.decrypt_block(GenericArray::from_mut_slice(key));
+        }
+        key_area
+    }
+}
+
+enum SectionCrypto {
+    None,
+    Ctr([u8; 0x10], [u8; 8]),
+}
+
+/// Decrypted, seekable view of a single NCA section. Offsets are relative to the section start.
+pub struct NcaSectionReader<'a> {
+    reader: Box<dyn ReadSeek + 'a>,
+    /// Absolute offset of the section in the NCA
+    section_offset: u64,
+    section_size: u64,
+    crypto: SectionCrypto,
+    position: u64,
+}
+
+impl NcaSectionReader<'_> {
+    pub fn section_size(&self) -> u64 {
+        self.section_size
+    }
+}
+
+impl Read for NcaSectionReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = (buf.len() as u64).min(self.section_size.saturating_sub(self.position)) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let absolute_offset = self.section_offset + self.position;
+        self.reader.seek(SeekFrom::Start(absolute_offset))?;
+        let read = self.reader.read(&mut buf[..len])?;
+        if let SectionCrypto::Ctr(key, section_ctr) = &self.crypto {
+            section_ctr_cipher(key, *section_ctr, absolute_offset).apply_keystream(&mut buf[..read]);
+        }
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for NcaSectionReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.section_size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Invalid seek to a negative or overflowing position",
+        ))?;
+        Ok(self.position)
+    }
+}
+
+/// Decrypted view of a section which checks each read against the section's hash layers. The
+/// data blocks a read covers are hashed and compared with their hash table entries, which are
+/// checked the same way up to the master hash in the FS header. Checked blocks are remembered,
+/// and a mismatch fails the read with [`std::io::ErrorKind::InvalidData`].
+pub struct VerifiedSectionReader {
+    section: NcaSectionReader<'static>,
+    layers: Vec<HashLayer>,
+    /// Blocks of each layer which matched their hash
+    verified: Vec<HashSet<u64>>,
+    position: u64,
+}
+
+impl VerifiedSectionReader {
+    /// Check the blocks of the data layer overlapping `start..end`
+    fn verify_range(&mut self, start: u64, end: u64) -> std::io::Result<()> {
+        let Some(level) = self.layers.len().checked_sub(1) else {
+            return Ok(());
+        };
+        let layer = self.layers[level];
+        let (start, end) = (start.max(layer.offset), end.min(layer.offset + layer.size));
+        if start >= end {
+            return Ok(());
+        }
+        let block_size = layer.block_size as u64;
+        for block in (start - layer.offset) / block_size..=(end - 1 - layer.offset) / block_size {
+            self.verify_block(level, block)?;
+        }
+        Ok(())
+    }
+
+    fn verify_block(&mut self, level: usize, block: u64) -> std::io::Result<()> {
+        if self.verified[level].contains(&block) {
+            return Ok(());
+        }
+        let layer = self.layers[level];
+        let block_size = layer.block_size as u64;
+        let start = block * block_size;
+        let expected = match layer.expected {
+            HashSource::Master(hash) => (block == 0).then_some(hash),
+            HashSource::Table { offset, size } if block * 0x20 + 0x20 <= size => {
+                let parent = self.layers[level - 1];
+                self.verify_block(level - 1, (offset + block * 0x20 - parent.offset) / parent.block_size as u64)?;
+                let mut hash = SHA256Hash::default();
+                self.section.seek(SeekFrom::Start(offset + block * 0x20))?;
+                self.section.read_exact(&mut hash)?;
+                Some(hash)
+            }
+            HashSource::Table { .. } => None,
+        };
+
+        self.section.seek(SeekFrom::Start(layer.offset + start))?;
+        let actual = hash_blocks(&mut self.section, block_size.min(layer.size - start), layer.block_size, layer.pad_last_block)?;
+        if expected.as_ref().map(|hash| hash.as_slice()) != Some(actual.as_slice()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Block {} of hash level {} does not match its hash", block, level),
+            ));
+        }
+        self.verified[level].insert(block);
+        Ok(())
+    }
+}
+
+impl Read for VerifiedSectionReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = (buf.len() as u64).min(self.section.section_size().saturating_sub(self.position));
+        self.verify_range(self.position, self.position + len)?;
+        self.section.seek(SeekFrom::Start(self.position))?;
+        let read = self.section.read(&mut buf[..len as usize])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for VerifiedSectionReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = self.section.seek(match pos {
+            SeekFrom::Current(offset) => SeekFrom::Start(self.position.checked_add_signed(offset).ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            ))?),
+            pos => pos,
+        })?;
+        Ok(self.position)
+    }
+}
+
+fn not_a_section_of_type(index: usize, fs_type: &str) -> binrw::Error {
+    binrw::Error::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("NCA section {} is not a {} section.", index, fs_type),
+    ))
+}
+
+pub(crate) fn get_nintendo_tweak(sector_index: u128) -> [u8; 0x10] {
+    sector_index.to_be_bytes()
+}
+
+/// Whether the header starts with a plaintext NCA2/NCA3 header (with an empty key area padding)
+fn is_plaintext_header(header: &[u8]) -> bool {
+    let magic = u32::from_le_bytes(header[0x200..0x204].try_into().unwrap());
+    (magic == u32::from(NcaVersion::Nca3) || magic == u32::from(NcaVersion::Nca2))
+        && header[0x340..(0x340 + 0xC0)].iter().all(|&b| b == 0)
+}
+
+/// Check whether `header` (at least the first 0x400 bytes of a file) is an NCA2/NCA3 header,
+/// either in plaintext or encrypted with the header key
+pub fn is_nca_header(header: &[u8], key_set: &NcaKeys) -> bool {
+    if header.len() < 0x400 {
+        return false;
+    }
+    if is_plaintext_header(header) {
+        return true;
+    }
+    let mut decrypted = header[..0x400].to_vec();
+    header_xts_context(key_set).decrypt_area(&mut decrypted, 0x200, 0, get_nintendo_tweak);
+    let magic = u32::from_le_bytes(decrypted[0x200..0x204].try_into().unwrap());
+    magic == u32::from(NcaVersion::Nca3) || magic == u32::from(NcaVersion::Nca2)
+}
+
+fn header_xts_context(key_set: &NcaKeys) -> Xts128<Aes128> {
+    Xts128::new(
+        Aes128::new(GenericArray::from_slice(&key_set.header_key[..0x10])),
+        Aes128::new(GenericArray::from_slice(&key_set.header_key[0x10..])),
+    )
+}
+
+/// AES-CTR cipher positioned at `offset` bytes into the NCA, for a section with the given counter
+pub(crate) fn section_ctr_cipher(key: &[u8; 0x10], section_ctr: [u8; 8], offset: u64) -> Ctr128BE<Aes128> {
+    let counter = (u128::from(u64::from_le_bytes(section_ctr)) << 64) | u128::from(offset >> 4);
+    let mut cipher = Ctr128BE::<Aes128>::new(GenericArray::from_slice(key), &counter.to_be_bytes().into());
+    cipher.seek(offset % 0x10);
+    cipher
+}
+
+/// Block size used for the HierarchicalSha256 hash table of PFS0 sections
+const PFS0_HASH_BLOCK_SIZE: u32 = 0x1000;
+/// Block size used for every IVFC level of RomFS sections
+const IVFC_BLOCK_SIZE_LOG2: u32 = 14;
+
+/// Builds (NCA3) NCA files from PFS0 and RomFS section images.
+///
+/// Each section image is read twice: once to generate its hash layers and once to encrypt it
+/// into the output, so only the hash layers are held in memory.
+pub struct NcaBuilder<'a> {
+    content_type: ContentType,
+    distribution_type: DistributionType,
+    program_id: u64,
+    content_index: u32,
+    sdk_addon_version: u32,
+    key_generation: KeyGeneration,
+    key_area_index: KeyAreaIndex,
+    content_key: Option<[u8; 0x10]>,
+    rights_id: [u8; 0x10],
+    header_signing_key: Option<RsaPrivateKey>,
+    npdm_signing_key: Option<RsaPrivateKey>,
+    sections: [Option<NcaBuilderSection<'a>>; 4],
+}
+
+struct NcaBuilderSection<'a> {
+    fs_type: fs::FsType,
+    size: u64,
+    reader: Box<dyn ReadSeek + 'a>,
+}
+
+/// A section whose hash layers have been generated
+struct PreparedSection<'a> {
+    fs_header: fs::FsHeader,
+    /// Hash layers and padding stored before the section data
+    hash_region: Vec<u8>,
+    size: u64,
+    reader: Box<dyn ReadSeek + 'a>,
+}
+
+impl<'a> NcaBuilder<'a> {
+    pub fn new(content_type: ContentType, program_id: u64) -> Self {
+        Self {
+            content_type,
+            distribution_type: DistributionType::Download,
+            program_id,
+            content_index: 0,
+            sdk_addon_version: 0x000C1100,
+            key_generation: KeyGeneration::OldOne,
+            key_area_index: KeyAreaIndex::Application,
+            content_key: None,
+            rights_id: [0; 0x10],
+            header_signing_key: None,
+            npdm_signing_key: None,
+            sections: Default::default(),
+        }
+    }
+
+    pub fn set_distribution_type(&mut self, distribution_type: DistributionType) -> &mut Self {
+        self.distribution_type = distribution_type;
+        self
+    }
+
+    pub fn set_content_index(&mut self, content_index: u32) -> &mut Self {
+        self.content_index = content_index;
+        self
+    }
+
+    pub fn set_sdk_addon_version(&mut self, sdk_addon_version: u32) -> &mut Self {
+        self.sdk_addon_version = sdk_addon_version;
+        self
+    }
+
+    pub fn set_key_generation(&mut self, key_generation: KeyGeneration) -> &mut Self {
+        self.key_generation = key_generation;
+        self
+    }
+
+    pub fn set_key_area_index(&mut self, key_area_index: KeyAreaIndex) -> &mut Self {
+        self.key_area_index = key_area_index;
+        self
+    }
+
+    /// Use a fixed AES-CTR content key rather than a randomly generated one
+    pub fn set_content_key(&mut self, content_key: [u8; 0x10]) -> &mut Self {
+        self.content_key = Some(content_key);
+        self
+    }
+
+    /// Use titlekey crypto: the content key becomes the (decrypted) title key of `rights_id` and
+    /// the key area is left zeroed
+    pub fn set_rights_id(&mut self, rights_id: [u8; 0x10]) -> &mut Self {
+        self.rights_id = rights_id;
+        self
+    }
+
+    /// Sign the header with the given fixed key (e.g. for development keysets). Without a key the
+    /// signature is left zeroed.
+    pub fn set_header_signing_key(&mut self, key: RsaPrivateKey) -> &mut Self {
+        self.header_signing_key = Some(key);
+        self
+    }
+
+    /// Produce the second header signature with the key whose modulus is in the NPDM's ACID
+    pub fn set_npdm_signing_key(&mut self, key: RsaPrivateKey) -> &mut Self {
+        self.npdm_signing_key = Some(key);
+        self
+    }
+
+    /// Add a PFS0 image (ExeFS, logo) as the given section, hashed with HierarchicalSha256
+    pub fn add_pfs0_section<R: ReadSeek + 'a>(&mut self, index: usize, size: u64, reader: R) -> &mut Self {
+        self.sections[index] = Some(NcaBuilderSection {
+            fs_type: fs::FsType::Pfs0,
+            size,
+            reader: Box::new(reader),
+        });
+        self
+    }
+
+    /// Add a RomFS image as the given section, hashed with IVFC
+    pub fn add_romfs_section<R: ReadSeek + 'a>(&mut self, index: usize, size: u64, reader: R) -> &mut Self {
+        self.sections[index] = Some(NcaBuilderSection {
+            fs_type: fs::FsType::RomFs,
+            size,
+            reader: Box::new(reader),
+        });
+        self
+    }
+
+    /// Write the NCA to `writer`, returning the number of bytes written.
+    pub fn write<W: Write>(self, writer: &mut W, key_set: &NcaKeys) -> BinResult<u64> {
+        let key_generation = u8::from(self.key_generation);
+        let master_key_revision = key_generation.saturating_sub(1) as usize;
+        let key_area_key = key_set.key_area_keys[u8::from(self.key_area_index) as usize][master_key_revision];
+        let content_key = self.content_key.unwrap_or_else(|| {
+            let mut key = [0u8; 0x10];
+            OsRng.fill_bytes(&mut key);
+            key
+        });
+
+        let mut section_entries = [fs::FsEntry::default(); 4];
+        let mut section_hashes = [[0u8; 0x20]; 4];
+        let mut fs_headers: [fs::FsHeader; 4] = Default::default();
+        let mut prepared_sections = Vec::new();
+        let mut offset = 0xC00u64;
+        for (index, section) in self.sections.into_iter().enumerate() {
+            let Some(section) = section else { continue };
+            let mut prepared = section.prepare(index)?;
+            let section_size = (prepared.hash_region.len() as u64 + prepared.size).next_multiple_of(MEDIA_UNIT_SIZE);
+
+            section_entries[index] = fs::FsEntry {
+                start_block_offset: (offset / MEDIA_UNIT_SIZE) as u32,
+                end_block_offset: ((offset + section_size) / MEDIA_UNIT_SIZE) as u32,
+                hash_sectors: 0,
+            };
+            let mut fs_header = Cursor::new(Vec::with_capacity(0x200));
+            fs_header.write_le(&prepared.fs_header)?;
+            section_hashes[index] = Sha256::digest(fs_header.get_ref()).into();
+            std::mem::swap(&mut fs_headers[index], &mut prepared.fs_header);
+
+            prepared_sections.push((offset, fs_headers[index].section_ctr, prepared));
+            offset += section_size;
+        }
+
+        let mut encrypted_key_area = [0u8; 0x40];
+        if self.rights_id == [0; 0x10] {
+            encrypted_key_area[0x20..0x30].copy_from_slice(&content_key);
+            let key_area_cipher = Aes128::new(GenericArray::from_slice(&key_area_key));
+            for block in encrypted_key_area.chunks_exact_mut(0x10) {
+                key_area_cipher.encrypt_block(GenericArray::from_mut_slice(block));
+            }
+        }
+
+        let nca_ctx = NcaFileCtx {
+            signature: [0; 0x100],
+            modulus: [0; 0x100],
+            file_version: NcaVersion::Nca3,
+            distribution_type: self.distribution_type,
+            content_type: self.content_type,
+            key_generation_old: KeyGeneration::from(key_generation.min(2)),
+            key_area_index: self.key_area_index,
+            content_size: offset,
+            program_id: self.program_id,
+            content_index: self.content_index,
+            sdk_addon_version: SdkAddonVersion(self.sdk_addon_version),
+            key_generation: KeyGeneration::from(if key_generation > 2 { key_generation } else { 0 }),
+            signature_key_generation: 0,
+            rights_id: self.rights_id,
+            section_entries,
+            section_hashes,
+            encrypted_key_area,
+            fs_headers,
+        };
+        let mut header = Cursor::new(Vec::with_capacity(0xC00));
+        header.write_le(&nca_ctx)?;
+        let mut header = header.into_inner();
+
+        for (key, signature_range) in [(&self.header_signing_key, 0..0x100), (&self.npdm_signing_key, 0x100..0x200)] {
+            if let Some(key) = key {
+                let signing_key = BlindedSigningKey::<Sha256>::new(key.clone());
+                let signature = signing_key
+                    .try_sign_with_rng(&mut OsRng, &header[0x200..0x400])
+                    .map_err(|e| binrw::Error::Custom { pos: 0, err: Box::new(e.to_string()) })?;
+                header[signature_range].copy_from_slice(&signature.to_bytes());
+            }
+        }
+
+        header_xts_context(key_set).encrypt_area(&mut header, 0x200, 0, get_nintendo_tweak);
+        writer.write_all(&header)?;
+
+        let mut buffer = vec![0u8; 0x10000];
+        for (section_offset, section_ctr, mut prepared) in prepared_sections {
+            let mut cipher = section_ctr_cipher(&content_key, section_ctr, section_offset);
+
+            let mut hash_region = std::mem::take(&mut prepared.hash_region);
+            cipher.apply_keystream(&mut hash_region);
+            writer.write_all(&hash_region)?;
+
+            prepared.reader.rewind()?;
+            let mut remaining = prepared.size;
+            while remaining > 0 {
+                let chunk = &mut buffer[..remaining.min(0x10000) as usize];
+                prepared.reader.read_exact(chunk)?;
+                cipher.apply_keystream(chunk);
+                writer.write_all(chunk)?;
+                remaining -= chunk.len() as u64;
+            }
+
+            let written = hash_region.len() as u64 + prepared.size;
+            let mut padding = vec![0u8; (written.next_multiple_of(MEDIA_UNIT_SIZE) - written) as usize];
+            cipher.apply_keystream(&mut padding);
+            writer.write_all(&padding)?;
+        }
+
+        Ok(offset)
+    }
+}
+
+impl<'a> NcaBuilderSection<'a> {
+    /// Generate the hash layers and FS header for the section
+    fn prepare(mut self, index: usize) -> BinResult<PreparedSection<'a>> {
+        self.reader.rewind()?;
+        let (partition_type, superblock, hash_region) = match self.fs_type {
+            fs::FsType::Pfs0 => {
+                let hash_table = hash_blocks(&mut self.reader, self.size, PFS0_HASH_BLOCK_SIZE as usize, false)?;
+                let pfs0_offset = (hash_table.len() as u64).next_multiple_of(MEDIA_UNIT_SIZE);
+                let superblock = fs::pfs0::Pfs0SuperBlock {
+                    master_hash: Sha256::digest(&hash_table).into(),
+                    block_size_bytes: PFS0_HASH_BLOCK_SIZE,
+                    hash_table_offset: 0,
+                    hash_table_size: hash_table.len() as u64,
+                    pfs0_offset,
+                    pfs0_size: self.size,
+                };
+                let mut hash_region = hash_table;
+                hash_region.resize(pfs0_offset as usize, 0);
+                (fs::PartitionType::PartitionFs, fs::SuperBlock::Pfs0(superblock), hash_region)
+            }
+            _ => {
+                let block_size = 1usize << IVFC_BLOCK_SIZE_LOG2;
+
+                // Hash levels from the data upwards, so `hash_levels[0]` hashes the RomFS itself
+                let mut hash_levels = vec![hash_blocks(&mut self.reader, self.size, block_size, true)?];
+                while hash_levels.len() < 5 {
+                    let previous = hash_levels.last().expect("At least one level exists");
+                    let level = hash_blocks(&mut previous.as_slice(), previous.len() as u64, block_size, true)?;
+                    hash_levels.push(level);
+                }
+                hash_levels.reverse();
+
+                let mut levels = [fs::romfs::IvfcLevel::default(); 6];
+                let mut hash_region = Vec::new();
+                for (level, data) in levels.iter_mut().zip(hash_levels.iter()) {
+                    *level = fs::romfs::IvfcLevel {
+                        logical_offset: hash_region.len() as u64,
+                        hash_data_size: data.len() as u64,
+                        block_size_log2: IVFC_BLOCK_SIZE_LOG2,
+                    };
+                    hash_region.extend_from_slice(data);
+                    hash_region.resize(hash_region.len().next_multiple_of(block_size), 0);
+                }
+                levels[5] = fs::romfs::IvfcLevel {
+                    logical_offset: hash_region.len() as u64,
+                    hash_data_size: self.size,
+                    block_size_log2: IVFC_BLOCK_SIZE_LOG2,
+                };
+
+                let mut master_block = hash_levels[0].clone();
+                master_block.resize(block_size, 0);
+                let superblock = fs::romfs::RomFsSuperBlock {
+                    version: 0x20000,
+                    master_hash_size: 0x20,
+                    level_count: fs::romfs::IVFC_MAX_LEVEL,
+                    levels,
+                    signature_salt: [0; 0x20],
+                    master_hash: Sha256::digest(&master_block).into(),
+                };
+                (fs::PartitionType::RomFs, fs::SuperBlock::RomFs(superblock), hash_region)
+            }
+        };
+
+        Ok(PreparedSection {
+            fs_header: fs::FsHeader {
+                version: 2,
+                partition_type,
+                fs_type: self.fs_type,
+                encryption_type: fs::EncryptionType::AesCtr,
+                superblock,
+                section_ctr: ((index as u64 + 1) << 32).to_le_bytes(),
+            },
+            hash_region,
+            size: self.size,
+            reader: self.reader,
+        })
+    }
+}
+
+/// A level of a section hash tree: the region hashed and where its expected hashes live
+#[derive(Clone, Copy)]
+struct HashLayer {
+    expected: HashSource,
+    offset: u64,
+    size: u64,
+    block_size: usize,
+    pad_last_block: bool,
+}
+
+#[derive(Clone, Copy)]
+enum HashSource {
+    /// Single hash stored in the superblock
+    Master(SHA256Hash),
+    /// Hash table stored in the section
+    Table { offset: u64, size: u64 },
+}
+
+/// SHA256 every `block_size` bytes of the first `size` bytes of `reader`. The last block is zero
+/// padded to a full block when `pad_last_block` is set (IVFC) and hashed as-is otherwise.
+fn hash_blocks<R: Read + ?Sized>(reader: &mut R, size: u64, block_size: usize, pad_last_block: bool) -> std::io::Result<Vec<u8>> {
+    let mut hashes = Vec::with_capacity((size.div_ceil(block_size as u64) * 0x20) as usize);
+    let mut block = vec![0u8; block_size];
+    let mut remaining = size;
+    while remaining > 0 {
+        let len = remaining.min(block_size as u64) as usize;
+        reader.read_exact(&mut block[..len])?;
+        let hashed_len = if pad_last_block {
+            block[len..].fill(0);
+            block_size
+        } else {
+            len
+        };
+        hashes.extend_from_slice(&Sha256::digest(&block[..hashed_len]));
+        remaining -= len as u64;
+    }
+    Ok(hashes)
 }
 
 pub mod fs {
-    use binrw::{ prelude::*};
+    use binrw::{ prelude::*, BinWriterExt};
     use num_enum::{IntoPrimitive, TryFromPrimitive};
+    use serde::Serialize;
 
-    #[binread]
-    #[derive(Debug, Default)]
+    use crate::utils::serde_helpers;
+
+    #[binrw]
+    #[derive(Serialize, Debug, Default, Clone, Copy)]
+    #[brw(little)]
     pub struct FsEntry {
         pub start_block_offset: u32,
         pub end_block_offset: u32,
+        #[brw(pad_after = 4)]
         pub hash_sectors: u32,
-        #[br(temp)]
-        _padding: [u8; 4],
     }
 
     #[repr(u8)]
-    #[binread]
-    #[br(little, repr = u8)]
-    #[derive(Debug, TryFromPrimitive, IntoPrimitive)]
+    #[binrw]
+    #[brw(little, repr = u8)]
+    #[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
     pub enum PartitionType {
+        #[default]
         RomFs,
         PartitionFs,
     }
 
     #[repr(u8)]
-    #[binread]
-    #[br(little, repr = u8)]
-    #[derive(Clone, Copy, Debug, TryFromPrimitive, IntoPrimitive)]
+    #[binrw]
+    #[brw(little, repr = u8)]
+    #[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
     pub enum FsType {
+        #[default]
         None = 0,
         Pfs0 = 2,
         RomFs = 3,
     }
 
     #[repr(u8)]
-    #[binread]
-    #[derive(Debug, TryFromPrimitive, IntoPrimitive)]
-    #[br(little, repr = u8)]
+    #[binrw]
+    #[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+    #[brw(little, repr = u8)]
     pub enum EncryptionType {
+        #[default]
         Auto,
         None,
         AesXts,
@@ -338,20 +1303,23 @@ pub mod fs {
         pub metadata_hash: MetadataHashInfo,
     }*/
 
-    #[binread]
-    #[derive(Debug)]
-    //#[br(magic = 2u16)]
+    /// Section header. Unused sections are entirely zeroed, which is also the `Default`.
+    #[binrw]
+    #[derive(Serialize, Debug, Default)]
+    #[brw(little)]
     pub struct FsHeader {
-        #[br(temp)] _unknown: [u8;2],
-        pub partition_type: u8, // TODO: enum
+        /// Always 2
+        pub version: u16,
+        pub partition_type: PartitionType,
         pub fs_type: FsType,
+        #[brw(pad_after = 3)]
         pub encryption_type: EncryptionType,
-        #[br(temp)]
-        _0x5: [u8;3],
         #[br(args(fs_type))]
         pub superblock: SuperBlock,
+        /// Upper half of the AES-CTR counter, stored little endian
+        #[brw(pad_after = 0xB8)]
+        #[serde(serialize_with = "serde_helpers::hex")]
         pub section_ctr: [u8;0x8],
-        #[br(temp)] _0x148: [u8;0xB8]
     }
 
     mod fs_patch {
@@ -412,10 +1380,12 @@ pub mod fs {
         }
     }
 
-    #[derive(Debug)]
+    #[derive(Serialize, Debug, Default)]
     pub enum SuperBlock {
+        #[default]
         None,
-        Pfs0(pfs0::Pfs0SuperBlock)
+        Pfs0(pfs0::Pfs0SuperBlock),
+        RomFs(romfs::RomFsSuperBlock),
     }
 
     impl BinRead for SuperBlock {
@@ -433,24 +1403,91 @@ pub mod fs {
             else if matches!(args.0, FsType::Pfs0) {
                 Ok(Self::Pfs0(reader.read_le()?))
             } else {
-                todo!()
+                Ok(Self::RomFs(reader.read_le()?))
             }
         }
     }
+
+    impl BinWrite for SuperBlock {
+        type Args<'a> = ();
+
+        fn write_options<W: std::io::Write + std::io::Seek>(
+                &self,
+                writer: &mut W,
+                _endian: binrw::Endian,
+                _args: Self::Args<'_>,
+            ) -> BinResult<()> {
+            match self {
+                Self::None => writer.write_all(&[0u8; 0x138])?,
+                Self::Pfs0(superblock) => writer.write_le(superblock)?,
+                Self::RomFs(superblock) => writer.write_le(superblock)?,
+            }
+            Ok(())
+        }
+    }
+
     pub mod pfs0 {
-        use binrw::binread;
+        use binrw::binrw;
+        use serde::Serialize;
 
-        #[binread]
-        #[derive(Debug)]
+        use crate::utils::serde_helpers;
+
+        /// HierarchicalSha256 hash info for a PFS0 section
+        #[binrw]
+        #[derive(Serialize, Debug)]
+        #[brw(little)]
         pub struct Pfs0SuperBlock {
+            #[serde(serialize_with = "serde_helpers::hex")]
             pub master_hash: crate::file_formats::SHA256Hash,
             pub block_size_bytes: u32,
-            #[br(temp)] _always_2: u32,
+            #[br(temp)]
+            #[bw(calc = 2)]
+            _always_2: u32,
             pub hash_table_offset: u64,
             pub hash_table_size: u64,
             pub pfs0_offset: u64,
+            #[brw(pad_after = 0xF0)]
             pub pfs0_size: u64,
-            #[br(temp)] _0x48: [u8;0xF0]
+        }
+    }
+
+    pub mod romfs {
+        use binrw::binrw;
+        use serde::Serialize;
+
+        use crate::utils::serde_helpers;
+
+        /// Number of levels in the IVFC hash tree, including the master hash
+        pub const IVFC_MAX_LEVEL: u32 = 7;
+
+        /// HierarchicalIntegrity (IVFC) hash info for a RomFS section
+        #[binrw]
+        #[derive(Serialize, Debug)]
+        #[brw(little, magic = b"IVFC")]
+        pub struct RomFsSuperBlock {
+            /// Always 0x20000
+            pub version: u32,
+            pub master_hash_size: u32,
+            pub level_count: u32,
+            /// Hash levels, the last of which is the RomFS itself
+            pub levels: [IvfcLevel; 6],
+            #[serde(serialize_with = "serde_helpers::hex")]
+            pub signature_salt: [u8; 0x20],
+            /// Hash of the first level
+            #[brw(pad_after = 0x58)]
+            #[serde(serialize_with = "serde_helpers::hex")]
+            pub master_hash: crate::file_formats::SHA256Hash,
+        }
+
+        #[binrw]
+        #[derive(Serialize, Debug, Default, Clone, Copy)]
+        #[brw(little)]
+        pub struct IvfcLevel {
+            /// Offset of the level from the start of the section
+            pub logical_offset: u64,
+            pub hash_data_size: u64,
+            #[brw(pad_after = 4)]
+            pub block_size_log2: u32,
         }
     }
 }
@@ -1,22 +1,32 @@
-use std::{io::Cursor, path::Path};
+use std::{
+    io::{Cursor, Write},
+    path::Path,
+};
 
-use binrw::{BinRead, BinReaderExt, BinResult, FilePtr32, VecArgs, binread};
+use binrw::{BinRead, BinReaderExt, BinResult, BinWrite, BinWriterExt, FilePtr32, VecArgs, binread, binrw, binwrite};
 use num_enum::{FromPrimitive, IntoPrimitive};
 use proc_bitfield::bitfield;
-use rsa::pss::{Signature, VerifyingKey};
+use rsa::{
+    RsaPrivateKey, RsaPublicKey,
+    pss::{BlindedSigningKey, Signature, VerifyingKey},
+    rand_core::OsRng,
+    traits::PublicKeyParts,
+};
 use sha2::Sha256;
-use signature::Verifier;
+use signature::{RandomizedSigner, SignatureEncoding, Verifier};
+
+use serde::Serialize;
 
 use super::Validity;
-use crate::{keys::KeysetType, utils::Placement};
+use crate::{keys::KeysetType, utils::{Placement, serde_helpers, serialize_bitfield}};
 
-//const MAGIC_META: u32 = 0x4154454D;
+const MAGIC_META: u32 = 0x4154454D;
 const MAGIC_ACID: u32 = 0x44494341;
 const MAGIC_ACI0: u32 = 0x30494341;
 
 bitfield! {
-    #[derive(BinRead)]
-    pub struct FsAccessFlags(u64): Debug {
+    #[derive(BinRead, BinWrite, Clone, Copy)]
+    pub struct FsAccessFlags(pub u64): Debug {
         pub raw: u64 @ ..,
 
         pub application_info: bool @ 0,
@@ -56,34 +66,155 @@ bitfield! {
         pub register_program_index_map_info: bool @ 34,
         pub create_own_save_data: bool @ 35,
         pub move_cache_storage: bool @ 36,
-        //pub _reserved: bool @ 37..=61,
+        pub device_tree_blob: bool @ 37,
+        pub notify_error_context_service_ready: bool @ 38,
+        //pub _reserved: bool @ 39..=61,
         pub debug: bool @ 62,
         pub full_permission : bool @ 63,
     }
 }
 
-#[derive(BinRead, Debug)]
+impl Serialize for FsAccessFlags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("FsAccessFlags", 2)?;
+        state.serialize_field("raw", &self.0)?;
+        state.serialize_field("flags", &self.enabled_names().collect::<Vec<_>>())?;
+        state.end()
+    }
+}
+
+impl FsAccessFlags {
+    /// Names of the permissions which are set, in bit order
+    pub fn enabled_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        const NAMES: [(u8, &str); 41] = [
+            (0, "ApplicationInfo"),
+            (1, "BootModeControl"),
+            (2, "Calibration"),
+            (3, "SystemSaveData"),
+            (4, "GameCard"),
+            (5, "SaveDataBackUp"),
+            (6, "SaveDataManagement"),
+            (7, "BisAllRaw"),
+            (8, "GameCardRaw"),
+            (9, "GameCardPrivate"),
+            (10, "SetTime"),
+            (11, "ContentManager"),
+            (12, "ImageManager"),
+            (13, "CreateSaveData"),
+            (14, "SystemSaveDataManagement"),
+            (15, "BisFileSystem"),
+            (16, "SystemUpdate"),
+            (17, "SaveDataMeta"),
+            (18, "DeviceSaveData"),
+            (19, "SettingsControl"),
+            (20, "SystemData"),
+            (21, "SdCard"),
+            (22, "Host"),
+            (23, "FillBis"),
+            (24, "CorruptSaveData"),
+            (25, "SaveDataForDebug"),
+            (26, "FormatSdCard"),
+            (27, "GetRightsId"),
+            (28, "RegisterExternalKey"),
+            (29, "RegisterUpdatePartition"),
+            (30, "SaveDataTransfer"),
+            (31, "DeviceDetection"),
+            (32, "AccessFailureResolution"),
+            (33, "SaveDataTransferVersion2"),
+            (34, "RegisterProgramIndexMapInfo"),
+            (35, "CreateOwnSaveData"),
+            (36, "MoveCacheStorage"),
+            (37, "DeviceTreeBlob"),
+            (38, "NotifyErrorContextServiceReady"),
+            (62, "Debug"),
+            (63, "FullPermission"),
+        ];
+        NAMES
+            .iter()
+            .filter(|(bit, _)| self.0 & (1 << bit) != 0)
+            .map(|(_, name)| *name)
+    }
+}
+
+/// An entry in a service access control list
+#[binrw]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[bw(assert(
+    (1..=ServiceRecord::MAX_NAME_LEN).contains(&name.len()),
+    "service name \"{}\" must be 1 to {} bytes",
+    name,
+    ServiceRecord::MAX_NAME_LEN
+))]
 pub struct ServiceRecord {
-    pub header: ServiceRecordHeader,
-    #[br(count = header.len())]
-    pub service_name: Vec<u8>,
+    #[br(temp)]
+    #[bw(calc = ServiceRecordHeader::for_name(name, *is_server))]
+    header: ServiceRecordHeader,
+    /// Service name, which may end in a `*` wildcard
+    #[br(count = header.len() + 1, map = |bytes: Vec<u8>| String::from_utf8_lossy(&bytes).into_owned())]
+    #[bw(map = |name: &String| name.as_bytes().to_vec())]
+    pub name: String,
+    /// Whether the service is hosted rather than accessed
+    #[br(calc = header.is_server())]
+    #[bw(ignore)]
+    pub is_server: bool,
 }
 bitfield! {
-    #[derive(BinRead)]
-    pub struct ServiceRecordHeader(u8): Debug {
+    #[derive(BinRead, BinWrite, Clone, Copy)]
+    pub struct ServiceRecordHeader(pub u8): Debug {
         pub raw: u8 @ ..,
+        /// Length of the name minus one
         pub len: u8 @ 0..=2,
         pub is_server: bool @ 7
     }
 }
 
-mod kernel_capability {
+impl ServiceRecordHeader {
+    fn for_name(name: &str, is_server: bool) -> Self {
+        Self(0).with_len(name.len().saturating_sub(1) as u8).with_is_server(is_server)
+    }
+}
+
+impl ServiceRecord {
+    /// Maximum length of a service name in bytes
+    pub const MAX_NAME_LEN: usize = 8;
+
+    /// Create a record for a service name of 1 to 8 bytes
+    pub fn new(name: &str, is_server: bool) -> Option<Self> {
+        if name.is_empty() || name.len() > Self::MAX_NAME_LEN {
+            return None;
+        }
+        Some(Self { name: name.to_string(), is_server })
+    }
+
+    /// Whether this entry covers `service`. A trailing `*` matches any suffix, so `fsp-*` covers
+    /// `fsp-srv` and `*` covers every service.
+    pub fn matches(&self, service: &str) -> bool {
+        match self.name.strip_suffix('*') {
+            Some(prefix) => service.starts_with(prefix),
+            None => self.name == service,
+        }
+    }
+}
+
+/// Whether any entry in `services` allows accessing (or, for `is_server`, hosting) `service`
+fn service_permitted(services: &[ServiceRecord], service: &str, is_server: bool) -> bool {
+    services.iter().any(|record| record.is_server == is_server && record.matches(service))
+}
+
+pub mod kernel_capability {
+
+    use std::{collections::BTreeSet, fmt, ops::RangeInclusive};
 
-    use binrw::{BinRead, Endian};
+    use binrw::{BinRead, BinWrite, Endian};
     use num_enum::{FromPrimitive, IntoPrimitive};
     use proc_bitfield::bitfield;
+    use serde::Serialize;
+
+    use crate::utils::serialize_bitfield;
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Serialize)]
     pub enum KernelCapability {
         ThreadInfo(ThreadInfo),
         EnableSystemCalls(SystemCalls),
@@ -99,7 +230,8 @@ mod kernel_capability {
     }
 
     bitfield! {
-        pub struct ThreadInfo(u32): Debug {
+        #[derive(Clone, Copy)]
+        pub struct ThreadInfo(pub u32): Debug {
             pub raw: u32 @ ..,
             pub lowest_thread_priority: u8 @ 4..=9,
             pub hightest_thread_priority: u8@ 10..=15,
@@ -107,53 +239,62 @@ mod kernel_capability {
             pub highest_cpu_id: u8 @ 24..=31,
         }
     }
+    serialize_bitfield!(ThreadInfo { lowest_thread_priority, hightest_thread_priority, lowest_cpu_id, highest_cpu_id });
 
     bitfield! {
-        pub struct SystemCalls(u32): Debug {
+        #[derive(Clone, Copy)]
+        pub struct SystemCalls(pub u32): Debug {
             pub raw: u32 @ ..,
             pub syscall_id: i32 @ 5..=28,
             pub index: u8 @ 29..=31
         }
     }
+    serialize_bitfield!(SystemCalls { syscall_id, index });
 
     #[repr(u8)]
-    #[derive(Debug, IntoPrimitive, FromPrimitive)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, IntoPrimitive, FromPrimitive)]
     pub enum MemoryMapPermission {
         #[default]
         Rw = 0,
         Ro,
     }
     #[repr(u8)]
-    #[derive(Debug, IntoPrimitive, FromPrimitive)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, IntoPrimitive, FromPrimitive)]
     pub enum MemoryMapType {
         #[default]
         Io,
         Static,
     }
     bitfield! {
-        pub struct MemoryMapAddrRo(u32): Debug {
+        #[derive(Clone, Copy)]
+        pub struct MemoryMapAddrRo(pub u32): Debug {
             pub raw: u32 @ ..,
             pub start_address: u32 @ 7..=30,
             pub memory_permission: u8 [MemoryMapPermission] @ 31..=31
         }
     }
+    serialize_bitfield!(MemoryMapAddrRo { start_address, memory_permission });
     bitfield! {
-        pub struct MemoryMapSizeType(u32): Debug {
+        #[derive(Clone, Copy)]
+        pub struct MemoryMapSizeType(pub u32): Debug {
             pub raw: u32 @ ..,
             pub size: u32 @ 7..=26,
             pub map_type: u8 [MemoryMapType] @ 31..=31
         }
     }
+    serialize_bitfield!(MemoryMapSizeType { size, map_type });
 
     bitfield! {
-        pub struct IoMemoryMap(u32): Debug{
+        #[derive(Clone, Copy)]
+        pub struct IoMemoryMap(pub u32): Debug{
             pub raw: u32 @ ..,
             pub start_address: u32 @ 8..=31
         }
     }
+    serialize_bitfield!(IoMemoryMap { start_address });
 
     #[repr(u8)]
-    #[derive(Debug, IntoPrimitive, FromPrimitive)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, IntoPrimitive, FromPrimitive)]
     pub enum MemoryRegionType {
         NoMapping = 0,
         KernelTraceBuffer,
@@ -164,7 +305,8 @@ mod kernel_capability {
         Invalid,
     }
     bitfield! {
-        pub struct MemoryRegionMap(u32): Debug {
+        #[derive(Clone, Copy)]
+        pub struct MemoryRegionMap(pub u32): Debug {
             pub raw: u32 @ ..,
             pub region0_type: u8 [MemoryRegionType] @ 11..=16,
             pub region0_is_ro: bool @ 17,
@@ -174,17 +316,20 @@ mod kernel_capability {
             pub region2_is_ro: bool @ 31
         }
     }
+    serialize_bitfield!(MemoryRegionMap { region0_type, region0_is_ro, region1_type, region1_is_ro, region2_type, region2_is_ro });
 
     bitfield! {
-        pub struct Interrupts(u32): Debug {
+        #[derive(Clone, Copy)]
+        pub struct Interrupts(pub u32): Debug {
             pub raw: u32 @ ..,
             pub irq1: u16 @ 12..=21,
             pub irq2: u16 @ 22..=31
         }
     }
+    serialize_bitfield!(Interrupts { irq1, irq2 });
 
     #[repr(u8)]
-    #[derive(Debug, IntoPrimitive, FromPrimitive)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, IntoPrimitive, FromPrimitive)]
     pub enum ProgramType {
         System = 0,
         Application,
@@ -194,32 +339,391 @@ mod kernel_capability {
         Invalid,
     }
     bitfield! {
-        pub struct MiscParams(u32): Debug {
+        #[derive(Clone, Copy)]
+        pub struct MiscParams(pub u32): Debug {
             pub raw: u32 @ ..,
             pub program_type: u8 [ProgramType] @ 14..=16,
         }
     }
+    serialize_bitfield!(MiscParams { program_type });
 
     bitfield! {
-        pub struct KernelVersion(u32): Debug {
+        #[derive(Clone, Copy)]
+        pub struct KernelVersion(pub u32): Debug {
             pub raw: u32 @ ..,
             pub minor_version: u8 @ 15..=18,
             pub major_version: u16 @ 19..=31
         }
     }
+    serialize_bitfield!(KernelVersion { minor_version, major_version });
     bitfield! {
-        pub struct HandleTableSize(u32): Debug {
+        #[derive(Clone, Copy)]
+        pub struct HandleTableSize(pub u32): Debug {
             pub raw: u32 @ ..,
             pub table_size: u16 @ 16..=25
         }
     }
+    serialize_bitfield!(HandleTableSize { table_size });
     bitfield! {
-        pub struct MiscFlags(u32): Debug {
+        #[derive(Clone, Copy)]
+        pub struct MiscFlags(pub u32): Debug {
             pub raw: u32 @ ..,
             pub enable_debug: bool @ 17,
             pub force_debug: bool @ 18
         }
     }
+    serialize_bitfield!(MiscFlags { enable_debug, force_debug });
+
+    /// Number of syscalls covered by a single syscall capability
+    pub const SYSCALLS_PER_CAPABILITY: u32 = 24;
+
+    /// Names of the known syscalls, by id
+    const SYSCALL_NAMES: &[(u32, &str)] = &[
+        (0x01, "svcSetHeapSize"),
+        (0x02, "svcSetMemoryPermission"),
+        (0x03, "svcSetMemoryAttribute"),
+        (0x04, "svcMapMemory"),
+        (0x05, "svcUnmapMemory"),
+        (0x06, "svcQueryMemory"),
+        (0x07, "svcExitProcess"),
+        (0x08, "svcCreateThread"),
+        (0x09, "svcStartThread"),
+        (0x0A, "svcExitThread"),
+        (0x0B, "svcSleepThread"),
+        (0x0C, "svcGetThreadPriority"),
+        (0x0D, "svcSetThreadPriority"),
+        (0x0E, "svcGetThreadCoreMask"),
+        (0x0F, "svcSetThreadCoreMask"),
+        (0x10, "svcGetCurrentProcessorNumber"),
+        (0x11, "svcSignalEvent"),
+        (0x12, "svcClearEvent"),
+        (0x13, "svcMapSharedMemory"),
+        (0x14, "svcUnmapSharedMemory"),
+        (0x15, "svcCreateTransferMemory"),
+        (0x16, "svcCloseHandle"),
+        (0x17, "svcResetSignal"),
+        (0x18, "svcWaitSynchronization"),
+        (0x19, "svcCancelSynchronization"),
+        (0x1A, "svcArbitrateLock"),
+        (0x1B, "svcArbitrateUnlock"),
+        (0x1C, "svcWaitProcessWideKeyAtomic"),
+        (0x1D, "svcSignalProcessWideKey"),
+        (0x1E, "svcGetSystemTick"),
+        (0x1F, "svcConnectToNamedPort"),
+        (0x20, "svcSendSyncRequestLight"),
+        (0x21, "svcSendSyncRequest"),
+        (0x22, "svcSendSyncRequestWithUserBuffer"),
+        (0x23, "svcSendAsyncRequestWithUserBuffer"),
+        (0x24, "svcGetProcessId"),
+        (0x25, "svcGetThreadId"),
+        (0x26, "svcBreak"),
+        (0x27, "svcOutputDebugString"),
+        (0x28, "svcReturnFromException"),
+        (0x29, "svcGetInfo"),
+        (0x2A, "svcFlushEntireDataCache"),
+        (0x2B, "svcFlushDataCache"),
+        (0x2C, "svcMapPhysicalMemory"),
+        (0x2D, "svcUnmapPhysicalMemory"),
+        (0x2E, "svcGetDebugFutureThreadInfo"),
+        (0x2F, "svcGetLastThreadInfo"),
+        (0x30, "svcGetResourceLimitLimitValue"),
+        (0x31, "svcGetResourceLimitCurrentValue"),
+        (0x32, "svcSetThreadActivity"),
+        (0x33, "svcGetThreadContext3"),
+        (0x34, "svcWaitForAddress"),
+        (0x35, "svcSignalToAddress"),
+        (0x36, "svcSynchronizePreemptionState"),
+        (0x37, "svcGetResourceLimitPeakValue"),
+        (0x39, "svcCreateIoPool"),
+        (0x3A, "svcCreateIoRegion"),
+        (0x3C, "svcKernelDebug"),
+        (0x3D, "svcChangeKernelTraceState"),
+        (0x40, "svcCreateSession"),
+        (0x41, "svcAcceptSession"),
+        (0x42, "svcReplyAndReceiveLight"),
+        (0x43, "svcReplyAndReceive"),
+        (0x44, "svcReplyAndReceiveWithUserBuffer"),
+        (0x45, "svcCreateEvent"),
+        (0x46, "svcMapIoRegion"),
+        (0x47, "svcUnmapIoRegion"),
+        (0x48, "svcMapPhysicalMemoryUnsafe"),
+        (0x49, "svcUnmapPhysicalMemoryUnsafe"),
+        (0x4A, "svcSetUnsafeLimit"),
+        (0x4B, "svcCreateCodeMemory"),
+        (0x4C, "svcControlCodeMemory"),
+        (0x4D, "svcSleepSystem"),
+        (0x4E, "svcReadWriteRegister"),
+        (0x4F, "svcSetProcessActivity"),
+        (0x50, "svcCreateSharedMemory"),
+        (0x51, "svcMapTransferMemory"),
+        (0x52, "svcUnmapTransferMemory"),
+        (0x53, "svcCreateInterruptEvent"),
+        (0x54, "svcQueryPhysicalAddress"),
+        (0x55, "svcQueryIoMapping"),
+        (0x56, "svcCreateDeviceAddressSpace"),
+        (0x57, "svcAttachDeviceAddressSpace"),
+        (0x58, "svcDetachDeviceAddressSpace"),
+        (0x59, "svcMapDeviceAddressSpaceByForce"),
+        (0x5A, "svcMapDeviceAddressSpaceAligned"),
+        (0x5B, "svcMapDeviceAddressSpace"),
+        (0x5C, "svcUnmapDeviceAddressSpace"),
+        (0x5D, "svcInvalidateProcessDataCache"),
+        (0x5E, "svcStoreProcessDataCache"),
+        (0x5F, "svcFlushProcessDataCache"),
+        (0x60, "svcDebugActiveProcess"),
+        (0x61, "svcBreakDebugProcess"),
+        (0x62, "svcTerminateDebugProcess"),
+        (0x63, "svcGetDebugEvent"),
+        (0x64, "svcContinueDebugEvent"),
+        (0x65, "svcGetProcessList"),
+        (0x66, "svcGetThreadList"),
+        (0x67, "svcGetDebugThreadContext"),
+        (0x68, "svcSetDebugThreadContext"),
+        (0x69, "svcQueryDebugProcessMemory"),
+        (0x6A, "svcReadDebugProcessMemory"),
+        (0x6B, "svcWriteDebugProcessMemory"),
+        (0x6C, "svcSetHardwareBreakPoint"),
+        (0x6D, "svcGetDebugThreadParam"),
+        (0x6F, "svcGetSystemInfo"),
+        (0x70, "svcCreatePort"),
+        (0x71, "svcManageNamedPort"),
+        (0x72, "svcConnectToPort"),
+        (0x73, "svcSetProcessMemoryPermission"),
+        (0x74, "svcMapProcessMemory"),
+        (0x75, "svcUnmapProcessMemory"),
+        (0x76, "svcQueryProcessMemory"),
+        (0x77, "svcMapProcessCodeMemory"),
+        (0x78, "svcUnmapProcessCodeMemory"),
+        (0x79, "svcCreateProcess"),
+        (0x7A, "svcStartProcess"),
+        (0x7B, "svcTerminateProcess"),
+        (0x7C, "svcGetProcessInfo"),
+        (0x7D, "svcCreateResourceLimit"),
+        (0x7E, "svcSetResourceLimitLimitValue"),
+        (0x7F, "svcCallSecureMonitor"),
+        (0x90, "svcMapInsecurePhysicalMemory"),
+        (0x91, "svcUnmapInsecurePhysicalMemory"),
+    ];
+
+    /// Name of a syscall, such as `svcSetHeapSize` for 0x01
+    pub fn syscall_name(id: u32) -> Option<&'static str> {
+        SYSCALL_NAMES
+            .binary_search_by_key(&id, |(known, _)| *known)
+            .ok()
+            .map(|index| SYSCALL_NAMES[index].1)
+    }
+
+    /// Look up a syscall id from its name, with or without the `svc` prefix
+    pub fn syscall_id(name: &str) -> Option<u32> {
+        let name = name.strip_prefix("svc").unwrap_or(name);
+        SYSCALL_NAMES
+            .iter()
+            .find(|(_, known)| known[3..].eq_ignore_ascii_case(name))
+            .map(|(id, _)| *id)
+    }
+
+    impl SystemCalls {
+        /// Ids of the syscalls enabled by this capability
+        pub fn ids(&self) -> impl Iterator<Item = u32> + use<> {
+            let (mask, base) = (self.syscall_id() as u32, self.index() as u32 * SYSCALLS_PER_CAPABILITY);
+            (0..SYSCALLS_PER_CAPABILITY).filter(move |bit| mask & 1 << bit != 0).map(move |bit| base + bit)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+    pub struct Syscall {
+        pub id: u32,
+        pub name: Option<&'static str>,
+    }
+
+    impl Syscall {
+        pub fn new(id: u32) -> Self {
+            Self { id, name: syscall_name(id) }
+        }
+    }
+
+    impl fmt::Display for Syscall {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:#04X}", self.id)?;
+            if let Some(name) = self.name {
+                write!(f, " ({})", name)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// All syscalls enabled by a list of capabilities, sorted by id
+    pub fn allowed_syscalls(capabilities: &[KernelCapability]) -> Vec<Syscall> {
+        let ids: BTreeSet<u32> = capabilities
+            .iter()
+            .filter_map(|capability| match capability {
+                KernelCapability::EnableSystemCalls(calls) => Some(calls.ids()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        ids.into_iter().map(Syscall::new).collect()
+    }
+
+    /// A static or IO memory range mapped into the process
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+    pub struct MemoryMapRange {
+        pub start: u64,
+        pub size: u64,
+        pub permission: MemoryMapPermission,
+        pub map_type: MemoryMapType,
+    }
+
+    impl fmt::Display for MemoryMapRange {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "0x{:010X}-0x{:010X} ({:?}, {:?})",
+                self.start,
+                self.start + self.size,
+                self.permission,
+                self.map_type
+            )
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+    pub struct MemoryRegion {
+        pub region_type: MemoryRegionType,
+        pub read_only: bool,
+    }
+
+    impl fmt::Display for MemoryRegion {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}{}", self.region_type, if self.read_only { " (RO)" } else { "" })
+        }
+    }
+
+    /// A kernel capability decoded into its meaning. Syscall capabilities are combined by
+    /// [`summarize`] into a single list.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+    #[serde(tag = "type", content = "value", rename_all = "snake_case")]
+    pub enum CapabilitySummary {
+        ThreadInfo { priorities: RangeInclusive<u8>, cpu_cores: RangeInclusive<u8> },
+        Syscalls(Vec<Syscall>),
+        MemoryMap(MemoryMapRange),
+        IoPage(u64),
+        MemoryRegions(Vec<MemoryRegion>),
+        Interrupts(Vec<u16>),
+        ProgramType(ProgramType),
+        KernelVersion { major: u16, minor: u8 },
+        HandleTableSize(u16),
+        DebugFlags { allow_debug: bool, force_debug: bool },
+        Unknown(u32),
+    }
+
+    impl fmt::Display for CapabilitySummary {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::ThreadInfo { priorities, cpu_cores } => write!(
+                    f,
+                    "Thread priorities {}-{}, CPU cores {}-{}",
+                    priorities.start(),
+                    priorities.end(),
+                    cpu_cores.start(),
+                    cpu_cores.end()
+                ),
+                Self::Syscalls(syscalls) => {
+                    f.write_str("Syscalls ")?;
+                    write_joined(f, syscalls)
+                }
+                Self::MemoryMap(range) => write!(f, "Memory map {}", range),
+                Self::IoPage(address) => write!(f, "IO page 0x{:010X}", address),
+                Self::MemoryRegions(regions) => {
+                    f.write_str("Memory regions ")?;
+                    write_joined(f, regions)
+                }
+                Self::Interrupts(irqs) => {
+                    f.write_str("Interrupts ")?;
+                    write_joined(f, irqs)
+                }
+                Self::ProgramType(program_type) => write!(f, "Program type {:?}", program_type),
+                Self::KernelVersion { major, minor } => write!(f, "Kernel version {}.{}", major, minor),
+                Self::HandleTableSize(size) => write!(f, "Handle table size {}", size),
+                Self::DebugFlags { allow_debug, force_debug } => {
+                    write!(f, "Debug flags (allow: {}, force: {})", allow_debug, force_debug)
+                }
+                Self::Unknown(raw) => write!(f, "Unknown capability 0x{:08X}", raw),
+            }
+        }
+    }
+
+    fn write_joined<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+        for (index, item) in items.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", item)?;
+        }
+        Ok(())
+    }
+
+    impl KernelCapability {
+        pub fn summary(&self) -> CapabilitySummary {
+            match self {
+                Self::ThreadInfo(info) => CapabilitySummary::ThreadInfo {
+                    priorities: info.hightest_thread_priority()..=info.lowest_thread_priority(),
+                    cpu_cores: info.lowest_cpu_id()..=info.highest_cpu_id(),
+                },
+                Self::EnableSystemCalls(calls) => CapabilitySummary::Syscalls(calls.ids().map(Syscall::new).collect()),
+                Self::MemoryMap(address, size) => CapabilitySummary::MemoryMap(MemoryMapRange {
+                    start: u64::from(address.start_address()) << 12,
+                    size: u64::from(size.size()) << 12,
+                    permission: address.memory_permission(),
+                    map_type: size.map_type(),
+                }),
+                Self::IoMemoryMap(map) => CapabilitySummary::IoPage(u64::from(map.start_address()) << 12),
+                Self::MemoryRegionMap(map) => CapabilitySummary::MemoryRegions(
+                    [
+                        (map.region0_type(), map.region0_is_ro()),
+                        (map.region1_type(), map.region1_is_ro()),
+                        (map.region2_type(), map.region2_is_ro()),
+                    ]
+                    .into_iter()
+                    .filter(|(region_type, _)| *region_type != MemoryRegionType::NoMapping)
+                    .map(|(region_type, read_only)| MemoryRegion { region_type, read_only })
+                    .collect(),
+                ),
+                Self::EnableInterrupts(interrupts) => CapabilitySummary::Interrupts(
+                    [interrupts.irq1(), interrupts.irq2()].into_iter().filter(|&irq| irq != 0x3FF).collect(),
+                ),
+                Self::MiscParams(params) => CapabilitySummary::ProgramType(params.program_type()),
+                Self::KernelVersion(version) => CapabilitySummary::KernelVersion {
+                    major: version.major_version(),
+                    minor: version.minor_version(),
+                },
+                Self::HandleTableSize(size) => CapabilitySummary::HandleTableSize(size.table_size()),
+                Self::MiscFlags(flags) => CapabilitySummary::DebugFlags {
+                    allow_debug: flags.enable_debug(),
+                    force_debug: flags.force_debug(),
+                },
+                Self::Invalid(raw) => CapabilitySummary::Unknown(*raw),
+            }
+        }
+    }
+
+    /// Summaries of a list of capabilities, with every syscall capability merged into one entry
+    /// at the position of the first
+    pub fn summarize(capabilities: &[KernelCapability]) -> Vec<CapabilitySummary> {
+        let mut summaries = Vec::new();
+        let mut syscalls_added = false;
+        for capability in capabilities {
+            match capability {
+                KernelCapability::EnableSystemCalls(_) if syscalls_added => {}
+                KernelCapability::EnableSystemCalls(_) => {
+                    syscalls_added = true;
+                    summaries.push(CapabilitySummary::Syscalls(allowed_syscalls(capabilities)));
+                }
+                _ => summaries.push(capability.summary()),
+            }
+        }
+        summaries
+    }
 
     impl BinRead for KernelCapability {
         type Args<'a> = ();
@@ -259,28 +763,174 @@ mod kernel_capability {
             }
         }
     }
+
+    impl BinWrite for KernelCapability {
+        type Args<'a> = ();
+        fn write_options<W: std::io::Write + std::io::Seek>(
+            &self,
+            writer: &mut W,
+            options: Endian,
+            args: Self::Args<'_>,
+        ) -> binrw::BinResult<()> {
+            match self {
+                Self::ThreadInfo(value) => value.0.write_options(writer, options, args),
+                Self::EnableSystemCalls(value) => value.0.write_options(writer, options, args),
+                Self::MemoryMap(address, size) => {
+                    address.0.write_options(writer, options, args)?;
+                    size.0.write_options(writer, options, args)
+                }
+                Self::IoMemoryMap(value) => value.0.write_options(writer, options, args),
+                Self::MemoryRegionMap(value) => value.0.write_options(writer, options, args),
+                Self::EnableInterrupts(value) => value.0.write_options(writer, options, args),
+                Self::MiscParams(value) => value.0.write_options(writer, options, args),
+                Self::KernelVersion(value) => value.0.write_options(writer, options, args),
+                Self::HandleTableSize(value) => value.0.write_options(writer, options, args),
+                Self::MiscFlags(value) => value.0.write_options(writer, options, args),
+                Self::Invalid(value) => value.write_options(writer, options, args),
+            }
+        }
+    }
 }
-mod aci0 {
-    use core::mem::size_of;
+
+pub mod aci0 {
+    use std::{
+        fmt,
+        io::{Cursor, SeekFrom},
+    };
+
+    use proc_bitfield::bitfield;
 
     use crate::utils::{Placement, until_eob};
 
-    use super::{FsAccessFlags, MAGIC_ACI0, ServiceRecord, kernel_capability::KernelCapability};
+    use super::{
+        FsAccessFlags, MAGIC_ACI0, ServiceRecord, kernel_capability::KernelCapability, layout_regions, service_permitted,
+        write_all,
+    };
 
     use binrw::{VecArgs, prelude::*};
+    use serde::Serialize;
+
+    /// Size of the fixed ACI0 header
+    const ACI0_HEADER_SIZE: usize = 0x40;
+    /// Size of the fixed part of the FS access header
+    const FAH_HEADER_SIZE: u32 = 0x1C;
+
+    /// On-disk layout of the ACI0 header, used when writing
+    #[binwrite]
+    #[bw(little, magic = b"ACI0")]
+    struct RawAci0Header {
+        #[bw(pad_before = 0xC)]
+        title_id: u64,
+        #[bw(pad_before = 8)]
+        fah_offset: u32,
+        fah_size: u32,
+        services_offset: u32,
+        services_size: u32,
+        kernel_capabilities_offset: u32,
+        #[bw(pad_after = 8)]
+        kernel_capabilities_size: u32,
+    }
+
+    impl Aci0 {
+        /// Whether the process may connect to `service`
+        pub fn can_access_service(&self, service: &str) -> bool {
+            service_permitted(&self.services, service, false)
+        }
+
+        /// Whether the process may register `service`
+        pub fn can_host_service(&self, service: &str) -> bool {
+            service_permitted(&self.services, service, true)
+        }
+
+        /// Names of the services the process connects to, including wildcard patterns
+        pub fn accessed_services(&self) -> impl Iterator<Item = &str> {
+            self.services.iter().filter(|record| !record.is_server).map(|record| record.name.as_str())
+        }
+
+        /// Names of the services the process registers, including wildcard patterns
+        pub fn hosted_services(&self) -> impl Iterator<Item = &str> {
+            self.services.iter().filter(|record| record.is_server).map(|record| record.name.as_str())
+        }
+
+        /// Serialize the ACI0 with its FS access header, services and kernel capabilities
+        pub fn to_bytes(&self) -> BinResult<Vec<u8>> {
+            let regions = [
+                self.fs_access_control.to_bytes()?,
+                write_all(&self.services)?,
+                write_all(&self.kernel_capabilities)?,
+            ];
+            let (placements, mut output) = layout_regions(ACI0_HEADER_SIZE, &regions);
+            let header = RawAci0Header {
+                title_id: self.title_id,
+                fah_offset: placements[0].0,
+                fah_size: placements[0].1,
+                services_offset: placements[1].0,
+                services_size: placements[1].1,
+                kernel_capabilities_offset: placements[2].0,
+                kernel_capabilities_size: placements[2].1,
+            };
+            header.write_le(&mut Cursor::new(&mut output[..ACI0_HEADER_SIZE]))?;
+            Ok(output)
+        }
+    }
+
+    impl Aci0FsAccessControlRecord {
+        /// Serialize the FS access header. Content owners are written as a count followed by the
+        /// IDs, save data owners as a count, their accessibilities and then the IDs.
+        pub fn to_bytes(&self) -> BinResult<Vec<u8>> {
+            let mut content_owner_info = Vec::new();
+            if !self.content_owner_ids.is_empty() {
+                let mut writer = Cursor::new(&mut content_owner_info);
+                writer.write_le(&(self.content_owner_ids.len() as u32))?;
+                writer.write_le(&self.content_owner_ids)?;
+            }
+            let mut save_data_owner_info = Vec::new();
+            if !self.save_data_owners.is_empty() {
+                let mut writer = Cursor::new(&mut save_data_owner_info);
+                writer.write_le(&(self.save_data_owners.len() as u32))?;
+                for owner in &self.save_data_owners {
+                    writer.write_le(&owner.accessibility)?;
+                }
+                let aligned = (writer.position() as usize).next_multiple_of(4);
+                writer.get_mut().resize(aligned, 0);
+                writer.set_position(aligned as u64);
+                for owner in &self.save_data_owners {
+                    writer.write_le(&owner.id)?;
+                }
+            }
+
+            let content_owner_info_offset = FAH_HEADER_SIZE;
+            let save_data_owner_info_offset = content_owner_info_offset + content_owner_info.len() as u32;
+            let mut output = Cursor::new(Vec::new());
+            output.write_le(&self.version)?;
+            output.write_le(&[0u8; 3])?;
+            output.write_le(&self.access_flags)?;
+            output.write_le(&content_owner_info_offset)?;
+            output.write_le(&(content_owner_info.len() as u32))?;
+            output.write_le(&save_data_owner_info_offset)?;
+            output.write_le(&(save_data_owner_info.len() as u32))?;
+            output.write_le(&content_owner_info)?;
+            output.write_le(&save_data_owner_info)?;
+            Ok(output.into_inner())
+        }
+    }
 
     #[binread]
-    #[derive(Debug)]
+    #[derive(Debug, Serialize)]
     #[br(little, assert(magic == MAGIC_ACI0))]
     pub struct Aci0 {
         #[br(temp)]
         _cursor_position: crate::utils::CurPos,
         pub magic: u32,
+        #[serde(skip)]
         pub _0x4: [u8; 0xC],
         pub title_id: u64,
+        #[serde(skip)]
         pub _0x18: u64,
         pub fah_offset: u32,
         pub fah_size: u32,
+        #[br(parse_with = Placement::parse, args {offset: _cursor_position.0 + fah_offset as u64, inner: ()})]
+        pub fs_access_control: Aci0FsAccessControlRecord,
         #[br(temp)]
         services_buffer_offset: u32,
         #[br(temp)]
@@ -297,54 +947,242 @@ mod aci0 {
         kernel_capability_buffer: Vec<u8>,
         #[br(parse_with = until_eob(kernel_capability_buffer))]
         pub kernel_capabilities: Vec<KernelCapability>,
+        #[serde(skip)]
         pub _padding: u64,
     }
 
+    /// The FS access header (FAH) of an ACI0
     #[binread]
-    #[derive(Debug)]
+    #[derive(Debug, Serialize)]
     pub struct Aci0FsAccessControlRecord {
         #[br(temp)]
         _cursor_position: crate::utils::CurPos,
+        #[brw(pad_after = 3)]
         pub version: u8,
-        #[br(temp, count = 3)]
-        pub _padding: Vec<u8>,
         pub access_flags: FsAccessFlags,
         #[br(temp)]
-        content_owner_id_buffer_offset: u32,
+        content_owner_info_offset: u32,
+        #[br(temp)]
+        content_owner_info_size: u32,
+        #[br(temp)]
+        save_data_owner_info_offset: u32,
         #[br(temp)]
-        content_owner_id_buffer_size: u32,
-        #[br(parse_with = Placement::parse, args {offset: _cursor_position.0 + content_owner_id_buffer_offset as u64, inner: VecArgs {count: content_owner_id_buffer_size as usize / size_of::<u64>(), inner: ()}})]
+        save_data_owner_info_size: u32,
+        #[br(parse_with = parse_owner_info::<ContentOwnerInfo, u64, _>, args(_cursor_position.0 + content_owner_info_offset as u64, content_owner_info_size))]
         pub content_owner_ids: Vec<u64>,
+        #[br(parse_with = parse_owner_info::<SaveDataOwnerInfo, SaveDataOwner, _>, args(_cursor_position.0 + save_data_owner_info_offset as u64, save_data_owner_info_size))]
+        pub save_data_owners: Vec<SaveDataOwner>,
+    }
+
+    impl Aci0FsAccessControlRecord {
+        /// Whether `id` is listed as a content owner
+        pub fn has_content_owner(&self, id: u64) -> bool {
+            self.content_owner_ids.contains(&id)
+        }
+
+        /// Accessibility granted to the save data of owner `id`, if it is listed
+        pub fn save_data_owner_accessibility(&self, id: u64) -> Option<SaveDataAccessibility> {
+            self.save_data_owners.iter().find(|owner| owner.id == id).map(|owner| owner.accessibility)
+        }
+    }
+
+    bitfield! {
+        /// Access a title has to the save data of a save data owner
+        #[derive(BinRead, BinWrite, Clone, Copy, PartialEq, Eq)]
+        pub struct SaveDataAccessibility(pub u8): Debug {
+            pub raw: u8 @ ..,
+            pub can_read: bool @ 0,
+            pub can_write: bool @ 1,
+        }
+    }
+
+    impl SaveDataAccessibility {
+        pub const READ: Self = Self(1);
+        pub const WRITE: Self = Self(2);
+        pub const READ_WRITE: Self = Self(3);
+    }
+
+    impl fmt::Display for SaveDataAccessibility {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match (self.can_read(), self.can_write()) {
+                (true, true) => "ReadWrite",
+                (true, false) => "Read",
+                (false, true) => "Write",
+                (false, false) => "None",
+            })
+        }
+    }
+
+    impl Serialize for SaveDataAccessibility {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+
+    impl<'de> serde::Deserialize<'de> for SaveDataAccessibility {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let name = String::deserialize(deserializer)?;
+            match name.as_str() {
+                "ReadWrite" => Ok(Self::READ_WRITE),
+                "Read" => Ok(Self::READ),
+                "Write" => Ok(Self::WRITE),
+                "None" => Ok(Self(0)),
+                _ => Err(serde::de::Error::custom(format!("invalid save data accessibility \"{name}\""))),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+    pub struct SaveDataOwner {
+        pub id: u64,
+        pub accessibility: SaveDataAccessibility,
+    }
+
+    /// Content owner info: a count followed by the IDs
+    #[binread]
+    struct ContentOwnerInfo {
         #[br(temp)]
-        save_data_owner_id_buffer_offset: u32,
+        count: u32,
+        #[br(count = count)]
+        ids: Vec<u64>,
+    }
+
+    /// Save data owner info: a count, one accessibility byte per owner, then the IDs aligned to 4 bytes
+    #[binread]
+    struct SaveDataOwnerInfo {
         #[br(temp)]
-        save_data_owner_id_buffer_size: u32,
-        #[br(parse_with = Placement::parse, args {offset: _cursor_position.0 + save_data_owner_id_buffer_offset as u64, inner: VecArgs {count: save_data_owner_id_buffer_size as usize / size_of::<u64>(), inner: ()}})]
-        pub save_data_owner_ids: Vec<u64>,
-        #[br(if(content_owner_id_buffer_offset != 0x1C))]
-        pub save_data_owner_id_count: Option<u32>,
+        count: u32,
+        #[br(temp, count = count)]
+        accessibilities: Vec<SaveDataAccessibility>,
+        #[br(temp, align_before = 4, count = count)]
+        ids: Vec<u64>,
+        #[br(calc = ids.iter().zip(accessibilities.iter()).map(|(&id, &accessibility)| SaveDataOwner { id, accessibility }).collect())]
+        owners: Vec<SaveDataOwner>,
+    }
+
+    impl From<ContentOwnerInfo> for Vec<u64> {
+        fn from(info: ContentOwnerInfo) -> Self {
+            info.ids
+        }
+    }
+
+    impl From<SaveDataOwnerInfo> for Vec<SaveDataOwner> {
+        fn from(info: SaveDataOwnerInfo) -> Self {
+            info.owners
+        }
+    }
+
+    /// Read an owner info block at an absolute offset, or nothing if it is empty
+    #[binrw::parser(reader, endian)]
+    fn parse_owner_info<T, I>(offset: u64, size: u32) -> BinResult<Vec<I>>
+    where
+        T: for<'a> BinRead<Args<'a> = ()> + Into<Vec<I>>,
+    {
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let restore_position = reader.stream_position()?;
+        reader.seek(SeekFrom::Start(offset))?;
+        let info = T::read_options(reader, endian, ())?;
+        reader.seek(SeekFrom::Start(restore_position))?;
+        Ok(info.into())
     }
 }
 use aci0::*;
 
-mod acid {
+pub mod acid {
+
+    use std::io::Cursor;
 
     use binrw::{VecArgs, prelude::*};
 
     use num_enum::{FromPrimitive, IntoPrimitive};
     use proc_bitfield::bitfield;
+    use serde::Serialize;
 
-    use crate::utils::{Placement, until_eob};
+    use crate::utils::{Placement, serde_helpers, serialize_bitfield, until_eob};
+
+    use super::{MAGIC_ACID, ServiceRecord, kernel_capability::KernelCapability, layout_regions, service_permitted, write_all};
 
-    use super::{MAGIC_ACID, ServiceRecord, kernel_capability::KernelCapability};
+    /// Size of the fixed ACID header, including the signature and modulus
+    const ACID_HEADER_SIZE: usize = 0x240;
+    /// Size of the signature which precedes the signed part of the ACID
+    pub(crate) const ACID_SIGNATURE_SIZE: usize = 0x100;
+
+    /// On-disk layout of the ACID header, used when writing
+    #[binwrite]
+    #[bw(little)]
+    struct RawAcidHeader<'a> {
+        signature: &'a [u8; 0x100],
+        modulus: &'a [u8; 0x100],
+        #[bw(calc = MAGIC_ACID)]
+        magic: u32,
+        size: u32,
+        version: u8,
+        #[bw(pad_after = 2)]
+        v14_plus: u8,
+        flags: u32,
+        title_id_range_min: u64,
+        title_id_range_max: u64,
+        fac_offset: u32,
+        fac_size: u32,
+        services_offset: u32,
+        services_size: u32,
+        kernel_capabilities_offset: u32,
+        #[bw(pad_after = 8)]
+        kernel_capabilities_size: u32,
+    }
+
+    impl Acid {
+        /// Whether the ACID allows connecting to `service`
+        pub fn can_access_service(&self, service: &str) -> bool {
+            service_permitted(&self.services, service, false)
+        }
+
+        /// Whether the ACID allows registering `service`
+        pub fn can_host_service(&self, service: &str) -> bool {
+            service_permitted(&self.services, service, true)
+        }
+
+        /// Serialize the ACID. The `size` field is recalculated from the contents, while the
+        /// signature and modulus are written as-is.
+        pub fn to_bytes(&self) -> BinResult<Vec<u8>> {
+            let regions = [
+                write_all(&self.file_access_control_entries)?,
+                write_all(&self.services)?,
+                write_all(&self.kernel_capabilities)?,
+            ];
+            let (placements, mut output) = layout_regions(ACID_HEADER_SIZE, &regions);
+            let header = RawAcidHeader {
+                signature: &self.signature,
+                modulus: &self.modulus,
+                size: (output.len() - ACID_SIGNATURE_SIZE) as u32,
+                version: self.version,
+                v14_plus: self.v14_plus,
+                flags: self.flags.0,
+                title_id_range_min: self.title_id_range_min,
+                title_id_range_max: self.title_id_range_max,
+                fac_offset: placements[0].0,
+                fac_size: placements[0].1,
+                services_offset: placements[1].0,
+                services_size: placements[1].1,
+                kernel_capabilities_offset: placements[2].0,
+                kernel_capabilities_size: placements[2].1,
+            };
+            header.write_le(&mut Cursor::new(&mut output[..ACID_HEADER_SIZE]))?;
+            Ok(output)
+        }
+    }
 
     #[binread]
-    #[derive(Debug)]
+    #[derive(Debug, Serialize)]
     #[br(little, assert(magic == MAGIC_ACID))]
     pub struct Acid {
         #[br(temp)]
         _cursor_position: crate::utils::CurPos,
+        #[serde(serialize_with = "serde_helpers::hex")]
         pub signature: [u8; 0x100],
+        #[serde(serialize_with = "serde_helpers::hex")]
         pub modulus: [u8; 0x100],
         pub magic: u32,
         pub size: u32,
@@ -380,8 +1218,8 @@ mod acid {
         pub kernel_capabilities: Vec<KernelCapability>,
     }
 
-    #[binread]
-    #[derive(Debug)]
+    #[binrw]
+    #[derive(Debug, Serialize)]
     pub struct AcidFsAccessControlRecord {
         pub version: u8,
         pub content_owner_id_count: u8,
@@ -399,7 +1237,7 @@ mod acid {
     }
 
     #[repr(u8)]
-    #[derive(Debug, FromPrimitive, IntoPrimitive)]
+    #[derive(Debug, Serialize, FromPrimitive, IntoPrimitive)]
     pub enum MemoryRegion {
         Application = 0,
         Applet,
@@ -412,7 +1250,7 @@ mod acid {
 
     bitfield! {
         #[derive(BinRead)]
-        pub struct AcidFlags(u32): Debug {
+        pub struct AcidFlags(pub u32): Debug {
             pub raw: u32 @ .., // access raw byte
 
             // flags
@@ -424,10 +1262,11 @@ mod acid {
             pub memory_region: u8 [MemoryRegion] @ 2..=5,
         }
     }
+    serialize_bitfield!(AcidFlags { is_production, unqualified_approval, memory_region });
 }
 use acid::*;
 
-#[derive(Debug, FromPrimitive, IntoPrimitive)]
+#[derive(Debug, Serialize, FromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 pub enum ProcessAddressSpace {
     Address32Bit,
@@ -441,7 +1280,7 @@ pub enum ProcessAddressSpace {
 
 bitfield! {
     #[derive(BinRead)]
-    pub struct NpdmHeaderFlags(u8): Debug {
+    pub struct NpdmHeaderFlags(pub u8): Debug {
         pub raw: u8 @ .., // access raw byte
 
         // flags
@@ -454,9 +1293,68 @@ bitfield! {
         pub address_space: u8 [ProcessAddressSpace] @ 1..=3,
     }
 }
+serialize_bitfield!(NpdmHeaderFlags { is_64bit, optimize_memory_allocation, disable_device_address_space_merge, address_space });
+
+/// Size of the META header
+const META_HEADER_SIZE: usize = 0x80;
+/// Alignment of the ACID, ACI0 and their sub-regions
+const NPDM_REGION_ALIGNMENT: usize = 0x10;
+
+/// Serialize each item in turn
+fn write_all<T>(items: &[T]) -> BinResult<Vec<u8>>
+where
+    T: for<'a> BinWrite<Args<'a> = ()>,
+{
+    let mut output = Cursor::new(Vec::new());
+    for item in items {
+        output.write_le(item)?;
+    }
+    Ok(output.into_inner())
+}
+
+/// Place `regions` one after another following a zeroed header of `header_size` bytes, aligning
+/// each region and the total size. Returns the (offset, size) of each region and the buffer.
+fn layout_regions(header_size: usize, regions: &[Vec<u8>]) -> (Vec<(u32, u32)>, Vec<u8>) {
+    let mut output = vec![0u8; header_size.next_multiple_of(NPDM_REGION_ALIGNMENT)];
+    let placements = regions
+        .iter()
+        .map(|region| {
+            let offset = output.len();
+            output.extend_from_slice(region);
+            output.resize(output.len().next_multiple_of(NPDM_REGION_ALIGNMENT), 0);
+            (offset as u32, region.len() as u32)
+        })
+        .collect();
+    (placements, output)
+}
+
+/// On-disk layout of the META header, used when writing
+#[binwrite]
+#[bw(little)]
+struct RawMetaHeader<'a> {
+    #[bw(calc = MAGIC_META)]
+    magic: u32,
+    #[bw(pad_after = 4)]
+    acid_sign_key_index: u32,
+    #[bw(pad_after = 1)]
+    flags: u8,
+    main_thread_priority: u8,
+    #[bw(pad_after = 4)]
+    default_cpu_core: u8,
+    system_resource_size: u32,
+    version: u32,
+    main_stack_size: u32,
+    title_name: &'a [u8; 0x10],
+    #[bw(pad_after = 0x30)]
+    product_code: &'a [u8; 0x10],
+    aci0_offset: u32,
+    aci0_size: u32,
+    acid_offset: u32,
+    acid_size: u32,
+}
 
 #[binread]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 #[br(little, magic = b"META")]
 pub struct NpdmFile {
     #[brw(pad_after = 4)]
@@ -469,8 +1367,10 @@ pub struct NpdmFile {
     pub system_resource_size: u32,
     pub version: u32,
     pub main_stack_size: u32,
+    #[serde(serialize_with = "serde_helpers::c_string")]
     pub title_name: [u8; 0x10],
     #[brw(pad_after = 0x30)]
+    #[serde(serialize_with = "serde_helpers::c_string")]
     pub product_code: [u8; 0x10],
     #[br(parse_with = FilePtr32::parse)]
     pub aci0: Aci0,
@@ -480,6 +1380,7 @@ pub struct NpdmFile {
     #[br(temp)]
     acid_size: u32,
     #[br(parse_with = Placement::parse, args {offset: acid_ptr as u64, inner: VecArgs {count: acid_size as usize, inner: ()}})]
+    #[serde(skip)]
     acid_raw: Vec<u8>,
     #[br(calc = Cursor::new(acid_raw.clone()).read_le()?)]
     pub acid: Acid,
@@ -492,32 +1393,77 @@ impl NpdmFile {
         file.read_le()
     }
 
+    /// Serialize the NPDM, placing the ACID directly after the META header followed by the ACI0
+    pub fn to_bytes(&self) -> BinResult<Vec<u8>> {
+        let acid = self.acid.to_bytes()?;
+        let aci0 = self.aci0.to_bytes()?;
+        let (placements, mut output) = layout_regions(META_HEADER_SIZE, &[acid, aci0]);
+        let header = RawMetaHeader {
+            acid_sign_key_index: self.acid_sign_key_index,
+            flags: self.flags.0,
+            main_thread_priority: self.main_thread_priority,
+            default_cpu_core: self.default_cpu_core,
+            system_resource_size: self.system_resource_size,
+            version: self.version,
+            main_stack_size: self.main_stack_size,
+            title_name: &self.title_name,
+            product_code: &self.product_code,
+            aci0_offset: placements[1].0,
+            aci0_size: placements[1].1,
+            acid_offset: placements[0].0,
+            acid_size: placements[0].1,
+        };
+        header.write_le(&mut Cursor::new(&mut output[..META_HEADER_SIZE]))?;
+        Ok(output)
+    }
+
+    /// Write the serialized NPDM, returning the number of bytes written
+    pub fn write<W: Write>(&self, writer: &mut W) -> BinResult<u64> {
+        let bytes = self.to_bytes()?;
+        writer.write_all(&bytes)?;
+        Ok(bytes.len() as u64)
+    }
+
+    /// Re-sign the ACID with an RSA-2048 key using PSS-SHA256. The ACID modulus is replaced with
+    /// the key's, so the result is written out by [`NpdmFile::write`].
+    pub fn sign_acid(&mut self, key: &RsaPrivateKey) -> BinResult<()> {
+        let modulus = key.n().to_bytes_be();
+        if modulus.len() != self.acid.modulus.len() {
+            return Err(binrw::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "ACID signing key must be RSA-2048",
+            )));
+        }
+        self.acid.modulus.copy_from_slice(&modulus);
+
+        let mut acid_raw = self.acid.to_bytes()?;
+        let signature = BlindedSigningKey::<Sha256>::new(key.clone())
+            .try_sign_with_rng(&mut OsRng, &acid_raw[ACID_SIGNATURE_SIZE..])
+            .map_err(|e| binrw::Error::Custom { pos: 0, err: Box::new(e.to_string()) })?;
+        self.acid.signature.copy_from_slice(&signature.to_bytes());
+        acid_raw[..ACID_SIGNATURE_SIZE].copy_from_slice(&self.acid.signature);
+        self.acid.size = (acid_raw.len() - ACID_SIGNATURE_SIZE) as u32;
+        self.acid_raw = acid_raw;
+        Ok(())
+    }
+
     pub fn verify_with_hex_str(
         &self,
         verification_key_modulus: String,
     ) -> Result<Validity, Validity> {
-        if let Some(modulus_bigint) =
-            rsa::BigUint::from_radix_le(verification_key_modulus.as_bytes(), 16)
-        {
-            let exponent = rsa::BigUint::from_bytes_le([1u8, 0, 1].as_slice());
-            if let Ok(rsa_pubkey) = rsa::RsaPublicKey::new(modulus_bigint, exponent) {
-                let verifying_key: VerifyingKey<Sha256> = VerifyingKey::new(rsa_pubkey);
-                let signature = Signature::try_from(self.acid.signature.as_slice()).unwrap();
-                let valid = verifying_key
-                    .verify(self.acid_raw.split_at(0x200).1, &signature)
-                    .is_ok();
-
-                return if valid {
-                    Ok(Validity::Valid)
-                } else {
-                    Ok(Validity::Invalid)
-                };
-            } else {
-                return Err(Validity::CheckError);
-            }
-        } else {
-            Err(Validity::CheckError)
-        }
+        let modulus_bigint = rsa::BigUint::from_radix_le(verification_key_modulus.as_bytes(), 16)
+            .ok_or(Validity::CheckError)?;
+        let exponent = rsa::BigUint::from_bytes_le([1u8, 0, 1].as_slice());
+        let rsa_pubkey =
+            rsa::RsaPublicKey::new(modulus_bigint, exponent).map_err(|_| Validity::CheckError)?;
+        Ok(self.verify_acid_with_key(&rsa_pubkey))
+    }
+
+    /// Verify the ACID signature against a PEM encoded public key
+    pub fn verify_acid_with_pem(&self, public_key_pem: &str) -> Result<Validity, Validity> {
+        let rsa_pubkey = crate::keys::rsa_public_key_from_bytes(public_key_pem.as_bytes())
+            .map_err(|_| Validity::CheckError)?;
+        Ok(self.verify_acid_with_key(&rsa_pubkey))
     }
 
     pub fn verify_acid(&self, key_type: KeysetType) -> Result<Validity, Validity> {
@@ -533,15 +1479,812 @@ impl NpdmFile {
         let exponent = rsa::BigUint::from_bytes_be([1, 0, 1].as_slice());
         let rsa_pubkey =
             rsa::RsaPublicKey::new(modulus_bigint, exponent).map_err(|_| Validity::CheckError)?;
-        let verifying_key: VerifyingKey<Sha256> = VerifyingKey::new(rsa_pubkey);
+        Ok(self.verify_acid_with_key(&rsa_pubkey))
+    }
+
+    /// Whether the process may connect to `service`, which requires both the ACI0 and ACID to allow it
+    pub fn can_access_service(&self, service: &str) -> bool {
+        self.aci0.can_access_service(service) && self.acid.can_access_service(service)
+    }
+
+    /// Whether the process may register `service`, which requires both the ACI0 and ACID to allow it
+    pub fn can_host_service(&self, service: &str) -> bool {
+        self.aci0.can_host_service(service) && self.acid.can_host_service(service)
+    }
+
+    /// Whether the title can access content owned by `id`. The ID must be listed in the ACI0 and
+    /// covered by the ACID.
+    pub fn can_access_content_owner(&self, id: u64) -> bool {
+        self.aci0.fs_access_control.has_content_owner(id)
+            && self
+                .acid
+                .file_access_control_entries
+                .iter()
+                .any(|record| restrictions::content_owner_allowed(id, record))
+    }
+
+    /// Accessibility the title has to save data owned by `id`, or `None` if the ACI0 doesn't list
+    /// the owner or the ACID doesn't cover it
+    pub fn save_data_owner_accessibility(&self, id: u64) -> Option<SaveDataAccessibility> {
+        let covered = self
+            .acid
+            .file_access_control_entries
+            .iter()
+            .any(|record| restrictions::save_data_owner_allowed(id, record));
+        self.aci0.fs_access_control.save_data_owner_accessibility(id).filter(|_| covered)
+    }
+
+    /// Check that everything the ACI0 requests is allowed by the ACID, as the loader does when
+    /// starting the process. Returns every violation found, so an empty list means the NPDM is valid.
+    pub fn validate_restrictions(&self) -> Vec<restrictions::RestrictionViolation> {
+        restrictions::validate(self)
+    }
+
+    /// Verify the ACID signature against an arbitrary RSA-2048 public key
+    pub fn verify_acid_with_key(&self, public_key: &RsaPublicKey) -> Validity {
+        let verifying_key: VerifyingKey<Sha256> = VerifyingKey::new(public_key.clone());
         let signature = Signature::try_from(self.acid.signature.as_slice()).expect("try_from with a slice always succeeds.");
-        let data = self.acid_raw.split_at(0x100).1;
-        let validity = verifying_key.verify(data, &signature);
-        
-        return if validity.is_ok() {
-            Ok(Validity::Valid)
+        let data = self.acid_raw.split_at(ACID_SIGNATURE_SIZE).1;
+
+        if verifying_key.verify(data, &signature).is_ok() {
+            Validity::Valid
         } else {
-            Ok(Validity::Invalid)
-        };
+            Validity::Invalid
+        }
+    }
+}
+
+/// Validation of the ACI0 against the restrictions in the ACID
+pub mod restrictions {
+    use std::fmt;
+
+    use super::{
+        FsAccessFlags, NpdmFile,
+        acid::AcidFsAccessControlRecord,
+        kernel_capability::{KernelCapability, MemoryMapPermission, Syscall, allowed_syscalls},
+    };
+
+    /// Interrupt number marking an unused interrupt slot
+    const IRQ_NONE: u16 = 0x3FF;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum RestrictionViolation {
+        TitleIdOutOfRange { title_id: u64, min: u64, max: u64 },
+        /// FS permissions requested by the ACI0 which the ACID doesn't grant
+        FsAccessNotAllowed { flags: u64 },
+        ContentOwnerIdNotAllowed(u64),
+        SaveDataOwnerIdNotAllowed(u64),
+        ServiceNotAllowed { name: String, is_server: bool },
+        KernelCapabilityNotAllowed(String),
+    }
+
+    impl fmt::Display for RestrictionViolation {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::TitleIdOutOfRange { title_id, min, max } => {
+                    write!(f, "title ID {title_id:016X} is outside the ACID range {min:016X}-{max:016X}")
+                }
+                Self::FsAccessNotAllowed { flags } => {
+                    let names: Vec<_> = FsAccessFlags(*flags).enabled_names().collect();
+                    write!(f, "FS permissions {flags:#018X} are not granted by the ACID ({})", names.join(", "))
+                }
+                Self::ContentOwnerIdNotAllowed(id) => write!(f, "content owner ID {id:016X} is not allowed by the ACID"),
+                Self::SaveDataOwnerIdNotAllowed(id) => write!(f, "save data owner ID {id:016X} is not allowed by the ACID"),
+                Self::ServiceNotAllowed { name, is_server } => {
+                    let kind = if *is_server { "hosting" } else { "accessing" };
+                    write!(f, "{kind} service \"{name}\" is not allowed by the ACID")
+                }
+                Self::KernelCapabilityNotAllowed(reason) => f.write_str(reason),
+            }
+        }
+    }
+
+    pub(super) fn validate(npdm: &NpdmFile) -> Vec<RestrictionViolation> {
+        let (aci0, acid) = (&npdm.aci0, &npdm.acid);
+        let mut violations = Vec::new();
+
+        if !(acid.title_id_range_min..=acid.title_id_range_max).contains(&aci0.title_id) {
+            violations.push(RestrictionViolation::TitleIdOutOfRange {
+                title_id: aci0.title_id,
+                min: acid.title_id_range_min,
+                max: acid.title_id_range_max,
+            });
+        }
+
+        let fac = &acid.file_access_control_entries;
+        let granted = fac.iter().fold(0, |flags, record| flags | record.access_flags.raw());
+        let requested = aci0.fs_access_control.access_flags.raw();
+        if requested & !granted != 0 {
+            violations.push(RestrictionViolation::FsAccessNotAllowed { flags: requested & !granted });
+        }
+        for &id in &aci0.fs_access_control.content_owner_ids {
+            if !fac.iter().any(|record| content_owner_allowed(id, record)) {
+                violations.push(RestrictionViolation::ContentOwnerIdNotAllowed(id));
+            }
+        }
+        for owner in &aci0.fs_access_control.save_data_owners {
+            if !fac.iter().any(|record| save_data_owner_allowed(owner.id, record)) {
+                violations.push(RestrictionViolation::SaveDataOwnerIdNotAllowed(owner.id));
+            }
+        }
+
+        for service in &aci0.services {
+            if !acid.services.iter().any(|pattern| pattern.is_server == service.is_server && pattern.matches(&service.name)) {
+                violations.push(RestrictionViolation::ServiceNotAllowed {
+                    name: service.name.clone(),
+                    is_server: service.is_server,
+                });
+            }
+        }
+
+        for capability in &aci0.kernel_capabilities {
+            violations.extend(
+                validate_kernel_capability(capability, &acid.kernel_capabilities)
+                    .into_iter()
+                    .map(RestrictionViolation::KernelCapabilityNotAllowed),
+            );
+        }
+        violations
+    }
+
+    /// Owner IDs are allowed if listed explicitly or if they fall in the (non-empty) ID range
+    fn owner_allowed(id: u64, ids: &[u64], min: u64, max: u64) -> bool {
+        ids.contains(&id) || ((min, max) != (0, 0) && (min..=max).contains(&id))
+    }
+
+    pub(super) fn content_owner_allowed(id: u64, record: &AcidFsAccessControlRecord) -> bool {
+        owner_allowed(id, &record.content_owner_ids, record.content_owner_id_min, record.content_owner_id_max)
+    }
+
+    pub(super) fn save_data_owner_allowed(id: u64, record: &AcidFsAccessControlRecord) -> bool {
+        owner_allowed(id, &record.save_data_owner_ids, record.save_data_owner_min, record.save_data_owner_max)
+    }
+
+    /// Check a single ACI0 kernel capability against the ACID ones, describing each problem found
+    fn validate_kernel_capability(capability: &KernelCapability, acid: &[KernelCapability]) -> Vec<String> {
+        let mut problems = Vec::new();
+        match capability {
+            KernelCapability::ThreadInfo(info) => {
+                let Some(allowed) = acid.iter().find_map(|c| match c {
+                    KernelCapability::ThreadInfo(allowed) => Some(allowed),
+                    _ => None,
+                }) else {
+                    return vec!["thread info is not present in the ACID".to_string()];
+                };
+                // Bits 4..=9 hold the numerically largest priority and bits 10..=15 the smallest
+                if info.hightest_thread_priority() < allowed.hightest_thread_priority()
+                    || info.lowest_thread_priority() > allowed.lowest_thread_priority()
+                {
+                    problems.push(format!(
+                        "thread priorities {}-{} are outside the ACID range {}-{}",
+                        info.hightest_thread_priority(),
+                        info.lowest_thread_priority(),
+                        allowed.hightest_thread_priority(),
+                        allowed.lowest_thread_priority()
+                    ));
+                }
+                if info.lowest_cpu_id() < allowed.lowest_cpu_id() || info.highest_cpu_id() > allowed.highest_cpu_id() {
+                    problems.push(format!(
+                        "CPU cores {}-{} are outside the ACID range {}-{}",
+                        info.lowest_cpu_id(),
+                        info.highest_cpu_id(),
+                        allowed.lowest_cpu_id(),
+                        allowed.highest_cpu_id()
+                    ));
+                }
+            }
+            KernelCapability::EnableSystemCalls(calls) => {
+                let allowed = allowed_syscalls(acid);
+                for syscall in calls.ids().map(Syscall::new).filter(|syscall| !allowed.contains(syscall)) {
+                    problems.push(format!("syscall {} is not enabled in the ACID", syscall));
+                }
+            }
+            KernelCapability::MemoryMap(address, size) => {
+                let start = (address.start_address() as u64) << 12;
+                let end = start + ((size.size() as u64) << 12);
+                let covered = acid.iter().any(|c| match c {
+                    KernelCapability::MemoryMap(allowed_address, allowed_size) => {
+                        let allowed_start = (allowed_address.start_address() as u64) << 12;
+                        let allowed_end = allowed_start + ((allowed_size.size() as u64) << 12);
+                        allowed_address.memory_permission() == address.memory_permission()
+                            && allowed_size.map_type() == size.map_type()
+                            && allowed_start <= start
+                            && end <= allowed_end
+                    }
+                    _ => false,
+                });
+                if !covered {
+                    let permission = if address.memory_permission() == MemoryMapPermission::Ro { "RO" } else { "RW" };
+                    problems.push(format!(
+                        "{:?} memory map {start:#X}-{end:#X} ({permission}) is not allowed by the ACID",
+                        size.map_type()
+                    ));
+                }
+            }
+            KernelCapability::IoMemoryMap(map) => {
+                let allowed = acid.iter().any(|c| {
+                    matches!(c, KernelCapability::IoMemoryMap(allowed) if allowed.start_address() == map.start_address())
+                });
+                if !allowed {
+                    problems.push(format!(
+                        "IO page {:#X} is not mapped in the ACID",
+                        (map.start_address() as u64) << 12
+                    ));
+                }
+            }
+            KernelCapability::MemoryRegionMap(map) => {
+                let regions = |raw: u32| (0..3).map(move |i| (((raw >> (11 + 7 * i)) & 0x3F) as u8, raw >> (17 + 7 * i) & 1 != 0));
+                for (region_type, is_ro) in regions(map.raw()).filter(|(region_type, _)| *region_type != 0) {
+                    let allowed = acid.iter().any(|c| match c {
+                        KernelCapability::MemoryRegionMap(allowed) => regions(allowed.raw())
+                            .any(|(allowed_type, allowed_ro)| allowed_type == region_type && (is_ro || !allowed_ro)),
+                        _ => false,
+                    });
+                    if !allowed {
+                        problems.push(format!("memory region {region_type} is not mapped in the ACID"));
+                    }
+                }
+            }
+            KernelCapability::EnableInterrupts(interrupts) => {
+                for irq in [interrupts.irq1(), interrupts.irq2()].into_iter().filter(|&irq| irq != IRQ_NONE) {
+                    let allowed = acid.iter().any(|c| {
+                        matches!(c, KernelCapability::EnableInterrupts(allowed) if allowed.irq1() == irq || allowed.irq2() == irq)
+                    });
+                    if !allowed {
+                        problems.push(format!("interrupt {irq} is not enabled in the ACID"));
+                    }
+                }
+            }
+            KernelCapability::MiscParams(params) => {
+                if !acid.iter().any(|c| matches!(c, KernelCapability::MiscParams(allowed) if allowed.raw() == params.raw())) {
+                    problems.push(format!("application type {:?} does not match the ACID", params.program_type()));
+                }
+            }
+            KernelCapability::KernelVersion(version) => {
+                if !acid.iter().any(|c| matches!(c, KernelCapability::KernelVersion(allowed) if allowed.raw() == version.raw())) {
+                    problems.push(format!(
+                        "kernel version {}.{} does not match the ACID",
+                        version.major_version(),
+                        version.minor_version()
+                    ));
+                }
+            }
+            KernelCapability::HandleTableSize(size) => {
+                let allowed = acid.iter().find_map(|c| match c {
+                    KernelCapability::HandleTableSize(allowed) => Some(allowed.table_size()),
+                    _ => None,
+                });
+                if allowed.is_none_or(|allowed| size.table_size() > allowed) {
+                    problems.push(format!("handle table size {} exceeds the ACID limit", size.table_size()));
+                }
+            }
+            KernelCapability::MiscFlags(flags) => {
+                let allowed = acid.iter().find_map(|c| match c {
+                    KernelCapability::MiscFlags(allowed) => Some(allowed),
+                    _ => None,
+                });
+                let (allow_debug, force_debug) = allowed.map_or((false, false), |a| (a.enable_debug(), a.force_debug()));
+                if (flags.enable_debug() && !allow_debug) || (flags.force_debug() && !force_debug) {
+                    problems.push("debug flags are not allowed by the ACID".to_string());
+                }
+            }
+            KernelCapability::Invalid(raw) => problems.push(format!("unknown kernel capability {raw:#010X}")),
+        }
+        problems
+    }
+}
+
+/// NPDM JSON descriptors in the format used by npdmtool and Atmosphère
+pub mod descriptor {
+    use std::{collections::BTreeMap, io::Cursor};
+
+    use binrw::{BinReaderExt, BinResult};
+    use serde::{Deserialize, Serialize};
+
+    use super::{
+        Aci0, Aci0FsAccessControlRecord, Acid, AcidFlags, AcidFsAccessControlRecord, FsAccessFlags, MAGIC_ACI0,
+        MAGIC_ACID, NpdmFile, NpdmHeaderFlags, SaveDataAccessibility, SaveDataOwner, ServiceRecord,
+        kernel_capability::{
+            HandleTableSize, Interrupts, IoMemoryMap, KernelCapability, KernelVersion, MemoryMapAddrRo,
+            MemoryMapSizeType, MemoryMapType, MemoryRegionMap, MiscFlags, MiscParams, SYSCALLS_PER_CAPABILITY,
+            SystemCalls, ThreadInfo, syscall_name,
+        },
+    };
+
+    /// Highest syscall id which can be encoded in a kernel capability
+    const MAX_SYSCALL_ID: u32 = SYSCALLS_PER_CAPABILITY * 8 - 1;
+    /// Interrupt number marking an unused interrupt slot
+    const IRQ_NONE: u16 = 0x3FF;
+    /// Number of regions in a map region capability
+    const MAP_REGION_COUNT: usize = 3;
+    /// Version written to the ACID FAC and the ACI0 FAH
+    const FS_ACCESS_CONTROL_VERSION: u8 = 1;
+    const PAGE_SIZE: u64 = 0x1000;
+
+    /// A number written as a hex string, which also accepts plain JSON numbers when read
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct HexNumber(pub u64);
+
+    impl Serialize for HexNumber {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(&format_args!("{:#X}", self.0))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for HexNumber {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum Raw {
+                Number(u64),
+                String(String),
+            }
+
+            match Raw::deserialize(deserializer)? {
+                Raw::Number(value) => Ok(Self(value)),
+                Raw::String(value) => {
+                    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+                        Some(hex) => u64::from_str_radix(hex, 16),
+                        None => value.parse(),
+                    };
+                    parsed
+                        .map(Self)
+                        .map_err(|_| serde::de::Error::custom(format!("invalid number \"{value}\"")))
+                }
+            }
+        }
+    }
+
+    /// `with` helpers for integer fields stored as [`HexNumber`]s
+    mod hex_number {
+        use serde::{Deserialize, Serialize};
+
+        use super::HexNumber;
+
+        pub fn serialize<S: serde::Serializer, T: Copy + Into<u64>>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+            HexNumber((*value).into()).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: serde::Deserializer<'de>, T: TryFrom<u64>>(deserializer: D) -> Result<T, D::Error> {
+            let HexNumber(value) = HexNumber::deserialize(deserializer)?;
+            T::try_from(value).map_err(|_| serde::de::Error::custom(format!("{value:#X} is out of range")))
+        }
+    }
+
+    /// `with` helpers for IDs and flags which are written with all 16 digits
+    mod hex_u64 {
+        pub fn serialize<S: serde::Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(&format_args!("{value:#018X}"))
+        }
+
+        pub use super::hex_number::deserialize;
+    }
+
+    /// `with` helpers for lists of IDs, written like [`hex_u64`]
+    mod hex_u64_list {
+        use serde::{Deserialize, ser::SerializeSeq};
+
+        use super::HexNumber;
+
+        pub fn serialize<S: serde::Serializer>(values: &[u64], serializer: S) -> Result<S::Ok, S::Error> {
+            let mut seq = serializer.serialize_seq(Some(values.len()))?;
+            for value in values {
+                seq.serialize_element(&format!("{value:#018X}"))?;
+            }
+            seq.end()
+        }
+
+        pub fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<u64>, D::Error> {
+            Ok(Vec::<HexNumber>::deserialize(deserializer)?.into_iter().map(|HexNumber(value)| value).collect())
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct NpdmDescriptor {
+        pub name: String,
+        #[serde(alias = "program_id", with = "hex_u64")]
+        pub title_id: u64,
+        #[serde(alias = "program_id_range_min", with = "hex_u64")]
+        pub title_id_range_min: u64,
+        #[serde(alias = "program_id_range_max", with = "hex_u64")]
+        pub title_id_range_max: u64,
+        #[serde(with = "hex_number")]
+        pub main_thread_stack_size: u32,
+        pub main_thread_priority: u8,
+        pub default_cpu_id: u8,
+        #[serde(default)]
+        pub version: u32,
+        #[serde(default, with = "hex_number")]
+        pub system_resource_size: u32,
+        /// Index of the fixed key used to sign the ACID
+        #[serde(default)]
+        pub signature_key_generation: u32,
+        pub is_retail: bool,
+        pub pool_partition: u8,
+        pub is_64_bit: bool,
+        pub address_space_type: u8,
+        #[serde(default)]
+        pub optimize_memory_allocation: bool,
+        #[serde(default)]
+        pub disable_device_address_space_merge: bool,
+        pub filesystem_access: FilesystemAccessDescriptor,
+        #[serde(default)]
+        pub service_access: Vec<String>,
+        #[serde(default)]
+        pub service_host: Vec<String>,
+        #[serde(default)]
+        pub kernel_capabilities: Vec<KernelCapabilityDescriptor>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct FilesystemAccessDescriptor {
+        #[serde(with = "hex_u64")]
+        pub permissions: u64,
+        /// Owners of the content the title may access
+        #[serde(default, with = "hex_u64_list")]
+        pub content_owner_ids: Vec<u64>,
+        /// Owners of the save data the title may access, with the access granted to each
+        #[serde(default)]
+        pub save_data_owners: Vec<SaveDataOwnerDescriptor>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct SaveDataOwnerDescriptor {
+        #[serde(with = "hex_u64")]
+        pub id: u64,
+        pub accessibility: SaveDataAccessibility,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(tag = "type", content = "value", rename_all = "snake_case")]
+    pub enum KernelCapabilityDescriptor {
+        KernelFlags(KernelFlagsDescriptor),
+        /// Syscall ids keyed by name. Only the ids are encoded in the NPDM.
+        Syscalls(BTreeMap<String, HexNumber>),
+        Map(MapDescriptor),
+        MapPage(#[serde(with = "hex_number")] u64),
+        MapRegion(Vec<MapRegionDescriptor>),
+        IrqPair([Option<u16>; 2]),
+        ApplicationType(u8),
+        MinKernelVersion(#[serde(with = "hex_number")] u32),
+        HandleTableSize(u16),
+        DebugFlags(DebugFlagsDescriptor),
+    }
+
+    /// Thread priority and core ranges. As in npdmtool, `highest_thread_priority` is the numerically
+    /// largest (least urgent) priority the process may use.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct KernelFlagsDescriptor {
+        pub highest_thread_priority: u8,
+        pub lowest_thread_priority: u8,
+        pub lowest_cpu_id: u8,
+        pub highest_cpu_id: u8,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct MapDescriptor {
+        #[serde(with = "hex_number")]
+        pub address: u64,
+        #[serde(with = "hex_number")]
+        pub size: u64,
+        pub is_ro: bool,
+        pub is_io: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct MapRegionDescriptor {
+        pub region_type: u8,
+        pub is_ro: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct DebugFlagsDescriptor {
+        pub allow_debug: bool,
+        pub force_debug: bool,
+    }
+
+    fn invalid_input(message: String) -> binrw::Error {
+        binrw::Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, message))
+    }
+
+    fn ensure(condition: bool, message: impl FnOnce() -> String) -> BinResult<()> {
+        if condition { Ok(()) } else { Err(invalid_input(message())) }
+    }
+
+    impl KernelCapabilityDescriptor {
+        /// Encode into kernel capabilities. Syscalls expand to one capability per group of 24 ids.
+        pub fn to_kernel_capabilities(&self) -> BinResult<Vec<KernelCapability>> {
+            Ok(match self {
+                Self::KernelFlags(flags) => {
+                    ensure(flags.highest_thread_priority <= 0x3F && flags.lowest_thread_priority <= 0x3F, || {
+                        "thread priorities must be at most 0x3F".to_string()
+                    })?;
+                    vec![KernelCapability::ThreadInfo(
+                        ThreadInfo(0b111)
+                            .with_lowest_thread_priority(flags.highest_thread_priority)
+                            .with_hightest_thread_priority(flags.lowest_thread_priority)
+                            .with_lowest_cpu_id(flags.lowest_cpu_id)
+                            .with_highest_cpu_id(flags.highest_cpu_id),
+                    )]
+                }
+                Self::Syscalls(syscalls) => {
+                    let mut masks = BTreeMap::new();
+                    for (name, &HexNumber(id)) in syscalls {
+                        ensure(id <= MAX_SYSCALL_ID as u64, || format!("syscall {name} has an invalid id {id:#X}"))?;
+                        let id = id as u32;
+                        *masks.entry(id / SYSCALLS_PER_CAPABILITY).or_insert(0u32) |=
+                            1 << (id % SYSCALLS_PER_CAPABILITY);
+                    }
+                    masks
+                        .into_iter()
+                        .map(|(index, mask)| {
+                            KernelCapability::EnableSystemCalls(SystemCalls(0b1111 | mask << 5 | index << 29))
+                        })
+                        .collect()
+                }
+                Self::Map(map) => {
+                    ensure(map.address % PAGE_SIZE == 0 && map.address >> 12 <= 0xFF_FFFF, || {
+                        format!("invalid map address {:#X}", map.address)
+                    })?;
+                    ensure(map.size % PAGE_SIZE == 0 && map.size >> 12 <= 0xF_FFFF, || {
+                        format!("invalid map size {:#X}", map.size)
+                    })?;
+                    let address = 0x3F | ((map.address >> 12) as u32) << 7 | (map.is_ro as u32) << 31;
+                    let size = 0x3F | ((map.size >> 12) as u32) << 7 | (!map.is_io as u32) << 31;
+                    vec![KernelCapability::MemoryMap(MemoryMapAddrRo(address), MemoryMapSizeType(size))]
+                }
+                Self::MapPage(address) => {
+                    ensure(address % PAGE_SIZE == 0 && address >> 12 <= 0xFF_FFFF, || {
+                        format!("invalid map page address {address:#X}")
+                    })?;
+                    vec![KernelCapability::IoMemoryMap(IoMemoryMap(0x7F | ((address >> 12) as u32) << 8))]
+                }
+                Self::MapRegion(regions) => {
+                    ensure(regions.len() <= MAP_REGION_COUNT, || {
+                        format!("at most {MAP_REGION_COUNT} regions can be mapped per capability")
+                    })?;
+                    let mut raw = 0x3FF;
+                    for (i, region) in regions.iter().enumerate() {
+                        ensure(region.region_type <= 0x3F, || format!("invalid region type {}", region.region_type))?;
+                        raw |= (region.region_type as u32 | (region.is_ro as u32) << 6) << (11 + 7 * i);
+                    }
+                    vec![KernelCapability::MemoryRegionMap(MemoryRegionMap(raw))]
+                }
+                Self::IrqPair(irqs) => {
+                    let [irq0, irq1] = irqs.map(|irq| irq.unwrap_or(IRQ_NONE));
+                    ensure(irq0 <= IRQ_NONE && irq1 <= IRQ_NONE, || format!("invalid irq pair {irqs:?}"))?;
+                    vec![KernelCapability::EnableInterrupts(Interrupts(
+                        0x7FF | (irq0 as u32) << 12 | (irq1 as u32) << 22,
+                    ))]
+                }
+                Self::ApplicationType(program_type) => {
+                    ensure(*program_type <= 7, || format!("invalid application type {program_type}"))?;
+                    vec![KernelCapability::MiscParams(MiscParams(0x1FFF | (*program_type as u32) << 14))]
+                }
+                Self::MinKernelVersion(version) => {
+                    ensure(*version <= 0x1FFFF, || format!("invalid kernel version {version:#X}"))?;
+                    vec![KernelCapability::KernelVersion(KernelVersion(0x3FFF | version << 15))]
+                }
+                Self::HandleTableSize(size) => {
+                    ensure(*size <= 0x3FF, || format!("invalid handle table size {size}"))?;
+                    vec![KernelCapability::HandleTableSize(HandleTableSize(0x7FFF | (*size as u32) << 16))]
+                }
+                Self::DebugFlags(flags) => vec![KernelCapability::MiscFlags(
+                    MiscFlags(0xFFFF).with_enable_debug(flags.allow_debug).with_force_debug(flags.force_debug),
+                )],
+            })
+        }
+
+        /// Decode a list of kernel capabilities, merging all syscall capabilities into one entry.
+        /// Invalid capabilities have no descriptor and are skipped.
+        pub fn from_kernel_capabilities(capabilities: &[KernelCapability]) -> Vec<Self> {
+            let mut descriptors = Vec::new();
+            let mut syscalls: Option<usize> = None;
+            for capability in capabilities {
+                let descriptor = match capability {
+                    KernelCapability::ThreadInfo(info) => Self::KernelFlags(KernelFlagsDescriptor {
+                        highest_thread_priority: info.lowest_thread_priority(),
+                        lowest_thread_priority: info.hightest_thread_priority(),
+                        lowest_cpu_id: info.lowest_cpu_id(),
+                        highest_cpu_id: info.highest_cpu_id(),
+                    }),
+                    KernelCapability::EnableSystemCalls(calls) => {
+                        let index = *syscalls.get_or_insert_with(|| {
+                            descriptors.push(Self::Syscalls(BTreeMap::new()));
+                            descriptors.len() - 1
+                        });
+                        let Self::Syscalls(map) = &mut descriptors[index] else { unreachable!() };
+                        for id in calls.ids() {
+                            let name = syscall_name(id).map_or_else(|| format!("svc{id:#04X}"), str::to_string);
+                            map.insert(name, HexNumber(id as u64));
+                        }
+                        continue;
+                    }
+                    KernelCapability::MemoryMap(address, size) => Self::Map(MapDescriptor {
+                        address: (address.start_address() as u64) << 12,
+                        size: (size.size() as u64) << 12,
+                        is_ro: address.raw() >> 31 != 0,
+                        is_io: size.map_type() == MemoryMapType::Io,
+                    }),
+                    KernelCapability::IoMemoryMap(map) => Self::MapPage((map.start_address() as u64) << 12),
+                    KernelCapability::MemoryRegionMap(map) => {
+                        let mut regions: Vec<_> = (0..MAP_REGION_COUNT)
+                            .map(|i| {
+                                let region = (map.raw() >> (11 + 7 * i)) & 0x7F;
+                                MapRegionDescriptor { region_type: (region & 0x3F) as u8, is_ro: region >> 6 != 0 }
+                            })
+                            .collect();
+                        while regions.last().is_some_and(|region| region.region_type == 0 && !region.is_ro) {
+                            regions.pop();
+                        }
+                        Self::MapRegion(regions)
+                    }
+                    KernelCapability::EnableInterrupts(irqs) => Self::IrqPair(
+                        [irqs.irq1(), irqs.irq2()].map(|irq| if irq == IRQ_NONE { None } else { Some(irq) }),
+                    ),
+                    KernelCapability::MiscParams(params) => Self::ApplicationType(((params.raw() >> 14) & 7) as u8),
+                    KernelCapability::KernelVersion(version) => Self::MinKernelVersion(version.raw() >> 15),
+                    KernelCapability::HandleTableSize(size) => Self::HandleTableSize(size.table_size()),
+                    KernelCapability::MiscFlags(flags) => Self::DebugFlags(DebugFlagsDescriptor {
+                        allow_debug: flags.enable_debug(),
+                        force_debug: flags.force_debug(),
+                    }),
+                    KernelCapability::Invalid(_) => continue,
+                };
+                descriptors.push(descriptor);
+            }
+            descriptors
+        }
+    }
+
+    impl NpdmDescriptor {
+        pub fn from_json(json: &str) -> serde_json::Result<Self> {
+            serde_json::from_str(json)
+        }
+
+        pub fn to_json(&self) -> serde_json::Result<String> {
+            serde_json::to_string_pretty(self)
+        }
+
+        /// Describe an NPDM. Services and kernel capabilities are taken from the ACI0.
+        pub fn from_npdm(npdm: &NpdmFile) -> Self {
+            let name_end = npdm.title_name.iter().position(|&b| b == 0).unwrap_or(npdm.title_name.len());
+            let (service_host, service_access) = npdm.aci0.services.iter().partition::<Vec<_>, _>(|s| s.is_server);
+            let service_names = |services: Vec<&ServiceRecord>| services.iter().map(|s| s.name.clone()).collect();
+            Self {
+                name: String::from_utf8_lossy(&npdm.title_name[..name_end]).into_owned(),
+                title_id: npdm.aci0.title_id,
+                title_id_range_min: npdm.acid.title_id_range_min,
+                title_id_range_max: npdm.acid.title_id_range_max,
+                main_thread_stack_size: npdm.main_stack_size,
+                main_thread_priority: npdm.main_thread_priority,
+                default_cpu_id: npdm.default_cpu_core,
+                version: npdm.version,
+                system_resource_size: npdm.system_resource_size,
+                signature_key_generation: npdm.acid_sign_key_index,
+                is_retail: npdm.acid.flags.is_production(),
+                pool_partition: ((npdm.acid.flags.raw() >> 2) & 0xF) as u8,
+                is_64_bit: npdm.flags.is_64bit(),
+                address_space_type: npdm.flags.address_space_raw(),
+                optimize_memory_allocation: npdm.flags.optimize_memory_allocation(),
+                disable_device_address_space_merge: npdm.flags.disable_device_address_space_merge(),
+                filesystem_access: FilesystemAccessDescriptor {
+                    permissions: npdm.aci0.fs_access_control.access_flags.raw(),
+                    content_owner_ids: npdm.aci0.fs_access_control.content_owner_ids.clone(),
+                    save_data_owners: npdm
+                        .aci0
+                        .fs_access_control
+                        .save_data_owners
+                        .iter()
+                        .map(|owner| SaveDataOwnerDescriptor { id: owner.id, accessibility: owner.accessibility })
+                        .collect(),
+                },
+                service_access: service_names(service_access),
+                service_host: service_names(service_host),
+                kernel_capabilities: KernelCapabilityDescriptor::from_kernel_capabilities(&npdm.aci0.kernel_capabilities),
+            }
+        }
+
+        /// Build an unsigned NPDM. The ACID and ACI0 share the services and kernel capabilities.
+        pub fn to_npdm(&self) -> BinResult<NpdmFile> {
+            ensure(self.name.len() <= 0x10, || format!("name \"{}\" is longer than 0x10 bytes", self.name))?;
+            ensure(self.pool_partition <= 0xF, || format!("invalid pool partition {}", self.pool_partition))?;
+            ensure(self.address_space_type <= 7, || format!("invalid address space type {}", self.address_space_type))?;
+
+            let mut title_name = [0u8; 0x10];
+            title_name[..self.name.len()].copy_from_slice(self.name.as_bytes());
+            let services = self
+                .service_access
+                .iter()
+                .map(|name| (name, false))
+                .chain(self.service_host.iter().map(|name| (name, true)))
+                .map(|(name, is_server)| {
+                    ServiceRecord::new(name, is_server).ok_or_else(|| {
+                        invalid_input(format!("service name \"{name}\" must be 1 to {} bytes", ServiceRecord::MAX_NAME_LEN))
+                    })
+                })
+                .collect::<BinResult<Vec<_>>>()?;
+            let kernel_capabilities = self
+                .kernel_capabilities
+                .iter()
+                .map(KernelCapabilityDescriptor::to_kernel_capabilities)
+                .collect::<BinResult<Vec<_>>>()?
+                .concat();
+            let access_flags = FsAccessFlags(self.filesystem_access.permissions);
+            let content_owner_ids = self.filesystem_access.content_owner_ids.clone();
+            let save_data_owners: Vec<_> = self
+                .filesystem_access
+                .save_data_owners
+                .iter()
+                .map(|owner| SaveDataOwner { id: owner.id, accessibility: owner.accessibility })
+                .collect();
+            ensure(content_owner_ids.len() <= u8::MAX as usize && save_data_owners.len() <= u8::MAX as usize, || {
+                "at most 255 content and save data owners can be listed".to_string()
+            })?;
+
+            let acid = Acid {
+                signature: [0; 0x100],
+                modulus: [0; 0x100],
+                magic: MAGIC_ACID,
+                size: 0,
+                version: 0,
+                v14_plus: 0,
+                flags: AcidFlags(self.is_retail as u32 | (self.pool_partition as u32) << 2),
+                title_id_range_min: self.title_id_range_min,
+                title_id_range_max: self.title_id_range_max,
+                file_access_control_entries: vec![AcidFsAccessControlRecord {
+                    version: FS_ACCESS_CONTROL_VERSION,
+                    content_owner_id_count: content_owner_ids.len() as u8,
+                    save_data_owner_id_count: save_data_owners.len() as u8,
+                    access_flags,
+                    content_owner_id_min: 0,
+                    content_owner_id_max: 0,
+                    save_data_owner_min: 0,
+                    save_data_owner_max: 0,
+                    content_owner_ids: content_owner_ids.clone(),
+                    save_data_owner_ids: save_data_owners.iter().map(|owner| owner.id).collect(),
+                }],
+                services: services.clone(),
+                kernel_capabilities: kernel_capabilities.clone(),
+            };
+            let aci0 = Aci0 {
+                magic: MAGIC_ACI0,
+                _0x4: [0; 0xC],
+                title_id: self.title_id,
+                _0x18: 0,
+                fah_offset: 0,
+                fah_size: 0,
+                fs_access_control: Aci0FsAccessControlRecord {
+                    version: FS_ACCESS_CONTROL_VERSION,
+                    access_flags,
+                    content_owner_ids,
+                    save_data_owners,
+                },
+                services,
+                kernel_capabilities,
+                _padding: 0,
+            };
+            let npdm = NpdmFile {
+                acid_sign_key_index: self.signature_key_generation,
+                flags: NpdmHeaderFlags(0)
+                    .with_is_64bit(self.is_64_bit)
+                    .with_address_space_raw(self.address_space_type)
+                    .with_optimize_memory_allocation(self.optimize_memory_allocation)
+                    .with_disable_device_address_space_merge(self.disable_device_address_space_merge),
+                main_thread_priority: self.main_thread_priority,
+                default_cpu_core: self.default_cpu_id,
+                system_resource_size: self.system_resource_size,
+                version: self.version,
+                main_stack_size: self.main_thread_stack_size,
+                title_name,
+                product_code: [0; 0x10],
+                aci0,
+                aci0_size: 0,
+                acid_raw: Vec::new(),
+                acid,
+            };
+            // Parse the serialized file back so offsets, sizes and the raw ACID are consistent
+            Cursor::new(npdm.to_bytes()?).read_le()
+        }
     }
 }
@@ -0,0 +1,251 @@
+use std::fs::File;
+use std::io::{Cursor, Error, ErrorKind, Result, SeekFrom, Write};
+use std::path::Path;
+
+use binrw::prelude::*;
+use serde::Serialize;
+
+use super::elf::ModuleImage;
+use super::nacp::Nacp;
+use super::romfs::RomFs;
+use crate::utils::{read_restore, read_restore_into, read_up_to, serde_helpers, CurPos, ReadSeek, ReaderType};
+
+/// Offset of the NRO header, after the entry point and MOD0 offset
+pub const NRO_HEADER_OFFSET: u64 = 0x10;
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct Nro {
+    /// Embed current cursor position since the NRO may be embedded in another container
+    #[br(temp)] cursor_position: CurPos,
+    /// Offset of the MOD0 header from the start of the NRO
+    #[br(pad_before = 4, pad_after = 8)]
+    pub mod0_offset: u32,
+    pub header: NroHeader,
+    /// Asset section appended after the executable, present in most homebrew
+    #[br(parse_with = parse_assets, args(cursor_position.0 + u64::from(header.size)))]
+    pub assets: Option<AssetHeader>,
+}
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"NRO0")]
+pub struct NroHeader {
+    pub version: u32,
+    /// Size of the executable image, which is also the offset of the asset section
+    pub size: u32,
+    pub flags: u32,
+    /// .text, .rodata and .data. Segments are stored uncompressed at their memory offsets.
+    pub segments: [NroSegment; 3],
+    pub bss_size: u32,
+    #[br(pad_before = 4)]
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub module_id: [u8; 0x20],
+    #[br(pad_after = 4)]
+    pub dso_handle_offset: u32,
+    /// Offsets within .rodata
+    pub api_info: NroSegment,
+    pub dynstr: NroSegment,
+    pub dynsym: NroSegment,
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[br(little)]
+pub struct NroSegment {
+    pub offset: u32,
+    pub size: u32,
+}
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"ASET")]
+pub struct AssetHeader {
+    #[br(temp)] cursor_position: CurPos,
+    pub version: u32,
+    #[br(args(cursor_position.0 - 4))]
+    pub icon: AssetSection,
+    #[br(args(cursor_position.0 - 4))]
+    pub nacp: AssetSection,
+    #[br(args(cursor_position.0 - 4))]
+    pub romfs: AssetSection,
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[br(little, import(asset_start: u64))]
+pub struct AssetSection {
+    /// Offset of the section from the asset header
+    #[br(temp)] relative_offset: u64,
+    /// Absolute offset of the section in the underlying reader
+    #[br(calc = asset_start + relative_offset)]
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl AssetSection {
+    pub fn is_present(&self) -> bool {
+        self.size != 0
+    }
+}
+
+/// Read the asset header at an absolute offset, or nothing if there is no `ASET` magic there
+#[binrw::parser(reader, endian)]
+fn parse_assets(offset: u64) -> BinResult<Option<AssetHeader>> {
+    let restore_position = reader.stream_position()?;
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut magic = [0u8; 4];
+    let assets = if read_up_to(reader, &mut magic)? == magic.len() && &magic == b"ASET" {
+        reader.seek(SeekFrom::Start(offset))?;
+        Some(AssetHeader::read_options(reader, endian, ())?)
+    } else {
+        None
+    };
+    reader.seek(SeekFrom::Start(restore_position))?;
+    Ok(assets)
+}
+
+fn parse_at<R: ReadSeek + ?Sized, T: for<'a> BinRead<Args<'a> = ()>>(mut reader: &mut R, offset: u64) -> BinResult<T> {
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_le()
+}
+
+#[derive(Debug)]
+pub struct NroReader {
+    reader: ReaderType,
+    pub nro: Nro,
+    /// Control property from the asset section
+    pub nacp: Option<Nacp>,
+}
+
+impl Serialize for NroReader {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("NroReader", 2)?;
+        state.serialize_field("nro", &self.nro)?;
+        state.serialize_field("nacp", &self.nacp)?;
+        state.end()
+    }
+}
+
+impl NroReader {
+    pub fn parse_file<P: AsRef<Path>>(nro_file: P) -> BinResult<NroReader> {
+        Self::from_reader_type(ReaderType::Raw(File::open(nro_file.as_ref())?))
+    }
+
+    pub fn parse_file_mmap<P: AsRef<Path>>(nro_file: P) -> BinResult<NroReader> {
+        let memmap = unsafe { memmap::MmapOptions::new().map(&File::open(nro_file.as_ref())?)? };
+        Self::from_reader_type(ReaderType::Mapped(memmap))
+    }
+
+    /// Parse an NRO starting at the start of `reader`
+    pub fn parse_reader<R: ReadSeek + 'static>(reader: R) -> BinResult<NroReader> {
+        Self::from_reader_type(ReaderType::Stream(Box::new(reader)))
+    }
+
+    fn from_reader_type(mut reader: ReaderType) -> BinResult<NroReader> {
+        let (nro, nacp) = match reader {
+            ReaderType::Raw(ref mut f) => Self::parse_with_nacp(f)?,
+            ReaderType::Mapped(ref map) => Self::parse_with_nacp(&mut Cursor::new(&map[..]))?,
+            ReaderType::Stream(ref mut r) => Self::parse_with_nacp(r)?,
+        };
+        Ok(NroReader { reader, nro, nacp })
+    }
+
+    fn parse_with_nacp<R: ReadSeek + ?Sized>(mut reader: &mut R) -> BinResult<(Nro, Option<Nacp>)> {
+        reader.seek(SeekFrom::Start(0))?;
+        let nro: Nro = reader.read_le()?;
+        let nacp = match nro.assets.as_ref().map(|assets| assets.nacp).filter(AssetSection::is_present) {
+            Some(section) => Some(parse_at(reader, section.offset)?),
+            None => None,
+        };
+        Ok((nro, nacp))
+    }
+
+    fn read_section(&mut self, offset: u64, size: u64) -> Result<Vec<u8>> {
+        match self.reader {
+            ReaderType::Mapped(ref map) => read_restore(&mut Cursor::new(&map[..]), offset, size),
+            ReaderType::Raw(ref mut f) => read_restore(f, offset, size),
+            ReaderType::Stream(ref mut r) => read_restore(r, offset, size),
+        }
+    }
+
+    fn read_section_into(&mut self, section: AssetSection, writer: &mut dyn Write) -> Result<()> {
+        match self.reader {
+            ReaderType::Mapped(ref map) => read_restore_into(&mut Cursor::new(&map[..]), writer, section.offset, section.size as usize),
+            ReaderType::Raw(ref mut f) => read_restore_into(f, writer, section.offset, section.size as usize),
+            ReaderType::Stream(ref mut r) => read_restore_into(r, writer, section.offset, section.size as usize),
+        }
+    }
+
+    fn asset(&self, select: fn(&AssetHeader) -> AssetSection) -> Option<AssetSection> {
+        self.nro.assets.as_ref().map(select).filter(AssetSection::is_present)
+    }
+
+    /// The executable image (header and segments), without the asset section
+    pub fn executable(&mut self) -> Result<Vec<u8>> {
+        self.read_section(0, self.nro.header.size.into())
+    }
+
+    /// The executable as it is laid out in memory. NRO segments are stored uncompressed at their
+    /// memory offsets, so this is the executable image itself.
+    pub fn module_image(&mut self) -> Result<ModuleImage> {
+        let range = |index: usize, segment: NroSegment| match segment.offset.checked_add(segment.size) {
+            Some(end) => Ok(u64::from(segment.offset)..u64::from(end)),
+            None => Err(Error::new(ErrorKind::InvalidData, format!("Segment {} ends past the 32-bit address space", index))),
+        };
+        let [text, rodata, data] = self.nro.header.segments;
+        Ok(ModuleImage {
+            text: range(0, text)?,
+            rodata: range(1, rodata)?,
+            data: range(2, data)?,
+            image: self.executable()?,
+            bss_size: self.nro.header.bss_size.into(),
+        })
+    }
+
+    /// The JPEG icon from the asset section
+    pub fn icon(&mut self) -> Option<Result<Vec<u8>>> {
+        let section = self.asset(|assets| assets.icon)?;
+        Some(self.read_section(section.offset, section.size))
+    }
+
+    /// The raw `control.nacp` from the asset section
+    pub fn nacp_data(&mut self) -> Option<Result<Vec<u8>>> {
+        let section = self.asset(|assets| assets.nacp)?;
+        Some(self.read_section(section.offset, section.size))
+    }
+
+    /// Parse the RomFS embedded in the asset section. File offsets are absolute.
+    pub fn romfs(&mut self) -> Option<BinResult<RomFs>> {
+        let section = self.asset(|assets| assets.romfs)?;
+        Some(match self.reader {
+            ReaderType::Mapped(ref map) => parse_at(&mut Cursor::new(&map[..]), section.offset),
+            ReaderType::Raw(ref mut f) => parse_at(f, section.offset),
+            ReaderType::Stream(ref mut r) => parse_at(r, section.offset),
+        })
+    }
+
+    /// Extract `icon.jpg`, `control.nacp` and the `romfs` directory from the asset section
+    pub fn extract_assets<P: AsRef<Path>>(&mut self, output_folder: P) -> BinResult<()> {
+        let output_folder = output_folder.as_ref();
+        std::fs::create_dir_all(output_folder)?;
+        for (name, section) in [("icon.jpg", self.asset(|a| a.icon)), ("control.nacp", self.asset(|a| a.nacp))] {
+            if let Some(section) = section {
+                self.read_section_into(section, &mut File::create(output_folder.join(name))?)?;
+            }
+        }
+        if let Some(romfs) = self.romfs() {
+            let romfs = romfs?;
+            let output_folder = output_folder.join("romfs");
+            match self.reader {
+                ReaderType::Mapped(ref map) => romfs.extract_to(&mut Cursor::new(&map[..]), output_folder)?,
+                ReaderType::Raw(ref mut f) => romfs.extract_to(f, output_folder)?,
+                ReaderType::Stream(ref mut r) => romfs.extract_to(r, output_folder)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use std::fs::File;
+use std::io::{Cursor, Error, ErrorKind};
+use std::path::Path;
+
+use binrw::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::elf::ModuleImage;
+use super::nro::NroSegment;
+use super::pfs0::Pfs0Reader;
+use super::{SHA256Hash, Validity};
+use crate::utils::{read_restore, serde_helpers, ReadSeek, ReaderType};
+
+/// Size of the NSO header, which is followed by the module name and the segments
+pub const NSO_HEADER_SIZE: u64 = 0x100;
+
+/// ExeFS modules in the order the loader maps them. Other ExeFS files are not loaded.
+pub const EXEFS_MODULE_ORDER: [&str; 13] = [
+    "rtld", "main", "subsdk0", "subsdk1", "subsdk2", "subsdk3", "subsdk4", "subsdk5", "subsdk6", "subsdk7", "subsdk8",
+    "subsdk9", "sdk",
+];
+
+/// Address of the first module of a 64-bit process with ASLR disabled
+pub const DEFAULT_LOAD_BASE: u64 = 0x800_0000;
+const PAGE_SIZE: u64 = 0x1000;
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"NSO0")]
+pub struct NsoHeader {
+    pub version: u32,
+    /// Bits 0-2: .text, .rodata and .data are LZ4 compressed. Bits 3-5: their hashes are
+    /// checked when loading.
+    #[br(pad_before = 4)]
+    pub flags: u32,
+    pub text: NsoSegmentHeader,
+    pub module_name_offset: u32,
+    pub rodata: NsoSegmentHeader,
+    pub module_name_size: u32,
+    pub data: NsoSegmentHeader,
+    pub bss_size: u32,
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub module_id: [u8; 0x20],
+    /// Size of each segment in the file, which is the compressed size for compressed segments
+    #[br(pad_after = 0x1C)]
+    pub file_sizes: [u32; 3],
+    /// Offsets within .rodata
+    pub api_info: NroSegment,
+    pub dynstr: NroSegment,
+    pub dynsym: NroSegment,
+    /// SHA-256 of each decompressed segment
+    #[serde(serialize_with = "serde_helpers::hex_list")]
+    pub hashes: [SHA256Hash; 3],
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[br(little)]
+pub struct NsoSegmentHeader {
+    pub file_offset: u32,
+    pub memory_offset: u32,
+    /// Size of the decompressed segment
+    pub size: u32,
+}
+
+impl NsoHeader {
+    /// .text, .rodata and .data
+    pub fn segments(&self) -> [NsoSegmentHeader; 3] {
+        [self.text, self.rodata, self.data]
+    }
+
+    /// Size of the module in memory, including .bss, rounded up to a page
+    pub fn memory_size(&self) -> u64 {
+        (u64::from(self.data.memory_offset) + u64::from(self.data.size) + u64::from(self.bss_size)).next_multiple_of(PAGE_SIZE)
+    }
+
+    pub fn is_compressed(&self, segment: usize) -> bool {
+        self.flags & (1 << segment) != 0
+    }
+
+    pub fn is_hash_checked(&self, segment: usize) -> bool {
+        self.flags & (1 << (segment + 3)) != 0
+    }
+}
+
+#[derive(Debug)]
+pub struct NsoReader {
+    reader: ReaderType,
+    pub header: NsoHeader,
+}
+
+impl NsoReader {
+    pub fn parse_file<P: AsRef<Path>>(nso_file: P) -> BinResult<NsoReader> {
+        Self::from_reader_type(ReaderType::Raw(File::open(nso_file.as_ref())?))
+    }
+
+    pub fn parse_file_mmap<P: AsRef<Path>>(nso_file: P) -> BinResult<NsoReader> {
+        let memmap = unsafe { memmap::MmapOptions::new().map(&File::open(nso_file.as_ref())?)? };
+        Self::from_reader_type(ReaderType::Mapped(memmap))
+    }
+
+    /// Parse an NSO starting at the start of `reader`
+    pub fn parse_reader<R: ReadSeek + 'static>(reader: R) -> BinResult<NsoReader> {
+        Self::from_reader_type(ReaderType::Stream(Box::new(reader)))
+    }
+
+    fn from_reader_type(mut reader: ReaderType) -> BinResult<NsoReader> {
+        let header = match reader {
+            ReaderType::Raw(ref mut f) => f.read_le()?,
+            ReaderType::Mapped(ref map) => Cursor::new(&map[..]).read_le()?,
+            ReaderType::Stream(ref mut r) => r.read_le()?,
+        };
+        Ok(NsoReader { reader, header })
+    }
+
+    /// Read and decompress a segment: 0 for .text, 1 for .rodata and 2 for .data
+    pub fn read_segment(&mut self, segment: usize) -> BinResult<Vec<u8>> {
+        let header = self.header.segments()[segment];
+        let (offset, file_size) = (header.file_offset.into(), self.header.file_sizes[segment].into());
+        let stored: Vec<u8> = match self.reader {
+            ReaderType::Mapped(ref map) => read_restore(&mut Cursor::new(&map[..]), offset, file_size)?,
+            ReaderType::Raw(ref mut f) => read_restore(f, offset, file_size)?,
+            ReaderType::Stream(ref mut r) => read_restore(r, offset, file_size)?,
+        };
+        if !self.header.is_compressed(segment) {
+            return Ok(stored);
+        }
+        lz4_flex::block::decompress(&stored, header.size as usize)
+            .map_err(|e| binrw::Error::Io(Error::new(ErrorKind::InvalidData, format!("Unable to decompress segment {}: {}", segment, e))))
+    }
+
+    /// The module path the linker records at the start of .rodata, e.g. `D:\build\main.nss`.
+    /// `None` if .rodata does not start with one.
+    pub fn module_name(&mut self) -> BinResult<Option<String>> {
+        let rodata = self.read_segment(1)?;
+        let (Some(zero), Some(length)) = (rodata.get(0..4), rodata.get(4..8)) else {
+            return Ok(None);
+        };
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        let name = match rodata.get(8..8 + length) {
+            Some(name) if zero == [0; 4] && length > 0 => name,
+            _ => return Ok(None),
+        };
+        let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        Ok(Some(String::from_utf8_lossy(&name[..end]).into_owned()))
+    }
+
+    /// Compare the hash of a decompressed segment against the header
+    pub fn verify_segment_hash(&mut self, segment: usize) -> Validity {
+        match self.read_segment(segment) {
+            Ok(data) if Sha256::digest(&data).as_slice() == self.header.hashes[segment] => Validity::Valid,
+            Ok(_) => Validity::Invalid,
+            Err(_) => Validity::CheckError,
+        }
+    }
+
+    /// The module as it is laid out in memory, with every segment decompressed
+    pub fn module_image(&mut self) -> BinResult<ModuleImage> {
+        let [text, rodata, data] = self.header.segments();
+        let end = |index: usize, segment: NsoSegmentHeader| {
+            segment.memory_offset.checked_add(segment.size).ok_or_else(|| {
+                binrw::Error::Io(Error::new(ErrorKind::InvalidData, format!("Segment {} ends past the 32-bit address space", index)))
+            })
+        };
+        let ends = [end(0, text)?, end(1, rodata)?, end(2, data)?];
+        let segments = [self.read_segment(0)?, self.read_segment(1)?, self.read_segment(2)?];
+        // Segments are page aligned, so the module is at most a page per segment larger than its
+        // contents. Anything more is a corrupt header rather than a module worth allocating.
+        let contents_size: u64 = segments.iter().map(|contents| contents.len() as u64 + PAGE_SIZE).sum();
+        if u64::from(ends[2]) > contents_size {
+            return Err(binrw::Error::Io(Error::new(
+                ErrorKind::InvalidData,
+                format!("Module size 0x{:X} is larger than its segments", ends[2]),
+            )));
+        }
+        let mut image = vec![0u8; ends[2] as usize];
+        for (index, (segment, contents)) in [text, rodata, data].into_iter().zip(segments).enumerate() {
+            let start = segment.memory_offset as usize;
+            image
+                .get_mut(start..start + contents.len())
+                .ok_or_else(|| binrw::Error::Io(Error::new(ErrorKind::InvalidData, format!("Segment {} is outside of the module", index))))?
+                .copy_from_slice(&contents);
+        }
+
+        let range = |segment: NsoSegmentHeader, end: u32| u64::from(segment.memory_offset)..u64::from(end);
+        Ok(ModuleImage {
+            image,
+            text: range(text, ends[0]),
+            rodata: range(rodata, ends[1]),
+            data: range(data, ends[2]),
+            bss_size: self.header.bss_size.into(),
+        })
+    }
+}
+
+/// Identification and layout of an ExeFS module, as needed to symbolicate crash reports
+#[derive(Debug, Serialize)]
+pub struct ModuleInfo {
+    pub file_name: String,
+    /// Module path from .rodata
+    pub module_name: Option<String>,
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub build_id: [u8; 0x20],
+    pub text: NsoSegmentHeader,
+    pub rodata: NsoSegmentHeader,
+    pub data: NsoSegmentHeader,
+    pub bss_size: u32,
+    /// Address the module is mapped at, with each module placed directly after the previous one
+    pub load_address: u64,
+    pub memory_size: u64,
+}
+
+/// List the modules of an ExeFS in load order, assigning addresses from `load_base` the way
+/// the loader does when ASLR is disabled. With ASLR, only the base differs.
+pub fn exefs_modules(exefs: &mut Pfs0Reader, load_base: u64) -> BinResult<Vec<ModuleInfo>> {
+    let mut modules = Vec::new();
+    let mut load_address = load_base;
+    for file_name in EXEFS_MODULE_ORDER {
+        let Some(data) = exefs.get_file_data(file_name) else {
+            continue;
+        };
+        let mut nso = NsoReader::parse_reader(Cursor::new(data?))?;
+        let header = &nso.header;
+        let (build_id, text, rodata, data, bss_size, memory_size) =
+            (header.module_id, header.text, header.rodata, header.data, header.bss_size, header.memory_size());
+        modules.push(ModuleInfo {
+            file_name: file_name.to_string(),
+            module_name: nso.module_name()?,
+            build_id,
+            text,
+            rodata,
+            data,
+            bss_size,
+            load_address,
+            memory_size,
+        });
+        load_address += memory_size;
+    }
+    Ok(modules)
+}
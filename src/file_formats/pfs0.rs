@@ -1,19 +1,22 @@
 use std::fs::File;
 use std::io::Cursor;
+use std::io::Read;
 use std::io::Result;
 use std::io::Write;
 use std::path::Path;
 
 use binrw::prelude::*;
 use binrw::BinReaderExt;
+use binrw::BinWriterExt;
 use binrw::FilePtr32;
 use binrw::NullString;
+use serde::Serialize;
 
-use crate::utils::{CurPos, ReaderType};
+use crate::utils::{serde_helpers, CurPos, ReadSeek, ReaderType};
 use crate::utils::{read_restore, read_restore_into};
 
 #[binread]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Pfs0 {
     /// Embed current cursor position since the PFS0 may be embedded in an NCA file
     #[br(temp)] cursor_position: CurPos,
@@ -31,8 +34,8 @@ pub struct Pfs0 {
     pub files: Vec<Pfs0FileRecord>,
 }
 
-#[binread]
-#[derive(Debug, Clone, Copy)]
+#[binrw]
+#[derive(Debug, Clone, Copy, Serialize)]
 #[brw(little, magic = b"PFS0")]
 pub struct Pfs0Header {
     /// number of embedded files
@@ -43,7 +46,7 @@ pub struct Pfs0Header {
 }
 
 #[binread]
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 #[br(little, import(string_table_absolute_start: u64, file_data_absolute_start: u64))]
 pub struct Pfs0FileRecord {
     /// Offset and size of file from PFS0 header start
@@ -54,9 +57,22 @@ pub struct Pfs0FileRecord {
     pub file_size: u64,
     /// Embedded file path
     #[br(parse_with = FilePtr32::parse, pad_after = 4, offset = string_table_absolute_start )]
+    #[serde(serialize_with = "serde_helpers::display")]
     pub file_name: NullString,
 }
 
+/// On-disk layout of a file entry, used when writing archives
+#[binwrite]
+#[bw(little)]
+struct Pfs0FileEntry {
+    /// Offset of the file data from the start of the data region
+    data_offset: u64,
+    data_size: u64,
+    /// Offset of the file name in the string table
+    #[bw(pad_after = 4)]
+    string_table_offset: u32,
+}
+
 #[derive(Debug)]
 pub struct Pfs0Reader {
     reader: ReaderType,
@@ -86,6 +102,14 @@ impl Pfs0Reader {
         Ok(Pfs0Reader { reader: ReaderType::Mapped(memmap), pfs: pfs0 })
     }
 
+    /// Parse a PFS0 starting at the current position of `reader`, e.g. a partition nested in
+    /// another container
+    pub fn parse_reader<R: ReadSeek + 'static>(mut reader: R) -> BinResult<Pfs0Reader> {
+        let pfs = reader.read_le()?;
+
+        Ok(Pfs0Reader { reader: ReaderType::Stream(Box::new(reader)), pfs })
+    }
+
     pub fn list_files(&self) -> Vec<String> {
         self.pfs.files.iter().map( |f: &Pfs0FileRecord| {
             f.file_name.to_string()
@@ -99,7 +123,8 @@ impl Pfs0Reader {
 
         match self.reader {
             ReaderType::Mapped(ref map) =>  Some(read_restore(&mut Cursor::new(&map[..]), *file_offset, *file_size)),
-            ReaderType::Raw(ref mut f) => Some(read_restore(f, *file_offset, *file_size))
+            ReaderType::Raw(ref mut f) => Some(read_restore(f, *file_offset, *file_size)),
+            ReaderType::Stream(ref mut r) => Some(read_restore(r, *file_offset, *file_size)),
         }
     }
 
@@ -109,7 +134,139 @@ impl Pfs0Reader {
 
         match self.reader {
             ReaderType::Mapped(ref map) =>  read_restore_into(&mut Cursor::new(&map[..]), writer, *file_offset, *file_size as usize),
-            ReaderType::Raw(ref mut f) => read_restore_into(f, writer, *file_offset, *file_size as usize)
+            ReaderType::Raw(ref mut f) => read_restore_into(f, writer, *file_offset, *file_size as usize),
+            ReaderType::Stream(ref mut r) => read_restore_into(r, writer, *file_offset, *file_size as usize),
         }
     }
 }
+
+/// Alignment of the PFS0 header (including the string table) in bytes
+const PFS0_HEADER_ALIGNMENT: u64 = 0x20;
+
+/// Builds PFS0 archives (e.g. NSP files).
+///
+/// File data is only read from the added files/readers while the archive is written, so the
+/// contents are streamed into the output rather than buffered in memory.
+#[derive(Default)]
+pub struct Pfs0Builder<'a> {
+    files: Vec<Pfs0BuilderEntry<'a>>,
+}
+
+struct Pfs0BuilderEntry<'a> {
+    name: String,
+    size: u64,
+    reader: Box<dyn Read + 'a>,
+}
+
+impl<'a> Pfs0Builder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a builder containing every regular file in `dir`, sorted by name.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let mut paths = std::fs::read_dir(dir.as_ref())?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.retain(|path| path.is_file());
+        paths.sort();
+
+        let mut builder = Self::new();
+        for path in paths {
+            builder.add_file(path)?;
+        }
+        Ok(builder)
+    }
+
+    /// Add a file from disk, named after the final component of its path.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Path to embed in Pfs0 archive has no file name.",
+            ))?
+            .to_string_lossy()
+            .into_owned();
+        self.add_named_file(name, path)
+    }
+
+    /// Add a file from disk under the given name.
+    pub fn add_named_file<S: Into<String>, P: AsRef<Path>>(&mut self, name: S, path: P) -> Result<&mut Self> {
+        let file = File::open(path.as_ref())?;
+        let size = file.metadata()?.len();
+        Ok(self.add_reader(name, size, file))
+    }
+
+    /// Add `size` bytes read from `reader` under the given name.
+    pub fn add_reader<S: Into<String>, R: Read + 'a>(&mut self, name: S, size: u64, reader: R) -> &mut Self {
+        self.files.push(Pfs0BuilderEntry {
+            name: name.into(),
+            size,
+            reader: Box::new(reader),
+        });
+        self
+    }
+
+    /// Size of the header, file entry table and padded string table in bytes
+    pub fn header_size(&self) -> u64 {
+        let (_, header_size) = self.string_table_layout();
+        header_size
+    }
+
+    /// Total size of the archive in bytes
+    pub fn total_size(&self) -> u64 {
+        self.header_size() + self.files.iter().map(|f| f.size).sum::<u64>()
+    }
+
+    /// Returns the unpadded string table size and the padded size of the whole header.
+    fn string_table_layout(&self) -> (u64, u64) {
+        let string_table_size: u64 = self.files.iter().map(|f| f.name.len() as u64 + 1).sum();
+        let unpadded = 0x10 + self.files.len() as u64 * 0x18 + string_table_size;
+        (string_table_size, unpadded.next_multiple_of(PFS0_HEADER_ALIGNMENT))
+    }
+
+    /// Write the archive to `writer`, returning the number of bytes written.
+    pub fn write<W: Write>(self, writer: &mut W) -> BinResult<u64> {
+        let (string_table_size, header_size) = self.string_table_layout();
+        let entry_table_end = 0x10 + self.files.len() as u64 * 0x18;
+        let padded_string_table_size = header_size - entry_table_end;
+
+        let mut header = Cursor::new(Vec::with_capacity(header_size as usize));
+        header.write_le(&Pfs0Header {
+            file_count: self.files.len() as u32,
+            string_table_byte_size: padded_string_table_size as u32,
+        })?;
+
+        let mut string_table = Vec::with_capacity(padded_string_table_size as usize);
+        let mut data_offset = 0u64;
+        for file in self.files.iter() {
+            header.write_le(&Pfs0FileEntry {
+                data_offset,
+                data_size: file.size,
+                string_table_offset: string_table.len() as u32,
+            })?;
+            string_table.extend_from_slice(file.name.as_bytes());
+            string_table.push(0);
+            data_offset += file.size;
+        }
+        debug_assert_eq!(string_table.len() as u64, string_table_size);
+        string_table.resize(padded_string_table_size as usize, 0);
+        header.write_all(&string_table)?;
+
+        writer.write_all(header.get_ref())?;
+
+        for file in self.files {
+            let copied = std::io::copy(&mut file.reader.take(file.size), writer)?;
+            if copied != file.size {
+                return Err(binrw::Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("Expected {} bytes for Pfs0 entry \"{}\", only read {}", file.size, file.name, copied),
+                )));
+            }
+        }
+
+        Ok(header_size + data_offset)
+    }
+}
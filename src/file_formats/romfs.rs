@@ -0,0 +1,635 @@
+use std::fs::File;
+use std::io::{Cursor, Read, Result, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use binrw::prelude::*;
+use binrw::{BinReaderExt, BinWriterExt, VecArgs};
+use serde::Serialize;
+
+use crate::utils::{CurPos, Placement, ReadSeek, ReaderType};
+use crate::utils::{read_restore, read_restore_into};
+
+/// Marker for an empty hash bucket or the end of a sibling/child/hash chain
+pub const ROMFS_ENTRY_EMPTY: u32 = 0xFFFFFFFF;
+
+/// Offset of the file data region in RomFS images created by [`RomFsBuilder`]
+const ROMFS_DATA_OFFSET: u64 = 0x200;
+/// Alignment of file data within the data region
+const ROMFS_FILE_ALIGNMENT: u64 = 0x10;
+
+#[binrw]
+#[derive(Debug, Default, Clone, Copy)]
+#[brw(little)]
+pub struct RomFsHeader {
+    /// Size of this header (0x50)
+    pub header_size: u64,
+    pub dir_hash_table_offset: u64,
+    pub dir_hash_table_size: u64,
+    pub dir_meta_table_offset: u64,
+    pub dir_meta_table_size: u64,
+    pub file_hash_table_offset: u64,
+    pub file_hash_table_size: u64,
+    pub file_meta_table_offset: u64,
+    pub file_meta_table_size: u64,
+    /// Offset of the file data region from the start of the RomFS
+    pub data_offset: u64,
+}
+
+#[binread]
+#[derive(Debug)]
+#[br(little)]
+pub struct RomFs {
+    /// Embed current cursor position since the RomFS is usually embedded in an NCA section
+    #[br(temp)] cursor_position: CurPos,
+    pub header: RomFsHeader,
+    #[br(parse_with = Placement::parse, args {offset: cursor_position.0 + header.dir_hash_table_offset, inner: VecArgs {count: header.dir_hash_table_size as usize / 4, inner: ()}})]
+    pub dir_hash_table: Vec<u32>,
+    #[br(parse_with = Placement::parse, args {offset: cursor_position.0 + header.dir_meta_table_offset, inner: VecArgs {count: header.dir_meta_table_size as usize, inner: ()}})]
+    dir_meta_table: Vec<u8>,
+    #[br(parse_with = Placement::parse, args {offset: cursor_position.0 + header.file_hash_table_offset, inner: VecArgs {count: header.file_hash_table_size as usize / 4, inner: ()}})]
+    pub file_hash_table: Vec<u32>,
+    #[br(parse_with = Placement::parse, args {offset: cursor_position.0 + header.file_meta_table_offset, inner: VecArgs {count: header.file_meta_table_size as usize, inner: ()}})]
+    file_meta_table: Vec<u8>,
+    /// Absolute offset of the file data region in the underlying reader
+    #[br(calc = cursor_position.0 + header.data_offset)]
+    pub data_absolute_start: u64,
+}
+
+/// Directory entry in the directory meta table
+#[binrw]
+#[derive(Debug, Clone)]
+#[brw(little)]
+pub struct RomFsDirEntry {
+    /// Offset of the parent directory entry (the root is its own parent)
+    pub parent: u32,
+    /// Offset of the next directory in the parent directory
+    pub sibling: u32,
+    /// Offset of the first sub-directory
+    pub child_dir: u32,
+    /// Offset of the first file in the file meta table
+    pub child_file: u32,
+    /// Offset of the next entry in the same hash bucket
+    pub hash_next: u32,
+    #[br(temp)]
+    #[bw(calc = name.len() as u32)]
+    name_size: u32,
+    #[br(count = name_size, try_map = String::from_utf8)]
+    #[bw(map = |name: &String| name.as_bytes().to_vec(), align_after = 4)]
+    pub name: String,
+}
+
+/// File entry in the file meta table
+#[binrw]
+#[derive(Debug, Clone)]
+#[brw(little)]
+pub struct RomFsFileEntry {
+    /// Offset of the parent directory entry
+    pub parent: u32,
+    /// Offset of the next file in the parent directory
+    pub sibling: u32,
+    /// Offset of the file data from the start of the data region
+    pub data_offset: u64,
+    pub data_size: u64,
+    /// Offset of the next entry in the same hash bucket
+    pub hash_next: u32,
+    #[br(temp)]
+    #[bw(calc = name.len() as u32)]
+    name_size: u32,
+    #[br(count = name_size, try_map = String::from_utf8)]
+    #[bw(map = |name: &String| name.as_bytes().to_vec(), align_after = 4)]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RomFsFileRecord {
+    /// Path of the file relative to the RomFS root, using `/` as the separator
+    pub path: String,
+    /// Absolute offset of the file data in the underlying reader
+    pub file_offset: u64,
+    pub file_size: u64,
+}
+
+/// Hash used to index both the directory and file hash tables
+pub fn romfs_path_hash(parent: u32, name: &[u8]) -> u32 {
+    name.iter().fold(parent ^ 123456789, |hash, &c| hash.rotate_right(5) ^ u32::from(c))
+}
+
+/// Number of buckets used for a hash table holding `entry_count` entries
+pub fn romfs_hash_table_count(entry_count: usize) -> usize {
+    if entry_count < 3 {
+        3
+    } else if entry_count < 19 {
+        entry_count | 1
+    } else {
+        let mut count = entry_count;
+        while [2, 3, 5, 7, 11, 13, 17].iter().any(|&p| count.is_multiple_of(p)) {
+            count += 1;
+        }
+        count
+    }
+}
+
+impl RomFs {
+    pub fn dir_entry(&self, offset: u32) -> BinResult<RomFsDirEntry> {
+        let mut cursor = Cursor::new(&self.dir_meta_table);
+        cursor.set_position(offset.into());
+        cursor.read_le()
+    }
+
+    pub fn file_entry(&self, offset: u32) -> BinResult<RomFsFileEntry> {
+        let mut cursor = Cursor::new(&self.file_meta_table);
+        cursor.set_position(offset.into());
+        cursor.read_le()
+    }
+
+    /// All directories below the root, parents before children
+    pub fn directories(&self) -> BinResult<Vec<String>> {
+        let mut directories = Vec::new();
+        self.walk(0, "", &mut |path, _| directories.push(path.to_string()), &mut |_| ())?;
+        Ok(directories)
+    }
+
+    /// All files in the RomFS, in directory order
+    pub fn files(&self) -> BinResult<Vec<RomFsFileRecord>> {
+        let mut files = Vec::new();
+        self.walk(0, "", &mut |_, _| (), &mut |record| files.push(record))?;
+        Ok(files)
+    }
+
+    fn walk(
+        &self,
+        dir_offset: u32,
+        dir_path: &str,
+        on_dir: &mut dyn FnMut(&str, &RomFsDirEntry),
+        on_file: &mut dyn FnMut(RomFsFileRecord),
+    ) -> BinResult<()> {
+        let dir = self.dir_entry(dir_offset)?;
+
+        let mut file_offset = dir.child_file;
+        while file_offset != ROMFS_ENTRY_EMPTY {
+            let file = self.file_entry(file_offset)?;
+            on_file(self.file_record(join_path(dir_path, &file.name), &file));
+            file_offset = file.sibling;
+        }
+
+        let mut child_offset = dir.child_dir;
+        while child_offset != ROMFS_ENTRY_EMPTY {
+            let child = self.dir_entry(child_offset)?;
+            let child_path = join_path(dir_path, &child.name);
+            on_dir(&child_path, &child);
+            self.walk(child_offset, &child_path, on_dir, on_file)?;
+            child_offset = child.sibling;
+        }
+        Ok(())
+    }
+
+    fn file_record(&self, path: String, file: &RomFsFileEntry) -> RomFsFileRecord {
+        RomFsFileRecord {
+            path,
+            file_offset: self.data_absolute_start + file.data_offset,
+            file_size: file.data_size,
+        }
+    }
+
+    /// Look up a file by path using the hash tables
+    pub fn find_file<S: AsRef<str>>(&self, path: S) -> BinResult<Option<RomFsFileRecord>> {
+        let path = path.as_ref().trim_matches('/');
+        let (dir_path, file_name) = path.rsplit_once('/').unwrap_or(("", path));
+
+        let mut dir_offset = 0;
+        for component in dir_path.split('/').filter(|c| !c.is_empty()) {
+            match self.find_dir_child(dir_offset, component)? {
+                Some(offset) => dir_offset = offset,
+                None => return Ok(None),
+            }
+        }
+
+        if self.file_hash_table.is_empty() {
+            return Ok(None);
+        }
+        let bucket = romfs_path_hash(dir_offset, file_name.as_bytes()) as usize % self.file_hash_table.len();
+        let mut offset = self.file_hash_table[bucket];
+        while offset != ROMFS_ENTRY_EMPTY {
+            let file = self.file_entry(offset)?;
+            if file.parent == dir_offset && file.name == file_name {
+                return Ok(Some(self.file_record(path.to_string(), &file)));
+            }
+            offset = file.hash_next;
+        }
+        Ok(None)
+    }
+
+    fn find_dir_child(&self, parent: u32, name: &str) -> BinResult<Option<u32>> {
+        if self.dir_hash_table.is_empty() {
+            return Ok(None);
+        }
+        let bucket = romfs_path_hash(parent, name.as_bytes()) as usize % self.dir_hash_table.len();
+        let mut offset = self.dir_hash_table[bucket];
+        while offset != ROMFS_ENTRY_EMPTY {
+            let dir = self.dir_entry(offset)?;
+            if dir.parent == parent && dir.name == name {
+                return Ok(Some(offset));
+            }
+            offset = dir.hash_next;
+        }
+        Ok(None)
+    }
+
+    /// Extract every directory and file into `output_folder`
+    pub fn extract_to<R: Read + Seek, P: AsRef<Path>>(&self, reader: &mut R, output_folder: P) -> BinResult<()> {
+        let output_folder = output_folder.as_ref();
+        std::fs::create_dir_all(output_folder)?;
+        for dir in self.directories()? {
+            std::fs::create_dir_all(output_folder.join(&dir))?;
+        }
+        for file in self.files()? {
+            let mut output_file = File::create(output_folder.join(&file.path))?;
+            read_restore_into(reader, &mut output_file, file.file_offset, file.file_size as usize)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn join_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+#[derive(Debug)]
+pub struct RomFsReader {
+    reader: ReaderType,
+    pub romfs: RomFs,
+}
+
+impl RomFsReader {
+    pub fn parse_file<P: AsRef<Path>>(romfs_file: P) -> BinResult<RomFsReader> {
+        let mut file: File = File::open(romfs_file.as_ref())?;
+        let romfs = file.read_le()?;
+
+        Ok(RomFsReader {
+            reader: ReaderType::Raw(file),
+            romfs,
+        })
+    }
+
+    pub fn parse_file_mmap<P: AsRef<Path>>(romfs_file: P) -> BinResult<RomFsReader> {
+        let memmap = unsafe { memmap::MmapOptions::new().map(&File::open(romfs_file.as_ref())?)? };
+        let romfs = Cursor::new(&memmap[..]).read_le()?;
+
+        Ok(RomFsReader {
+            reader: ReaderType::Mapped(memmap),
+            romfs,
+        })
+    }
+
+    /// Parse a RomFS starting at the current position of `reader`, e.g. an NCA section
+    pub fn parse_reader<R: ReadSeek + 'static>(mut reader: R) -> BinResult<RomFsReader> {
+        let romfs = reader.read_le()?;
+
+        Ok(RomFsReader {
+            reader: ReaderType::Stream(Box::new(reader)),
+            romfs,
+        })
+    }
+
+    pub fn list_files(&self) -> BinResult<Vec<String>> {
+        Ok(self.romfs.files()?.into_iter().map(|f| f.path).collect())
+    }
+
+    /// Contents of the file at `path`, or `None` if there is no such file
+    pub fn get_file_data<S: AsRef<str>>(&mut self, path: S) -> BinResult<Option<Vec<u8>>> {
+        let Some(RomFsFileRecord { file_offset, file_size, .. }) = self.romfs.find_file(path)? else {
+            return Ok(None);
+        };
+
+        let data = match self.reader {
+            ReaderType::Mapped(ref map) => read_restore(&mut Cursor::new(&map[..]), file_offset, file_size),
+            ReaderType::Raw(ref mut f) => read_restore(f, file_offset, file_size),
+            ReaderType::Stream(ref mut r) => read_restore(r, file_offset, file_size),
+        }?;
+        Ok(Some(data))
+    }
+
+    pub fn read_file_into<S: AsRef<str>>(&mut self, path: S, writer: &mut dyn Write) -> BinResult<()> {
+        let RomFsFileRecord { file_offset, file_size, .. } = self
+            .romfs
+            .find_file(path)?
+            .ok_or(std::io::Error::new(std::io::ErrorKind::NotFound, "Can't find named file in RomFs image."))?;
+
+        Ok(match self.reader {
+            ReaderType::Mapped(ref map) => read_restore_into(&mut Cursor::new(&map[..]), writer, file_offset, file_size as usize),
+            ReaderType::Raw(ref mut f) => read_restore_into(f, writer, file_offset, file_size as usize),
+            ReaderType::Stream(ref mut r) => read_restore_into(r, writer, file_offset, file_size as usize),
+        }?)
+    }
+
+    pub fn extract_to<P: AsRef<Path>>(&mut self, output_folder: P) -> BinResult<()> {
+        match self.reader {
+            ReaderType::Mapped(ref map) => self.romfs.extract_to(&mut Cursor::new(&map[..]), output_folder),
+            ReaderType::Raw(ref mut f) => self.romfs.extract_to(f, output_folder),
+            ReaderType::Stream(ref mut r) => self.romfs.extract_to(r, output_folder),
+        }
+    }
+}
+
+/// Builds RomFS images.
+///
+/// Directory and file tables are generated up front from the names and sizes of the added
+/// files, while file data is streamed from disk (or the added readers) when the image is written.
+pub struct RomFsBuilder<'a> {
+    /// Directories, with the root directory at index 0
+    dirs: Vec<RomFsBuilderDir>,
+    files: Vec<RomFsBuilderFile<'a>>,
+}
+
+#[derive(Default)]
+struct RomFsBuilderDir {
+    name: String,
+    parent: usize,
+    child_dirs: Vec<usize>,
+    child_files: Vec<usize>,
+}
+
+struct RomFsBuilderFile<'a> {
+    name: String,
+    parent: usize,
+    size: u64,
+    source: RomFsSource<'a>,
+}
+
+enum RomFsSource<'a> {
+    Path(PathBuf),
+    Reader(Box<dyn Read + 'a>),
+}
+
+/// Offsets assigned to each directory and file in the meta tables
+struct RomFsLayout {
+    dir_offsets: Vec<u32>,
+    file_offsets: Vec<u32>,
+    /// Order in which directories and files are written to the meta tables
+    dir_order: Vec<usize>,
+    file_order: Vec<usize>,
+    /// Offset of each file's data from the start of the data region
+    file_data_offsets: Vec<u64>,
+    data_size: u64,
+}
+
+impl Default for RomFsBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> RomFsBuilder<'a> {
+    pub fn new() -> Self {
+        Self {
+            dirs: vec![RomFsBuilderDir::default()],
+            files: Vec::new(),
+        }
+    }
+
+    /// Create a builder containing the directory tree rooted at `dir`
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let mut builder = Self::new();
+        builder.add_host_dir(0, dir.as_ref())?;
+        Ok(builder)
+    }
+
+    fn add_host_dir(&mut self, dir_index: usize, host_dir: &Path) -> Result<()> {
+        let mut paths = std::fs::read_dir(host_dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.sort();
+
+        for path in paths {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            if path.is_dir() {
+                let child = self.get_or_create_dir(dir_index, &name);
+                self.add_host_dir(child, &path)?;
+            } else if path.is_file() {
+                let size = path.metadata()?.len();
+                self.push_file(dir_index, name, size, RomFsSource::Path(path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a directory (and any missing parents), which is kept even if it stays empty
+    pub fn add_dir<S: AsRef<str>>(&mut self, path: S) -> &mut Self {
+        let mut dir_index = 0;
+        for component in path.as_ref().split('/').filter(|c| !c.is_empty()) {
+            dir_index = self.get_or_create_dir(dir_index, component);
+        }
+        self
+    }
+
+    /// Add `size` bytes read from `reader` at `path` (e.g. `data/file.bin`), creating any
+    /// missing parent directories
+    pub fn add_reader<S: AsRef<str>, R: Read + 'a>(&mut self, path: S, size: u64, reader: R) -> &mut Self {
+        let path = path.as_ref().trim_matches('/');
+        let (dir_path, file_name) = path.rsplit_once('/').unwrap_or(("", path));
+
+        let mut dir_index = 0;
+        for component in dir_path.split('/').filter(|c| !c.is_empty()) {
+            dir_index = self.get_or_create_dir(dir_index, component);
+        }
+        self.push_file(dir_index, file_name.to_string(), size, RomFsSource::Reader(Box::new(reader)));
+        self
+    }
+
+    fn get_or_create_dir(&mut self, parent: usize, name: &str) -> usize {
+        if let Some(&existing) = self.dirs[parent].child_dirs.iter().find(|&&d| self.dirs[d].name == name) {
+            return existing;
+        }
+        let index = self.dirs.len();
+        self.dirs.push(RomFsBuilderDir {
+            name: name.to_string(),
+            parent,
+            ..Default::default()
+        });
+        self.dirs[parent].child_dirs.push(index);
+        index
+    }
+
+    fn push_file(&mut self, parent: usize, name: String, size: u64, source: RomFsSource<'a>) {
+        self.dirs[parent].child_files.push(self.files.len());
+        self.files.push(RomFsBuilderFile { name, parent, size, source });
+    }
+
+    /// Assign meta table offsets (breadth first, children sorted by name) and data offsets.
+    fn layout(&mut self) -> RomFsLayout {
+        for dir_index in 0..self.dirs.len() {
+            let mut child_dirs = std::mem::take(&mut self.dirs[dir_index].child_dirs);
+            child_dirs.sort_by(|&a, &b| self.dirs[a].name.cmp(&self.dirs[b].name));
+            self.dirs[dir_index].child_dirs = child_dirs;
+
+            let mut child_files = std::mem::take(&mut self.dirs[dir_index].child_files);
+            child_files.sort_by(|&a, &b| self.files[a].name.cmp(&self.files[b].name));
+            self.dirs[dir_index].child_files = child_files;
+        }
+
+        let mut dir_order = vec![0];
+        let mut next = 0;
+        while next < dir_order.len() {
+            dir_order.extend_from_slice(&self.dirs[dir_order[next]].child_dirs);
+            next += 1;
+        }
+        let file_order: Vec<usize> = dir_order
+            .iter()
+            .flat_map(|&d| self.dirs[d].child_files.iter().copied())
+            .collect();
+
+        let mut dir_offsets = vec![0; self.dirs.len()];
+        let mut offset = 0;
+        for &dir in dir_order.iter() {
+            dir_offsets[dir] = offset;
+            offset += 0x18 + (self.dirs[dir].name.len() as u32).next_multiple_of(4);
+        }
+
+        let mut file_offsets = vec![0; self.files.len()];
+        let mut file_data_offsets = vec![0; self.files.len()];
+        let mut offset = 0;
+        let mut data_size = 0u64;
+        for &file in file_order.iter() {
+            file_offsets[file] = offset;
+            offset += 0x20 + (self.files[file].name.len() as u32).next_multiple_of(4);
+
+            data_size = data_size.next_multiple_of(ROMFS_FILE_ALIGNMENT);
+            file_data_offsets[file] = data_size;
+            data_size += self.files[file].size;
+        }
+
+        RomFsLayout {
+            dir_offsets,
+            file_offsets,
+            dir_order,
+            file_order,
+            file_data_offsets,
+            data_size,
+        }
+    }
+
+    /// Generate the directory hash table, directory meta table, file hash table and file meta table
+    fn build_tables(&self, layout: &RomFsLayout) -> BinResult<[Vec<u8>; 4]> {
+        let link = |entries: &[usize], position: usize, offsets: &[u32]| {
+            entries.get(position).map(|&e| offsets[e]).unwrap_or(ROMFS_ENTRY_EMPTY)
+        };
+
+        let mut dir_buckets = vec![ROMFS_ENTRY_EMPTY; romfs_hash_table_count(self.dirs.len())];
+        let mut dir_meta = Cursor::new(Vec::new());
+        for &dir_index in layout.dir_order.iter() {
+            let dir = &self.dirs[dir_index];
+            let parent = layout.dir_offsets[dir.parent];
+            let siblings = &self.dirs[dir.parent].child_dirs;
+            let sibling = match siblings.iter().position(|&d| d == dir_index) {
+                Some(position) if dir_index != 0 => link(siblings, position + 1, &layout.dir_offsets),
+                _ => ROMFS_ENTRY_EMPTY,
+            };
+
+            let bucket = romfs_path_hash(parent, dir.name.as_bytes()) as usize % dir_buckets.len();
+            let hash_next = std::mem::replace(&mut dir_buckets[bucket], layout.dir_offsets[dir_index]);
+
+            dir_meta.write_le(&RomFsDirEntry {
+                parent,
+                sibling,
+                child_dir: link(&dir.child_dirs, 0, &layout.dir_offsets),
+                child_file: link(&dir.child_files, 0, &layout.file_offsets),
+                hash_next,
+                name: dir.name.clone(),
+            })?;
+        }
+
+        let mut file_buckets = vec![ROMFS_ENTRY_EMPTY; romfs_hash_table_count(self.files.len())];
+        let mut file_meta = Cursor::new(Vec::new());
+        for &file_index in layout.file_order.iter() {
+            let file = &self.files[file_index];
+            let parent = layout.dir_offsets[file.parent];
+            let siblings = &self.dirs[file.parent].child_files;
+            let position = siblings.iter().position(|&f| f == file_index).unwrap_or_default();
+
+            let bucket = romfs_path_hash(parent, file.name.as_bytes()) as usize % file_buckets.len();
+            let hash_next = std::mem::replace(&mut file_buckets[bucket], layout.file_offsets[file_index]);
+
+            file_meta.write_le(&RomFsFileEntry {
+                parent,
+                sibling: link(siblings, position + 1, &layout.file_offsets),
+                data_offset: layout.file_data_offsets[file_index],
+                data_size: file.size,
+                hash_next,
+                name: file.name.clone(),
+            })?;
+        }
+
+        let bucket_bytes = |buckets: Vec<u32>| buckets.into_iter().flat_map(u32::to_le_bytes).collect::<Vec<u8>>();
+        Ok([
+            bucket_bytes(dir_buckets),
+            dir_meta.into_inner(),
+            bucket_bytes(file_buckets),
+            file_meta.into_inner(),
+        ])
+    }
+
+    /// Write the RomFS image to `writer`, returning the number of bytes written.
+    pub fn write<W: Write>(mut self, writer: &mut W) -> BinResult<u64> {
+        let layout = self.layout();
+        let [dir_hash_table, dir_meta_table, file_hash_table, file_meta_table] = self.build_tables(&layout)?;
+
+        let dir_hash_table_offset = (ROMFS_DATA_OFFSET + layout.data_size).next_multiple_of(4);
+        let dir_meta_table_offset = dir_hash_table_offset + dir_hash_table.len() as u64;
+        let file_hash_table_offset = dir_meta_table_offset + dir_meta_table.len() as u64;
+        let file_meta_table_offset = file_hash_table_offset + file_hash_table.len() as u64;
+        let header = RomFsHeader {
+            header_size: 0x50,
+            dir_hash_table_offset,
+            dir_hash_table_size: dir_hash_table.len() as u64,
+            dir_meta_table_offset,
+            dir_meta_table_size: dir_meta_table.len() as u64,
+            file_hash_table_offset,
+            file_hash_table_size: file_hash_table.len() as u64,
+            file_meta_table_offset,
+            file_meta_table_size: file_meta_table.len() as u64,
+            data_offset: ROMFS_DATA_OFFSET,
+        };
+
+        let mut header_bytes = Cursor::new(Vec::new());
+        header_bytes.write_le(&header)?;
+        let mut header_bytes = header_bytes.into_inner();
+        header_bytes.resize(ROMFS_DATA_OFFSET as usize, 0);
+        writer.write_all(&header_bytes)?;
+
+        let mut position = 0;
+        let mut files: Vec<Option<RomFsBuilderFile<'a>>> = self.files.into_iter().map(Some).collect();
+        for &file_index in layout.file_order.iter() {
+            let file = files[file_index].take().expect("Each file is only written once");
+            let data_offset = layout.file_data_offsets[file_index];
+            write_zeros(writer, data_offset - position)?;
+
+            let mut reader: Box<dyn Read + 'a> = match file.source {
+                RomFsSource::Path(path) => Box::new(File::open(path)?),
+                RomFsSource::Reader(reader) => reader,
+            };
+            let copied = std::io::copy(&mut reader.by_ref().take(file.size), writer)?;
+            if copied != file.size {
+                return Err(binrw::Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("Expected {} bytes for RomFs entry \"{}\", only read {}", file.size, file.name, copied),
+                )));
+            }
+            position = data_offset + file.size;
+        }
+        write_zeros(writer, dir_hash_table_offset - ROMFS_DATA_OFFSET - position)?;
+
+        for table in [dir_hash_table, dir_meta_table, file_hash_table, file_meta_table] {
+            writer.write_all(&table)?;
+        }
+
+        Ok(file_meta_table_offset + header.file_meta_table_size)
+    }
+}
+
+fn write_zeros<W: Write>(writer: &mut W, count: u64) -> Result<u64> {
+    std::io::copy(&mut std::io::repeat(0).take(count), writer)
+}
@@ -0,0 +1,1200 @@
+//! Save data images, as found in the SYSTEM and USER partitions and (NAX0 encrypted) on the SD
+//! card.
+//!
+//! A save is a stack of storages, each addressing the one below it:
+//! - the data remap storage maps virtual offsets onto the file map data of the image
+//! - inside it, two duplex (DPFS) layers keep an A and a B copy of every metadata block, with a
+//!   bitmap selecting the current copy of each
+//! - the metadata remap storage maps virtual offsets onto the duplex data, and holds the journal
+//!   map, the IVFC hash levels and the allocation table
+//! - the journal storage maps the blocks of the file system onto blocks of the data remap storage
+//! - the IVFC data level of the journal storage is the SAVE file system, whose allocation table
+//!   chains blocks together into the directory table, the file table and the file data
+//!
+//! Files can be rewritten in place, after which [`SaveReader::commit`] brings the IVFC hashes,
+//! the duplex bitmaps and the header CMAC up to date.
+
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use aes::Aes128;
+use binrw::prelude::*;
+use cmac::Cmac;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::nca::fs::romfs::IvfcLevel;
+use super::romfs::join_path;
+use super::{SHA256Hash, Validity};
+use crate::keys::NcaKeys;
+use crate::utils::{serde_helpers, ReadSeek, ReaderType};
+
+/// Size of the save header, which is followed by the rest of the image
+pub const SAVE_HEADER_SIZE: u64 = 0x4000;
+/// Size of an entry in the directory and file tables
+pub const SAVE_FS_ENTRY_SIZE: u64 = 0x60;
+/// Maximum length of a file or directory name
+pub const SAVE_NAME_LENGTH: usize = 0x40;
+/// Part of the header covered by the layout hash
+const LAYOUT_HASH_RANGE: std::ops::Range<usize> = 0x300..0x4000;
+/// Part of the header covered by the CMAC, the file system layout
+const CMAC_RANGE: std::ops::Range<usize> = 0x100..0x300;
+const LAYOUT_HASH_OFFSET: usize = 0x108;
+const DUPLEX_INDEX_OFFSET: usize = 0x258;
+/// HMAC keys deriving the salt of each IVFC level from the salt source
+const IVFC_SALT_KEYS: [&str; 6] = [
+    "HierarchicalIntegrityVerificationStorage::Master",
+    "HierarchicalIntegrityVerificationStorage::L1",
+    "HierarchicalIntegrityVerificationStorage::L2",
+    "HierarchicalIntegrityVerificationStorage::L3",
+    "HierarchicalIntegrityVerificationStorage::L4",
+    "HierarchicalIntegrityVerificationStorage::L5",
+];
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct SaveHeader {
+    /// AES-CMAC of the layout, with the save MAC key
+    #[br(pad_after = 0xF0)]
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub cmac: [u8; 0x10],
+    pub layout: FsLayout,
+    pub duplex: DuplexHeader,
+    #[br(pad_after = 4)]
+    pub data_ivfc: SaveIvfcHeader,
+    pub journal: JournalHeader,
+    #[br(pad_after = 0x1D0)]
+    pub journal_map: JournalMapHeader,
+    pub save: SaveFsHeader,
+    pub fat: FatHeader,
+    pub main_remap: RemapHeader,
+    #[br(pad_after = 8)]
+    pub meta_remap: RemapHeader,
+    #[br(pad_after = 0x390)]
+    pub extra_data: ExtraData,
+    /// IVFC levels over the allocation table, from layout version 5
+    pub fat_ivfc: SaveIvfcHeader,
+}
+
+/// Location of every storage of the save. Offsets in the data and metadata remap storages are
+/// virtual offsets of those storages.
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"DISF")]
+pub struct FsLayout {
+    pub version: u32,
+    /// SHA-256 of the header from the duplex header to the end
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub hash: SHA256Hash,
+    /// Data remap entries, in the image
+    pub file_map_entry_offset: u64,
+    pub file_map_entry_size: u64,
+    /// Metadata remap entries, in the image
+    pub meta_map_entry_offset: u64,
+    pub meta_map_entry_size: u64,
+    /// Data mapped by the data remap storage, in the image
+    pub file_map_data_offset: u64,
+    pub file_map_data_size: u64,
+    /// Duplex bitmaps of the data layer, in the data remap storage
+    pub duplex_l1_offset_a: u64,
+    pub duplex_l1_offset_b: u64,
+    pub duplex_l1_size: u64,
+    /// Duplex data, in the data remap storage
+    pub duplex_data_offset_a: u64,
+    pub duplex_data_offset_b: u64,
+    pub duplex_data_size: u64,
+    /// Journal data, in the data remap storage
+    pub journal_data_offset: u64,
+    pub journal_data_size_a: u64,
+    pub journal_data_size_b: u64,
+    pub journal_size: u64,
+    /// Duplex bitmaps of the L1 layer, in the image
+    pub duplex_master_offset_a: u64,
+    pub duplex_master_offset_b: u64,
+    pub duplex_master_size: u64,
+    /// Top level hashes of the data IVFC, in the image
+    pub ivfc_master_hash_offset_a: u64,
+    pub ivfc_master_hash_offset_b: u64,
+    pub ivfc_master_hash_size: u64,
+    /// Journal map and bitmaps, in the metadata remap storage
+    pub journal_map_table_offset: u64,
+    pub journal_map_table_size: u64,
+    pub journal_physical_bitmap_offset: u64,
+    pub journal_physical_bitmap_size: u64,
+    pub journal_virtual_bitmap_offset: u64,
+    pub journal_virtual_bitmap_size: u64,
+    pub journal_free_bitmap_offset: u64,
+    pub journal_free_bitmap_size: u64,
+    /// Hash levels of the data IVFC, in the metadata remap storage
+    pub ivfc_l1_offset: u64,
+    pub ivfc_l1_size: u64,
+    pub ivfc_l2_offset: u64,
+    pub ivfc_l2_size: u64,
+    pub ivfc_l3_offset: u64,
+    pub ivfc_l3_size: u64,
+    /// Allocation table, in the metadata remap storage
+    pub fat_offset: u64,
+    pub fat_size: u64,
+    /// Whether the B copies of the duplex master bitmap and IVFC master hash are current
+    #[br(pad_after = 7)]
+    pub duplex_index: u8,
+    pub fat_ivfc_master_hash_a: u64,
+    pub fat_ivfc_master_hash_b: u64,
+    pub fat_ivfc_l1_offset: u64,
+    pub fat_ivfc_l1_size: u64,
+    pub fat_ivfc_l2_offset: u64,
+    #[br(pad_after = 0x70)]
+    pub fat_ivfc_l2_size: u64,
+}
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"DPFS")]
+pub struct DuplexHeader {
+    pub version: u32,
+    /// Master bitmap, L1 bitmap and data
+    pub layers: [DuplexLayer; 3],
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[br(little)]
+pub struct DuplexLayer {
+    pub offset: u64,
+    pub size: u64,
+    pub block_size_log2: u32,
+}
+
+/// IVFC hash tree of a save. Unlike in NCAs, the master hash is stored outside of the header.
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"IVFC")]
+pub struct SaveIvfcHeader {
+    pub version: u32,
+    pub master_hash_size: u32,
+    /// Number of levels, including the master hash
+    pub level_count: u32,
+    /// Hash levels, the last of which is the data
+    pub levels: [IvfcLevel; 6],
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub salt_source: [u8; 0x20],
+}
+
+impl SaveIvfcHeader {
+    /// The level holding the data, after the hash levels
+    pub fn data_level(&self) -> IvfcLevel {
+        self.levels[(self.level_count as usize).saturating_sub(2).min(self.levels.len() - 1)]
+    }
+}
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"JNGL")]
+pub struct JournalHeader {
+    pub version: u32,
+    pub total_size: u64,
+    pub journal_size: u64,
+    pub block_size: u64,
+}
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct JournalMapHeader {
+    pub version: u32,
+    /// Number of blocks of the journal storage, each mapped to a physical block
+    pub main_data_block_count: u32,
+    #[br(pad_after = 4)]
+    pub journal_block_count: u32,
+}
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"SAVE")]
+pub struct SaveFsHeader {
+    pub version: u32,
+    pub block_count: u64,
+    pub block_size: u64,
+}
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct FatHeader {
+    pub block_size: u64,
+    pub allocation_table_offset: u64,
+    #[br(pad_after = 4)]
+    pub allocation_table_block_count: u32,
+    pub data_offset: u64,
+    #[br(pad_after = 4)]
+    pub data_block_count: u32,
+    /// First block of the directory table
+    pub directory_table_block: u32,
+    /// First block of the file table
+    pub file_table_block: u32,
+}
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little, magic = b"RMAP")]
+pub struct RemapHeader {
+    pub version: u32,
+    pub map_entry_count: u32,
+    pub map_segment_count: u32,
+    /// Number of high bits of a virtual offset that select the segment
+    #[br(pad_after = 0x2C)]
+    pub segment_bits: u32,
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[br(little)]
+pub struct RemapEntry {
+    pub virtual_offset: u64,
+    pub physical_offset: u64,
+    pub size: u64,
+    #[br(pad_after = 4)]
+    pub alignment: u32,
+}
+
+/// Owner and size of the save, as shown by the system settings
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct ExtraData {
+    pub title_id: u64,
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub user_id: [u8; 0x10],
+    pub save_id: u64,
+    pub save_data_type: u8,
+    pub rank: u8,
+    #[br(pad_after = 0x1C)]
+    pub index: u16,
+    pub owner_id: u64,
+    /// Creation time, in seconds since the Unix epoch
+    pub timestamp: u64,
+    #[br(pad_after = 4)]
+    pub flags: u32,
+    pub data_size: u64,
+    pub journal_size: u64,
+    pub commit_id: u64,
+}
+
+impl ExtraData {
+    pub fn save_data_type_name(&self) -> &'static str {
+        match self.save_data_type {
+            0 => "System",
+            1 => "Account",
+            2 => "BCAT",
+            3 => "Device",
+            4 => "Temporary",
+            5 => "Cache",
+            6 => "System BCAT",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// Entry of the directory or file table. Entry 0 heads the free list and entry 1 the used list.
+#[binread]
+#[derive(Debug, Clone)]
+#[br(little)]
+pub struct SaveFsEntry<T: for<'a> BinRead<Args<'a> = ()> + 'static> {
+    /// Index of the parent directory
+    pub parent: u32,
+    #[br(map = |name: [u8; SAVE_NAME_LENGTH]| c_string(&name))]
+    pub name: String,
+    /// Index of the next entry in the parent directory
+    pub next_sibling: u32,
+    pub info: T,
+    /// Index of the next entry in the used or free list
+    pub next: u32,
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy)]
+#[br(little)]
+pub struct SaveFileInfo {
+    pub start_block: u32,
+    #[br(pad_after = 8)]
+    pub size: u64,
+}
+
+#[binread]
+#[derive(Debug, Clone, Copy)]
+#[br(little)]
+pub struct SaveDirectoryInfo {
+    /// Index of the first sub-directory in the directory table
+    pub child_dir: u32,
+    /// Index of the first file in the file table
+    #[br(pad_after = 0xC)]
+    pub child_file: u32,
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SaveFileRecord {
+    /// Path of the file relative to the save root, using `/` as the separator
+    pub path: String,
+    /// First block of the file data in the allocation table
+    pub start_block: u32,
+    pub size: u64,
+    /// Index of the entry in the file table
+    #[serde(skip)]
+    entry_index: u32,
+}
+
+/// Next field of an allocation table entry that starts a segment of consecutive blocks, whose
+/// last entry is given by the following entry
+const MULTI_BLOCK_SEGMENT: u32 = 0x8000_0000;
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Split `len` bytes at virtual `offset` of a remap storage into the parts mapped by each entry,
+/// as (offset into the range, physical offset, length)
+fn remap(entries: &[RemapEntry], offset: u64, len: usize) -> Result<Vec<(usize, u64, usize)>> {
+    let mut parts = Vec::new();
+    let mut done = 0;
+    while done < len {
+        let position = offset + done as u64;
+        let entry = entries
+            .iter()
+            .find(|entry| entry.virtual_offset <= position && position - entry.virtual_offset < entry.size)
+            .ok_or_else(|| invalid_data(format!("Virtual offset 0x{:X} is not mapped", position)))?;
+        let entry_offset = position - entry.virtual_offset;
+        let part_len = (len - done).min((entry.size - entry_offset) as usize);
+        parts.push((done, entry.physical_offset + entry_offset, part_len));
+        done += part_len;
+    }
+    Ok(parts)
+}
+
+/// Whether the B copy of `block` is current. The bitmap is made of little endian words, with
+/// the first block in the most significant bit.
+fn duplex_bit(bitmap: &[u8], block: usize) -> bool {
+    let word = bitmap.get(block / 32 * 4..block / 32 * 4 + 4).map_or(0, |word| u32::from_le_bytes(word.try_into().unwrap()));
+    word & (0x8000_0000 >> (block % 32)) != 0
+}
+
+/// Switch `block` over to its other copy
+fn flip_duplex_bit(bitmap: &mut [u8], block: usize) {
+    bitmap[block / 32 * 4 + 3 - block % 32 / 8] ^= 0x80 >> (block % 8);
+}
+
+/// Block of a duplex bitmap holding the bit of `block`
+fn duplex_bitmap_block(block: usize, bitmap_block_size: u64) -> usize {
+    block / 32 * 4 / bitmap_block_size as usize
+}
+
+/// Assemble a duplex layer of `size` bytes, with `read_copy` reading the A or B copy at an offset
+fn read_duplex(bitmap: &[u8], block_size: u64, size: u64, mut read_copy: impl FnMut(bool, u64, &mut [u8]) -> Result<()>) -> Result<Vec<u8>> {
+    let mut data = vec![0u8; size as usize];
+    for (block, chunk) in data.chunks_mut(block_size as usize).enumerate() {
+        read_copy(duplex_bit(bitmap, block), block as u64 * block_size, chunk)?;
+    }
+    Ok(data)
+}
+
+/// Salt of IVFC `level`, counting the first hash level below the master hash as 0
+fn ivfc_salt(salt_source: &[u8], level: usize) -> [u8; 0x20] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(IVFC_SALT_KEYS[level].as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(salt_source);
+    mac.finalize().into_bytes().into()
+}
+
+/// Hash of an IVFC block, zero padded to `block_size`. Saves set the top bit of the last byte,
+/// leaving all zero hashes to mark blocks that were never written.
+fn ivfc_hash(salt: &[u8], block: &[u8], block_size: u64) -> SHA256Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(block);
+    hasher.update(vec![0u8; (block_size as usize).saturating_sub(block.len())]);
+    let mut hash: SHA256Hash = hasher.finalize().into();
+    hash[0x1F] |= 0x80;
+    hash
+}
+
+fn header_cmac(header_data: &[u8], save_mac_key: &[u8; 0x10]) -> [u8; 0x10] {
+    let mut mac = <Cmac<Aes128> as Mac>::new_from_slice(save_mac_key).expect("the save MAC key is an AES-128 key");
+    mac.update(&header_data[CMAC_RANGE]);
+    mac.finalize().into_bytes().into()
+}
+
+fn read_at(reader: &mut ReaderType, offset: u64, buf: &mut [u8]) -> Result<()> {
+    match reader {
+        ReaderType::Mapped(map) => {
+            let data = usize::try_from(offset).ok().and_then(|start| map.get(start..start.checked_add(buf.len())?));
+            buf.copy_from_slice(data.ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Read past the end of the save"))?);
+            Ok(())
+        }
+        ReaderType::Raw(f) => {
+            f.seek(SeekFrom::Start(offset))?;
+            f.read_exact(buf)
+        }
+        ReaderType::Stream(r) => {
+            r.seek(SeekFrom::Start(offset))?;
+            r.read_exact(buf)
+        }
+    }
+}
+
+fn image_size(reader: &mut ReaderType) -> Result<u64> {
+    match reader {
+        ReaderType::Mapped(map) => Ok(map.len() as u64),
+        ReaderType::Raw(f) => f.seek(SeekFrom::End(0)),
+        ReaderType::Stream(r) => r.seek(SeekFrom::End(0)),
+    }
+}
+
+/// Check the header fields used as divisors, shifts and buffer sizes. None of them can exceed the
+/// image they describe.
+fn validate_header(header: &SaveHeader, image_size: u64) -> Result<()> {
+    let layout = &header.layout;
+    let sizes = [
+        ("journal block size", header.journal.block_size),
+        ("file system block size", header.save.block_size),
+    ];
+    for (name, size) in sizes {
+        if size == 0 || size > image_size {
+            return Err(invalid_data(format!("The {} 0x{:X} is invalid", name, size)));
+        }
+    }
+
+    let duplex_levels = header.duplex.layers.iter().map(|layer| layer.block_size_log2);
+    let level_count = (header.data_ivfc.level_count as usize).saturating_sub(1);
+    let ivfc_levels = header.data_ivfc.levels.iter().take(level_count).map(|level| level.block_size_log2);
+    for block_size_log2 in duplex_levels.chain(ivfc_levels) {
+        if 1u64.checked_shl(block_size_log2).is_none_or(|block_size| block_size > image_size) {
+            return Err(invalid_data(format!("The block size 2^{} is larger than the save", block_size_log2)));
+        }
+    }
+
+    let regions = [
+        ("data remap table", u64::from(header.main_remap.map_entry_count) * 0x20),
+        ("metadata remap table", u64::from(header.meta_remap.map_entry_count) * 0x20),
+        ("duplex master bitmap", layout.duplex_master_size),
+        ("duplex L1 bitmap", layout.duplex_l1_size),
+        ("duplex data", layout.duplex_data_size),
+        ("IVFC master hash", layout.ivfc_master_hash_size),
+        ("journal map", u64::from(header.journal_map.main_data_block_count) * 8),
+        ("allocation table", layout.fat_size),
+    ];
+    match regions.into_iter().find(|&(_, size)| size > image_size) {
+        Some((name, size)) => Err(invalid_data(format!("The {} size 0x{:X} is larger than the save", name, size))),
+        None => Ok(()),
+    }
+}
+
+/// Relative path to extract a save entry to. Names that would leave the output folder are rejected.
+fn extract_path(path: &str) -> Result<&Path> {
+    let relative = Path::new(path);
+    match relative.components().all(|component| matches!(component, std::path::Component::Normal(_))) {
+        true => Ok(relative),
+        false => Err(invalid_data(format!("Refusing to extract {} outside of the output folder", path))),
+    }
+}
+
+fn write_at(reader: &mut ReaderType, offset: u64, data: &[u8]) -> Result<()> {
+    match reader {
+        ReaderType::Raw(f) => {
+            f.seek(SeekFrom::Start(offset))?;
+            f.write_all(data)
+        }
+        _ => Err(Error::new(ErrorKind::Unsupported, "Only save files can be written to")),
+    }
+}
+
+#[derive(Debug)]
+pub struct SaveReader {
+    reader: ReaderType,
+    /// Key signing the header on commit, set when the save was opened with
+    /// [`SaveReader::parse_file_writable`]
+    save_mac_key: Option<[u8; 0x10]>,
+    /// Size of the image, which bounds every buffer read from it
+    image_size: u64,
+    pub header: SaveHeader,
+    /// The raw header, which the layout hash and CMAC cover
+    header_data: Vec<u8>,
+    data_remap: Vec<RemapEntry>,
+    meta_remap: Vec<RemapEntry>,
+    /// The current master bitmap, selecting the copy of each L1 bitmap block
+    duplex_master: Vec<u8>,
+    /// The L1 bitmap, selecting the copy of each duplex data block
+    duplex_l1: Vec<u8>,
+    /// The duplex data layer, which the metadata remap storage maps onto
+    meta_data: Vec<u8>,
+    /// Physical block of each block of the journal storage
+    journal_map: Vec<u32>,
+    /// Next field of each allocation table entry. Entry `n + 1` describes block `n`; entry 0 heads
+    /// the free list.
+    allocation_table: Vec<u32>,
+    directory_table: Vec<SaveFsEntry<SaveDirectoryInfo>>,
+    file_table: Vec<SaveFsEntry<SaveFileInfo>>,
+    /// Duplex data blocks changed since the last commit
+    dirty_meta_blocks: BTreeSet<usize>,
+    /// Ranges of the journal storage written since the last commit, as (offset, length)
+    dirty_journal: Vec<(u64, u64)>,
+}
+
+impl Serialize for SaveReader {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("SaveReader", 2)?;
+        state.serialize_field("header", &self.header)?;
+        state.serialize_field("files", &self.files().map_err(serde::ser::Error::custom)?)?;
+        state.end()
+    }
+}
+
+impl SaveReader {
+    pub fn parse_file<P: AsRef<Path>>(save_file: P) -> BinResult<SaveReader> {
+        Self::from_reader_type(ReaderType::Raw(File::open(save_file.as_ref())?))
+    }
+
+    /// Open a save for [`SaveReader::write_file`] and [`SaveReader::commit`]. `save_mac_key` is
+    /// needed to sign the header, so the save is not opened without it.
+    pub fn parse_file_writable<P: AsRef<Path>>(save_file: P, key_set: &NcaKeys) -> BinResult<SaveReader> {
+        if key_set.console.save_mac_key == [0; 0x10] {
+            return Err(Error::new(ErrorKind::InvalidInput, "save_mac_key is needed to sign the save").into());
+        }
+        let file = OpenOptions::new().read(true).write(true).open(save_file.as_ref())?;
+        let mut save = Self::from_reader_type(ReaderType::Raw(file))?;
+        save.save_mac_key = Some(key_set.console.save_mac_key);
+        Ok(save)
+    }
+
+    pub fn parse_file_mmap<P: AsRef<Path>>(save_file: P) -> BinResult<SaveReader> {
+        let memmap = unsafe { memmap::MmapOptions::new().map(&File::open(save_file.as_ref())?)? };
+        Self::from_reader_type(ReaderType::Mapped(memmap))
+    }
+
+    /// Parse a save starting at the start of `reader`
+    pub fn parse_reader<R: ReadSeek + 'static>(reader: R) -> BinResult<SaveReader> {
+        Self::from_reader_type(ReaderType::Stream(Box::new(reader)))
+    }
+
+    fn from_reader_type(mut reader: ReaderType) -> BinResult<SaveReader> {
+        let mut header_data = vec![0u8; SAVE_HEADER_SIZE as usize];
+        read_at(&mut reader, 0, &mut header_data)?;
+        let header: SaveHeader = Cursor::new(&header_data).read_le()?;
+        let image_size = image_size(&mut reader)?;
+        validate_header(&header, image_size)?;
+
+        let mut read_remap_entries = |offset: u64, count: u32| -> BinResult<Vec<RemapEntry>> {
+            let mut data = vec![0u8; count as usize * 0x20];
+            read_at(&mut reader, offset, &mut data)?;
+            Cursor::new(data).read_le_args(binrw::VecArgs { count: count as usize, inner: () })
+        };
+        let data_remap = read_remap_entries(header.layout.file_map_entry_offset, header.main_remap.map_entry_count)?;
+        let meta_remap = read_remap_entries(header.layout.meta_map_entry_offset, header.meta_remap.map_entry_count)?;
+
+        let mut save = SaveReader {
+            reader,
+            save_mac_key: None,
+            image_size,
+            header,
+            header_data,
+            data_remap,
+            meta_remap,
+            duplex_master: Vec::new(),
+            duplex_l1: Vec::new(),
+            meta_data: Vec::new(),
+            journal_map: Vec::new(),
+            allocation_table: Vec::new(),
+            directory_table: Vec::new(),
+            file_table: Vec::new(),
+            dirty_meta_blocks: BTreeSet::new(),
+            dirty_journal: Vec::new(),
+        };
+        save.read_duplex_data()?;
+
+        let layout = &save.header.layout;
+        let journal_map_size = u64::from(save.header.journal_map.main_data_block_count) * 8;
+        let (journal_map_offset, fat_offset, fat_size) = (layout.journal_map_table_offset, layout.fat_offset, layout.fat_size);
+        let journal_map = save.read_meta(journal_map_offset, journal_map_size)?;
+        save.journal_map = journal_map.chunks_exact(8).map(|entry| u32::from_le_bytes(entry[..4].try_into().unwrap()) & 0x7FFF_FFFF).collect();
+        let allocation_table = save.read_meta(fat_offset, fat_size)?;
+        save.allocation_table = allocation_table.chunks_exact(8).map(|entry| u32::from_le_bytes(entry[4..].try_into().unwrap())).collect();
+
+        let (directory_table_block, file_table_block) = (save.header.fat.directory_table_block, save.header.fat.file_table_block);
+        save.directory_table = save.read_table(directory_table_block)?;
+        save.file_table = save.read_table(file_table_block)?;
+        Ok(save)
+    }
+
+    /// Read from the data remap storage
+    fn read_data(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let base = self.header.layout.file_map_data_offset;
+        for (start, physical, len) in remap(&self.data_remap, offset, buf.len())? {
+            read_at(&mut self.reader, base + physical, &mut buf[start..start + len])?;
+        }
+        Ok(())
+    }
+
+    /// Write to the data remap storage
+    fn write_data(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let base = self.header.layout.file_map_data_offset;
+        for (start, physical, len) in remap(&self.data_remap, offset, data.len())? {
+            write_at(&mut self.reader, base + physical, &data[start..start + len])?;
+        }
+        Ok(())
+    }
+
+    /// Assemble the bitmaps and the duplex data layer from the current copy of each block
+    fn read_duplex_data(&mut self) -> Result<()> {
+        let layout = &self.header.layout;
+        let master_offset = match layout.duplex_index {
+            0 => layout.duplex_master_offset_a,
+            _ => layout.duplex_master_offset_b,
+        };
+        let mut master = vec![0u8; layout.duplex_master_size as usize];
+        let [_, l1_layer, data_layer] = self.header.duplex.layers;
+        let (l1_a, l1_b, l1_size) = (layout.duplex_l1_offset_a, layout.duplex_l1_offset_b, layout.duplex_l1_size);
+        let (data_a, data_b, data_size) = (layout.duplex_data_offset_a, layout.duplex_data_offset_b, layout.duplex_data_size);
+        self.read_region(master_offset, &mut master)?;
+
+        let l1 = read_duplex(&master, 1 << l1_layer.block_size_log2, l1_size, |is_b, offset, buf| {
+            self.read_data(if is_b { l1_b } else { l1_a } + offset, buf)
+        })?;
+        self.meta_data = read_duplex(&l1, 1 << data_layer.block_size_log2, data_size, |is_b, offset, buf| {
+            self.read_data(if is_b { data_b } else { data_a } + offset, buf)
+        })?;
+        self.duplex_master = master;
+        self.duplex_l1 = l1;
+        Ok(())
+    }
+
+    /// Read from the metadata remap storage
+    fn read_meta(&self, offset: u64, size: u64) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; size as usize];
+        for (start, physical, len) in remap(&self.meta_remap, offset, data.len())? {
+            data[start..start + len].copy_from_slice(self.meta_range(physical, len)?);
+        }
+        Ok(data)
+    }
+
+    /// Write to the metadata remap storage. The changes reach the image on commit.
+    fn write_meta(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let block_size = 1u64 << self.header.duplex.layers[2].block_size_log2;
+        for (start, physical, len) in remap(&self.meta_remap, offset, data.len())? {
+            self.meta_range(physical, len)?;
+            let physical_start = physical as usize;
+            self.meta_data[physical_start..physical_start + len].copy_from_slice(&data[start..start + len]);
+            let (first, last) = (physical / block_size, (physical + len as u64 - 1) / block_size);
+            self.dirty_meta_blocks.extend(first as usize..=last as usize);
+        }
+        Ok(())
+    }
+
+    fn meta_range(&self, physical: u64, len: usize) -> Result<&[u8]> {
+        usize::try_from(physical)
+            .ok()
+            .and_then(|start| self.meta_data.get(start..start.checked_add(len)?))
+            .ok_or_else(|| invalid_data(format!("Metadata offset 0x{:X} is outside of the duplex data", physical)))
+    }
+
+    /// Split `len` bytes at `offset` of the journal storage into the parts held by each block, as
+    /// (offset into the range, offset in the data remap storage, length)
+    fn journal(&self, offset: u64, len: usize) -> Result<Vec<(usize, u64, usize)>> {
+        let block_size = self.header.journal.block_size;
+        let mut parts = Vec::new();
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let block = (position / block_size) as usize;
+            let physical_block = *self
+                .journal_map
+                .get(block)
+                .ok_or_else(|| invalid_data(format!("Journal block {} is not mapped", block)))?;
+            let block_offset = position % block_size;
+            let part_len = (len - done).min((block_size - block_offset) as usize);
+            let data_offset = u64::from(physical_block)
+                .checked_mul(block_size)
+                .and_then(|physical_offset| physical_offset.checked_add(self.header.layout.journal_data_offset + block_offset))
+                .ok_or_else(|| invalid_data(format!("Journal block {} is outside of the save", physical_block)))?;
+            parts.push((done, data_offset, part_len));
+            done += part_len;
+        }
+        Ok(parts)
+    }
+
+    fn read_journal(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        for (start, data_offset, len) in self.journal(offset, buf.len())? {
+            self.read_data(data_offset, &mut buf[start..start + len])?;
+        }
+        Ok(())
+    }
+
+    /// Write to the journal storage, keeping track of the range for the IVFC update on commit
+    fn write_journal(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        for (start, data_offset, len) in self.journal(offset, data.len())? {
+            self.write_data(data_offset, &data[start..start + len])?;
+        }
+        self.dirty_journal.push((offset, data.len() as u64));
+        Ok(())
+    }
+
+    /// Read from the SAVE file system, the data level of the journal storage
+    fn read_fs(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.read_journal(self.header.data_ivfc.data_level().logical_offset + offset, buf)
+    }
+
+    fn write_fs(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        self.write_journal(self.header.data_ivfc.data_level().logical_offset + offset, data)
+    }
+
+    /// Segments of consecutive blocks making up the chain starting at `start_block`, as
+    /// (first block, block count)
+    fn chain(&self, start_block: u32) -> Result<Vec<(u32, u32)>> {
+        let next = |index: Option<u32>| {
+            let index = index.ok_or_else(|| invalid_data("Allocation table entry index overflows".to_string()))?;
+            self.allocation_table
+                .get(index as usize)
+                .copied()
+                .ok_or_else(|| invalid_data(format!("Allocation table entry {} is out of range", index)))
+        };
+
+        let mut segments = Vec::new();
+        let mut block = start_block;
+        loop {
+            let first = next(block.checked_add(1))?;
+            let count = match first & MULTI_BLOCK_SEGMENT != 0 {
+                true => (next(block.checked_add(2))? & !MULTI_BLOCK_SEGMENT).checked_sub(block).filter(|&count| count > 0),
+                false => Some(1),
+            }
+            .ok_or_else(|| invalid_data(format!("Invalid segment at block {}", block)))?;
+            segments.push((block, count));
+            if segments.len() > self.allocation_table.len() {
+                return Err(invalid_data(format!("Block chain starting at {} loops", start_block)));
+            }
+            // Entry index of the next segment, or 0 at the end of the chain
+            match first & !MULTI_BLOCK_SEGMENT {
+                0 => return Ok(segments),
+                next => block = next - 1,
+            }
+        }
+    }
+
+    /// Size of a segment of `count` blocks, which has to fit in the image
+    fn segment_size(&self, count: u32) -> Result<u64> {
+        u64::from(count)
+            .checked_mul(self.header.save.block_size)
+            .filter(|&size| size <= self.image_size)
+            .ok_or_else(|| invalid_data(format!("A segment of {} blocks is larger than the save", count)))
+    }
+
+    /// Read a directory or file table from its chain of blocks
+    fn read_table<T: for<'a> BinRead<Args<'a> = ()> + 'static>(&mut self, start_block: u32) -> BinResult<Vec<SaveFsEntry<T>>> {
+        let block_size = self.header.save.block_size;
+        let mut data = Vec::new();
+        for (block, count) in self.chain(start_block)? {
+            let start = data.len();
+            let end = (start as u64).checked_add(self.segment_size(count)?).filter(|&end| end <= self.image_size);
+            data.resize(end.ok_or_else(|| invalid_data(format!("The table at block {} is larger than the save", start_block)))? as usize, 0);
+            self.read_fs(u64::from(block) * block_size, &mut data[start..])?;
+        }
+        let count = data.len() / SAVE_FS_ENTRY_SIZE as usize;
+        Cursor::new(data).read_le_args(binrw::VecArgs { count, inner: () })
+    }
+
+    /// Index of the root directory, the used entry without a parent or name
+    fn root_directory(&self) -> Result<u32> {
+        let mut index = self.directory_table.get(1).map_or(0, |head| head.next);
+        for _ in 0..self.directory_table.len() {
+            let entry = self
+                .directory_table
+                .get(index as usize)
+                .filter(|_| index != 0)
+                .ok_or_else(|| invalid_data("The save has no root directory".to_string()))?;
+            if entry.parent == 0 && entry.name.is_empty() {
+                return Ok(index);
+            }
+            index = entry.next;
+        }
+        Err(invalid_data("The directory table loops".to_string()))
+    }
+
+    fn walk(&self, dir_index: u32, dir_path: &str, depth: usize, on_dir: &mut dyn FnMut(&str), on_file: &mut dyn FnMut(SaveFileRecord)) -> Result<()> {
+        // Every directory is visited once, so a deeper walk means the tree loops
+        if depth > self.directory_table.len() {
+            return Err(invalid_data("The directory tree loops".to_string()));
+        }
+        let dir = self.directory_entry(dir_index)?;
+
+        let mut file_index = dir.info.child_file;
+        for _ in 0..self.file_table.len() {
+            if file_index == 0 {
+                break;
+            }
+            let file = self
+                .file_table
+                .get(file_index as usize)
+                .ok_or_else(|| invalid_data(format!("File entry {} is out of range", file_index)))?;
+            on_file(SaveFileRecord {
+                path: join_path(dir_path, &file.name),
+                start_block: file.info.start_block,
+                size: file.info.size,
+                entry_index: file_index,
+            });
+            file_index = file.next_sibling;
+        }
+
+        let mut child_index = dir.info.child_dir;
+        while child_index != 0 {
+            let child = self.directory_entry(child_index)?;
+            let child_path = join_path(dir_path, &child.name);
+            on_dir(&child_path);
+            self.walk(child_index, &child_path, depth + 1, on_dir, on_file)?;
+            child_index = child.next_sibling;
+        }
+        Ok(())
+    }
+
+    fn directory_entry(&self, index: u32) -> Result<&SaveFsEntry<SaveDirectoryInfo>> {
+        self.directory_table
+            .get(index as usize)
+            .ok_or_else(|| invalid_data(format!("Directory entry {} is out of range", index)))
+    }
+
+    /// All directories below the root, parents before children
+    pub fn directories(&self) -> BinResult<Vec<String>> {
+        let mut directories = Vec::new();
+        self.walk(self.root_directory()?, "", 0, &mut |path| directories.push(path.to_string()), &mut |_| ())?;
+        Ok(directories)
+    }
+
+    /// All files in the save, in directory order
+    pub fn files(&self) -> BinResult<Vec<SaveFileRecord>> {
+        let mut files = Vec::new();
+        self.walk(self.root_directory()?, "", 0, &mut |_| (), &mut |record| files.push(record))?;
+        Ok(files)
+    }
+
+    pub fn find_file<S: AsRef<str>>(&self, path: S) -> BinResult<Option<SaveFileRecord>> {
+        let path = path.as_ref().trim_matches('/');
+        Ok(self.files()?.into_iter().find(|file| file.path == path))
+    }
+
+    /// Write the data of a file, returning its size
+    pub fn read_file_into(&mut self, file: &SaveFileRecord, writer: &mut dyn Write) -> BinResult<u64> {
+        if file.size == 0 {
+            return Ok(0);
+        }
+        let block_size = self.header.save.block_size;
+        let mut remaining = file.size;
+        let mut buf = Vec::new();
+        for (block, count) in self.chain(file.start_block)? {
+            let len = remaining.min(self.segment_size(count)?);
+            buf.resize(len as usize, 0);
+            self.read_fs(u64::from(block) * block_size, &mut buf)?;
+            writer.write_all(&buf)?;
+            remaining -= len;
+            if remaining == 0 {
+                return Ok(file.size);
+            }
+        }
+        Err(invalid_data(format!("The blocks of {} are shorter than its size", file.path)).into())
+    }
+
+    /// Extract every directory and file into `output_folder`
+    pub fn extract_to<P: AsRef<Path>>(&mut self, output_folder: P) -> BinResult<()> {
+        let output_folder = output_folder.as_ref();
+        std::fs::create_dir_all(output_folder)?;
+        for dir in self.directories()? {
+            std::fs::create_dir_all(output_folder.join(extract_path(&dir)?))?;
+        }
+        for file in self.files()? {
+            self.read_file_into(&file, &mut File::create(output_folder.join(extract_path(&file.path)?))?)?;
+        }
+        Ok(())
+    }
+
+    /// Compare the header from the duplex header onwards against the layout hash
+    pub fn verify_layout_hash(&self) -> Validity {
+        match Sha256::digest(&self.header_data[LAYOUT_HASH_RANGE]).as_slice() == self.header.layout.hash {
+            true => Validity::Valid,
+            false => Validity::Invalid,
+        }
+    }
+
+    /// Compare the file system layout against the header CMAC, keyed with `save_mac_key`
+    pub fn verify_header_cmac(&self, key_set: &NcaKeys) -> Validity {
+        if key_set.console.save_mac_key == [0; 0x10] {
+            return Validity::Unchecked;
+        }
+        match header_cmac(&self.header_data, &key_set.console.save_mac_key) == self.header.cmac {
+            true => Validity::Valid,
+            false => Validity::Invalid,
+        }
+    }
+
+    /// Check every block of the data IVFC against the level above it, up to the master hash.
+    /// Blocks with an all zero hash have never been written and are skipped.
+    pub fn verify_ivfc(&mut self) -> Validity {
+        match self.check_ivfc() {
+            Ok(true) => Validity::Valid,
+            Ok(false) => Validity::Invalid,
+            Err(_) => Validity::CheckError,
+        }
+    }
+
+    fn check_ivfc(&mut self) -> Result<bool> {
+        let Some(data_level) = self.ivfc_data_level() else {
+            return Ok(true);
+        };
+        let (levels, salt_source) = (self.header.data_ivfc.levels, self.header.data_ivfc.salt_source);
+        let mut hashes = self.read_ivfc_master_hash()?;
+        for (level, info) in levels.iter().enumerate().take(data_level + 1) {
+            let block_size = 1u64 << info.block_size_log2;
+            let salt = ivfc_salt(&salt_source, level);
+            let mut level_data = Vec::new();
+            let mut block = vec![0u8; block_size as usize];
+            for (index, start) in (0..info.hash_data_size).step_by(block_size as usize).enumerate() {
+                let len = block_size.min(info.hash_data_size - start) as usize;
+                self.read_ivfc_level(level, start, &mut block[..len])?;
+                let stored = hashes
+                    .get(index * 0x20..(index + 1) * 0x20)
+                    .ok_or_else(|| invalid_data(format!("IVFC level {} has no hash for block {}", level, index)))?;
+                if stored.iter().any(|&b| b != 0) && stored != ivfc_hash(&salt, &block[..len], block_size) {
+                    return Ok(false);
+                }
+                if level < data_level {
+                    level_data.extend_from_slice(&block[..len]);
+                }
+            }
+            hashes = level_data;
+        }
+        Ok(true)
+    }
+
+    /// Index of the data level of the data IVFC, if it has hash levels at all
+    fn ivfc_data_level(&self) -> Option<usize> {
+        let level_count = self.header.data_ivfc.level_count as usize;
+        (2..=self.header.data_ivfc.levels.len() + 1).contains(&level_count).then(|| level_count - 2)
+    }
+
+    fn read_ivfc_master_hash(&mut self) -> Result<Vec<u8>> {
+        let layout = &self.header.layout;
+        let offset = match layout.duplex_index {
+            0 => layout.ivfc_master_hash_offset_a,
+            _ => layout.ivfc_master_hash_offset_b,
+        };
+        let mut master_hash = vec![0u8; layout.ivfc_master_hash_size as usize];
+        self.read_region(offset, &mut master_hash)?;
+        Ok(master_hash)
+    }
+
+    /// Read a region given by a layout offset. Regions inside the header come from `header_data`,
+    /// which holds the changes not committed yet.
+    fn read_region(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        match self.header_range(offset, buf.len()) {
+            Some(range) => {
+                buf.copy_from_slice(&self.header_data[range]);
+                Ok(())
+            }
+            None => read_at(&mut self.reader, offset, buf),
+        }
+    }
+
+    /// Write a region given by a layout offset. Regions inside the header go to `header_data`, so
+    /// they are covered by the layout hash and CMAC and written out by [`SaveReader::commit`].
+    fn write_region(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        match self.header_range(offset, data.len()) {
+            Some(range) => {
+                self.header_data[range].copy_from_slice(data);
+                Ok(())
+            }
+            None => write_at(&mut self.reader, offset, data),
+        }
+    }
+
+    fn header_range(&self, offset: u64, len: usize) -> Option<std::ops::Range<usize>> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(len)?;
+        (end <= self.header_data.len()).then_some(start..end)
+    }
+
+    /// Read from a level of the data IVFC. Hash levels are in the metadata remap storage and the
+    /// data level is in the journal storage.
+    fn read_ivfc_level(&mut self, level: usize, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let offset = self.header.data_ivfc.levels[level].logical_offset + offset;
+        match Some(level) == self.ivfc_data_level() {
+            true => self.read_journal(offset, buf),
+            false => {
+                buf.copy_from_slice(&self.read_meta(offset, buf.len() as u64)?);
+                Ok(())
+            }
+        }
+    }
+
+    /// Replace the data of a file in place. The file keeps its blocks, so `data` must fit in
+    /// them. The save is only valid again once [`SaveReader::commit`] has been called.
+    pub fn write_file<S: AsRef<str>>(&mut self, path: S, data: &[u8]) -> BinResult<()> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        let file = self
+            .find_file(path)?
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{} is not in the save", path)))?;
+        let block_size = self.header.save.block_size;
+        let capacity = match file.size == 0 && file.start_block & MULTI_BLOCK_SEGMENT != 0 {
+            true => 0,
+            false => self.chain(file.start_block)?.iter().map(|&(_, count)| u64::from(count) * block_size).sum(),
+        };
+        if data.len() as u64 > capacity {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("0x{:X} bytes do not fit the 0x{:X} bytes allocated to {}", data.len(), capacity, file.path),
+            )
+            .into());
+        }
+
+        if !data.is_empty() {
+            self.write_chain(file.start_block, 0, data)?;
+        }
+        let size_offset = u64::from(file.entry_index) * SAVE_FS_ENTRY_SIZE + 0x4C;
+        self.write_chain(self.header.fat.file_table_block, size_offset, &(data.len() as u64).to_le_bytes())?;
+        self.file_table[file.entry_index as usize].info.size = data.len() as u64;
+        Ok(())
+    }
+
+    /// Write `data` at `offset` of the chain of blocks starting at `start_block`
+    fn write_chain(&mut self, start_block: u32, offset: u64, data: &[u8]) -> Result<()> {
+        let block_size = self.header.save.block_size;
+        let end = offset + data.len() as u64;
+        let mut segment_start = 0;
+        for (block, count) in self.chain(start_block)? {
+            let segment_end = segment_start + u64::from(count) * block_size;
+            let (start, stop) = (offset.max(segment_start), end.min(segment_end));
+            if start < stop {
+                let fs_offset = u64::from(block) * block_size + start - segment_start;
+                self.write_fs(fs_offset, &data[(start - offset) as usize..(stop - offset) as usize])?;
+            }
+            segment_start = segment_end;
+        }
+        match segment_start >= end {
+            true => Ok(()),
+            false => Err(invalid_data(format!("Block chain starting at {} is shorter than 0x{:X} bytes", start_block, end))),
+        }
+    }
+
+    /// The key to sign the header with, if the save was opened for writing
+    fn check_writable(&self) -> Result<[u8; 0x10]> {
+        self.save_mac_key
+            .ok_or_else(|| Error::new(ErrorKind::PermissionDenied, "The save was not opened for writing"))
+    }
+
+    /// Bring the save up to date after [`SaveReader::write_file`]: rehash the changed IVFC blocks,
+    /// write the changed duplex blocks to their other copy and switch over to it, then update the
+    /// layout hash and sign the header with the `save_mac_key` the save was opened with.
+    pub fn commit(&mut self) -> BinResult<()> {
+        let save_mac_key = self.check_writable()?;
+        self.update_ivfc()?;
+        self.write_duplex()?;
+
+        let layout_hash = Sha256::digest(&self.header_data[LAYOUT_HASH_RANGE]);
+        self.header_data[LAYOUT_HASH_OFFSET..LAYOUT_HASH_OFFSET + 0x20].copy_from_slice(&layout_hash);
+        let cmac = header_cmac(&self.header_data, &save_mac_key);
+        self.header_data[..0x10].copy_from_slice(&cmac);
+        write_at(&mut self.reader, 0, &self.header_data)?;
+        self.header = Cursor::new(&self.header_data).read_le()?;
+        Ok(())
+    }
+
+    /// Rehash the blocks of each IVFC level covering the journal ranges written since the last
+    /// commit, from the data level up to the master hash
+    fn update_ivfc(&mut self) -> Result<()> {
+        let dirty_journal = std::mem::take(&mut self.dirty_journal);
+        let Some(data_level) = self.ivfc_data_level() else {
+            return Ok(());
+        };
+        let (levels, salt_source) = (self.header.data_ivfc.levels, self.header.data_ivfc.salt_source);
+        let block_size = |level: usize| 1u64 << levels[level].block_size_log2;
+
+        let data_offset = levels[data_level].logical_offset;
+        let mut dirty_blocks: BTreeSet<u64> = BTreeSet::new();
+        for (offset, len) in dirty_journal {
+            let start = offset.saturating_sub(data_offset);
+            let end = (offset + len).saturating_sub(data_offset).min(levels[data_level].hash_data_size);
+            if start < end {
+                dirty_blocks.extend(start / block_size(data_level)..=(end - 1) / block_size(data_level));
+            }
+        }
+
+        let mut master_hash = self.read_ivfc_master_hash()?;
+        for level in (0..=data_level).rev() {
+            let salt = ivfc_salt(&salt_source, level);
+            let mut parent_blocks = BTreeSet::new();
+            for block in std::mem::take(&mut dirty_blocks) {
+                let start = block * block_size(level);
+                let mut data = vec![0u8; block_size(level).min(levels[level].hash_data_size - start) as usize];
+                self.read_ivfc_level(level, start, &mut data)?;
+                let hash = ivfc_hash(&salt, &data, block_size(level));
+                let hash_offset = block * 0x20;
+                match level {
+                    0 => master_hash
+                        .get_mut(hash_offset as usize..hash_offset as usize + 0x20)
+                        .ok_or_else(|| invalid_data(format!("The IVFC master hash has no hash for block {}", block)))?
+                        .copy_from_slice(&hash),
+                    _ => {
+                        self.write_meta(levels[level - 1].logical_offset + hash_offset, &hash)?;
+                        parent_blocks.insert(hash_offset / block_size(level - 1));
+                    }
+                }
+            }
+            dirty_blocks = parent_blocks;
+        }
+
+        let (master_a, master_b) = (self.header.layout.ivfc_master_hash_offset_a, self.header.layout.ivfc_master_hash_offset_b);
+        self.write_region(master_a, &master_hash)?;
+        self.write_region(master_b, &master_hash)
+    }
+
+    /// Write each changed duplex data block to its other copy and flip its L1 bit, then do the
+    /// same for the changed L1 blocks with the master bitmap. The new master bitmap goes to the
+    /// other master slot, which the flipped duplex index then selects.
+    fn write_duplex(&mut self) -> Result<()> {
+        let layout = &self.header.layout;
+        let [_, l1_layer, data_layer] = self.header.duplex.layers;
+        let (l1_block_size, data_block_size) = (1u64 << l1_layer.block_size_log2, 1u64 << data_layer.block_size_log2);
+        let (l1_a, l1_b) = (layout.duplex_l1_offset_a, layout.duplex_l1_offset_b);
+        let (data_a, data_b) = (layout.duplex_data_offset_a, layout.duplex_data_offset_b);
+        let inactive_master = match layout.duplex_index {
+            0 => layout.duplex_master_offset_b,
+            _ => layout.duplex_master_offset_a,
+        };
+
+        let mut dirty_l1_blocks = BTreeSet::new();
+        for block in std::mem::take(&mut self.dirty_meta_blocks) {
+            let start = block * data_block_size as usize;
+            let data = self.meta_data[start..(start + data_block_size as usize).min(self.meta_data.len())].to_vec();
+            let copy = if duplex_bit(&self.duplex_l1, block) { data_a } else { data_b };
+            self.write_data(copy + start as u64, &data)?;
+            flip_duplex_bit(&mut self.duplex_l1, block);
+            dirty_l1_blocks.insert(duplex_bitmap_block(block, l1_block_size));
+        }
+        for block in dirty_l1_blocks {
+            let start = block * l1_block_size as usize;
+            let data = self.duplex_l1[start..(start + l1_block_size as usize).min(self.duplex_l1.len())].to_vec();
+            let copy = if duplex_bit(&self.duplex_master, block) { l1_a } else { l1_b };
+            self.write_data(copy + start as u64, &data)?;
+            flip_duplex_bit(&mut self.duplex_master, block);
+        }
+
+        let master = std::mem::take(&mut self.duplex_master);
+        self.write_region(inactive_master, &master)?;
+        self.duplex_master = master;
+        self.header_data[DUPLEX_INDEX_OFFSET] ^= 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+//! The `Nintendo` folder of an SD card, where installed content and save data are stored as
+//! NAX0 files encrypted with keys derived from the console's SD seed.
+
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use binrw::BinResult;
+use serde::Serialize;
+
+use super::Validity;
+use super::nax0::{Nax0KeyType, Nax0Reader, sd_relative_path};
+use crate::keys::NcaKeys;
+
+/// Folders below `Nintendo` that hold NAX0 files
+pub const SD_CARD_FOLDERS: [&str; 2] = ["Contents", "save"];
+
+/// Size of the `private` file of system save 8000000000000043: the SD card ID, then the SD seed
+pub const PRIVATE_SIZE: usize = 0x20;
+
+/// A NAX0 file found on an SD card
+#[derive(Debug, Serialize)]
+pub struct SdCardFile {
+    /// Path relative to the `Nintendo` folder. Split files are named after their folder, which
+    /// for installed content is the NCA ID.
+    pub path: String,
+    /// Path the keys are derived from
+    pub sd_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<Nax0KeyType>,
+    /// Size of the decrypted data, if the keys were found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    pub header_mac: Validity,
+}
+
+/// Read the SD seed from the `private` file of system save 8000000000000043. When given, the
+/// SD card's own `Nintendo/Contents/private` must match the ID before the seed, as the save
+/// only holds the seed of the card last inserted.
+pub fn sd_seed_from_private(private: &[u8], sd_private: Option<&[u8]>) -> BinResult<[u8; 0x10]> {
+    let private = private.get(..PRIVATE_SIZE).ok_or_else(|| {
+        binrw::Error::Io(Error::new(ErrorKind::UnexpectedEof, format!("The private file must be at least 0x{:X} bytes", PRIVATE_SIZE)))
+    })?;
+    if let Some(sd_private) = sd_private
+        && sd_private.get(..0x10) != Some(&private[..0x10])
+    {
+        return Err(binrw::Error::Io(Error::new(
+            ErrorKind::InvalidData,
+            "The private file does not belong to this SD card",
+        )));
+    }
+    Ok(private[0x10..].try_into().unwrap())
+}
+
+/// Find every NAX0 file below the `Contents` and `save` folders of `nintendo_folder`, in path
+/// order. A folder of split parts is returned as the folder.
+pub fn find_sd_card_files<P: AsRef<Path>>(nintendo_folder: P) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for folder in SD_CARD_FOLDERS {
+        let folder = nintendo_folder.as_ref().join(folder);
+        if folder.is_dir() {
+            find_nax0_files(&folder, &mut files)?;
+        }
+    }
+    Ok(files)
+}
+
+fn find_nax0_files(folder: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(folder)?.map(|entry| entry.map(|e| e.path())).collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            match is_nax0(&path.join("00"))? {
+                true => files.push(path),
+                false => find_nax0_files(&path, files)?,
+            }
+        } else if is_nax0(&path)? {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_nax0(path: &Path) -> std::io::Result<bool> {
+    if !path.is_file() {
+        return Ok(false);
+    }
+    let mut header = [0u8; 0x24];
+    let read = crate::utils::read_up_to(&mut File::open(path)?, &mut header)?;
+    Ok(read == header.len() && &header[0x20..] == b"NAX0")
+}
+
+/// Check the header MAC of every NAX0 file in `nintendo_folder`, and decrypt the valid ones to
+/// the same relative paths below `output_folder` when given. Files whose keys cannot be found
+/// are reported rather than stopping the batch.
+pub fn decrypt_sd_card<P: AsRef<Path>>(nintendo_folder: P, output_folder: Option<&Path>, key_set: &NcaKeys) -> BinResult<Vec<SdCardFile>> {
+    let nintendo_folder = nintendo_folder.as_ref();
+    let mut results = Vec::new();
+    for path in find_sd_card_files(nintendo_folder)? {
+        let relative = path.strip_prefix(nintendo_folder).expect("found below the Nintendo folder");
+        // Keys are derived from the path below Contents or save, whatever the dump is named
+        let sd_path = sd_relative_path(&Path::new("Nintendo").join(relative)).expect("found below Contents or save");
+        let mut result = SdCardFile {
+            path: relative.to_string_lossy().replace('\\', "/"),
+            sd_path,
+            key_type: None,
+            size: None,
+            header_mac: Validity::CheckError,
+        };
+
+        match Nax0Reader::open(&path, Some(&result.sd_path), key_set) {
+            Ok(mut nax0) => {
+                result.header_mac = Validity::Valid;
+                result.key_type = Some(nax0.key_type);
+                result.size = Some(nax0.size());
+                if let Some(output_folder) = output_folder {
+                    let output_file = output_folder.join(relative);
+                    std::fs::create_dir_all(output_file.parent().expect("file has a parent"))?;
+                    std::io::copy(&mut nax0, &mut BufWriter::new(File::create(output_file)?))?;
+                }
+            }
+            Err(binrw::Error::Io(e)) if e.kind() == ErrorKind::InvalidData => result.header_mac = Validity::Invalid,
+            Err(_) => {}
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
@@ -0,0 +1,68 @@
+use std::fs::File;
+use std::path::Path;
+
+use binrw::prelude::*;
+use serde::Serialize;
+
+use super::{hfs0::Hfs0Index, SHA256Hash};
+use crate::utils::{serde_helpers, Placement};
+
+/// Offset of the "HEAD" magic, after the header signature
+pub const XCI_MAGIC_OFFSET: u64 = 0x100;
+
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct XciHeader {
+    /// RSA-2048 PKCS#1 signature over the header
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub header_signature: [u8; 0x100],
+    /// Start of the secure area, in media units
+    #[br(magic = b"HEAD")]
+    pub secure_area_start: u32,
+    /// Start of the backup area, in media units
+    pub backup_area_start: u32,
+    pub title_kek_index: u8,
+    pub cart_size: u8,
+    pub header_version: u8,
+    pub flags: u8,
+    pub package_id: u64,
+    /// End of the valid data, in media units
+    pub valid_data_end: u64,
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub iv: [u8; 0x10],
+    /// Offset of the root HFS0 partition in bytes
+    pub hfs0_partition_offset: u64,
+    pub hfs0_header_size: u64,
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub hfs0_header_hash: SHA256Hash,
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub initial_data_hash: SHA256Hash,
+    pub secure_mode_flag: u32,
+    pub title_key_flag: u32,
+    pub key_flag: u32,
+    /// End of the normal area, in media units
+    pub normal_area_end: u32,
+    /// Gamecard info, encrypted with the XCI header key
+    #[serde(serialize_with = "serde_helpers::hex")]
+    pub encrypted_gamecard_info: [u8; 0x70],
+}
+
+/// A gamecard image: the header and the index of the root partition. File data is not read.
+#[binread]
+#[derive(Debug, Serialize)]
+#[br(little)]
+pub struct Xci {
+    pub header: XciHeader,
+    /// Root partition, whose entries are the `update`, `normal`, `secure` and `logo` partitions
+    #[br(parse_with = Placement::parse, args { offset: header.hfs0_partition_offset, inner: () })]
+    pub root_partition: Hfs0Index,
+}
+
+impl Xci {
+    pub fn parse<P: AsRef<Path>>(xci_file: P) -> BinResult<Xci> {
+        let mut file = File::open(xci_file.as_ref())?;
+
+        file.read_le()
+    }
+}
@@ -0,0 +1,281 @@
+//! Read-only FUSE filesystem over a [`ContainerNode`] tree.
+//!
+//! Containers are exposed as directories and everything else as regular files. Children are
+//! only parsed when a directory is first looked up or listed, and reads go straight to the
+//! node readers, so NCA sections are decrypted on demand and nothing is copied up front. The
+//! hash blocks each read covers are checked against the section's hash layers, and reads of
+//! blocks which don't match fail with `EIO`.
+
+use std::ffi::OsStr;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use fuser::{FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen, Request, FUSE_ROOT_ID};
+use log::warn;
+
+use crate::file_formats::container::ContainerNode;
+use crate::utils::read_up_to;
+
+/// How long the kernel may cache attributes and lookups. The tree never changes.
+const TTL: Duration = Duration::from_secs(60);
+const BLOCK_SIZE: u32 = 0x200;
+
+enum InodeKind {
+    Node(ContainerNode),
+    /// Directory which only exists in the paths of its children, e.g. `data` for the RomFS file
+    /// `data/file.bin`
+    Directory,
+}
+
+struct Inode {
+    parent: u64,
+    kind: InodeKind,
+    /// Named children, or `None` for containers which have not been expanded yet
+    children: Option<Vec<(String, u64)>>,
+}
+
+impl Inode {
+    fn is_directory(&self) -> bool {
+        match &self.kind {
+            InodeKind::Node(node) => node.is_container(),
+            InodeKind::Directory => true,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match &self.kind {
+            InodeKind::Node(node) if !node.is_container() => node.size(),
+            _ => 0,
+        }
+    }
+}
+
+/// Owner and timestamps reported for every file
+#[derive(Debug, Clone, Copy)]
+pub struct MountAttributes {
+    pub uid: u32,
+    pub gid: u32,
+    pub time: SystemTime,
+}
+
+pub struct ContainerFs {
+    /// Inode `n` is stored at index `n - 1`
+    inodes: Vec<Inode>,
+    attributes: MountAttributes,
+}
+
+impl ContainerFs {
+    /// Expose `root` at the mount point. A root which is not a container is shown as the only
+    /// file in the mounted directory.
+    pub fn new(root: ContainerNode, attributes: MountAttributes) -> Self {
+        let mut fs = ContainerFs { inodes: Vec::new(), attributes };
+        if root.is_container() {
+            fs.push(FUSE_ROOT_ID, InodeKind::Node(root), None);
+        } else {
+            let name = root.name.rsplit('/').next().unwrap_or_default().to_string();
+            fs.push(FUSE_ROOT_ID, InodeKind::Directory, Some(Vec::new()));
+            let file = fs.push(FUSE_ROOT_ID, InodeKind::Node(root), Some(Vec::new()));
+            fs.inodes[0].children = Some(vec![(name, file)]);
+        }
+        fs
+    }
+
+    fn push(&mut self, parent: u64, kind: InodeKind, children: Option<Vec<(String, u64)>>) -> u64 {
+        self.inodes.push(Inode { parent, kind, children });
+        self.inodes.len() as u64
+    }
+
+    fn inode(&self, ino: u64) -> Option<&Inode> {
+        self.inodes.get(ino.checked_sub(1)? as usize)
+    }
+
+    /// Children of a directory, parsing the container the first time
+    fn children(&mut self, ino: u64) -> Result<&[(String, u64)], libc::c_int> {
+        let index = ino.checked_sub(1).ok_or(libc::ENOENT)? as usize;
+        let inode = self.inodes.get(index).ok_or(libc::ENOENT)?;
+        if !inode.is_directory() {
+            return Err(libc::ENOTDIR);
+        }
+        if inode.children.is_none() {
+            let InodeKind::Node(node) = &inode.kind else {
+                unreachable!("virtual directories are created with their children");
+            };
+            let nodes = node.children().map_err(|e| {
+                warn!("Unable to list {}: {}", node.name, e);
+                libc::EIO
+            })?;
+            self.inodes[index].children = Some(Vec::new());
+            for child in nodes {
+                self.add_child(ino, child);
+            }
+        }
+        Ok(self.inodes[index].children.as_deref().unwrap_or_default())
+    }
+
+    /// Add a node below `parent`, creating directories for the components of names such as
+    /// `data/file.bin`
+    fn add_child(&mut self, mut parent: u64, node: ContainerNode) {
+        let path = node.name.clone();
+        let mut components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let Some(file_name) = components.pop() else {
+            return;
+        };
+
+        for component in components {
+            let existing = self.inodes[parent as usize - 1]
+                .children
+                .iter()
+                .flatten()
+                .find(|(name, _)| name == component)
+                .map(|&(_, ino)| ino);
+            parent = match existing {
+                Some(ino) => ino,
+                None => {
+                    let ino = self.push(parent, InodeKind::Directory, Some(Vec::new()));
+                    self.children_mut(parent).push((component.to_string(), ino));
+                    ino
+                }
+            };
+        }
+
+        let leaf_children = (!node.is_container()).then(Vec::new);
+        let ino = self.push(parent, InodeKind::Node(node), leaf_children);
+        self.children_mut(parent).push((file_name.to_string(), ino));
+    }
+
+    fn children_mut(&mut self, ino: u64) -> &mut Vec<(String, u64)> {
+        self.inodes[ino as usize - 1].children.get_or_insert_with(Vec::new)
+    }
+
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        let inode = self.inode(ino)?;
+        let (kind, perm, nlink) = match inode.is_directory() {
+            true => (FileType::Directory, 0o555, 2),
+            false => (FileType::RegularFile, 0o444, 1),
+        };
+        let size = inode.size();
+        let MountAttributes { uid, gid, time } = self.attributes;
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(u64::from(BLOCK_SIZE)),
+            atime: time,
+            mtime: time,
+            ctime: time,
+            crtime: time,
+            kind,
+            perm,
+            nlink,
+            uid,
+            gid,
+            rdev: 0,
+            blksize: BLOCK_SIZE,
+            flags: 0,
+        })
+    }
+
+    fn read_node(&self, ino: u64, offset: u64, size: u32) -> Result<Vec<u8>, libc::c_int> {
+        let Some(InodeKind::Node(node)) = self.inode(ino).map(|inode| &inode.kind) else {
+            return Err(libc::ENOENT);
+        };
+        if node.is_container() {
+            return Err(libc::EISDIR);
+        }
+
+        let mut reader = node.reader();
+        let mut buffer = vec![0u8; (size as u64).min(node.size().saturating_sub(offset)) as usize];
+        reader.seek(SeekFrom::Start(offset)).map_err(|_| libc::EIO)?;
+        let read = read_up_to(&mut reader, &mut buffer).map_err(|e| {
+            warn!("Unable to read {}: {}", node.name, e);
+            libc::EIO
+        })?;
+        buffer.truncate(read);
+        Ok(buffer)
+    }
+}
+
+impl Filesystem for ContainerFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let ino = match self.children(parent) {
+            Ok(children) => children.iter().find(|(child, _)| OsStr::new(child) == name).map(|&(_, ino)| ino),
+            Err(e) => return reply.error(e),
+        };
+        match ino.and_then(|ino| self.attr(ino)) {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return reply.error(libc::EROFS);
+        }
+        match self.inode(ino) {
+            Some(inode) if inode.is_directory() => reply.error(libc::EISDIR),
+            Some(_) => reply.opened(0, 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Ok(offset) = u64::try_from(offset) else {
+            return reply.error(libc::EINVAL);
+        };
+        match self.read_node(ino, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let parent = match self.inode(ino) {
+            Some(inode) => inode.parent,
+            None => return reply.error(libc::ENOENT),
+        };
+        let children = match self.children(ino) {
+            Ok(children) => children.to_vec(),
+            Err(e) => return reply.error(e),
+        };
+
+        let entries = [(".".to_string(), ino), ("..".to_string(), parent)].into_iter().chain(children);
+        for (index, (name, child)) in entries.enumerate().skip(offset.max(0) as usize) {
+            let kind = match self.inode(child).is_some_and(Inode::is_directory) {
+                true => FileType::Directory,
+                false => FileType::RegularFile,
+            };
+            // The offset passed back to us is that of the next entry
+            if reply.add(child, index as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+/// Mount `root` read-only at `mountpoint`, blocking until it is unmounted
+pub fn mount<P: AsRef<Path>>(root: ContainerNode, mountpoint: P, attributes: MountAttributes) -> std::io::Result<()> {
+    let options = [
+        MountOption::RO,
+        MountOption::FSName(root.name.clone()),
+        MountOption::Subtype("hactool".to_string()),
+    ];
+    fuser::mount2(ContainerFs::new(root, attributes), mountpoint, &options)
+}
@@ -0,0 +1,722 @@
+//! Human readable, hactool style rendering of parsed files for the `info` action, and the
+//! versioned JSON report used for machine readable output.
+
+use std::fmt::{self, Display, Write};
+
+use serde::Serialize;
+
+use crate::{
+    file_formats::{
+        Validity,
+        container::ContainerTree,
+        hfs0::Hfs0,
+        nacp::Nacp,
+        nand::{BisPartition, NandReader},
+        nax0::Nax0Reader,
+        nca::{NcaFileReader, fs},
+        npdm::{
+            FsAccessFlags, NpdmFile, ServiceRecord,
+            kernel_capability::{CapabilitySummary, KernelCapability, summarize},
+        },
+        nro::NroReader,
+        nso::{ModuleInfo, NsoHeader, NsoReader},
+        pfs0::{Pfs0, Pfs0Reader},
+        romfs::RomFsReader,
+        save::SaveReader,
+        sd_card::SdCardFile,
+    },
+    keys::KeysetType,
+};
+
+/// Column at which field values start
+const VALUE_COLUMN: usize = 36;
+/// Indentation added by each nested section
+const INDENT_WIDTH: usize = 4;
+/// Number of bytes printed per line for long byte arrays
+const BYTES_PER_LINE: usize = 0x20;
+
+/// Formats a byte slice as upper case hex
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
+
+/// Writes labelled fields with their values aligned to a common column
+pub struct InfoWriter<'a> {
+    out: &'a mut dyn Write,
+    indent: usize,
+}
+
+impl<'a> InfoWriter<'a> {
+    pub fn new(out: &'a mut dyn Write) -> Self {
+        Self { out, indent: 0 }
+    }
+
+    /// Write a single `label: value` line
+    pub fn field(&mut self, label: &str, value: impl Display) -> fmt::Result {
+        let width = VALUE_COLUMN.saturating_sub(self.indent).max(label.len() + 2);
+        writeln!(self.out, "{:indent$}{:<width$}{}", "", format!("{}:", label), value, indent = self.indent)
+    }
+
+    /// Write a byte array as hex, wrapping long arrays onto continuation lines
+    pub fn bytes(&mut self, label: &str, data: &[u8]) -> fmt::Result {
+        let mut lines = data.chunks(BYTES_PER_LINE);
+        self.field(label, Hex(lines.next().unwrap_or_default()))?;
+        let column = VALUE_COLUMN.max(self.indent + label.len() + 2);
+        lines.try_for_each(|line| writeln!(self.out, "{:column$}{}", "", Hex(line)))
+    }
+
+    /// Write a titled group of fields, indented below the title
+    pub fn section(&mut self, title: &str, body: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        writeln!(self.out, "{:indent$}{}:", "", title, indent = self.indent)?;
+        self.indent += INDENT_WIDTH;
+        let result = body(self);
+        self.indent -= INDENT_WIDTH;
+        result
+    }
+
+    /// Write a list of values under a single label, one per line
+    pub fn list<T: Display>(&mut self, label: &str, values: impl IntoIterator<Item = T>) -> fmt::Result {
+        let mut values = values.into_iter().peekable();
+        if values.peek().is_none() {
+            return self.field(label, "None");
+        }
+        let column = VALUE_COLUMN.max(self.indent + label.len() + 2);
+        self.field(label, values.next().expect("peeked value is present"))?;
+        values.try_for_each(|value| writeln!(self.out, "{:column$}{}", "", value))
+    }
+}
+
+/// Types which can be rendered for the `info` action
+pub trait PrettyInfo {
+    fn write_info(&self, writer: &mut InfoWriter<'_>) -> fmt::Result;
+
+    fn info_string(&self) -> String {
+        let mut output = String::new();
+        self.write_info(&mut InfoWriter::new(&mut output))
+            .expect("writing to a String cannot fail");
+        output
+    }
+}
+
+/// Version of the JSON report schema. Bumped whenever a serialized field is renamed, removed or
+/// changes meaning; adding fields does not change the version.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// Top level object of the JSON output
+#[derive(Serialize)]
+pub struct JsonReport<'a, T: Serialize + ?Sized> {
+    pub schema_version: u32,
+    pub file_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<&'a T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<&'a [VerificationResult]>,
+}
+
+impl<'a, T: Serialize + ?Sized> JsonReport<'a, T> {
+    pub fn new(file_type: &'static str) -> Self {
+        Self { schema_version: JSON_SCHEMA_VERSION, file_type, info: None, verification: None }
+    }
+
+    pub fn with_info(mut self, info: &'a T) -> Self {
+        self.info = Some(info);
+        self
+    }
+
+    pub fn with_verification(mut self, verification: &'a [VerificationResult]) -> Self {
+        self.verification = Some(verification);
+        self
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Outcome of a single check performed by the `verify` action
+#[derive(Debug, Serialize)]
+pub struct VerificationResult {
+    pub check: &'static str,
+    /// Section or file the check applies to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub result: Validity,
+    /// Key set whose fixed key produced a valid signature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyset: Option<KeysetType>,
+}
+
+impl VerificationResult {
+    pub fn new(check: &'static str, target: Option<String>, result: Validity) -> Self {
+        Self { check, target, result, keyset: None }
+    }
+
+    /// Check a fixed-key signature against the retail then the dev key set
+    fn fixed_key(check: &'static str, verify: impl Fn(KeysetType) -> Result<Validity, Validity>) -> Self {
+        let (result, keyset) = match (verify(KeysetType::Retail), verify(KeysetType::Dev)) {
+            (Ok(Validity::Valid), _) => (Validity::Valid, Some(KeysetType::Retail)),
+            (_, Ok(Validity::Valid)) => (Validity::Valid, Some(KeysetType::Dev)),
+            (Err(e), _) | (Ok(e), _) => (e, None),
+        };
+        Self { check, target: None, result, keyset }
+    }
+}
+
+impl Display for VerificationResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.result)?;
+        if let Some(keyset) = self.keyset {
+            write!(f, " ({:?})", keyset)?;
+        }
+        Ok(())
+    }
+}
+
+impl PrettyInfo for [VerificationResult] {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        w.section("Verification", |w| {
+            self.iter().try_for_each(|result| match &result.target {
+                Some(target) => w.field(&format!("{} ({})", result.check, target), result),
+                None => w.field(result.check, result),
+            })
+        })
+    }
+}
+
+/// Types which can check their own hashes and signatures for the `verify` action
+pub trait Verify {
+    fn verification_results(&mut self) -> Vec<VerificationResult>;
+}
+
+impl Verify for NcaFileReader {
+    fn verification_results(&mut self) -> Vec<VerificationResult> {
+        let mut results = vec![VerificationResult::fixed_key("header_signature", |keyset| {
+            self.verify_header_signature(keyset)
+        })];
+        for index in 0..4 {
+            let entry = self.nca_ctx.section_entries[index];
+            if entry.end_block_offset <= entry.start_block_offset {
+                continue;
+            }
+            let target = Some(format!("section{}", index));
+            results.push(VerificationResult::new("fs_header_hash", target.clone(), self.verify_fs_header_hash(index)));
+            let section_hashes = self.verify_section_hashes(index).unwrap_or(Validity::CheckError);
+            results.push(VerificationResult::new("section_hashes", target, section_hashes));
+        }
+        results
+    }
+}
+
+impl Verify for NpdmFile {
+    fn verification_results(&mut self) -> Vec<VerificationResult> {
+        let mut results = vec![VerificationResult::fixed_key("acid_signature", |keyset| self.verify_acid(keyset))];
+        let violations = self.validate_restrictions();
+        if violations.is_empty() {
+            results.push(VerificationResult::new("aci0_restrictions", None, Validity::Valid));
+        }
+        results.extend(
+            violations
+                .iter()
+                .map(|violation| VerificationResult::new("aci0_restrictions", Some(violation.to_string()), Validity::Invalid)),
+        );
+        results
+    }
+}
+
+impl Verify for NsoReader {
+    fn verification_results(&mut self) -> Vec<VerificationResult> {
+        ["text", "rodata", "data"]
+            .iter()
+            .enumerate()
+            .map(|(index, name)| VerificationResult::new("segment_hash", Some(name.to_string()), self.verify_segment_hash(index)))
+            .collect()
+    }
+}
+
+impl Verify for SaveReader {
+    fn verification_results(&mut self) -> Vec<VerificationResult> {
+        vec![
+            VerificationResult::new("layout_hash", None, self.verify_layout_hash()),
+            VerificationResult::new("data_ivfc", None, self.verify_ivfc()),
+        ]
+    }
+}
+
+impl Verify for NandReader {
+    fn verification_results(&mut self) -> Vec<VerificationResult> {
+        self.partitions
+            .iter()
+            .filter(|partition| partition.kind.bis_key_index().is_some())
+            .map(|partition| VerificationResult::new("bis_key", Some(partition.name.clone()), self.verify_partition(partition)))
+            .collect()
+    }
+}
+
+/// PFS0 has no hashes, so its contents can't be checked
+impl Verify for Pfs0Reader {
+    fn verification_results(&mut self) -> Vec<VerificationResult> {
+        vec![VerificationResult::new("content_hashes", None, Validity::Unchecked)]
+    }
+}
+
+/// NRO has no hashes, so its contents can't be checked
+impl Verify for NroReader {
+    fn verification_results(&mut self) -> Vec<VerificationResult> {
+        vec![VerificationResult::new("content_hashes", None, Validity::Unchecked)]
+    }
+}
+
+/// RomFS has no hashes of its own. Only a directory tree which can't be walked is reported.
+impl Verify for RomFsReader {
+    fn verification_results(&mut self) -> Vec<VerificationResult> {
+        let mut results = vec![VerificationResult::new("content_hashes", None, Validity::Unchecked)];
+        if self.romfs.files().is_err() {
+            results.push(VerificationResult::new("file_table", None, Validity::Invalid));
+        }
+        results
+    }
+}
+
+impl Verify for Hfs0 {
+    fn verification_results(&mut self) -> Vec<VerificationResult> {
+        self.files
+            .iter()
+            .map(|file| VerificationResult::new("prefix_hash", Some(file.file_name.to_string()), file.verify_prefix_hash()))
+            .collect()
+    }
+}
+
+impl PrettyInfo for NcaFileReader {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        let ctx = &self.nca_ctx;
+        w.section("NCA", |w| {
+            w.field("Magic", ctx.file_version)?;
+            w.field(
+                "Fixed-Key Signature",
+                VerificationResult::fixed_key("header_signature", |keyset| self.verify_header_signature(keyset)),
+            )?;
+            w.bytes("Fixed-Key Signature Data", &ctx.signature)?;
+            w.bytes("NPDM Signing Key Modulus", &ctx.modulus)?;
+            w.field("Content Size", format_args!("0x{:012X}", ctx.content_size))?;
+            w.field("Program ID", format_args!("{:016X}", ctx.program_id))?;
+            w.field("Content Index", ctx.content_index)?;
+            w.field("SDK Version", ctx.sdk_addon_version)?;
+            w.field("Distribution Type", format_args!("{:?}", ctx.distribution_type))?;
+            w.field("Content Type", format_args!("{:?}", ctx.content_type))?;
+            w.field(
+                "Master Key Revision",
+                format_args!(
+                    "{} ({})",
+                    ctx.master_key_revision(),
+                    ctx.effective_key_generation().firmware_version()
+                ),
+            )?;
+            w.field("Header Signing Key Generation", ctx.signature_key_generation)?;
+            if ctx.has_rights_id() {
+                w.field("Encryption Type", "Titlekey crypto")?;
+                w.bytes("Rights ID", &ctx.rights_id)?;
+            } else {
+                w.field("Encryption Type", "Standard crypto")?;
+                w.field("Key Area Encryption Key", format_args!("{:?}", ctx.key_area_index))?;
+                w.list("Key Area (Encrypted)", ctx.encrypted_key_area.chunks(0x10).map(Hex))?;
+                w.list("Key Area (Decrypted)", self.decrypted_key_area.iter().map(|key| Hex(key)))?;
+            }
+
+            w.section("Sections", |w| {
+                for (index, (entry, fs_header)) in ctx.section_entries.iter().zip(ctx.fs_headers.iter()).enumerate() {
+                    if entry.end_block_offset <= entry.start_block_offset {
+                        continue;
+                    }
+                    let header_hash_validity = self.verify_fs_header_hash(index);
+                    w.section(&format!("Section {}", index), |w| {
+                        write_section_info(w, entry, fs_header, &ctx.section_hashes[index], header_hash_validity)
+                    })?;
+                }
+                Ok(())
+            })
+        })
+    }
+}
+
+fn write_section_info(
+    w: &mut InfoWriter<'_>,
+    entry: &fs::FsEntry,
+    fs_header: &fs::FsHeader,
+    header_hash: &[u8],
+    header_hash_validity: Validity,
+) -> fmt::Result {
+    let offset = u64::from(entry.start_block_offset) * crate::file_formats::MEDIA_UNIT_SIZE;
+    let size = u64::from(entry.end_block_offset - entry.start_block_offset) * crate::file_formats::MEDIA_UNIT_SIZE;
+    w.field("Offset", format_args!("0x{:012X}", offset))?;
+    w.field("Size", format_args!("0x{:012X}", size))?;
+    w.field("Header Hash", format_args!("{} ({})", Hex(header_hash), header_hash_validity))?;
+    w.field("Encryption Type", format_args!("{:?}", fs_header.encryption_type))?;
+    w.bytes("Section CTR", &fs_header.section_ctr)?;
+
+    match &fs_header.superblock {
+        fs::SuperBlock::None => w.field("Partition Type", "None"),
+        fs::SuperBlock::Pfs0(superblock) => {
+            w.field("Partition Type", "PFS0")?;
+            w.bytes("Master Hash", &superblock.master_hash)?;
+            w.field("Hash Block Size", format_args!("0x{:X}", superblock.block_size_bytes))?;
+            w.field("Hash Table Offset", format_args!("0x{:012X}", superblock.hash_table_offset))?;
+            w.field("Hash Table Size", format_args!("0x{:012X}", superblock.hash_table_size))?;
+            w.field("PFS0 Offset", format_args!("0x{:012X}", superblock.pfs0_offset))?;
+            w.field("PFS0 Size", format_args!("0x{:012X}", superblock.pfs0_size))
+        }
+        fs::SuperBlock::RomFs(superblock) => {
+            w.field("Partition Type", "RomFS")?;
+            w.bytes("Master Hash", &superblock.master_hash)?;
+            w.field("Level Count", superblock.level_count)?;
+            let level_count = (superblock.level_count as usize).saturating_sub(1).min(superblock.levels.len());
+            for (level_index, level) in superblock.levels[..level_count].iter().enumerate() {
+                w.section(&format!("Level {}", level_index), |w| {
+                    w.field("Data Offset", format_args!("0x{:012X}", level.logical_offset))?;
+                    w.field("Data Size", format_args!("0x{:012X}", level.hash_data_size))?;
+                    w.field("Block Size", format_args!("0x{:X}", 1u64 << level.block_size_log2))
+                })?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl PrettyInfo for NpdmFile {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        w.section("NPDM", |w| {
+            w.field("Title Name", String::from_utf8_lossy(&self.title_name).trim_end_matches('\0'))?;
+            w.field("Product Code", String::from_utf8_lossy(&self.product_code).trim_end_matches('\0'))?;
+            w.field("Version", self.version)?;
+            w.field("64-bit Process", self.flags.is_64bit())?;
+            w.field("Address Space", format_args!("{:?}", self.flags.address_space()))?;
+            w.field("Main Thread Priority", self.main_thread_priority)?;
+            w.field("Default CPU Core", self.default_cpu_core)?;
+            w.field("Main Stack Size", format_args!("0x{:X}", self.main_stack_size))?;
+            w.field("System Resource Size", format_args!("0x{:X}", self.system_resource_size))?;
+            w.field("ACID Sign Key Index", self.acid_sign_key_index)?;
+
+            let acid = &self.acid;
+            w.section("ACID", |w| {
+                w.field("Signature", VerificationResult::fixed_key("acid_signature", |keyset| self.verify_acid(keyset)))?;
+                w.bytes("Signature Data", &acid.signature)?;
+                w.bytes("Header Modulus", &acid.modulus)?;
+                w.field("Is Production", acid.flags.is_production())?;
+                w.field("Unqualified Approval", acid.flags.unqualified_approval())?;
+                w.field("Memory Region", format_args!("{:?}", acid.flags.memory_region()))?;
+                w.field(
+                    "Title ID Range",
+                    format_args!("{:016X}-{:016X}", acid.title_id_range_min, acid.title_id_range_max),
+                )?;
+                for record in acid.file_access_control_entries.iter() {
+                    w.section("Filesystem Access Control", |w| {
+                        w.field("Version", record.version)?;
+                        write_fs_access_flags(w, &record.access_flags)?;
+                        w.field(
+                            "Content Owner ID Range",
+                            format_args!("{:016X}-{:016X}", record.content_owner_id_min, record.content_owner_id_max),
+                        )?;
+                        w.field(
+                            "Save Data Owner ID Range",
+                            format_args!("{:016X}-{:016X}", record.save_data_owner_min, record.save_data_owner_max),
+                        )?;
+                        w.list("Content Owner IDs", record.content_owner_ids.iter().map(|id| format!("{:016X}", id)))?;
+                        w.list("Save Data Owner IDs", record.save_data_owner_ids.iter().map(|id| format!("{:016X}", id)))
+                    })?;
+                }
+                write_services(w, &acid.services)?;
+                write_kernel_capabilities(w, &acid.kernel_capabilities)
+            })?;
+
+            let aci0 = &self.aci0;
+            w.section("ACI0", |w| {
+                w.field("Title ID", format_args!("{:016X}", aci0.title_id))?;
+                let fah = &aci0.fs_access_control;
+                w.section("Filesystem Access Header", |w| {
+                    w.field("Version", fah.version)?;
+                    write_fs_access_flags(w, &fah.access_flags)?;
+                    w.list("Content Owner IDs", fah.content_owner_ids.iter().map(|id| format!("{:016X}", id)))?;
+                    w.list(
+                        "Save Data Owners",
+                        fah.save_data_owners.iter().map(|owner| format!("{:016X} ({})", owner.id, owner.accessibility)),
+                    )
+                })?;
+                write_services(w, &aci0.services)?;
+                write_kernel_capabilities(w, &aci0.kernel_capabilities)
+            })
+        })
+    }
+}
+
+fn write_fs_access_flags(w: &mut InfoWriter<'_>, flags: &FsAccessFlags) -> fmt::Result {
+    w.field("Permissions", format_args!("0x{:016X}", flags.raw()))?;
+    w.list("Permission Flags", flags.enabled_names())
+}
+
+fn write_services(w: &mut InfoWriter<'_>, services: &[ServiceRecord]) -> fmt::Result {
+    w.list(
+        "Services",
+        services.iter().map(|service| {
+            if service.is_server { format!("{} (server)", service.name) } else { service.name.clone() }
+        }),
+    )
+}
+
+fn write_kernel_capabilities(w: &mut InfoWriter<'_>, capabilities: &[KernelCapability]) -> fmt::Result {
+    w.section("Kernel Capabilities", |w| {
+        for summary in summarize(capabilities) {
+            match summary {
+                CapabilitySummary::ThreadInfo { priorities, cpu_cores } => {
+                    w.field("Thread Priority Range", format_args!("{}-{}", priorities.start(), priorities.end()))?;
+                    w.field("CPU Core Range", format_args!("{}-{}", cpu_cores.start(), cpu_cores.end()))?;
+                }
+                CapabilitySummary::Syscalls(syscalls) => w.list("Allowed Syscalls", syscalls)?,
+                CapabilitySummary::MemoryMap(range) => w.field("Memory Map", range)?,
+                CapabilitySummary::IoPage(address) => w.field("IO Memory Map", format_args!("0x{:010X}", address))?,
+                CapabilitySummary::MemoryRegions(regions) => w.list("Memory Region Map", regions)?,
+                CapabilitySummary::Interrupts(irqs) => w.list("Interrupts", irqs.iter().map(|irq| format!("0x{:03X}", irq)))?,
+                CapabilitySummary::ProgramType(program_type) => w.field("Program Type", format_args!("{:?}", program_type))?,
+                CapabilitySummary::KernelVersion { major, minor } => {
+                    w.field("Kernel Version", format_args!("{}.{}", major, minor))?
+                }
+                CapabilitySummary::HandleTableSize(size) => w.field("Handle Table Size", size)?,
+                CapabilitySummary::DebugFlags { allow_debug, force_debug } => {
+                    w.field("Enable Debug", allow_debug)?;
+                    w.field("Force Debug", force_debug)?;
+                }
+                CapabilitySummary::Unknown(raw) => w.field("Unknown Capability", format_args!("0x{:08X}", raw))?,
+            }
+        }
+        Ok(())
+    })
+}
+
+impl PrettyInfo for Pfs0 {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        w.section("PFS0", |w| {
+            w.field("Number of Files", self.header.file_count)?;
+            w.list(
+                "Files",
+                self.files.iter().map(|file| {
+                    format!(
+                        "pfs0:/{:<32} {:012X}-{:012X}",
+                        file.file_name.to_string(),
+                        file.file_offset,
+                        file.file_offset + file.file_size
+                    )
+                }),
+            )
+        })
+    }
+}
+
+impl PrettyInfo for Nax0Reader {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        w.section("NAX0", |w| {
+            w.field("Key Type", format!("{:?}", self.key_type))?;
+            w.field("Size", format!("0x{:X}", self.header.size))?;
+            w.bytes("Header MAC", &self.header.hmac)?;
+            let [data_key, tweak_key] = self.keys();
+            w.bytes("Data Key", &data_key)?;
+            w.bytes("Tweak Key", &tweak_key)
+        })
+    }
+}
+
+impl PrettyInfo for NsoHeader {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        w.section("NSO", |w| {
+            w.field("Version", self.version)?;
+            w.bytes("Module ID", &self.module_id)?;
+            w.list(
+                "Segments",
+                ["text", "rodata", "data"].iter().zip(self.segments()).enumerate().map(|(index, (name, segment))| {
+                    format!(
+                        "{:<8} {:08X}-{:08X} {}",
+                        name,
+                        segment.memory_offset,
+                        segment.memory_offset + segment.size,
+                        if self.is_compressed(index) { "(compressed)" } else { "" }
+                    )
+                    .trim_end()
+                    .to_string()
+                }),
+            )?;
+            w.field("BSS Size", format!("0x{:X}", self.bss_size))
+        })
+    }
+}
+
+impl PrettyInfo for [ModuleInfo] {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        w.section("Modules", |w| {
+            self.iter().try_for_each(|module| {
+                w.section(&module.file_name, |w| {
+                    w.field("Module Name", module.module_name.as_deref().unwrap_or("None"))?;
+                    w.bytes("Build ID", &module.build_id)?;
+                    w.field(
+                        "Load Address",
+                        format!("{:010X}-{:010X}", module.load_address, module.load_address + module.memory_size),
+                    )?;
+                    w.list(
+                        "Segments",
+                        [("text", module.text), ("rodata", module.rodata), ("data", module.data)].iter().map(|(name, segment)| {
+                            let start = module.load_address + u64::from(segment.memory_offset);
+                            format!("{:<8} {:010X}-{:010X}", name, start, start + u64::from(segment.size))
+                        }),
+                    )?;
+                    w.field("BSS Size", format!("0x{:X}", module.bss_size))
+                })
+            })
+        })
+    }
+}
+
+impl PrettyInfo for [SdCardFile] {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        w.section("SD Card", |w| {
+            self.iter().try_for_each(|file| {
+                w.section(&file.path, |w| {
+                    w.field("SD Path", &file.sd_path)?;
+                    if let (Some(key_type), Some(size)) = (file.key_type, file.size) {
+                        w.field("Key Type", format!("{:?}", key_type))?;
+                        w.field("Size", format!("0x{:X}", size))?;
+                    }
+                    w.field("Header MAC", &file.header_mac)
+                })
+            })
+        })
+    }
+}
+
+impl PrettyInfo for NandReader {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        w.section("NAND", |w| {
+            w.bytes("Disk GUID", &self.gpt_header.disk_guid)?;
+            w.list(
+                "Partitions",
+                self.partitions.iter().map(|partition| {
+                    format!("{:<24} {:<10} offset 0x{:X}, 0x{:X} bytes", partition.name, partition.kind, partition.offset, partition.size)
+                }),
+            )
+        })?;
+
+        for partition in self.partitions.iter().filter(|partition| matches!(partition.kind, BisPartition::System | BisPartition::User)) {
+            let label = format!("{} NCAs", partition.name);
+            match self.registered_ncas(partition) {
+                Ok(ncas) => w.list(&label, ncas.iter().map(|nca| format!("{} (0x{:X} bytes)", nca.path, nca.size)))?,
+                Err(e) => w.field(&label, format!("Unreadable: {}", e))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PrettyInfo for NroReader {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        let header = &self.nro.header;
+        w.section("NRO", |w| {
+            w.field("Version", header.version)?;
+            w.field("Size", format!("0x{:X}", header.size))?;
+            w.bytes("Module ID", &header.module_id)?;
+            w.field("MOD0 Offset", format!("0x{:X}", self.nro.mod0_offset))?;
+            w.list(
+                "Segments",
+                ["text", "rodata", "data"].iter().zip(header.segments.iter()).map(|(name, segment)| {
+                    format!("{:<8} {:08X}-{:08X}", name, segment.offset, segment.offset + segment.size)
+                }),
+            )?;
+            w.field("BSS Size", format!("0x{:X}", header.bss_size))
+        })?;
+
+        let Some(assets) = &self.nro.assets else {
+            return w.field("Assets", "None");
+        };
+        w.section("Assets", |w| {
+            w.field("Version", assets.version)?;
+            w.field("Icon Size", format!("0x{:X}", assets.icon.size))?;
+            w.field("NACP Size", format!("0x{:X}", assets.nacp.size))?;
+            w.field("RomFS Size", format!("0x{:X}", assets.romfs.size))
+        })?;
+
+        match &self.nacp {
+            Some(nacp) => nacp.write_info(w),
+            None => Ok(()),
+        }
+    }
+}
+
+impl PrettyInfo for SaveReader {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        let extra_data = &self.header.extra_data;
+        w.section("Save", |w| {
+            w.field("Layout Version", format!("0x{:X}", self.header.layout.version))?;
+            w.field("Save Type", extra_data.save_data_type_name())?;
+            w.field("Title ID", format!("{:016X}", extra_data.title_id))?;
+            w.field("Save ID", format!("{:016X}", extra_data.save_id))?;
+            w.bytes("User ID", &extra_data.user_id)?;
+            w.field("Owner ID", format!("{:016X}", extra_data.owner_id))?;
+            w.field("Timestamp", extra_data.timestamp)?;
+            w.field("Commit ID", format!("{:016X}", extra_data.commit_id))?;
+            w.field("Data Size", format!("0x{:X}", extra_data.data_size))?;
+            w.field("Journal Size", format!("0x{:X}", extra_data.journal_size))?;
+            w.field("Block Size", format!("0x{:X}", self.header.save.block_size))?;
+            w.bytes("CMAC", &self.header.cmac)
+        })?;
+
+        match self.files() {
+            Ok(files) => w.list("Files", files.iter().map(|file| format!("save:/{} ({:#x} bytes)", file.path, file.size))),
+            Err(e) => w.field("Files", format!("Unreadable: {}", e)),
+        }
+    }
+}
+
+impl PrettyInfo for Nacp {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        w.section("NACP", |w| {
+            w.field("Display Version", self.display_version())?;
+            w.field("Save Data Owner ID", format!("{:016X}", self.save_data_owner_id))?;
+            w.list(
+                "Titles",
+                self.titles().map(|(language, name, publisher)| format!("{:<20} {} ({})", language, name, publisher)),
+            )
+        })
+    }
+}
+
+impl PrettyInfo for ContainerTree {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        let summary = format!("{} 0x{:X} bytes", self.file_type, self.size);
+        if !self.file_type.is_container() {
+            return w.field(&self.name, summary);
+        }
+        w.section(&format!("{} ({})", self.name, summary), |w| {
+            if let Some(error) = &self.error {
+                w.field("Error", error)?;
+            }
+            self.children.iter().try_for_each(|child| child.write_info(w))
+        })
+    }
+}
+
+impl PrettyInfo for Hfs0 {
+    fn write_info(&self, w: &mut InfoWriter<'_>) -> fmt::Result {
+        w.section("HFS0", |w| {
+            w.field("Number of Files", self.files.len())?;
+            w.list(
+                "Files",
+                self.files.iter().map(|file| {
+                    format!(
+                        "hfs0:/{:<32} 0x{:X} bytes (hash {})",
+                        file.file_name.to_string(),
+                        file.file_data.len(),
+                        file.verify_prefix_hash()
+                    )
+                }),
+            )
+        })
+    }
+}
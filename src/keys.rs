@@ -6,30 +6,81 @@ use std::{
 
 include!(concat!(env!("OUT_DIR"), "/codegen.rs"));
 
+use aes::{Aes128, cipher::{BlockDecrypt, KeyInit, generic_array::GenericArray}};
 use clap::ValueEnum;
 use hex::FromHexError;
 use regex::Regex;
+use rsa::{RsaPrivateKey, RsaPublicKey};
 
-#[derive(Debug,ValueEnum, Clone, PartialEq, PartialOrd, Eq, Ord)]
+#[derive(Debug,ValueEnum, Clone, Copy, PartialEq, PartialOrd, Eq, Ord, serde::Serialize)]
 pub enum KeysetType {
     Dev,
     Retail,
 }
 
-#[derive(Debug)]
-pub struct NcaKeys {
-    /// Secure boot key for use in key derivation. NOTE: CONSOLE UNIQUE.
+/// Keys that are unique to a single console.
+///
+/// These are kept apart from the shared keys in [`NcaKeys`] so they are never mistaken for part of
+/// a shared keyset. The `Debug` impl only reports which of them are set, never their values.
+pub struct ConsoleKeys {
+    /// Secure boot key for use in key derivation.
     pub secure_boot_key: [u8; 0x10],
-    /// TSEC key for use in key derivation. NOTE: CONSOLE UNIQUE.
+    /// TSEC key for use in key derivation.
     pub tsec_key: [u8; 0x10],
-    /// Device key used to derive some FS keys. NOTE: CONSOLE UNIQUE.
+    /// Device key used to derive some FS keys.
     pub device_key: [u8; 0x10],
-    /// Actual keys used to decrypt keyblobs. NOTE: CONSOLE UNIQUE.
+    /// Actual keys used to decrypt keyblobs.
     pub keyblob_keys: [[u8; 0x10]; 0x20],
-    /// Keys used to validate keyblobs. NOTE: CONSOLE UNIQUE.
+    /// Keys used to validate keyblobs.
     pub keyblob_mac_keys: [[u8; 0x10]; 0x20],
-    /// Actual encrypted keyblobs (EKS). NOTE: CONSOLE UNIQUE.
+    /// Actual encrypted keyblobs (EKS).
     pub encrypted_keyblobs: [[u8; 0xB0]; 0x20],
+    /// Seed mixed into the SD card key sources, from the SD card's `private` file.
+    pub sd_seed: [u8; 0x10],
+    /// BIS keys (crypt then tweak key) of PRODINFO/PRODINFOF, SAFE, SYSTEM and USER.
+    pub bis_keys: [[u8; 0x20]; 4],
+    /// Key used to sign savedata, derived from the device key.
+    pub save_mac_key: [u8; 0x10],
+    /// SD card save and NCA keys, derived from `sd_seed` and used to derive the per-file NAX0 keys.
+    pub sd_card_keys: [[u8; 0x20]; 2],
+}
+
+impl Default for ConsoleKeys {
+    /// An empty set of console keys with every key zeroed
+    fn default() -> Self {
+        // SAFETY: the pattern of all zeros is valid for all data types contained in `ConsoleKeys`
+        unsafe { std::mem::zeroed() }
+    }
+}
+
+impl std::fmt::Debug for ConsoleKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn redact<const N: usize>(keys: &[[u8; N]]) -> std::fmt::Arguments<'_> {
+            match keys.iter().filter(|key| key.iter().any(|&byte| byte != 0)).count() {
+                0 => format_args!("<unset>"),
+                _ => format_args!("<redacted>"),
+            }
+        }
+
+        f.debug_struct("ConsoleKeys")
+            .field("secure_boot_key", &redact(&[self.secure_boot_key]))
+            .field("tsec_key", &redact(&[self.tsec_key]))
+            .field("device_key", &redact(&[self.device_key]))
+            .field("keyblob_keys", &redact(&self.keyblob_keys))
+            .field("keyblob_mac_keys", &redact(&self.keyblob_mac_keys))
+            .field("encrypted_keyblobs", &redact(&self.encrypted_keyblobs))
+            .field("sd_seed", &redact(&[self.sd_seed]))
+            .field("bis_keys", &redact(&self.bis_keys))
+            .field("save_mac_key", &redact(&[self.save_mac_key]))
+            .field("sd_card_keys", &redact(&self.sd_card_keys))
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct NcaKeys {
+    /// Keys unique to the console the keyset was dumped from.
+    pub console: ConsoleKeys,
     /// AES Class Keys set by mariko bootrom.
     pub mariko_aes_class_keys: [[u8; 0x10]; 0xC],
     /// Key Encryption Key for mariko.
@@ -104,9 +155,26 @@ pub struct NcaKeys {
     pub key_area_keys: [[[u8; 0x10]; 0x20]; 0x3],
     /// Key for XCI partially encrypted header.
     pub xci_header_key: [u8; 0x10],
-    /// Key used to sign savedata.
-    pub save_mac_key: [u8; 0x10],
-    pub sd_card_keys: [[u8; 0x20]; 2],
+    /// Seed for the BIS kek of SAFE, SYSTEM and USER.
+    pub bis_kek_source: [u8; 0x10],
+    /// Seeds for the BIS keys. The USER seed falls back to the SYSTEM one when missing.
+    pub bis_key_sources: [[u8; 0x20]; 4],
+    /// Seed for the retail BIS key of PRODINFO/PRODINFOF, left zero on development units.
+    pub retail_specific_aes_key_source: [u8; 0x10],
+    /// Seed for the eTicket RSA kek.
+    pub eticket_rsa_kek_source: [u8; 0x10],
+    /// Seed for the key wrapping the eTicket RSA kek.
+    pub eticket_rsa_kekek_source: [u8; 0x10],
+    /// Key protecting the console's eTicket RSA keypair in PRODINFO. Shared between consoles.
+    pub eticket_rsa_kek: [u8; 0x10],
+}
+
+impl Default for NcaKeys {
+    /// An empty keyset with every key zeroed
+    fn default() -> Self {
+        // SAFETY: the pattern of all zeros is valid for all data types contained in `NcaKeys`
+        unsafe { std::mem::zeroed() }
+    }
 }
 
 impl NcaKeys {
@@ -115,8 +183,7 @@ impl NcaKeys {
         let regex = Regex::new("^([a-z0-9_]+) = ([a-fA-F0-9]+)")
             .expect("Error building keyfile regex. Exiting...");
 
-        // SAFETY: the pattern of all zeros is valid for all data types contained in `NcaKeys`
-        let mut keys: NcaKeys = unsafe { std::mem::zeroed() };
+        let mut keys = NcaKeys::default();
 
         for line in BufReader::new(file).lines() {
             let line = line?;
@@ -134,8 +201,176 @@ impl NcaKeys {
             }
         }
 
+        keys.derive_console_keys();
+        if keys.eticket_rsa_kek == [0; 0x10] {
+            keys.derive_eticket_rsa_kek();
+        }
+        if keys.console.sd_seed != [0; 0x10] && keys.console.sd_card_keys == [[0; 0x20]; 2] {
+            keys.derive_sd_card_keys();
+        }
+
         return Ok(keys);
     }
+
+    /// Use the SD seed of another console, re-deriving the SD card keys
+    pub fn set_sd_seed(&mut self, sd_seed: [u8; 0x10]) {
+        self.console.sd_seed = sd_seed;
+        self.derive_sd_card_keys();
+    }
+
+    /// Derive the console unique keys from the secure boot and TSEC keys.
+    ///
+    /// Keys already present are kept: keyblob keys come from `keyblob_key_source_XX`, the device key
+    /// from keyblob key 0 and `per_console_key_source`, and the BIS keys and save MAC key from the
+    /// device key.
+    pub fn derive_console_keys(&mut self) {
+        let console = &mut self.console;
+        if console.secure_boot_key != [0; 0x10] && console.tsec_key != [0; 0x10] {
+            for (index, source) in self.keyblob_key_sources.iter().enumerate() {
+                if *source == [0; 0x10] || console.keyblob_keys[index] != [0; 0x10] {
+                    continue;
+                }
+                console.keyblob_keys[index] = decrypt_block(&console.secure_boot_key, &decrypt_block(&console.tsec_key, source));
+            }
+        }
+        if self.keyblob_mac_key_source != [0; 0x10] {
+            for (mac_key, keyblob_key) in console.keyblob_mac_keys.iter_mut().zip(console.keyblob_keys.iter()) {
+                if *mac_key == [0; 0x10] && *keyblob_key != [0; 0x10] {
+                    *mac_key = decrypt_block(keyblob_key, &self.keyblob_mac_key_source);
+                }
+            }
+        }
+        if console.device_key == [0; 0x10] && console.keyblob_keys[0] != [0; 0x10] && self.per_console_key_source != [0; 0x10] {
+            console.device_key = decrypt_block(&console.keyblob_keys[0], &self.per_console_key_source);
+        }
+        if console.device_key == [0; 0x10] {
+            return;
+        }
+
+        if console.bis_keys[0] == [0; 0x20] && self.bis_key_sources[0] != [0; 0x20] {
+            let kek = match self.retail_specific_aes_key_source {
+                source if source == [0; 0x10] => console.device_key,
+                source => decrypt_block(&console.device_key, &source),
+            };
+            console.bis_keys[0] = decrypt_key(&kek, &self.bis_key_sources[0]);
+        }
+        if console.save_mac_key == [0; 0x10]
+            && self.save_mac_kek_source != [0; 0x10]
+            && self.save_mac_key_source != [0; 0x10]
+            && self.aes_kek_generation_source != [0; 0x10]
+            && self.aes_key_generation_source != [0; 0x10]
+        {
+            let save_mac_kek = generate_kek(
+                &self.save_mac_kek_source,
+                &console.device_key,
+                &self.aes_kek_generation_source,
+                Some(&self.aes_key_generation_source),
+            );
+            console.save_mac_key = decrypt_block(&save_mac_kek, &self.save_mac_key_source);
+        }
+        if self.bis_kek_source == [0; 0x10] || self.aes_kek_generation_source == [0; 0x10] || self.aes_key_generation_source == [0; 0x10] {
+            return;
+        }
+        let bis_kek = generate_kek(
+            &self.bis_kek_source,
+            &console.device_key,
+            &self.aes_kek_generation_source,
+            Some(&self.aes_key_generation_source),
+        );
+        for index in 1..4 {
+            let source = match self.bis_key_sources[index] {
+                source if source == [0; 0x20] && index == 3 => self.bis_key_sources[2],
+                source => source,
+            };
+            if console.bis_keys[index] == [0; 0x20] && source != [0; 0x20] {
+                console.bis_keys[index] = decrypt_key(&bis_kek, &source);
+            }
+        }
+    }
+
+    /// Derive `eticket_rsa_kek` from its sources and master key 0
+    pub fn derive_eticket_rsa_kek(&mut self) {
+        if self.eticket_rsa_kek_source == [0; 0x10]
+            || self.eticket_rsa_kekek_source == [0; 0x10]
+            || self.aes_kek_generation_source == [0; 0x10]
+            || self.master_keys[0] == [0; 0x10]
+        {
+            return;
+        }
+        let kekek = generate_kek(&self.eticket_rsa_kekek_source, &self.master_keys[0], &self.aes_kek_generation_source, None);
+        self.eticket_rsa_kek = decrypt_block(&kekek, &self.eticket_rsa_kek_source);
+    }
+
+    /// Derive `sd_card_keys` from their sources, `sd_seed` and master key 0
+    pub fn derive_sd_card_keys(&mut self) {
+        let sd_card_kek = generate_kek(
+            &self.sd_card_kek_source,
+            &self.master_keys[0],
+            &self.aes_kek_generation_source,
+            Some(&self.aes_key_generation_source),
+        );
+        let cipher = Aes128::new(GenericArray::from_slice(&sd_card_kek));
+        for (key, source) in self.console.sd_card_keys.iter_mut().zip(self.sd_card_key_sources.iter()) {
+            for (index, byte) in key.iter_mut().enumerate() {
+                *byte = source[index] ^ self.console.sd_seed[index % 0x10];
+            }
+            for block in key.chunks_exact_mut(0x10) {
+                cipher.decrypt_block(GenericArray::from_mut_slice(block));
+            }
+        }
+    }
+}
+
+/// Unwrap a key encryption key the way the `GenerateAesKek` and `GenerateAesKey` services do
+fn generate_kek(source: &[u8; 0x10], master_key: &[u8; 0x10], kek_seed: &[u8; 0x10], key_seed: Option<&[u8; 0x10]>) -> [u8; 0x10] {
+    let kek = decrypt_block(master_key, kek_seed);
+    let source_kek = decrypt_block(&kek, source);
+    match key_seed {
+        Some(key_seed) => decrypt_block(&source_kek, key_seed),
+        None => source_kek,
+    }
+}
+
+fn decrypt_block(key: &[u8; 0x10], data: &[u8; 0x10]) -> [u8; 0x10] {
+    let mut block = *data;
+    Aes128::new(GenericArray::from_slice(key)).decrypt_block(GenericArray::from_mut_slice(&mut block));
+    block
+}
+
+/// Decrypt a two block key (e.g. crypt and tweak key) with AES-128-ECB
+fn decrypt_key(key: &[u8; 0x10], data: &[u8; 0x20]) -> [u8; 0x20] {
+    let mut out = *data;
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    for block in out.chunks_exact_mut(0x10) {
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+    out
+}
+
+/// Load an RSA private key from PEM or DER, in either PKCS#8 or PKCS#1 form
+pub fn rsa_private_key_from_bytes(bytes: &[u8]) -> anyhow::Result<RsaPrivateKey> {
+    use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs8::DecodePrivateKey};
+
+    let key = match pem_str(bytes) {
+        Some(pem) => RsaPrivateKey::from_pkcs8_pem(pem).or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem)),
+        None => RsaPrivateKey::from_pkcs8_der(bytes).or_else(|_| RsaPrivateKey::from_pkcs1_der(bytes)),
+    };
+    key.map_err(|e| anyhow::anyhow!("Unable to parse RSA private key: {}", e))
+}
+
+/// Load an RSA public key from PEM or DER, as either a SubjectPublicKeyInfo or a PKCS#1 key
+pub fn rsa_public_key_from_bytes(bytes: &[u8]) -> anyhow::Result<RsaPublicKey> {
+    use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey};
+
+    let key = match pem_str(bytes) {
+        Some(pem) => RsaPublicKey::from_public_key_pem(pem).or_else(|_| RsaPublicKey::from_pkcs1_pem(pem)),
+        None => RsaPublicKey::from_public_key_der(bytes).or_else(|_| RsaPublicKey::from_pkcs1_der(bytes)),
+    };
+    key.map_err(|e| anyhow::anyhow!("Unable to parse RSA public key: {}", e))
+}
+
+fn pem_str(bytes: &[u8]) -> Option<&str> {
+    std::str::from_utf8(bytes).ok().filter(|text| text.trim_start().starts_with("-----BEGIN"))
 }
 
 fn hex_to_array<const N: usize>(str: &str) -> Result<[u8; N], FromHexError> {
@@ -1,4 +1,7 @@
 pub mod file_formats;
+#[cfg(feature = "fuse")]
+pub mod fuse;
+pub mod info;
 pub mod keys;
 pub mod settings;
 pub mod utils;
\ No newline at end of file
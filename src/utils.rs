@@ -3,12 +3,27 @@ use std::{fs::File, io::{ Cursor, Read, Seek, SeekFrom, Write}};
 use binrw::{file_ptr::IntoSeekFrom, helpers::until_eof, prelude::*, Endian, FilePtr};
 use memmap::Mmap;
 
-#[derive(Debug)]
 pub(crate) enum ReaderType {
     Raw(File),
-    Mapped(Mmap)
+    Mapped(Mmap),
+    /// Any other reader, e.g. a file nested inside another container
+    Stream(Box<dyn ReadSeek>),
 }
 
+impl std::fmt::Debug for ReaderType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReaderType::Raw(file) => f.debug_tuple("Raw").field(file).finish(),
+            ReaderType::Mapped(map) => f.debug_tuple("Mapped").field(map).finish(),
+            ReaderType::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+/// Readers which can also seek, for use as trait objects
+pub trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
 #[derive(Debug, Clone, Copy)]
 pub struct CurPos(pub u64);
 
@@ -46,6 +61,70 @@ impl IntoSeekFrom for DummySeekFrom {
 
 pub(crate)type Placement<T> = FilePtr<DummySeekFrom, T>;
 
+/// `serialize_with` helpers for the JSON output of parsed files
+pub(crate) mod serde_helpers {
+    use std::fmt::Display;
+
+    use serde::Serializer;
+
+    /// Serialize bytes as an upper case hex string
+    pub fn hex<S: Serializer, T: AsRef<[u8]>>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode_upper(bytes))
+    }
+
+    /// Serialize a list of byte arrays as a list of upper case hex strings
+    pub fn hex_list<S: Serializer, L: AsRef<[T]>, T: AsRef<[u8]>>(items: &L, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(items.as_ref().iter().map(hex::encode_upper))
+    }
+
+    /// Serialize a NUL padded byte string as a string
+    pub fn c_string<S: Serializer, T: AsRef<[u8]>>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = bytes.as_ref();
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        serializer.serialize_str(&String::from_utf8_lossy(&bytes[..end]))
+    }
+
+    /// Serialize a value through its `Display` implementation
+    pub fn display<S: Serializer, T: Display>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    /// Serialize only the length of a buffer, for file data which is too large to output
+    pub fn len<S: Serializer, T>(data: &[T], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(data.len() as u64)
+    }
+}
+
+/// Implement `Serialize` for a `proc_bitfield` struct as its raw value and the listed fields
+macro_rules! serialize_bitfield {
+    ($type:ident { $($field:ident),* $(,)? }) => {
+        impl serde::Serialize for $type {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                use serde::ser::SerializeStruct;
+
+                let field_count = 1 + [$(stringify!($field)),*].len();
+                let mut state = serializer.serialize_struct(stringify!($type), field_count)?;
+                state.serialize_field("raw", &self.0)?;
+                $(state.serialize_field(stringify!($field), &self.$field())?;)*
+                state.end()
+            }
+        }
+    };
+}
+pub(crate) use serialize_bitfield;
+
+/// Read until `buf` is full or the reader is exhausted, returning the number of bytes read
+pub(crate) fn read_up_to<R: Read + ?Sized>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match reader.read(&mut buf[total..])? {
+            0 => break,
+            read => total += read,
+        }
+    }
+    Ok(total)
+}
+
 pub(crate) fn read_restore<R: Read + Seek, T: From<Vec<u8>>>(reader: &mut R, offset: u64, byte_count:u64) -> std::io::Result<T> {
     let mut output = vec![0; byte_count as usize];
 
@@ -0,0 +1,194 @@
+mod common;
+
+use std::io::{Cursor, ErrorKind, Read};
+use std::rc::Rc;
+
+use hactool_rs::file_formats::{
+    container::{ContainerNode, FileType, VirtualPath},
+    hfs0::Hfs0Builder,
+    nca::{ContentType, KeyGeneration, NcaBuilder, NcaFileReader},
+    pfs0::Pfs0Builder,
+    romfs::RomFsBuilder,
+};
+
+/// A program NCA with an ExeFS holding a fake NSO and NPDM, and a RomFS with one file
+fn test_nca() -> Vec<u8> {
+    let mut main = b"NSO0".to_vec();
+    main.resize(0x300, 0x11);
+    let mut npdm = b"META".to_vec();
+    npdm.resize(0x80, 0);
+
+    let mut exefs = Vec::new();
+    let mut pfs0 = Pfs0Builder::new();
+    pfs0.add_reader("main", main.len() as u64, main.as_slice())
+        .add_reader("main.npdm", npdm.len() as u64, npdm.as_slice());
+    pfs0.write(&mut exefs).unwrap();
+
+    let data = vec![0x22u8; 0x1800];
+    let mut romfs = Vec::new();
+    let mut romfs_builder = RomFsBuilder::new();
+    romfs_builder.add_reader("data/file.bin", data.len() as u64, data.as_slice());
+    romfs_builder.write(&mut romfs).unwrap();
+
+    let mut nca = Vec::new();
+    let mut builder = NcaBuilder::new(ContentType::Program, 0x0100000000001000);
+    builder
+        .set_key_generation(KeyGeneration::Five)
+        .set_content_key([0x33; 0x10])
+        .add_pfs0_section(0, exefs.len() as u64, Cursor::new(exefs.as_slice()))
+        .add_romfs_section(1, romfs.len() as u64, Cursor::new(romfs.as_slice()));
+    builder.write(&mut nca, &common::test_keys()).unwrap();
+    nca
+}
+
+/// An XCI header pointing at a root partition directly after it
+fn test_xci(nca: &[u8]) -> Vec<u8> {
+    let mut secure = Hfs0Builder::new();
+    secure.add_reader("program.nca", nca.len() as u64, nca);
+    let mut root = Hfs0Builder::new();
+    root.add_partition("secure", secure);
+    let root_header_size = root.header_size();
+
+    let mut xci = vec![0u8; 0x200];
+    xci[0x100..0x104].copy_from_slice(b"HEAD");
+    xci[0x130..0x138].copy_from_slice(&0x200u64.to_le_bytes());
+    xci[0x138..0x140].copy_from_slice(&root_header_size.to_le_bytes());
+    root.write(&mut xci).unwrap();
+    xci
+}
+
+fn child(node: &ContainerNode, name: &str) -> ContainerNode {
+    node.find_child(name).unwrap().unwrap_or_else(|| panic!("{} has no child {}", node.name, name))
+}
+
+#[test]
+pub fn detect_file_types() {
+    let keys = common::test_keys();
+    let mut nro = vec![0u8; 0x80];
+    nro[0x10..0x14].copy_from_slice(b"NRO0");
+
+    assert_eq!(FileType::detect_bytes(b"PFS0\0\0\0\0", None), FileType::Pfs0);
+    assert_eq!(FileType::detect_bytes(b"HFS0\0\0\0\0", None), FileType::Hfs0);
+    assert_eq!(FileType::detect_bytes(b"META\0\0\0\0", None), FileType::Npdm);
+    assert_eq!(FileType::detect_bytes(b"NSO0\0\0\0\0", None), FileType::Nso);
+    assert_eq!(FileType::detect_bytes(b"KIP1\0\0\0\0", None), FileType::Kip1);
+    assert_eq!(FileType::detect_bytes(b"INI1\0\0\0\0", None), FileType::Ini1);
+    assert_eq!(FileType::detect_bytes(&nro, None), FileType::Nro);
+    assert_eq!(FileType::detect_bytes(&[0x5A; 0x400], Some(&keys)), FileType::Unknown);
+
+    let nca = test_nca();
+    assert_eq!(FileType::detect_bytes(&nca, Some(&keys)), FileType::Nca);
+    assert_eq!(FileType::detect_bytes(&nca, None), FileType::Unknown);
+    assert_eq!(FileType::detect_bytes(&test_xci(&nca), None), FileType::Xci);
+}
+
+#[test]
+pub fn descend_nested_containers() {
+    let xci = test_xci(&test_nca());
+    let root = ContainerNode::from_reader("game.xci", Cursor::new(xci), Rc::new(common::test_keys())).unwrap();
+    assert_eq!(root.file_type, FileType::Xci);
+
+    let secure = child(&root, "secure");
+    assert_eq!(secure.file_type, FileType::Hfs0);
+    let nca = child(&secure, "program.nca");
+    assert_eq!(nca.file_type, FileType::Nca);
+
+    let sections: Vec<_> = nca.children().unwrap().into_iter().map(|c| (c.name, c.file_type)).collect();
+    assert_eq!(sections, [("exefs".to_string(), FileType::Pfs0), ("romfs".to_string(), FileType::RomFs)]);
+
+    let exefs = child(&nca, "exefs");
+    assert_eq!(child(&exefs, "main").file_type, FileType::Nso);
+    assert_eq!(child(&exefs, "main.npdm").file_type, FileType::Npdm);
+
+    let file = child(&child(&nca, "romfs"), "data/file.bin");
+    assert_eq!(file.size(), 0x1800);
+    let mut data = Vec::new();
+    file.reader().read_to_end(&mut data).unwrap();
+    assert_eq!(data, vec![0x22u8; 0x1800]);
+
+    let tree = root.tree();
+    let exefs_tree = &tree.children[0].children[0].children[0];
+    assert_eq!(exefs_tree.name, "exefs");
+    assert_eq!(exefs_tree.children.len(), 2);
+    assert!(exefs_tree.error.is_none());
+}
+
+#[test]
+pub fn reject_corrupted_nca_sections() {
+    let keys = common::test_keys();
+    let mut nca = test_nca();
+    let (romfs_offset, data_offset) = {
+        let reader = NcaFileReader::parse_reader(Cursor::new(nca.clone()), &keys).unwrap();
+        let section_offset = reader.nca_ctx.section_entries[1].start_block_offset as usize * 0x200;
+        (section_offset, reader.section_data_offset(1).unwrap() as usize)
+    };
+    // Inside the data of data/file.bin; AES-CTR flips the same bit of the plaintext
+    nca[romfs_offset + data_offset + 0x400] ^= 1;
+
+    let node = ContainerNode::from_reader("program.nca", Cursor::new(nca), Rc::new(keys)).unwrap();
+    let main = child(&child(&node, "exefs"), "main");
+    let mut data = Vec::new();
+    main.reader().read_to_end(&mut data).unwrap();
+    assert_eq!(&data[..4], b"NSO0");
+
+    let romfs = child(&node, "romfs");
+    let mut data = Vec::new();
+    let error = romfs.reader().read_to_end(&mut data).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+pub fn list_ini1_processes() {
+    let mut ini1 = Vec::new();
+    let mut kips = Vec::new();
+    for (name, text_size) in [("FS", 0x20u32), ("Loader", 0x40)] {
+        let mut kip = vec![0u8; 0x100];
+        kip[..4].copy_from_slice(b"KIP1");
+        kip[4..4 + name.len()].copy_from_slice(name.as_bytes());
+        kip[0x28..0x2C].copy_from_slice(&text_size.to_le_bytes());
+        kip.resize(0x100 + text_size as usize, 0xCC);
+        kips.extend(kip);
+    }
+    ini1.extend(b"INI1");
+    ini1.extend(((0x10 + kips.len()) as u32).to_le_bytes());
+    ini1.extend(2u32.to_le_bytes());
+    ini1.extend(0u32.to_le_bytes());
+    ini1.extend(kips);
+
+    let root = ContainerNode::from_reader("ini1.bin", Cursor::new(ini1), Rc::new(common::test_keys())).unwrap();
+    assert_eq!(root.file_type, FileType::Ini1);
+    let children: Vec<_> = root.children().unwrap().into_iter().map(|c| (c.name.clone(), c.file_type, c.size())).collect();
+    assert_eq!(
+        children,
+        [
+            ("FS.kip1".to_string(), FileType::Kip1, 0x120),
+            ("Loader.kip1".to_string(), FileType::Kip1, 0x140),
+        ]
+    );
+}
+
+#[test]
+pub fn resolve_virtual_paths() {
+    let xci = test_xci(&test_nca());
+    let path = std::env::temp_dir().join(format!("hactool-rs-virtual-{}.xci", std::process::id()));
+    std::fs::write(&path, &xci).unwrap();
+
+    let virtual_path = format!("{}:/secure/program.nca/romfs/data/file.bin", path.display());
+    let parsed = VirtualPath::parse(&virtual_path).unwrap();
+    assert_eq!(parsed.host_path, path);
+    assert_eq!(parsed.inner_path, "secure/program.nca/romfs/data/file.bin");
+    assert_eq!(parsed.to_string(), virtual_path);
+
+    let file = ContainerNode::open_virtual(&virtual_path, Rc::new(common::test_keys())).unwrap();
+    assert_eq!(file.name, "data/file.bin");
+    let mut data = Vec::new();
+    assert_eq!(file.write_to(&mut data).unwrap(), 0x1800);
+    assert_eq!(data, vec![0x22u8; 0x1800]);
+
+    let root = ContainerNode::open(&path, Rc::new(common::test_keys())).unwrap();
+    assert_eq!(root.resolve("/secure/program.nca/exefs/main.npdm").unwrap().file_type, FileType::Npdm);
+    assert_eq!(root.resolve("").unwrap().file_type, FileType::Xci);
+    assert!(root.resolve("secure/missing.nca").is_err());
+    assert!(root.resolve("secure/program.nca/romfs/data/missing.bin").is_err());
+    std::fs::remove_file(&path).unwrap();
+}
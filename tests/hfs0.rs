@@ -0,0 +1,76 @@
+use hactool_rs::file_formats::{
+    hfs0::{Hfs0, Hfs0Builder},
+    Validity,
+};
+use binrw::BinReaderExt;
+use sha2::{Digest, Sha256};
+use std::io::{Cursor, Read, Write};
+
+#[test]
+pub fn create_and_parse_hfs0_partitions() {
+    let nca = vec![0x5Au8; 0x1234];
+    let small = b"tiny".to_vec();
+
+    let mut secure = Hfs0Builder::new();
+    secure
+        .add_reader("abc.nca", nca.len() as u64, nca.as_slice())
+        .add_reader("small.bin", small.len() as u64, small.as_slice());
+    let mut root = Hfs0Builder::new();
+    root.add_partition("secure", secure);
+    let expected_size = root.total_size();
+
+    let mut output = Vec::new();
+    let written = root.write(&mut output).unwrap();
+    assert_eq!(written, expected_size);
+    assert_eq!(output.len() as u64, written);
+
+    let parsed: Hfs0 = Cursor::new(&output).read_le().unwrap();
+    assert_eq!(parsed.files.len(), 1);
+    assert_eq!(parsed.files[0].file_name.to_string(), "secure");
+    assert_eq!(parsed.files[0].hashed_prefix_len, 0x200);
+    assert_eq!(parsed.files[0].verify_prefix_hash(), Validity::Valid);
+
+    let nested: Hfs0 = Cursor::new(&parsed.files[0].file_data).read_le().unwrap();
+    assert_eq!(nested.files.len(), 2);
+    assert_eq!(nested.files[0].file_data, nca);
+    assert_eq!(nested.files[0].hashed_prefix_len, 0x200);
+    assert_eq!(nested.files[1].file_data, small);
+    assert_eq!(nested.files[1].hashed_prefix_len, small.len() as u32);
+    assert!(nested.files.iter().all(|f| f.verify_prefix_hash() == Validity::Valid));
+}
+
+/// Keeps the first `limit` bytes written and fails after them, so only the header of a huge
+/// partition is generated
+struct HeaderWriter {
+    data: Vec<u8>,
+    limit: usize,
+}
+
+impl Write for HeaderWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.limit - self.data.len());
+        if len == 0 {
+            return Err(std::io::Error::other("Header written"));
+        }
+        self.data.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+pub fn keep_hashed_prefix_of_large_files() {
+    let size = 0x1_0000_0100u64;
+    let mut builder = Hfs0Builder::new();
+    builder.add_reader_with_hashed_prefix("large.nca", size, 0x8000, std::io::repeat(0x5A).take(size));
+    let mut writer = HeaderWriter { data: Vec::new(), limit: 0x200 };
+    assert!(builder.write(&mut writer).is_err());
+
+    let header = writer.data;
+    assert_eq!(u64::from_le_bytes(header[0x18..0x20].try_into().unwrap()), size);
+    assert_eq!(u32::from_le_bytes(header[0x24..0x28].try_into().unwrap()), 0x8000);
+    assert_eq!(header[0x30..0x50], Sha256::digest([0x5A; 0x8000])[..]);
+}
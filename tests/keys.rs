@@ -0,0 +1,165 @@
+use aes::{
+    Aes128,
+    cipher::{BlockDecrypt, KeyInit, generic_array::GenericArray},
+};
+use hactool_rs::keys::NcaKeys;
+
+fn decrypt(key: &[u8; 0x10], data: &[u8]) -> Vec<u8> {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    let mut out = data.to_vec();
+    for block in out.chunks_exact_mut(0x10) {
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+    out
+}
+
+fn block(data: Vec<u8>) -> [u8; 0x10] {
+    data.try_into().unwrap()
+}
+
+fn write_keys(name: &str, lines: &[(&str, u8, usize)]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("hactool-rs-{}-{}.keys", name, std::process::id()));
+    let text: String = lines
+        .iter()
+        .map(|(name, byte, len)| format!("{} = {}\n", name, hex::encode(vec![*byte; *len])))
+        .collect();
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+pub fn derive_bis_keys_from_secure_boot_key() {
+    let path = write_keys(
+        "console",
+        &[
+            ("secure_boot_key", 0x01, 0x10),
+            ("tsec_key", 0x02, 0x10),
+            ("keyblob_key_source_00", 0x03, 0x10),
+            ("per_console_key_source", 0x04, 0x10),
+            ("aes_kek_generation_source", 0x05, 0x10),
+            ("aes_key_generation_source", 0x06, 0x10),
+            ("bis_kek_source", 0x07, 0x10),
+            ("bis_key_source_00", 0x08, 0x20),
+            ("bis_key_source_01", 0x09, 0x20),
+            ("bis_key_source_02", 0x0A, 0x20),
+        ],
+    );
+    let keys = NcaKeys::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let keyblob_key = block(decrypt(&[0x01; 0x10], &decrypt(&[0x02; 0x10], &[0x03; 0x10])));
+    let device_key = block(decrypt(&keyblob_key, &[0x04; 0x10]));
+    assert_eq!(keys.console.keyblob_keys[0], keyblob_key);
+    assert_eq!(keys.console.device_key, device_key);
+
+    let kek = block(decrypt(&device_key, &[0x05; 0x10]));
+    let bis_kek = block(decrypt(&block(decrypt(&kek, &[0x07; 0x10])), &[0x06; 0x10]));
+    assert_eq!(keys.console.bis_keys[0].to_vec(), decrypt(&device_key, &[0x08; 0x20]));
+    assert_eq!(keys.console.bis_keys[1].to_vec(), decrypt(&bis_kek, &[0x09; 0x20]));
+    assert_eq!(keys.console.bis_keys[2].to_vec(), decrypt(&bis_kek, &[0x0A; 0x20]));
+    assert_eq!(keys.console.bis_keys[3], keys.console.bis_keys[2]);
+}
+
+#[test]
+pub fn keep_provided_console_keys() {
+    let path = write_keys(
+        "provided",
+        &[
+            ("device_key", 0x11, 0x10),
+            ("bis_key_source_00", 0x08, 0x20),
+            ("bis_key_00", 0x22, 0x20),
+            ("bis_key_source_01", 0x09, 0x20),
+        ],
+    );
+    let keys = NcaKeys::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(keys.console.device_key, [0x11; 0x10]);
+    assert_eq!(keys.console.bis_keys[0], [0x22; 0x20]);
+    // No BIS kek source, so SAFE, SYSTEM and USER stay unknown
+    assert_eq!(keys.console.bis_keys[1], [0; 0x20]);
+}
+
+#[test]
+pub fn derive_save_mac_key() {
+    let path = write_keys(
+        "save-mac",
+        &[
+            ("device_key", 0x11, 0x10),
+            ("aes_kek_generation_source", 0x05, 0x10),
+            ("aes_key_generation_source", 0x06, 0x10),
+            ("save_mac_kek_source", 0x0B, 0x10),
+            ("save_mac_key_source", 0x0C, 0x10),
+        ],
+    );
+    let keys = NcaKeys::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let kek = block(decrypt(&[0x11; 0x10], &[0x05; 0x10]));
+    let save_mac_kek = block(decrypt(&block(decrypt(&kek, &[0x0B; 0x10])), &[0x06; 0x10]));
+    assert_eq!(keys.console.save_mac_key, block(decrypt(&save_mac_kek, &[0x0C; 0x10])));
+    assert_eq!(hex::encode(keys.console.save_mac_key), "744e5820c3492504e952fa5eae5517b1");
+
+    // A save MAC key given in the file is kept
+    let path = write_keys(
+        "save-mac-provided",
+        &[
+            ("device_key", 0x11, 0x10),
+            ("aes_kek_generation_source", 0x05, 0x10),
+            ("aes_key_generation_source", 0x06, 0x10),
+            ("save_mac_kek_source", 0x0B, 0x10),
+            ("save_mac_key_source", 0x0C, 0x10),
+            ("save_mac_key", 0x33, 0x10),
+        ],
+    );
+    let keys = NcaKeys::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(keys.console.save_mac_key, [0x33; 0x10]);
+}
+
+#[test]
+pub fn derive_eticket_rsa_kek() {
+    let path = write_keys(
+        "eticket",
+        &[
+            ("master_key_00", 0x31, 0x10),
+            ("aes_kek_generation_source", 0x32, 0x10),
+            ("eticket_rsa_kekek_source", 0x33, 0x10),
+            ("eticket_rsa_kek_source", 0x34, 0x10),
+        ],
+    );
+    let keys = NcaKeys::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let kek = block(decrypt(&[0x31; 0x10], &[0x32; 0x10]));
+    let kekek = block(decrypt(&kek, &[0x33; 0x10]));
+    assert_eq!(keys.eticket_rsa_kek, block(decrypt(&kekek, &[0x34; 0x10])));
+
+    // Without the kek generation source the kek can't be derived
+    let path = write_keys(
+        "eticket-partial",
+        &[("master_key_00", 0x31, 0x10), ("eticket_rsa_kekek_source", 0x33, 0x10), ("eticket_rsa_kek_source", 0x34, 0x10)],
+    );
+    let keys = NcaKeys::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(keys.eticket_rsa_kek, [0; 0x10]);
+}
+
+#[test]
+pub fn redact_console_keys_in_debug_output() {
+    let mut keys = NcaKeys::default();
+    keys.console.device_key = [0xAB; 0x10];
+    keys.console.bis_keys[2] = [0xCD; 0x20];
+    keys.console.save_mac_key = [0xEF; 0x10];
+
+    let output = format!("{:?}", keys.console);
+    assert!(output.contains("device_key: <redacted>"));
+    assert!(output.contains("save_mac_key: <redacted>"));
+    assert!(output.contains("tsec_key: <unset>"));
+    assert!(!output.contains("171"));
+    assert!(!output.contains("205"));
+    assert!(!output.contains("239"));
+    let output = format!("{:?}", keys);
+    assert!(!output.contains("171, 171"));
+    assert!(!output.contains("239, 239"));
+}
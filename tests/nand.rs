@@ -0,0 +1,310 @@
+use std::io::{Cursor, Read};
+
+use aes::{
+    Aes128,
+    cipher::{KeyInit, generic_array::GenericArray},
+};
+use hactool_rs::file_formats::{
+    Validity,
+    container::FileType,
+    fat32::Fat32,
+    nand::{BisPartition, NandReader},
+};
+use hactool_rs::keys::{ConsoleKeys, NcaKeys};
+use xts_mode::Xts128;
+
+const PRODINFO_OFFSET: usize = 0x8000;
+const PACKAGE2_OFFSET: usize = 0xC000;
+const SYSTEM_OFFSET: usize = 0x10000;
+const SYSTEM_SIZE: usize = 0xC000;
+const NCA_NAME: &str = "0123456789abcdef0123456789abcdef.nca";
+const SPLIT_NCA_NAME: &str = "fedcba9876543210fedcba9876543210.nca";
+
+fn test_keys() -> NcaKeys {
+    NcaKeys {
+        console: ConsoleKeys {
+            bis_keys: [[0x10; 0x20], [0x11; 0x20], [0x12; 0x20], [0x12; 0x20]],
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn put(image: &mut [u8], offset: usize, bytes: &[u8]) {
+    image[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+fn test_data(size: usize, seed: usize) -> Vec<u8> {
+    (0..size).map(|i| ((i * 7 + seed) % 251) as u8).collect()
+}
+
+fn short_entry(short_name: &[u8; 11], attributes: u8, case_flags: u8, cluster: u32, size: u32) -> Vec<u8> {
+    let mut entry = vec![0u8; 0x20];
+    put(&mut entry, 0, short_name);
+    entry[0x0B] = attributes;
+    entry[0x0C] = case_flags;
+    put(&mut entry, 0x14, &((cluster >> 16) as u16).to_le_bytes());
+    put(&mut entry, 0x1A, &(cluster as u16).to_le_bytes());
+    put(&mut entry, 0x1C, &size.to_le_bytes());
+    entry
+}
+
+/// Long file name entries for `name`, followed by its short entry
+fn long_entry(name: &str, short_name: &[u8; 11], attributes: u8, cluster: u32, size: u32) -> Vec<u8> {
+    let checksum = short_name.iter().fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c));
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    chars.push(0);
+    chars.resize(chars.len().next_multiple_of(13), 0xFFFF);
+    let parts: Vec<_> = chars.chunks(13).collect();
+
+    let mut entries = Vec::new();
+    for (index, part) in parts.iter().enumerate().rev() {
+        let mut entry = vec![0u8; 0x20];
+        entry[0] = (index + 1) as u8 | if index == parts.len() - 1 { 0x40 } else { 0 };
+        entry[0x0B] = 0x0F;
+        entry[0x0D] = checksum;
+        let offsets = (1..11).step_by(2).chain((14..26).step_by(2)).chain((28..32).step_by(2));
+        for (offset, c) in offsets.zip(part.iter()) {
+            put(&mut entry, offset, &c.to_le_bytes());
+        }
+        entries.extend(entry);
+    }
+    entries.extend(short_entry(short_name, attributes, 0, cluster, size));
+    entries
+}
+
+/// A FAT32 file system with 0x200 byte clusters holding two NCAs, one split into two parts
+fn test_fat32() -> Vec<u8> {
+    let mut fs = vec![0u8; SYSTEM_SIZE];
+    put(&mut fs, 0x0B, &0x200u16.to_le_bytes());
+    fs[0x0D] = 1;
+    put(&mut fs, 0x0E, &2u16.to_le_bytes());
+    fs[0x10] = 1;
+    put(&mut fs, 0x24, &1u32.to_le_bytes());
+    put(&mut fs, 0x2C, &2u32.to_le_bytes());
+    put(&mut fs, 0x47, b"SYSTEM     ");
+    put(&mut fs, 0x52, b"FAT32   ");
+    put(&mut fs, 0x1FE, &[0x55, 0xAA]);
+
+    let fat: [u32; 11] = [0x0FFF_FFF8, 0x0FFF_FFFF, 0x0FFF_FFFF, 0x0FFF_FFFF, 0x0FFF_FFFF, 6, 0x0FFF_FFFF, 0x0FFF_FFFF, 0x0FFF_FFFF, 0x0FFF_FFFF, 0x0FFF_FFFF];
+    for (index, next) in fat.into_iter().enumerate() {
+        put(&mut fs, 0x400 + index * 4, &next.to_le_bytes());
+    }
+    let cluster = |index: usize| 0x600 + (index - 2) * 0x200;
+
+    let mut root = short_entry(b"SYSTEM     ", 0x08, 0, 0, 0);
+    root.extend(long_entry("Contents", b"CONTENTS   ", 0x10, 3, 0));
+    root.extend(short_entry(b"README  TXT", 0x20, 0x18, 10, 5));
+    put(&mut fs, cluster(2), &root);
+
+    let mut contents = short_entry(b".          ", 0x10, 0, 3, 0);
+    contents.extend(short_entry(b"..         ", 0x10, 0, 0, 0));
+    let mut deleted = short_entry(b"OLD     NCA", 0x20, 0, 0, 0);
+    deleted[0] = 0xE5;
+    contents.extend(deleted);
+    contents.extend(long_entry("registered", b"REGIST~1   ", 0x10, 4, 0));
+    put(&mut fs, cluster(3), &contents);
+
+    let mut registered = long_entry(NCA_NAME, b"01234567NCA", 0x20, 5, 0x300);
+    registered.extend(long_entry(SPLIT_NCA_NAME, b"FEDCBA~1NCA", 0x30, 7, 0));
+    put(&mut fs, cluster(4), &registered);
+
+    let mut parts = short_entry(b".          ", 0x10, 0, 7, 0);
+    parts.extend(short_entry(b"..         ", 0x10, 0, 4, 0));
+    parts.extend(short_entry(b"00         ", 0x20, 0, 8, 0x200));
+    parts.extend(short_entry(b"01         ", 0x20, 0, 9, 0x100));
+    put(&mut fs, cluster(7), &parts);
+
+    put(&mut fs, cluster(5), &test_data(0x300, 1));
+    let split = test_data(0x300, 2);
+    put(&mut fs, cluster(8), &split[..0x200]);
+    put(&mut fs, cluster(9), &split[0x200..]);
+    put(&mut fs, cluster(10), b"hello");
+    fs
+}
+
+fn encrypt_bis(data: &mut [u8], bis_key: &[u8; 0x20]) {
+    let xts = Xts128::new(
+        Aes128::new(GenericArray::from_slice(&bis_key[..0x10])),
+        Aes128::new(GenericArray::from_slice(&bis_key[0x10..])),
+    );
+    xts.encrypt_area(data, 0x4000, 0, |sector: u128| sector.to_be_bytes());
+}
+
+/// Fill in the CRC32s of the GPT partition entries and header
+fn set_gpt_crcs(image: &mut [u8]) {
+    let entries_crc = crc32fast::hash(&image[0x400..0x400 + 4 * 0x80]);
+    put(image, 0x258, &entries_crc.to_le_bytes());
+    put(image, 0x210, &[0; 4]);
+    let header_crc = crc32fast::hash(&image[0x200..0x25C]);
+    put(image, 0x210, &header_crc.to_le_bytes());
+}
+
+/// A NAND with PRODINFO, a package2 partition and SYSTEM, plus an unused GPT entry
+fn test_nand() -> Vec<u8> {
+    let mut image = vec![0u8; SYSTEM_OFFSET + SYSTEM_SIZE];
+    let keys = test_keys();
+
+    put(&mut image, 0x200, b"EFI PART");
+    put(&mut image, 0x208, &0x10000u32.to_le_bytes());
+    put(&mut image, 0x20C, &0x5Cu32.to_le_bytes());
+    put(&mut image, 0x248, &2u64.to_le_bytes());
+    put(&mut image, 0x250, &4u32.to_le_bytes());
+    put(&mut image, 0x254, &0x80u32.to_le_bytes());
+
+    let partitions = [("PRODINFO", PRODINFO_OFFSET, 0x4000), ("BCPKG2-1-Normal-Main", PACKAGE2_OFFSET, 0x4000), ("SYSTEM", SYSTEM_OFFSET, SYSTEM_SIZE)];
+    for (index, (name, offset, size)) in partitions.into_iter().enumerate() {
+        let entry = 0x400 + index * 0x80;
+        put(&mut image, entry, &[0xA0 + index as u8; 0x10]);
+        put(&mut image, entry + 0x20, &((offset / 0x200) as u64).to_le_bytes());
+        put(&mut image, entry + 0x28, &(((offset + size) / 0x200 - 1) as u64).to_le_bytes());
+        let name: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        put(&mut image, entry + 0x38, &name);
+    }
+    set_gpt_crcs(&mut image);
+
+    let mut prodinfo = vec![0u8; 0x4000];
+    put(&mut prodinfo, 0, b"CAL0");
+    encrypt_bis(&mut prodinfo, &keys.console.bis_keys[0]);
+    put(&mut image, PRODINFO_OFFSET, &prodinfo);
+    put(&mut image, PACKAGE2_OFFSET, b"package2 in the clear");
+    let mut system = test_fat32();
+    encrypt_bis(&mut system, &keys.console.bis_keys[2]);
+    put(&mut image, SYSTEM_OFFSET, &system);
+    image
+}
+
+#[test]
+pub fn parse_nand_partitions() {
+    let image = test_nand();
+    assert_eq!(FileType::detect_bytes(&image[..0x400], None), FileType::Nand);
+
+    let nand = NandReader::parse_reader(Cursor::new(image), &test_keys()).unwrap();
+    let partitions: Vec<_> = nand.partitions.iter().map(|p| (p.name.as_str(), p.kind, p.offset, p.size)).collect();
+    assert_eq!(
+        partitions,
+        [
+            ("PRODINFO", BisPartition::Prodinfo, PRODINFO_OFFSET as u64, 0x4000),
+            ("BCPKG2-1-Normal-Main", BisPartition::Package2, PACKAGE2_OFFSET as u64, 0x4000),
+            ("SYSTEM", BisPartition::System, SYSTEM_OFFSET as u64, SYSTEM_SIZE as u64),
+        ]
+    );
+    assert_eq!(BisPartition::from_name("boot0"), BisPartition::Boot0);
+    assert_eq!(BisPartition::User.bis_key_index(), Some(3));
+}
+
+#[test]
+pub fn reject_corrupt_gpt() {
+    let parse = |image: Vec<u8>| NandReader::parse_reader(Cursor::new(image), &test_keys()).map(|_| ()).unwrap_err().to_string();
+
+    // A huge entry count is refused before anything is allocated, even with valid CRCs
+    let mut image = test_nand();
+    put(&mut image, 0x250, &0xFFFF_FFFFu32.to_le_bytes());
+    put(&mut image, 0x254, &0x200u32.to_le_bytes());
+    let header_crc = {
+        put(&mut image, 0x210, &[0; 4]);
+        crc32fast::hash(&image[0x200..0x25C])
+    };
+    put(&mut image, 0x210, &header_crc.to_le_bytes());
+    assert!(parse(image).contains("at most 128"));
+
+    let mut image = test_nand();
+    image[0x248] = 3;
+    assert!(parse(image).contains("header CRC32"));
+
+    let mut image = test_nand();
+    image[0x438] = b'X';
+    assert!(parse(image).contains("partition entry CRC32"));
+
+    // LBAs whose byte offsets overflow
+    let mut image = test_nand();
+    put(&mut image, 0x248, &u64::MAX.to_le_bytes());
+    set_gpt_crcs(&mut image);
+    assert!(parse(image).contains("entry LBA"));
+
+    let system_entry = 0x400 + 2 * 0x80;
+    let mut image = test_nand();
+    put(&mut image, system_entry + 0x28, &u64::MAX.to_le_bytes());
+    set_gpt_crcs(&mut image);
+    assert!(parse(image).contains("ends before it starts"));
+
+    let mut image = test_nand();
+    put(&mut image, system_entry + 0x20, &(u64::MAX / 0x100).to_le_bytes());
+    put(&mut image, system_entry + 0x28, &(u64::MAX / 0x100).to_le_bytes());
+    set_gpt_crcs(&mut image);
+    assert!(parse(image).contains("out of range"));
+}
+
+#[test]
+pub fn decrypt_bis_partitions() {
+    let nand = NandReader::parse_reader(Cursor::new(test_nand()), &test_keys()).unwrap();
+    let read_start = |kind| {
+        let mut start = [0u8; 0x15];
+        nand.open_partition(nand.partition(kind).unwrap()).unwrap().read_exact(&mut start).unwrap();
+        start
+    };
+    assert_eq!(&read_start(BisPartition::Prodinfo)[..4], b"CAL0");
+    assert_eq!(&read_start(BisPartition::Package2), b"package2 in the clear");
+    for kind in [BisPartition::Prodinfo, BisPartition::System] {
+        assert_eq!(nand.verify_partition(nand.partition(kind).unwrap()), Validity::Valid);
+    }
+    assert_eq!(nand.verify_partition(nand.partition(BisPartition::Package2).unwrap()), Validity::Unchecked);
+
+    let wrong_keys = NcaKeys {
+        console: ConsoleKeys { bis_keys: [[0x20; 0x20]; 4], ..Default::default() },
+        ..Default::default()
+    };
+    let nand = NandReader::parse_reader(Cursor::new(test_nand()), &wrong_keys).unwrap();
+    assert_eq!(nand.verify_partition(nand.partition(BisPartition::System).unwrap()), Validity::Invalid);
+
+    let nand = NandReader::parse_reader(Cursor::new(test_nand()), &NcaKeys::default()).unwrap();
+    let system = nand.partition(BisPartition::System).unwrap();
+    assert_eq!(nand.verify_partition(system), Validity::Unchecked);
+    assert!(nand.open_partition(system).is_err());
+}
+
+#[test]
+pub fn list_system_ncas() {
+    let nand = NandReader::parse_reader(Cursor::new(test_nand()), &test_keys()).unwrap();
+    let system = nand.partition(BisPartition::System).unwrap();
+    let ncas: Vec<_> = nand.registered_ncas(system).unwrap().into_iter().map(|nca| (nca.path, nca.size, nca.split)).collect();
+    assert_eq!(
+        ncas,
+        [
+            (format!("Contents/registered/{}", NCA_NAME), 0x300, false),
+            (format!("Contents/registered/{}", SPLIT_NCA_NAME), 0x300, true),
+        ]
+    );
+
+    let fs = nand.open_fat32(system).unwrap();
+    assert_eq!(fs.boot_sector.volume_label, "SYSTEM");
+    let paths: Vec<_> = fs.entries().unwrap().into_iter().map(|entry| entry.path).collect();
+    assert_eq!(paths[..3], ["Contents", "Contents/registered", &format!("Contents/registered/{}", NCA_NAME)]);
+    assert_eq!(paths[4], "readme.txt");
+
+    let read = |path: &str| {
+        let entry = fs.find(path).unwrap().unwrap();
+        let mut data = Vec::new();
+        fs.open_file(&entry).unwrap().read_to_end(&mut data).unwrap();
+        data
+    };
+    assert_eq!(read(&format!("contents/REGISTERED/{}", NCA_NAME)), test_data(0x300, 1));
+    assert_eq!(read(&format!("Contents/registered/{}", SPLIT_NCA_NAME)), test_data(0x300, 2));
+    assert_eq!(read("/readme.txt"), b"hello");
+
+    // A FAT larger than the partition is refused before it is read
+    let mut fs = test_fat32();
+    put(&mut fs, 0x24, &0xFFFF_FFFFu32.to_le_bytes());
+    assert!(Fat32::parse_reader(Cursor::new(fs)).unwrap_err().to_string().contains("ends past the partition"));
+}
+
+#[test]
+pub fn report_unreadable_ncas_in_json() {
+    let nand = NandReader::parse_reader(Cursor::new(test_nand()), &NcaKeys::default()).unwrap();
+    let json = serde_json::to_value(&nand).unwrap();
+    assert!(json["ncas"]["SYSTEM"]["error"].as_str().unwrap().contains("bis_key_02"));
+
+    let nand = NandReader::parse_reader(Cursor::new(test_nand()), &test_keys()).unwrap();
+    let json = serde_json::to_value(&nand).unwrap();
+    assert_eq!(json["ncas"]["SYSTEM"].as_array().unwrap().len(), 2);
+}
@@ -0,0 +1,202 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use aes::{
+    Aes128,
+    cipher::{BlockEncrypt, KeyInit, generic_array::GenericArray},
+};
+use hactool_rs::file_formats::{
+    Validity,
+    nax0::{NAX0_HEADER_SIZE, Nax0KeyType, Nax0Reader, sd_relative_path},
+    sd_card::{decrypt_sd_card, sd_seed_from_private},
+};
+use hactool_rs::keys::{ConsoleKeys, NcaKeys};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use xts_mode::Xts128;
+
+const SD_PATH: &str = "/registered/000000AB/0123456789abcdef0123456789abcdef.nca";
+const XTS_KEYS: [[u8; 0x10]; 2] = [[0x11; 0x10], [0x22; 0x10]];
+
+fn test_keys() -> NcaKeys {
+    NcaKeys {
+        console: ConsoleKeys {
+            sd_card_keys: [[0x33; 0x20], [0x44; 0x20]],
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn test_data(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+/// `data` wrapped in a NAX0 for `SD_PATH`, encrypted with the NCA SD card key
+fn test_nax0(data: &[u8]) -> Vec<u8> {
+    nax0_at(data, SD_PATH, Nax0KeyType::Nca)
+}
+
+/// `data` wrapped in a NAX0 for `sd_path`. The path keys are the HMAC of the path keyed with the
+/// first half of the SD card key, and the header MAC is keyed with the second half and covers the
+/// header with the keys decrypted.
+fn nax0_at(data: &[u8], sd_path: &str, key_type: Nax0KeyType) -> Vec<u8> {
+    let sd_card_key = test_keys().console.sd_card_keys[(key_type == Nax0KeyType::Nca) as usize];
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&sd_card_key[..0x10]).unwrap();
+    mac.update(sd_path.as_bytes());
+    let path_keys = mac.finalize().into_bytes();
+
+    let mut nax0 = vec![0u8; NAX0_HEADER_SIZE as usize];
+    nax0[0x20..0x28].copy_from_slice(b"NAX0\0\0\0\0");
+    nax0[0x28..0x48].copy_from_slice(XTS_KEYS.as_flattened());
+    nax0[0x48..0x50].copy_from_slice(&(data.len() as u64).to_le_bytes());
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&sd_card_key[0x10..]).unwrap();
+    mac.update(&nax0[0x20..0x80]);
+    nax0[..0x20].copy_from_slice(&mac.finalize().into_bytes());
+
+    for (index, path_key) in path_keys.chunks_exact(0x10).enumerate() {
+        let mut key = GenericArray::clone_from_slice(&XTS_KEYS[index]);
+        Aes128::new(GenericArray::from_slice(path_key)).encrypt_block(&mut key);
+        nax0[0x28 + index * 0x10..0x38 + index * 0x10].copy_from_slice(&key);
+    }
+
+    let mut body = data.to_vec();
+    body.resize(data.len().next_multiple_of(0x10), 0);
+    let xts = Xts128::new(
+        Aes128::new(GenericArray::from_slice(&XTS_KEYS[0])),
+        Aes128::new(GenericArray::from_slice(&XTS_KEYS[1])),
+    );
+    xts.encrypt_area(&mut body, 0x4000, 0, |sector: u128| sector.to_be_bytes());
+    nax0.extend(body);
+    nax0
+}
+
+#[test]
+pub fn decrypt_nax0() {
+    let data = test_data(0x5004);
+    let mut nax0 = Nax0Reader::parse_reader(Cursor::new(test_nax0(&data)), SD_PATH, &test_keys()).unwrap();
+    assert_eq!(nax0.key_type, Nax0KeyType::Nca);
+    assert_eq!(nax0.keys(), XTS_KEYS);
+    assert_eq!(nax0.size(), data.len() as u64);
+
+    let mut decrypted = Vec::new();
+    nax0.read_to_end(&mut decrypted).unwrap();
+    assert_eq!(decrypted, data);
+
+    // Read across the sector boundary
+    let mut buf = [0u8; 0x20];
+    nax0.seek(SeekFrom::Start(0x3FF0)).unwrap();
+    nax0.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data[0x3FF0..0x4010]);
+}
+
+/// Header of a 0x100 byte NAX0 at `SD_PATH` with the NCA SD card key, computed independently
+/// with Python's `hmac` and `cryptography` modules
+const KNOWN_HEADER: &str = "C616829531CBF1F583B7E4DC46A05A07EACA44FF4CC8C3AA8E0E42783382E0F1\
+                            4E41583000000000E82E3A8F9B2EC661F883E46CBF855FD3821A1786B763C7E7\
+                            698A3EE9A373C8C4000100000000000000000000000000000000000000000000\
+                            0000000000000000000000000000000000000000000000000000000000000000";
+
+#[test]
+pub fn decrypt_known_nax0_header() {
+    let data = test_data(0x100);
+    let nax0 = test_nax0(&data);
+    assert_eq!(hex::encode_upper(&nax0[..0x80]), KNOWN_HEADER);
+
+    let mut reader = Nax0Reader::parse_reader(Cursor::new(nax0), SD_PATH, &test_keys()).unwrap();
+    assert_eq!(reader.keys(), XTS_KEYS);
+    let mut decrypted = Vec::new();
+    reader.read_to_end(&mut decrypted).unwrap();
+    assert_eq!(decrypted, data);
+}
+
+#[test]
+pub fn reject_nax0_with_wrong_path() {
+    let nax0 = test_nax0(&test_data(0x100));
+    let wrong_path = SD_PATH.replace("000000AB", "000000AC");
+    assert!(Nax0Reader::parse_reader(Cursor::new(nax0), &wrong_path, &test_keys()).is_err());
+}
+
+#[test]
+pub fn open_split_nax0() {
+    let data = test_data(0x6000);
+    let nax0 = test_nax0(&data);
+    let root = std::env::temp_dir().join(format!("hactool-rs-nax0-{}", std::process::id()));
+    let directory = root.join("Nintendo/Contents").join(&SD_PATH[1..]);
+    std::fs::create_dir_all(&directory).unwrap();
+    for (index, part) in nax0.chunks(0x3000).enumerate() {
+        std::fs::write(directory.join(format!("{:02}", index)), part).unwrap();
+    }
+
+    let mut reader = Nax0Reader::open(&directory, None, &test_keys()).unwrap();
+    let mut decrypted = Vec::new();
+    reader.read_to_end(&mut decrypted).unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+    assert_eq!(decrypted, data);
+}
+
+#[test]
+pub fn sd_relative_paths() {
+    assert_eq!(
+        sd_relative_path(Path::new("sd/Nintendo/Contents/registered/000000AB/abc.nca")).as_deref(),
+        Some("/registered/000000AB/abc.nca")
+    );
+    assert_eq!(
+        sd_relative_path(Path::new("sd/Nintendo/Contents/registered/000000AB/abc.nca/01")).as_deref(),
+        Some("/registered/000000AB/abc.nca")
+    );
+    assert_eq!(sd_relative_path(Path::new("sd/Nintendo/save/0123456789abcdef")).as_deref(), Some("/0123456789abcdef"));
+    assert_eq!(sd_relative_path(Path::new("sd/Nintendo/Contents")), None);
+    assert_eq!(sd_relative_path(Path::new("abc.nca")), None);
+}
+
+#[test]
+pub fn decrypt_sd_card_folder() {
+    let root = std::env::temp_dir().join(format!("hactool-rs-sd-{}", std::process::id()));
+    let nintendo = root.join("Nintendo");
+    let nca_path = "/registered/000000AB/0123456789abcdef0123456789abcdef.nca";
+    let save_path = "/0123456789abcdef";
+    let (nca_data, save_data) = (test_data(0x4800), test_data(0x300));
+
+    let nca_folder = nintendo.join("Contents").join(&nca_path[1..]);
+    std::fs::create_dir_all(&nca_folder).unwrap();
+    std::fs::write(nca_folder.join("00"), nax0_at(&nca_data, nca_path, Nax0KeyType::Nca)).unwrap();
+    std::fs::create_dir_all(nintendo.join("save")).unwrap();
+    std::fs::write(nintendo.join("save").join(&save_path[1..]), nax0_at(&save_data, save_path, Nax0KeyType::Save)).unwrap();
+    // Moved from elsewhere, so its keys do not match its path
+    let moved_folder = nintendo.join("Contents/registered/000000CD/fedcba9876543210fedcba9876543210.nca");
+    std::fs::create_dir_all(&moved_folder).unwrap();
+    std::fs::write(moved_folder.join("00"), nax0_at(&nca_data, nca_path, Nax0KeyType::Nca)).unwrap();
+    std::fs::write(nintendo.join("Contents/private"), [0u8; 0x10]).unwrap();
+
+    let output = root.join("output");
+    let files = decrypt_sd_card(&nintendo, Some(&output), &test_keys()).unwrap();
+    let summary: Vec<_> = files.iter().map(|file| (file.path.as_str(), file.key_type, &file.header_mac)).collect();
+    assert_eq!(
+        summary,
+        [
+            ("Contents/registered/000000AB/0123456789abcdef0123456789abcdef.nca", Some(Nax0KeyType::Nca), &Validity::Valid),
+            ("Contents/registered/000000CD/fedcba9876543210fedcba9876543210.nca", None, &Validity::Invalid),
+            ("save/0123456789abcdef", Some(Nax0KeyType::Save), &Validity::Valid),
+        ]
+    );
+    assert_eq!(files[0].sd_path, nca_path);
+
+    let decrypted_nca = std::fs::read(output.join("Contents").join(&nca_path[1..])).unwrap();
+    let decrypted_save = std::fs::read(output.join("save").join(&save_path[1..])).unwrap();
+    let moved_exists = output.join("Contents/registered/000000CD").exists();
+    std::fs::remove_dir_all(&root).unwrap();
+    assert_eq!(decrypted_nca, nca_data);
+    assert_eq!(decrypted_save, save_data);
+    assert!(!moved_exists);
+}
+
+#[test]
+pub fn read_sd_seed_from_private() {
+    let mut private = [0xAAu8; 0x20];
+    private[0x10..].copy_from_slice(&[0x55; 0x10]);
+    assert_eq!(sd_seed_from_private(&private, None).unwrap(), [0x55; 0x10]);
+    assert_eq!(sd_seed_from_private(&private, Some(&[0xAA; 0x10])).unwrap(), [0x55; 0x10]);
+    assert!(sd_seed_from_private(&private, Some(&[0xBB; 0x10])).is_err());
+    assert!(sd_seed_from_private(&private[..0x10], None).is_err());
+}
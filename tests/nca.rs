@@ -0,0 +1,215 @@
+mod common;
+
+use std::io::Cursor;
+
+use hactool_rs::file_formats::{
+    nca::{fs::{FsType, SuperBlock}, ContentType, KeyGeneration, NcaBuilder, NcaFileReader, NcaVersion},
+    pfs0::Pfs0Builder,
+    romfs::RomFsBuilder,
+    Validity,
+};
+use hactool_rs::info::{JsonReport, PrettyInfo, Verify, JSON_SCHEMA_VERSION};
+use sha2::{Digest, Sha256};
+
+#[test]
+pub fn create_and_parse_nca() {
+    let keys = common::test_keys();
+    let signing_key = common::test_rsa_key();
+
+    let main = vec![0x11u8; 0x2345];
+    let mut exefs = Vec::new();
+    let mut pfs0 = Pfs0Builder::new();
+    pfs0.add_reader("main", main.len() as u64, main.as_slice());
+    pfs0.write(&mut exefs).unwrap();
+
+    let data = vec![0x22u8; 0x9000];
+    let mut romfs = Vec::new();
+    let mut romfs_builder = RomFsBuilder::new();
+    romfs_builder.add_reader("data/file.bin", data.len() as u64, data.as_slice());
+    romfs_builder.write(&mut romfs).unwrap();
+
+    let mut builder = NcaBuilder::new(ContentType::Program, 0x0100000000001000);
+    builder
+        .set_key_generation(KeyGeneration::Five)
+        .set_content_key([0x33; 0x10])
+        .set_header_signing_key(signing_key.clone())
+        .add_pfs0_section(0, exefs.len() as u64, Cursor::new(exefs.as_slice()))
+        .add_romfs_section(1, romfs.len() as u64, Cursor::new(romfs.as_slice()));
+
+    let path = std::env::temp_dir().join(format!("hactool-rs-create-{}.nca", std::process::id()));
+    let written = builder.write(&mut std::fs::File::create(&path).unwrap(), &keys).unwrap();
+    assert_eq!(written, std::fs::metadata(&path).unwrap().len());
+
+    let mut nca = NcaFileReader::parse_file(&path, &keys).unwrap();
+    let ctx = &nca.nca_ctx;
+    assert_eq!(ctx.file_version, NcaVersion::Nca3);
+    assert_eq!(ctx.content_size, written);
+    assert_eq!(ctx.program_id, 0x0100000000001000);
+    assert_eq!(ctx.key_generation_old, KeyGeneration::OldThree);
+    assert_eq!(ctx.key_generation, KeyGeneration::Five);
+    assert_eq!(
+        nca.verify_header_signature_with_key(&signing_key.to_public_key()),
+        Validity::Valid
+    );
+
+    assert_eq!(ctx.fs_headers[0].fs_type, FsType::Pfs0);
+    assert!(matches!(&ctx.fs_headers[0].superblock, SuperBlock::Pfs0(sb) if sb.pfs0_size == exefs.len() as u64));
+    assert_eq!(ctx.fs_headers[1].fs_type, FsType::RomFs);
+    assert!(matches!(&ctx.fs_headers[1].superblock, SuperBlock::RomFs(sb) if sb.levels[5].hash_data_size == romfs.len() as u64));
+    assert_eq!(ctx.fs_headers[2].fs_type, FsType::None);
+    for index in 0..2 {
+        let fs_header = &nca.decrypted_header()[0x400 + index * 0x200..0x600 + index * 0x200];
+        assert_eq!(Sha256::digest(fs_header).as_slice(), ctx.section_hashes[index]);
+    }
+    assert!(ctx.section_entries[0].end_block_offset <= ctx.section_entries[1].start_block_offset);
+
+    let info = nca.info_string();
+    assert_eq!(info_field(&info, "Program ID"), Some("0100000000001000"));
+    assert_eq!(info_field(&info, "Master Key Revision"), Some("4 (5.0.0)"));
+    assert_eq!(info_field(&info, "Key Area (Decrypted)"), Some("00000000000000000000000000000000"));
+    assert_eq!(info.matches("(Valid)").count(), 2);
+
+    let results = nca.verification_results();
+    assert_eq!(results.len(), 5);
+    assert!(results.iter().skip(1).all(|result| result.result == Validity::Valid));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&JsonReport::new("nca").with_info(&nca).with_verification(&results).to_json().unwrap()).unwrap();
+    assert_eq!(json["schema_version"], JSON_SCHEMA_VERSION);
+    assert_eq!(json["file_type"], "nca");
+    let header = &json["info"]["header"];
+    assert_eq!(header["content_type"], "Program");
+    assert_eq!(header["key_generation"], "Five");
+    assert_eq!(header["sdk_addon_version"], "0.12.17.0");
+    assert_eq!(header["signature"].as_str().unwrap().len(), 0x200);
+    assert_eq!(header["fs_headers"][0]["fs_type"], "Pfs0");
+    assert_eq!(header["fs_headers"][1]["superblock"]["RomFs"]["levels"][5]["hash_data_size"], romfs.len() as u64);
+    assert_eq!(json["info"]["decrypted_key_area"][2], "33".repeat(0x10));
+    assert_eq!(json["verification"][1]["check"], "fs_header_hash");
+    assert_eq!(json["verification"][1]["target"], "section0");
+    assert_eq!(json["verification"][1]["result"], "Valid");
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+pub fn extract_nca_sections() {
+    let keys = common::test_keys();
+
+    let main = (0..0x2345u32).map(|i| i as u8).collect::<Vec<u8>>();
+    let mut exefs = Vec::new();
+    let mut pfs0 = Pfs0Builder::new();
+    pfs0.add_reader("main", main.len() as u64, main.as_slice());
+    pfs0.write(&mut exefs).unwrap();
+
+    let data = (0..0x9001u32).map(|i| (i * 7) as u8).collect::<Vec<u8>>();
+    let mut romfs = Vec::new();
+    let mut romfs_builder = RomFsBuilder::new();
+    romfs_builder.add_reader("data/file.bin", data.len() as u64, data.as_slice());
+    romfs_builder.write(&mut romfs).unwrap();
+
+    let mut builder = NcaBuilder::new(ContentType::Program, 0x0100000000002000);
+    builder
+        .add_pfs0_section(0, exefs.len() as u64, Cursor::new(exefs.as_slice()))
+        .add_romfs_section(1, romfs.len() as u64, Cursor::new(romfs.as_slice()));
+
+    let base = std::env::temp_dir().join(format!("hactool-rs-extract-{}", std::process::id()));
+    let path = base.with_extension("nca");
+    builder.write(&mut std::fs::File::create(&path).unwrap(), &keys).unwrap();
+
+    let mut nca = NcaFileReader::parse_file(&path, &keys).unwrap();
+    assert_eq!(nca.exefs_section_index(), Some(0));
+    assert_eq!(nca.romfs_section_index(), Some(1));
+
+    nca.extract_pfs0_section(0, base.join("exefs")).unwrap();
+    nca.extract_romfs_section(1, base.join("romfs")).unwrap();
+    assert_eq!(std::fs::read(base.join("exefs/main")).unwrap(), main);
+    assert_eq!(std::fs::read(base.join("romfs/data/file.bin")).unwrap(), data);
+
+    let mut plaintext = Vec::new();
+    let plaintext_size = nca.write_plaintext(&mut plaintext).unwrap();
+    assert_eq!(plaintext_size, plaintext.len() as u64);
+    assert_eq!(&plaintext[..0xC00], nca.decrypted_header());
+
+    let exefs_offset = ctx_section_offset(&nca, 0);
+    let pfs0_offset = match &nca.nca_ctx.fs_headers[0].superblock {
+        SuperBlock::Pfs0(sb) => sb.pfs0_offset as usize,
+        _ => unreachable!(),
+    };
+    assert_eq!(&plaintext[exefs_offset + pfs0_offset..][..exefs.len()], exefs.as_slice());
+
+    let mut section1 = Vec::new();
+    nca.write_section(1, &mut section1).unwrap();
+    let romfs_offset = match &nca.nca_ctx.fs_headers[1].superblock {
+        SuperBlock::RomFs(sb) => sb.levels[5].logical_offset as usize,
+        _ => unreachable!(),
+    };
+    assert_eq!(&section1[romfs_offset..][..romfs.len()], romfs.as_slice());
+    assert_eq!(&plaintext[ctx_section_offset(&nca, 1)..][..section1.len()], section1.as_slice());
+
+    std::fs::remove_dir_all(base).unwrap();
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+pub fn decrypt_nca_with_title_key() {
+    use aes::{Aes128, cipher::{BlockEncrypt, KeyInit, generic_array::GenericArray}};
+
+    let keys = hactool_rs::keys::NcaKeys { titlekeks: [[0x77; 0x10]; 0x20], ..common::test_keys() };
+    let title_key = [0x44u8; 0x10];
+    let mut encrypted_title_key = title_key;
+    Aes128::new(GenericArray::from_slice(&keys.titlekeks[4])).encrypt_block(GenericArray::from_mut_slice(&mut encrypted_title_key));
+
+    let main = vec![0x55u8; 0x1234];
+    let mut exefs = Vec::new();
+    let mut pfs0 = Pfs0Builder::new();
+    pfs0.add_reader("main", main.len() as u64, main.as_slice());
+    pfs0.write(&mut exefs).unwrap();
+
+    let mut builder = NcaBuilder::new(ContentType::Program, 0x0100000000003000);
+    builder
+        .set_key_generation(KeyGeneration::Five)
+        .set_content_key(title_key)
+        .set_rights_id([0x01; 0x10])
+        .add_pfs0_section(0, exefs.len() as u64, Cursor::new(exefs.as_slice()));
+    let mut nca_data = Vec::new();
+    builder.write(&mut nca_data, &keys).unwrap();
+
+    let mut nca = NcaFileReader::parse_reader(Cursor::new(nca_data.clone()), &keys).unwrap();
+    assert!(nca.nca_ctx.has_rights_id());
+    assert_eq!(nca.nca_ctx.encrypted_key_area, [0; 0x40]);
+    assert!(nca.content_key().is_err());
+    assert!(nca.section_pfs0(0).is_err());
+
+    nca.set_title_key(encrypted_title_key, &keys);
+    assert_eq!(nca.content_key().unwrap(), title_key);
+    assert_eq!(nca.section_pfs0(0).unwrap().files[0].file_name.to_string(), "main");
+
+    let mut nca = NcaFileReader::parse_reader(Cursor::new(nca_data), &keys).unwrap();
+    nca.set_decrypted_title_key(title_key);
+    assert_eq!(nca.section_pfs0(0).unwrap().files[0].file_name.to_string(), "main");
+
+    // Without a rights ID the key area is used, even if a title key was given
+    let mut builder = NcaBuilder::new(ContentType::Program, 0x0100000000003000);
+    builder
+        .set_key_generation(KeyGeneration::Five)
+        .set_content_key([0x66; 0x10])
+        .add_pfs0_section(0, exefs.len() as u64, Cursor::new(exefs.as_slice()));
+    let mut nca_data = Vec::new();
+    builder.write(&mut nca_data, &keys).unwrap();
+    let mut nca = NcaFileReader::parse_reader(Cursor::new(nca_data), &keys).unwrap();
+    nca.set_decrypted_title_key(title_key);
+    assert_eq!(nca.content_key().unwrap(), [0x66; 0x10]);
+    assert_eq!(nca.section_pfs0(0).unwrap().files[0].file_name.to_string(), "main");
+}
+
+fn ctx_section_offset(nca: &NcaFileReader, index: usize) -> usize {
+    nca.nca_ctx.section_entries[index].start_block_offset as usize * 0x200
+}
+
+/// Value of the first `label: value` line in `info` output
+fn info_field<'a>(info: &'a str, label: &str) -> Option<&'a str> {
+    info.lines()
+        .find_map(|line| line.trim_start().strip_prefix(label)?.strip_prefix(':'))
+        .map(str::trim)
+}
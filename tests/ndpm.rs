@@ -1,5 +1,18 @@
-use hactool_rs::file_formats::{npdm::NpdmFile, Validity};
-use std::path::PathBuf;
+mod common;
+
+use binrw::BinReaderExt;
+use hactool_rs::file_formats::{
+    npdm::{
+        FsAccessFlags, NpdmFile, ServiceRecord,
+        aci0::{SaveDataAccessibility, SaveDataOwner},
+        descriptor::NpdmDescriptor,
+        kernel_capability::{self, CapabilitySummary, KernelCapability, Syscall},
+        restrictions::RestrictionViolation,
+    },
+    Validity,
+};
+use hactool_rs::info::PrettyInfo;
+use std::{io::Cursor, path::PathBuf};
 
 #[test]
 pub fn parse_test_ndpm() {
@@ -13,3 +26,301 @@ pub fn parse_test_ndpm() {
     
     assert_eq!(parsed.verify_acid(hactool_rs::keys::KeysetType::Retail), Ok(Validity::Valid))
 }
+
+const DESCRIPTOR_JSON: &str = r#"{
+    "name": "sysmodule",
+    "title_id": "0x0100000000000F12",
+    "title_id_range_min": "0x0100000000000F12",
+    "title_id_range_max": "0x0100000000000F12",
+    "main_thread_stack_size": "0x4000",
+    "main_thread_priority": 49,
+    "default_cpu_id": 3,
+    "process_category": 1,
+    "is_retail": true,
+    "pool_partition": 2,
+    "is_64_bit": true,
+    "address_space_type": 3,
+    "filesystem_access": { "permissions": "0xFFFFFFFFFFFFFFFF" },
+    "service_access": ["fsp-srv", "sm:", "*"],
+    "service_host": ["ldr:shel"],
+    "kernel_capabilities": [
+        { "type": "kernel_flags", "value": { "highest_thread_priority": 63, "lowest_thread_priority": 24, "lowest_cpu_id": 3, "highest_cpu_id": 3 } },
+        { "type": "syscalls", "value": { "svcSetHeapSize": "0x01", "svcExitProcess": "0x07", "svcCallSecureMonitor": "0x7F" } },
+        { "type": "map", "value": { "address": "0x70019000", "size": "0x1000", "is_ro": false, "is_io": true } },
+        { "type": "map_page", "value": "0x7000F000" },
+        { "type": "map_region", "value": [{ "region_type": 1, "is_ro": true }] },
+        { "type": "irq_pair", "value": [36, null] },
+        { "type": "application_type", "value": 0 },
+        { "type": "min_kernel_version", "value": "0x0030" },
+        { "type": "handle_table_size", "value": 128 },
+        { "type": "debug_flags", "value": { "allow_debug": true, "force_debug": false } }
+    ]
+}"#;
+
+#[test]
+pub fn create_npdm_from_descriptor() {
+    let descriptor = NpdmDescriptor::from_json(DESCRIPTOR_JSON).unwrap();
+    let npdm = descriptor.to_npdm().unwrap();
+    let bytes = npdm.to_bytes().unwrap();
+
+    assert_eq!(&bytes[..4], b"META");
+    let parsed: NpdmFile = Cursor::new(&bytes).read_le().unwrap();
+    assert_eq!(parsed.aci0.title_id, 0x0100000000000F12);
+    assert_eq!(parsed.main_stack_size, 0x4000);
+    assert!(parsed.flags.is_64bit());
+    assert!(parsed.acid.flags.is_production());
+    let acid_size = u32::from_le_bytes(bytes[0x7C..0x80].try_into().unwrap());
+    assert_eq!(parsed.acid.size, acid_size - 0x100);
+    assert_eq!(parsed.aci0.fs_access_control.access_flags.raw(), u64::MAX);
+    assert_eq!(parsed.acid.file_access_control_entries[0].access_flags.raw(), u64::MAX);
+
+    let service_names: Vec<_> = parsed.aci0.services.iter().map(|s| (s.name.as_str(), s.is_server)).collect();
+    assert_eq!(service_names, [("fsp-srv", false), ("sm:", false), ("*", false), ("ldr:shel", true)]);
+    // Syscalls 0x01 and 0x07 share the first capability, 0x7F is in the sixth
+    let syscall_caps = parsed
+        .aci0
+        .kernel_capabilities
+        .iter()
+        .filter(|cap| matches!(cap, KernelCapability::EnableSystemCalls(_)))
+        .count();
+    assert_eq!(syscall_caps, 2);
+    assert_eq!(parsed.aci0.kernel_capabilities.len(), parsed.acid.kernel_capabilities.len());
+
+    // Written bytes are stable and the descriptor round trips
+    assert_eq!(parsed.to_bytes().unwrap(), bytes);
+    let round_tripped = NpdmDescriptor::from_npdm(&parsed);
+    assert_eq!(round_tripped, descriptor);
+    assert_eq!(NpdmDescriptor::from_json(&round_tripped.to_json().unwrap()).unwrap(), round_tripped);
+}
+
+#[test]
+pub fn reject_invalid_descriptor() {
+    let descriptor = NpdmDescriptor::from_json(&DESCRIPTOR_JSON.replace("ldr:shel", "too-long-name")).unwrap();
+    assert!(descriptor.to_npdm().is_err());
+
+    let descriptor = NpdmDescriptor::from_json(&DESCRIPTOR_JSON.replace("0x7F", "0xC0")).unwrap();
+    assert!(descriptor.to_npdm().is_err());
+}
+
+#[test]
+pub fn sign_acid_with_custom_key() {
+    use rsa::{pkcs8::{EncodePublicKey, LineEnding}, traits::PublicKeyParts};
+
+    let key = common::test_rsa_key();
+    let public_key = key.to_public_key();
+    let mut npdm = NpdmDescriptor::from_json(DESCRIPTOR_JSON).unwrap().to_npdm().unwrap();
+    assert_eq!(npdm.verify_acid_with_key(&public_key), Validity::Invalid);
+
+    npdm.sign_acid(&key).unwrap();
+    assert_eq!(npdm.acid.modulus.as_slice(), public_key.n().to_bytes_be());
+    assert_eq!(npdm.verify_acid_with_key(&public_key), Validity::Valid);
+
+    // The signature survives writing the file back out
+    let mut bytes = Vec::new();
+    npdm.write(&mut bytes).unwrap();
+    let parsed: NpdmFile = Cursor::new(&bytes).read_le().unwrap();
+    let pem = public_key.to_public_key_pem(LineEnding::LF).unwrap();
+    assert_eq!(parsed.verify_acid_with_pem(&pem), Ok(Validity::Valid));
+    assert_eq!(parsed.verify_acid_with_pem("not a key"), Err(Validity::CheckError));
+
+    // Any change to the signed part of the ACID invalidates it
+    let acid_offset = u32::from_le_bytes(bytes[0x78..0x7C].try_into().unwrap()) as usize;
+    bytes[acid_offset + 0x210] ^= 1;
+    let tampered: NpdmFile = Cursor::new(&bytes).read_le().unwrap();
+    assert_eq!(tampered.verify_acid_with_key(&public_key), Validity::Invalid);
+}
+
+#[test]
+pub fn validate_aci0_against_acid() {
+    let mut npdm = NpdmDescriptor::from_json(DESCRIPTOR_JSON).unwrap().to_npdm().unwrap();
+    assert_eq!(npdm.validate_restrictions(), []);
+
+    npdm.aci0.title_id += 1;
+    npdm.acid.file_access_control_entries[0].access_flags.0 = 0x1;
+    npdm.acid.services = vec![ServiceRecord::new("fsp-*", false).unwrap(), ServiceRecord::new("ldr:shel", true).unwrap()];
+    npdm.aci0.services.push(ServiceRecord::new("pm:shell", true).unwrap());
+    // Only keep syscall 0x01 in the ACID, dropping 0x07 and 0x7F
+    npdm.acid.kernel_capabilities.retain(|cap| match cap {
+        KernelCapability::EnableSystemCalls(calls) => calls.index() == 0,
+        _ => true,
+    });
+    for cap in npdm.acid.kernel_capabilities.iter_mut() {
+        if let KernelCapability::EnableSystemCalls(calls) = cap {
+            calls.0 = calls.0 & !(0xFFFFFF << 5) | 1 << 6;
+        }
+    }
+
+    let violations = npdm.validate_restrictions();
+    assert_eq!(
+        violations,
+        [
+            RestrictionViolation::TitleIdOutOfRange {
+                title_id: 0x0100000000000F13,
+                min: 0x0100000000000F12,
+                max: 0x0100000000000F12
+            },
+            RestrictionViolation::FsAccessNotAllowed { flags: u64::MAX - 1 },
+            RestrictionViolation::ServiceNotAllowed { name: "sm:".to_string(), is_server: false },
+            RestrictionViolation::ServiceNotAllowed { name: "*".to_string(), is_server: false },
+            RestrictionViolation::ServiceNotAllowed { name: "pm:shell".to_string(), is_server: true },
+            RestrictionViolation::KernelCapabilityNotAllowed("syscall 0x07 (svcExitProcess) is not enabled in the ACID".to_string()),
+            RestrictionViolation::KernelCapabilityNotAllowed("syscall 0x7F (svcCallSecureMonitor) is not enabled in the ACID".to_string()),
+        ]
+    );
+    assert_eq!(
+        violations[0].to_string(),
+        "title ID 0100000000000F13 is outside the ACID range 0100000000000F12-0100000000000F12"
+    );
+}
+
+#[test]
+pub fn summarize_kernel_capabilities() {
+    let npdm = NpdmDescriptor::from_json(DESCRIPTOR_JSON).unwrap().to_npdm().unwrap();
+    let capabilities = &npdm.aci0.kernel_capabilities;
+
+    let syscalls = kernel_capability::allowed_syscalls(capabilities);
+    assert_eq!(syscalls.iter().map(|syscall| syscall.id).collect::<Vec<_>>(), [0x01, 0x07, 0x7F]);
+    assert_eq!(syscalls[0].to_string(), "0x01 (svcSetHeapSize)");
+    assert_eq!(Syscall::new(0x38).to_string(), "0x38");
+    assert_eq!(kernel_capability::syscall_id("MapMemory"), Some(0x04));
+
+    let summaries: Vec<_> = kernel_capability::summarize(capabilities).iter().map(ToString::to_string).collect();
+    assert_eq!(
+        summaries,
+        [
+            "Thread priorities 24-63, CPU cores 3-3",
+            "Syscalls 0x01 (svcSetHeapSize), 0x07 (svcExitProcess), 0x7F (svcCallSecureMonitor)",
+            "Memory map 0x0070019000-0x007001A000 (Rw, Io)",
+            "IO page 0x007000F000",
+            "Memory regions KernelTraceBuffer (RO)",
+            "Interrupts 36",
+            "Program type System",
+            "Kernel version 3.0",
+            "Handle table size 128",
+            "Debug flags (allow: true, force: false)",
+        ]
+    );
+    // The text output is printed from the same summaries
+    let info = npdm.info_string();
+    assert!(info.contains("0x01 (svcSetHeapSize)") && info.contains("0x024"));
+    assert!(matches!(
+        capabilities[0].summary(),
+        CapabilitySummary::ThreadInfo { ref priorities, .. } if *priorities == (24..=63)
+    ));
+}
+
+#[test]
+pub fn query_service_access() {
+    let mut npdm = NpdmDescriptor::from_json(DESCRIPTOR_JSON).unwrap().to_npdm().unwrap();
+    assert_eq!(npdm.aci0.accessed_services().collect::<Vec<_>>(), ["fsp-srv", "sm:", "*"]);
+    assert_eq!(npdm.aci0.hosted_services().collect::<Vec<_>>(), ["ldr:shel"]);
+
+    // The "*" entry allows connecting to anything, but not hosting
+    assert!(npdm.can_access_service("fsp-srv"));
+    assert!(npdm.can_access_service("set:sys"));
+    assert!(npdm.can_host_service("ldr:shel"));
+    assert!(!npdm.can_host_service("ldr:pm"));
+
+    npdm.acid.services = vec![ServiceRecord::new("fsp-*", false).unwrap(), ServiceRecord::new("ldr:*", true).unwrap()];
+    assert!(npdm.acid.can_access_service("fsp-ldr"));
+    assert!(!npdm.acid.can_access_service("fsp"));
+    assert!(npdm.acid.can_host_service("ldr:pm"));
+    assert!(!npdm.can_access_service("set:sys"));
+    assert!(!npdm.can_host_service("ldr:pm"));
+    assert!(npdm.can_host_service("ldr:shel"));
+
+    // Names are written with the length minus one in the header
+    let bytes = npdm.to_bytes().unwrap();
+    let parsed: NpdmFile = Cursor::new(&bytes).read_le().unwrap();
+    assert_eq!(parsed.acid.services, npdm.acid.services);
+    assert_eq!(parsed.aci0.services[3], ServiceRecord::new("ldr:shel", true).unwrap());
+
+    // Names which don't fit the 3-bit length are refused when writing
+    for name in ["", "too-long-name"] {
+        npdm.aci0.services.push(ServiceRecord { name: name.to_string(), is_server: false });
+        assert!(npdm.to_bytes().is_err());
+        npdm.aci0.services.pop();
+    }
+}
+
+#[test]
+pub fn fs_access_owner_queries() {
+    let mut npdm = NpdmDescriptor::from_json(DESCRIPTOR_JSON).unwrap().to_npdm().unwrap();
+    let fah = &mut npdm.aci0.fs_access_control;
+    fah.content_owner_ids = vec![0x0100000000001000, 0x0100000000002000];
+    fah.save_data_owners = vec![
+        SaveDataOwner { id: 0x0100000000003000, accessibility: SaveDataAccessibility::READ },
+        SaveDataOwner { id: 0x0100000000004000, accessibility: SaveDataAccessibility::READ_WRITE },
+        SaveDataOwner { id: 0x0100000000005000, accessibility: SaveDataAccessibility::WRITE },
+    ];
+    let fac = &mut npdm.acid.file_access_control_entries[0];
+    fac.content_owner_ids = vec![0x0100000000001000];
+    fac.content_owner_id_count = 1;
+    fac.save_data_owner_min = 0x0100000000003000;
+    fac.save_data_owner_max = 0x0100000000004000;
+
+    // The FAH round trips, including the accessibility of each save data owner
+    let bytes = npdm.to_bytes().unwrap();
+    let parsed: NpdmFile = Cursor::new(&bytes).read_le().unwrap();
+    let fah = &parsed.aci0.fs_access_control;
+    assert_eq!(fah.content_owner_ids, [0x0100000000001000, 0x0100000000002000]);
+    assert_eq!(fah.save_data_owners, npdm.aci0.fs_access_control.save_data_owners);
+    assert_eq!(fah.save_data_owner_accessibility(0x0100000000005000), Some(SaveDataAccessibility::WRITE));
+    assert_eq!(SaveDataAccessibility::READ_WRITE.to_string(), "ReadWrite");
+
+    assert!(parsed.can_access_content_owner(0x0100000000001000));
+    // Listed in the ACI0 but not covered by the ACID
+    assert!(!parsed.can_access_content_owner(0x0100000000002000));
+    assert!(!parsed.can_access_content_owner(0x0100000000003000));
+
+    let accessibility = parsed.save_data_owner_accessibility(0x0100000000003000).unwrap();
+    assert!(accessibility.can_read() && !accessibility.can_write());
+    assert_eq!(parsed.save_data_owner_accessibility(0x0100000000004000), Some(SaveDataAccessibility::READ_WRITE));
+    assert_eq!(parsed.save_data_owner_accessibility(0x0100000000005000), None);
+
+    assert_eq!(
+        parsed.validate_restrictions(),
+        [
+            RestrictionViolation::ContentOwnerIdNotAllowed(0x0100000000002000),
+            RestrictionViolation::SaveDataOwnerIdNotAllowed(0x0100000000005000),
+        ]
+    );
+}
+
+#[test]
+pub fn round_trip_fs_access_owners() {
+    let json = DESCRIPTOR_JSON.replace(
+        r#""filesystem_access": { "permissions": "0xFFFFFFFFFFFFFFFF" }"#,
+        r#""filesystem_access": {
+            "permissions": "0xFFFFFFFFFFFFFFFF",
+            "content_owner_ids": ["0x0100000000001000"],
+            "save_data_owners": [
+                { "id": "0x0100000000003000", "accessibility": "Read" },
+                { "id": "0x0100000000004000", "accessibility": "ReadWrite" }
+            ]
+        }"#,
+    );
+    let descriptor = NpdmDescriptor::from_json(&json).unwrap();
+    let npdm = descriptor.to_npdm().unwrap();
+    assert_eq!(npdm.validate_restrictions(), []);
+
+    let bytes = npdm.to_bytes().unwrap();
+    let parsed: NpdmFile = Cursor::new(&bytes).read_le().unwrap();
+    assert_eq!(parsed.aci0.fs_access_control.content_owner_ids, [0x0100000000001000]);
+    assert_eq!(parsed.save_data_owner_accessibility(0x0100000000004000), Some(SaveDataAccessibility::READ_WRITE));
+
+    // NPDM -> JSON -> NPDM keeps the owners and their accessibility
+    let round_tripped = NpdmDescriptor::from_npdm(&parsed);
+    assert_eq!(round_tripped, descriptor);
+    let json = round_tripped.to_json().unwrap();
+    assert!(json.contains("0x0100000000003000") && json.contains("\"Read\""));
+    let rebuilt = NpdmDescriptor::from_json(&json).unwrap().to_npdm().unwrap();
+    assert_eq!(rebuilt.to_bytes().unwrap(), bytes);
+}
+
+#[test]
+pub fn fs_access_flag_names() {
+    let flags = FsAccessFlags(1 << 37 | 1 << 38 | 1 << 62);
+    assert!(flags.device_tree_blob() && flags.notify_error_context_service_ready());
+    assert_eq!(flags.enabled_names().collect::<Vec<_>>(), ["DeviceTreeBlob", "NotifyErrorContextServiceReady", "Debug"]);
+}
@@ -0,0 +1,109 @@
+use std::io::Cursor;
+
+use hactool_rs::file_formats::{
+    container::{ContainerNode, FileType},
+    nacp::NACP_SIZE,
+    nro::NroReader,
+    romfs::RomFsBuilder,
+};
+
+const ICON: &[u8] = b"\xFF\xD8\xFFnot really a jpeg";
+
+/// An NRO with three small segments, followed by an asset section with an icon, a NACP and a
+/// RomFS containing `data/file.bin`
+fn test_nro(with_assets: bool) -> Vec<u8> {
+    let mut nro = vec![0u8; 0x80];
+    nro[0x4..0x8].copy_from_slice(&0x80u32.to_le_bytes());
+    nro[0x10..0x14].copy_from_slice(b"NRO0");
+    nro[0x18..0x1C].copy_from_slice(&0x200u32.to_le_bytes());
+    for (index, (offset, size)) in [(0u32, 0x100u32), (0x100, 0x80), (0x180, 0x80)].into_iter().enumerate() {
+        nro[0x20 + index * 8..0x24 + index * 8].copy_from_slice(&offset.to_le_bytes());
+        nro[0x24 + index * 8..0x28 + index * 8].copy_from_slice(&size.to_le_bytes());
+    }
+    nro[0x38..0x3C].copy_from_slice(&0x1000u32.to_le_bytes());
+    nro[0x40..0x60].copy_from_slice(&[0xAB; 0x20]);
+    nro.resize(0x200, 0xCC);
+    if !with_assets {
+        return nro;
+    }
+
+    let mut nacp = vec![0u8; NACP_SIZE as usize];
+    nacp[..8].copy_from_slice(b"Homebrew");
+    nacp[0x200..0x206].copy_from_slice(b"Author");
+    nacp[0x3060..0x3065].copy_from_slice(b"1.2.3");
+
+    let data = vec![0x22u8; 0x300];
+    let mut romfs = Vec::new();
+    let mut builder = RomFsBuilder::new();
+    builder.add_reader("data/file.bin", data.len() as u64, data.as_slice());
+    builder.write(&mut romfs).unwrap();
+
+    let icon_offset = 0x38u64;
+    let nacp_offset = icon_offset + ICON.len() as u64;
+    let romfs_offset = nacp_offset + nacp.len() as u64;
+    nro.extend(b"ASET");
+    nro.extend(0u32.to_le_bytes());
+    for (offset, size) in [(icon_offset, ICON.len()), (nacp_offset, nacp.len()), (romfs_offset, romfs.len())] {
+        nro.extend(offset.to_le_bytes());
+        nro.extend((size as u64).to_le_bytes());
+    }
+    nro.extend(ICON);
+    nro.extend(nacp);
+    nro.extend(romfs);
+    nro
+}
+
+#[test]
+pub fn parse_nro_with_assets() {
+    let mut nro = NroReader::parse_reader(Cursor::new(test_nro(true))).unwrap();
+    let header = &nro.nro.header;
+    assert_eq!(nro.nro.mod0_offset, 0x80);
+    assert_eq!(header.size, 0x200);
+    assert_eq!(header.segments[1].offset, 0x100);
+    assert_eq!(header.segments[2].size, 0x80);
+    assert_eq!(header.bss_size, 0x1000);
+    assert_eq!(header.module_id, [0xAB; 0x20]);
+
+    let nacp = nro.nacp.as_ref().unwrap();
+    assert_eq!(nacp.display_version(), "1.2.3");
+    let titles: Vec<_> = nacp.titles().collect();
+    assert_eq!(titles, [("AmericanEnglish", "Homebrew".to_string(), "Author".to_string())]);
+
+    assert_eq!(nro.icon().unwrap().unwrap(), ICON);
+    assert_eq!(nro.nacp_data().unwrap().unwrap().len() as u64, NACP_SIZE);
+    assert_eq!(nro.executable().unwrap().len(), 0x200);
+    let romfs = nro.romfs().unwrap().unwrap();
+    assert_eq!(romfs.files().unwrap()[0].path, "data/file.bin");
+
+    let output = std::env::temp_dir().join(format!("hactool-rs-nro-{}", std::process::id()));
+    nro.extract_assets(&output).unwrap();
+    assert_eq!(std::fs::read(output.join("icon.jpg")).unwrap(), ICON);
+    assert_eq!(std::fs::metadata(output.join("control.nacp")).unwrap().len(), NACP_SIZE);
+    assert_eq!(std::fs::read(output.join("romfs/data/file.bin")).unwrap(), vec![0x22u8; 0x300]);
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[test]
+pub fn parse_nro_without_assets() {
+    let mut nro = NroReader::parse_reader(Cursor::new(test_nro(false))).unwrap();
+    assert!(nro.nro.assets.is_none());
+    assert!(nro.nacp.is_none());
+    assert!(nro.icon().is_none());
+    assert!(nro.romfs().is_none());
+
+    // An asset header which is cut short is an error rather than a missing section
+    let mut truncated = test_nro(true);
+    truncated.truncate(0x210);
+    assert!(NroReader::parse_reader(Cursor::new(truncated)).is_err());
+}
+
+#[test]
+pub fn descend_into_nro_assets() {
+    let keys = std::rc::Rc::new(hactool_rs::keys::NcaKeys::default());
+    let root = ContainerNode::from_reader("hbmenu.nro", Cursor::new(test_nro(true)), keys).unwrap();
+    assert_eq!(root.file_type, FileType::Nro);
+
+    let children: Vec<_> = root.children().unwrap().into_iter().map(|c| c.name).collect();
+    assert_eq!(children, ["icon.jpg", "control.nacp", "romfs"]);
+    assert_eq!(root.resolve("romfs/data/file.bin").unwrap().size(), 0x300);
+}
@@ -0,0 +1,264 @@
+mod common;
+
+use std::io::Cursor;
+
+use hactool_rs::file_formats::{
+    Validity,
+    elf::{ElfModule, ModuleImage},
+    nca::{ContentType, KeyGeneration, NcaBuilder, NcaFileReader},
+    nro::NroReader,
+    nso::{DEFAULT_LOAD_BASE, NsoReader, exefs_modules},
+    pfs0::Pfs0Builder,
+};
+use hactool_rs::info::Verify;
+use sha2::{Digest, Sha256};
+
+const TEXT: std::ops::Range<u64> = 0..0x1000;
+const RODATA: std::ops::Range<u64> = 0x1000..0x2000;
+const DATA: std::ops::Range<u64> = 0x2000..0x2100;
+const MOD0_OFFSET: usize = 0x80;
+
+fn put(image: &mut [u8], offset: u64, data: &[u8]) {
+    image[offset as usize..offset as usize + data.len()].copy_from_slice(data);
+}
+
+fn symbol(name: u32, info: u8, section_index: u16, value: u64, size: u64) -> Vec<u8> {
+    let mut symbol = name.to_le_bytes().to_vec();
+    symbol.extend([info, 0]);
+    symbol.extend(section_index.to_le_bytes());
+    symbol.extend(value.to_le_bytes());
+    symbol.extend(size.to_le_bytes());
+    symbol
+}
+
+fn rela(offset: u64, info: u64, addend: i64) -> Vec<u8> {
+    [offset.to_le_bytes(), info.to_le_bytes(), addend.to_le_bytes()].concat()
+}
+
+/// A module exporting `nnMain` and importing `malloc`, with its dynamic tables in .rodata and
+/// .data and the MOD0 header after the space reserved for an NRO header
+fn test_module() -> Vec<u8> {
+    let mut image = vec![0u8; DATA.end as usize];
+    put(&mut image, 4, &(MOD0_OFFSET as u32).to_le_bytes());
+    image[0x100..0x110].fill(0x1F);
+
+    let mut mod0 = b"MOD0".to_vec();
+    for address in [0x2000u64, 0x2100, 0x2400, 0x1800, 0x1820, 0x2100] {
+        mod0.extend(((address as i64 - MOD0_OFFSET as i64) as i32).to_le_bytes());
+    }
+    put(&mut image, MOD0_OFFSET as u64, &mod0);
+
+    // The original section index of nnMain is meaningless once the section headers are stripped
+    let dynsym = [symbol(0, 0, 0, 0, 0), symbol(1, 0x12, 7, 0x100, 0x10), symbol(8, 0x12, 0, 0, 0)].concat();
+    put(&mut image, 0x1000, &dynsym);
+    put(&mut image, 0x1048, b"\0nnMain\0malloc\0");
+    put(&mut image, 0x1100, &[rela(0x2080, 0x403, 0x100), rela(0x2088, 0x403, 0x200)].concat());
+    put(&mut image, 0x1140, &rela(0x2090, (2 << 32) | 0x402, 0));
+    put(&mut image, 0x1800, &[0x01, 0x1B, 0x03, 0x3B]);
+
+    let dynamic: Vec<u8> = [(6i64, 0x1000u64), (5, 0x1048), (10, 15), (7, 0x1100), (8, 0x30), (9, 0x18), (23, 0x1140), (2, 0x18), (0, 0)]
+        .into_iter()
+        .flat_map(|(tag, value)| [tag.to_le_bytes(), value.to_le_bytes()].concat())
+        .collect();
+    put(&mut image, 0x2000, &dynamic);
+    image
+}
+
+/// An NSO of `image` with a compressed .text
+fn test_nso(image: &[u8]) -> Vec<u8> {
+    let segments = [TEXT, RODATA, DATA].map(|range| &image[range.start as usize..range.end as usize]);
+    let stored = [lz4_flex::block::compress(segments[0]), segments[1].to_vec(), segments[2].to_vec()];
+
+    let mut nso = vec![0u8; 0x100];
+    put(&mut nso, 0, b"NSO0");
+    put(&mut nso, 0xC, &0b111001u32.to_le_bytes());
+    let mut file_offset = 0x100u32;
+    for (index, range) in [TEXT, RODATA, DATA].into_iter().enumerate() {
+        put(&mut nso, 0x10 + index as u64 * 0x10, &file_offset.to_le_bytes());
+        put(&mut nso, 0x14 + index as u64 * 0x10, &(range.start as u32).to_le_bytes());
+        put(&mut nso, 0x18 + index as u64 * 0x10, &((range.end - range.start) as u32).to_le_bytes());
+        put(&mut nso, 0x60 + index as u64 * 4, &(stored[index].len() as u32).to_le_bytes());
+        put(&mut nso, 0xA0 + index as u64 * 0x20, &Sha256::digest(segments[index]));
+        file_offset += stored[index].len() as u32;
+    }
+    put(&mut nso, 0x3C, &0x300u32.to_le_bytes());
+    put(&mut nso, 0x40, &[0xAB; 0x20]);
+    nso.extend(stored.concat());
+    nso
+}
+
+/// Read the names and addresses of the section headers of an ELF
+fn elf_sections(elf: &[u8]) -> Vec<(String, u64, u64)> {
+    let u64_at = |offset: usize| u64::from_le_bytes(elf[offset..offset + 8].try_into().unwrap());
+    let u16_at = |offset: usize| u16::from_le_bytes(elf[offset..offset + 2].try_into().unwrap()) as usize;
+    let (table, count, names_index) = (u64_at(0x28) as usize, u16_at(0x3C), u16_at(0x3E));
+    let names = u64_at(table + names_index * 0x40 + 0x18) as usize;
+    (0..count)
+        .map(|index| {
+            let header = table + index * 0x40;
+            let name = &elf[names + u32::from_le_bytes(elf[header..header + 4].try_into().unwrap()) as usize..];
+            let name = String::from_utf8_lossy(&name[..name.iter().position(|&b| b == 0).unwrap()]).into_owned();
+            (name, u64_at(header + 0x10), u64_at(header + 0x18))
+        })
+        .collect()
+}
+
+#[test]
+pub fn decompress_nso_segments() {
+    let image = test_module();
+    let mut nso = NsoReader::parse_reader(Cursor::new(test_nso(&image))).unwrap();
+    assert!(nso.header.is_compressed(0));
+    assert!(!nso.header.is_compressed(1));
+    assert!(nso.header.is_hash_checked(0));
+    assert_eq!(nso.header.bss_size, 0x300);
+    assert_eq!(nso.header.module_id, [0xAB; 0x20]);
+
+    let results = nso.verification_results();
+    assert!(results.iter().all(|result| result.result == Validity::Valid));
+
+    let module = nso.module_image().unwrap();
+    assert_eq!(module.image, image);
+    assert_eq!((module.text, module.rodata, module.data), (TEXT, RODATA, DATA));
+}
+
+#[test]
+pub fn rebuild_dynamic_symbols() {
+    let module = ModuleImage { image: test_module(), text: TEXT, rodata: RODATA, data: DATA, bss_size: 0x300 };
+    let elf = ElfModule::parse(module).unwrap();
+    assert_eq!(elf.mod0.dynamic, 0x2000);
+    assert_eq!((elf.mod0.bss_start, elf.mod0.bss_end), (0x2100, 0x2400));
+    assert_eq!((elf.mod0.eh_frame_hdr_start, elf.mod0.eh_frame_hdr_end), (0x1800, 0x1820));
+    assert_eq!(elf.dynamic.len(), 8);
+    assert_eq!(elf.relocations.len(), 2);
+    assert_eq!(elf.plt_relocations.len(), 1);
+
+    let symbols: Vec<_> = elf.symbols.iter().map(|s| (s.name.as_str(), s.is_defined(), s.symbol.section_index)).collect();
+    // nnMain now points at .text, the first rebuilt section
+    assert_eq!(symbols, [("", false, 0), ("nnMain", true, 1), ("malloc", false, 0)]);
+}
+
+#[test]
+pub fn convert_nso_to_elf() {
+    let image = test_module();
+    let mut nso = NsoReader::parse_reader(Cursor::new(test_nso(&image))).unwrap();
+    let elf_module = ElfModule::parse(nso.module_image().unwrap()).unwrap();
+    let mut elf = Vec::new();
+    let written = elf_module.write(&mut elf).unwrap();
+    assert_eq!(written, elf.len() as u64);
+
+    assert_eq!(&elf[..7], b"\x7FELF\x02\x01\x01");
+    assert_eq!(u16::from_le_bytes([elf[0x12], elf[0x13]]), 183);
+    // Three loadable segments, .dynamic and .eh_frame_hdr
+    assert_eq!(u16::from_le_bytes([elf[0x38], elf[0x39]]), 5);
+
+    let sections = elf_sections(&elf);
+    let names: Vec<_> = sections.iter().map(|(name, _, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["", ".text", ".rodata", ".data", ".bss", ".dynamic", ".dynsym", ".dynstr", ".rela.dyn", ".rela.plt", ".eh_frame_hdr", ".shstrtab"]
+    );
+    for (name, address, offset) in &sections[1..sections.len() - 1] {
+        assert_eq!(offset - address, 0x1000, "{} is not at its address in the image", name);
+    }
+
+    // The image is copied verbatim apart from the patched section index of nnMain
+    let mut expected = image.clone();
+    expected[0x1018 + 6] = 1;
+    assert_eq!(&elf[0x1000..0x1000 + image.len()], expected.as_slice());
+}
+
+#[test]
+pub fn convert_nro_to_elf() {
+    let mut nro = test_module();
+    put(&mut nro, 0x10, b"NRO0");
+    put(&mut nro, 0x18, &(DATA.end as u32).to_le_bytes());
+    for (index, range) in [TEXT, RODATA, DATA].into_iter().enumerate() {
+        put(&mut nro, 0x20 + index as u64 * 8, &(range.start as u32).to_le_bytes());
+        put(&mut nro, 0x24 + index as u64 * 8, &((range.end - range.start) as u32).to_le_bytes());
+    }
+
+    let mut reader = NroReader::parse_reader(Cursor::new(nro.clone())).unwrap();
+    let module = reader.module_image().unwrap();
+    assert_eq!(module.image, nro);
+    assert_eq!(module.data, DATA);
+
+    let elf = ElfModule::parse(module).unwrap();
+    assert_eq!(elf.symbols[1].name, "nnMain");
+    let mut output = Vec::new();
+    elf.write(&mut output).unwrap();
+    assert_eq!(elf_sections(&output).len(), 12);
+}
+
+#[test]
+pub fn reject_overflowing_segments() {
+    // .data claims to end past 4 GiB
+    let mut nso = test_nso(&test_module());
+    put(&mut nso, 0x34, &0xFFFF_FF80u32.to_le_bytes());
+    let mut reader = NsoReader::parse_reader(Cursor::new(nso)).unwrap();
+    assert!(reader.module_image().is_err());
+
+    // .data claims to be mapped far past the end of the segments
+    let mut nso = test_nso(&test_module());
+    put(&mut nso, 0x34, &0xF000_0000u32.to_le_bytes());
+    let mut reader = NsoReader::parse_reader(Cursor::new(nso)).unwrap();
+    assert!(reader.module_image().is_err());
+
+    let mut nro = test_module();
+    put(&mut nro, 0x10, b"NRO0");
+    put(&mut nro, 0x18, &(DATA.end as u32).to_le_bytes());
+    put(&mut nro, 0x20, &0xFFFF_F000u32.to_le_bytes());
+    put(&mut nro, 0x24, &0x1000u32.to_le_bytes());
+    let mut reader = NroReader::parse_reader(Cursor::new(nro)).unwrap();
+    assert_eq!(reader.module_image().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    // DT_STRSZ and DT_HASH values which overflow when added to their addresses
+    for (offset, value) in [(0x2028u64, u64::MAX), (0x2078, u64::MAX - 2)] {
+        let mut image = test_module();
+        put(&mut image, offset, &value.to_le_bytes());
+        let module = ModuleImage { image, text: TEXT, rodata: RODATA, data: DATA, bss_size: 0x300 };
+        assert!(ElfModule::parse(module).is_err());
+    }
+}
+
+#[test]
+pub fn list_exefs_modules() {
+    let mut named = vec![0u8; DATA.end as usize];
+    let path = b"D:\\build\\Release\\main.nss";
+    put(&mut named, RODATA.start + 4, &(path.len() as u32).to_le_bytes());
+    put(&mut named, RODATA.start + 8, path);
+    let main = test_nso(&named);
+    let mut sdk = test_nso(&test_module());
+    put(&mut sdk, 0x40, &[0xCD; 0x20]);
+    let mut npdm = b"META".to_vec();
+    npdm.resize(0x80, 0);
+
+    // Modules are listed in load order, not archive order, and other files are skipped
+    let mut exefs = Vec::new();
+    let mut pfs0 = Pfs0Builder::new();
+    pfs0.add_reader("main.npdm", npdm.len() as u64, npdm.as_slice())
+        .add_reader("sdk", sdk.len() as u64, sdk.as_slice())
+        .add_reader("main", main.len() as u64, main.as_slice());
+    pfs0.write(&mut exefs).unwrap();
+
+    let mut nca = Vec::new();
+    let mut builder = NcaBuilder::new(ContentType::Program, 0x0100000000001000);
+    builder
+        .set_key_generation(KeyGeneration::Five)
+        .set_content_key([0x44; 0x10])
+        .add_pfs0_section(0, exefs.len() as u64, Cursor::new(exefs.as_slice()));
+    builder.write(&mut nca, &common::test_keys()).unwrap();
+
+    let nca = NcaFileReader::parse_reader(Cursor::new(nca), &common::test_keys()).unwrap();
+    let modules = exefs_modules(&mut nca.into_exefs().unwrap(), DEFAULT_LOAD_BASE).unwrap();
+    let summary: Vec<_> = modules.iter().map(|m| (m.file_name.as_str(), m.module_name.as_deref(), m.load_address, m.memory_size)).collect();
+    assert_eq!(
+        summary,
+        [
+            ("main", Some("D:\\build\\Release\\main.nss"), DEFAULT_LOAD_BASE, 0x3000),
+            ("sdk", None, DEFAULT_LOAD_BASE + 0x3000, 0x3000),
+        ]
+    );
+    assert_eq!(modules[0].build_id, [0xAB; 0x20]);
+    assert_eq!(modules[1].build_id, [0xCD; 0x20]);
+    assert_eq!(modules[1].rodata.memory_offset, 0x1000);
+}
@@ -1,4 +1,4 @@
-use hactool_rs::file_formats::pfs0::Pfs0Reader;
+use hactool_rs::file_formats::pfs0::{Pfs0Builder, Pfs0Reader};
 use std::path::PathBuf;
 
 #[test]
@@ -12,3 +12,29 @@ pub fn parse_test_pfs0() {
     
     //assert_eq!(parsed.verify_acid(hactool_rs::keys::KeysetType::Retail), Ok(Validity::Valid))
 }
+
+#[test]
+pub fn create_and_parse_pfs0() {
+    let first = b"first file contents".to_vec();
+    let second = vec![0xA5u8; 0x1234];
+
+    let mut builder = Pfs0Builder::new();
+    builder
+        .add_reader("first.bin", first.len() as u64, first.as_slice())
+        .add_reader("second.nca", second.len() as u64, second.as_slice());
+    let expected_size = builder.total_size();
+    assert_eq!(builder.header_size() % 0x20, 0);
+
+    let path = std::env::temp_dir().join(format!("hactool-rs-create-{}.nsp", std::process::id()));
+    let written = builder
+        .write(&mut std::fs::File::create(&path).unwrap())
+        .unwrap();
+    assert_eq!(written, expected_size);
+
+    let mut parsed = Pfs0Reader::parse_file(&path).unwrap();
+    assert_eq!(parsed.list_files(), vec!["first.bin", "second.nca"]);
+    assert_eq!(parsed.get_file_data("first.bin").unwrap().unwrap(), first);
+    assert_eq!(parsed.get_file_data("second.nca").unwrap().unwrap(), second);
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,48 @@
+use hactool_rs::{
+    file_formats::{
+        Validity,
+        romfs::{RomFsBuilder, RomFsReader},
+    },
+    info::Verify,
+};
+
+#[test]
+pub fn create_and_parse_romfs() {
+    let root = std::env::temp_dir().join(format!("hactool-rs-romfs-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let input = root.join("input");
+    std::fs::create_dir_all(input.join("data/nested")).unwrap();
+    std::fs::create_dir_all(input.join("empty")).unwrap();
+    std::fs::write(input.join("top.txt"), b"top level").unwrap();
+    std::fs::write(input.join("data/a.bin"), vec![1u8; 0x21]).unwrap();
+    std::fs::write(input.join("data/nested/deep.bin"), vec![2u8; 0x4000]).unwrap();
+
+    let extra = b"added from a reader".to_vec();
+    let mut builder = RomFsBuilder::from_dir(&input).unwrap();
+    builder.add_reader("data/nested/extra.txt", extra.len() as u64, extra.as_slice());
+
+    let image = root.join("out.romfs");
+    let written = builder.write(&mut std::fs::File::create(&image).unwrap()).unwrap();
+    assert_eq!(written, std::fs::metadata(&image).unwrap().len());
+
+    let mut reader = RomFsReader::parse_file(&image).unwrap();
+    assert_eq!(
+        reader.list_files().unwrap(),
+        vec!["top.txt", "data/a.bin", "data/nested/deep.bin", "data/nested/extra.txt"]
+    );
+    assert_eq!(reader.romfs.directories().unwrap(), vec!["data", "data/nested", "empty"]);
+    assert_eq!(reader.get_file_data("data/a.bin").unwrap().unwrap(), vec![1u8; 0x21]);
+    assert_eq!(reader.get_file_data("/data/nested/deep.bin").unwrap().unwrap(), vec![2u8; 0x4000]);
+    assert_eq!(reader.get_file_data("data/nested/extra.txt").unwrap().unwrap(), extra);
+    assert!(reader.get_file_data("data/missing.bin").unwrap().is_none());
+    let results = reader.verification_results();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].check, "content_hashes");
+    assert_eq!(results[0].result, Validity::Unchecked);
+
+    reader.extract_to(root.join("output")).unwrap();
+    assert_eq!(std::fs::read(root.join("output/top.txt")).unwrap(), b"top level");
+    assert!(root.join("output/empty").is_dir());
+
+    std::fs::remove_dir_all(root).unwrap();
+}
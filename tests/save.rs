@@ -0,0 +1,390 @@
+use std::io::Cursor;
+
+use hactool_rs::file_formats::{Validity, save::SaveReader};
+use hactool_rs::keys::{ConsoleKeys, NcaKeys};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+const IMAGE_SIZE: usize = 0x8000;
+const FILE_MAP_DATA: usize = 0x5000;
+/// The two halves of the data remap storage are swapped in the image
+const REMAP_SPLIT: u64 = 0x1800;
+const BLOCK_SIZE: usize = 0x200;
+const BLOCK_COUNT: usize = 7;
+/// Duplex data block read from the B copy
+const B_BLOCK: usize = 4;
+/// IVFC hash levels in the metadata, as (offset, size)
+const IVFC_LEVELS: [(usize, usize); 3] = [(0x100, 0x20), (0x200, 0x20), (0x400, BLOCK_COUNT * 0x20)];
+const SALT_SOURCE: [u8; 0x20] = [0x5A; 0x20];
+
+fn put(image: &mut [u8], offset: usize, bytes: &[u8]) {
+    image[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+fn put_u32(image: &mut [u8], offset: usize, value: u32) {
+    put(image, offset, &value.to_le_bytes());
+}
+
+fn put_u64(image: &mut [u8], offset: usize, value: u64) {
+    put(image, offset, &value.to_le_bytes());
+}
+
+/// Write at a virtual offset of the data remap storage
+fn put_data(image: &mut [u8], offset: u64, bytes: &[u8]) {
+    for (index, &byte) in bytes.iter().enumerate() {
+        let virtual_offset = offset + index as u64;
+        let physical = match virtual_offset < REMAP_SPLIT {
+            true => virtual_offset + REMAP_SPLIT,
+            false => virtual_offset - REMAP_SPLIT,
+        };
+        image[FILE_MAP_DATA + physical as usize] = byte;
+    }
+}
+
+fn put_entry(table: &mut [u8], index: usize, parent: u32, name: &str, next_sibling: u32, info: (u32, u64), next: u32) {
+    let entry = &mut table[index * 0x60..(index + 1) * 0x60];
+    put_u32(entry, 0, parent);
+    put(entry, 4, name.as_bytes());
+    put_u32(entry, 0x44, next_sibling);
+    put_u32(entry, 0x48, info.0);
+    put_u64(entry, 0x4C, info.1);
+    put_u32(entry, 0x5C, next);
+}
+
+/// Hashes of the 0x200 byte blocks of `data`
+fn ivfc_hashes(data: &[u8], level: &str) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(format!("HierarchicalIntegrityVerificationStorage::{}", level).as_bytes()).unwrap();
+    mac.update(&SALT_SOURCE);
+    let salt = mac.finalize().into_bytes();
+    data.chunks(BLOCK_SIZE)
+        .flat_map(|block| {
+            let mut padded = block.to_vec();
+            padded.resize(BLOCK_SIZE, 0);
+            let mut hash: [u8; 0x20] = Sha256::new().chain_update(salt).chain_update(padded).finalize().into();
+            hash[0x1F] |= 0x80;
+            hash
+        })
+        .collect()
+}
+
+fn test_keys() -> NcaKeys {
+    NcaKeys {
+        console: ConsoleKeys { save_mac_key: [0x66; 0x10], ..Default::default() },
+        ..Default::default()
+    }
+}
+
+fn a_bin() -> Vec<u8> {
+    (0..0x500).map(|i| (i % 253) as u8).collect()
+}
+
+/// A save holding `root.txt`, an empty file and `dir/a.bin`, whose data is split over a single
+/// block and a two block segment
+fn test_save() -> Vec<u8> {
+    test_save_with_masters_at(0x4000)
+}
+
+/// [`test_save`] with the duplex master bitmaps and the IVFC master hashes at `master_offset`.
+/// Real saves keep them inside the header.
+fn test_save_with_masters_at(master_offset: usize) -> Vec<u8> {
+    let mut image = vec![0u8; IMAGE_SIZE];
+
+    // File system blocks
+    let mut fs = vec![0u8; BLOCK_COUNT * BLOCK_SIZE];
+    let directories = &mut fs[..BLOCK_SIZE];
+    put_entry(directories, 1, 0, "", 0, (0, 0), 2);
+    put_entry(directories, 2, 0, "", 0, (3, 3), 3);
+    put_entry(directories, 3, 2, "dir", 0, (0, 2), 0);
+    let files = &mut fs[BLOCK_SIZE..2 * BLOCK_SIZE];
+    put_entry(files, 1, 0, "", 0, (0, 0), 2);
+    put_entry(files, 2, 3, "a.bin", 0, (2, 0x500), 3);
+    put_entry(files, 3, 2, "root.txt", 4, (4, 0x10), 4);
+    put_entry(files, 4, 2, "empty", 0, (0x8000_0000, 0), 0);
+    let a_bin = a_bin();
+    put(&mut fs, 2 * BLOCK_SIZE, &a_bin[..BLOCK_SIZE]);
+    put(&mut fs, 5 * BLOCK_SIZE, &a_bin[BLOCK_SIZE..]);
+    put(&mut fs, 4 * BLOCK_SIZE, b"root text file!\n");
+    for block in 0..BLOCK_COUNT {
+        let physical = BLOCK_COUNT - 1 - block;
+        put_data(&mut image, 0x2200 + (physical * BLOCK_SIZE) as u64, &fs[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]);
+    }
+
+    put(&mut image, 0x100, b"DISF");
+    put_u32(&mut image, 0x104, 0x50000);
+    let layout = [
+        (0x00, 0x4100), // data remap entries
+        (0x01, 0x40),
+        (0x02, 0x4200), // metadata remap entries
+        (0x03, 0x20),
+        (0x04, FILE_MAP_DATA as u64),
+        (0x05, 0x3000),
+        (0x06, 0x0), // duplex L1 A and B
+        (0x07, 0x40),
+        (0x08, 0x40),
+        (0x09, 0x200), // duplex data A and B
+        (0x0A, 0x1200),
+        (0x0B, 0x1000),
+        (0x0C, 0x2200), // journal data
+        (0x0E, (BLOCK_COUNT * BLOCK_SIZE) as u64),
+        (0x10, master_offset as u64), // duplex master bitmaps
+        (0x11, master_offset as u64 + 0x10),
+        (0x12, 0x10),
+        (0x13, master_offset as u64 + 0x20), // IVFC master hashes
+        (0x14, master_offset as u64 + 0x40),
+        (0x15, 0x20),
+        (0x16, 0x0), // journal map
+        (0x17, (BLOCK_COUNT * 8) as u64),
+        (0x24, 0x800), // allocation table
+        (0x25, 0x40),
+    ];
+    for (index, value) in layout {
+        put_u64(&mut image, 0x128 + index * 8, value);
+    }
+
+    put(&mut image, 0x300, b"DPFS");
+    put_u32(&mut image, 0x308 + 0x14 + 0x10, 6);
+    put_u32(&mut image, 0x308 + 0x28 + 0x10, 9);
+    put(&mut image, 0x344, b"IVFC");
+    put_u32(&mut image, 0x348, 0x20);
+    put_u32(&mut image, 0x350, 5);
+    let ivfc_levels = IVFC_LEVELS.into_iter().chain([(0, BLOCK_COUNT * BLOCK_SIZE)]);
+    for (index, (offset, size)) in ivfc_levels.enumerate() {
+        put_u64(&mut image, 0x354 + index * 0x18, offset as u64);
+        put_u64(&mut image, 0x354 + index * 0x18 + 8, size as u64);
+        put_u32(&mut image, 0x354 + index * 0x18 + 0x10, 9);
+    }
+    put(&mut image, 0x3E4, &SALT_SOURCE);
+    put(&mut image, 0x408, b"JNGL");
+    put_u64(&mut image, 0x410, (BLOCK_COUNT * BLOCK_SIZE) as u64);
+    put_u64(&mut image, 0x420, BLOCK_SIZE as u64);
+    put_u32(&mut image, 0x42C, BLOCK_COUNT as u32);
+    put(&mut image, 0x608, b"SAVE");
+    put_u64(&mut image, 0x610, BLOCK_COUNT as u64);
+    put_u64(&mut image, 0x618, BLOCK_SIZE as u64);
+    put_u64(&mut image, 0x620, BLOCK_SIZE as u64);
+    put_u32(&mut image, 0x648, 0);
+    put_u32(&mut image, 0x64C, 1);
+    for (offset, count) in [(0x650, 2), (0x690, 1)] {
+        put(&mut image, offset, b"RMAP");
+        put_u32(&mut image, offset + 8, count);
+    }
+    put_u64(&mut image, 0x6D8, 0x0100_0000_0000_1000);
+    put_u64(&mut image, 0x6F0, 0x8000_0000_0000_0001);
+    put(&mut image, 0xAD8, b"IVFC");
+
+    // Remap entries: virtual, physical, size
+    for (index, (virtual_offset, physical, size)) in [(0, REMAP_SPLIT, REMAP_SPLIT), (REMAP_SPLIT, 0, 0x3000 - REMAP_SPLIT)].into_iter().enumerate() {
+        put_u64(&mut image, 0x4100 + index * 0x20, virtual_offset);
+        put_u64(&mut image, 0x4108 + index * 0x20, physical);
+        put_u64(&mut image, 0x4110 + index * 0x20, size);
+    }
+    put_u64(&mut image, 0x4210, 0x1000);
+
+    // Master bitmaps select L1 A, which selects data B for one block
+    put(&mut image, master_offset + 0x10, &[0xFF; 0x10]);
+    put_data(&mut image, 0x0, &(0x8000_0000u32 >> B_BLOCK).to_le_bytes());
+    put_data(&mut image, 0x40, &[0xFF; 0x40]);
+
+    // Metadata: the journal map maps block n to physical block 6 - n, then the allocation table
+    let mut meta = vec![0u8; 0x1000];
+    for block in 0..BLOCK_COUNT {
+        put_u32(&mut meta, block * 8, 0x8000_0000 | (BLOCK_COUNT - 1 - block) as u32);
+    }
+    let allocation_table: [(u32, u32); 8] = [
+        (0, 0),
+        (0x8000_0000, 0), // block 0: directory table
+        (0x8000_0000, 0), // block 1: file table
+        (0x8000_0000, 6), // block 2: a.bin, continued at block 5
+        (0, 0),
+        (0x8000_0000, 0), // block 4: root.txt
+        (3, 0x8000_0000), // blocks 5-6: rest of a.bin
+        (0x8000_0006, 7),
+    ];
+    for (index, (prev, next)) in allocation_table.into_iter().enumerate() {
+        put_u32(&mut meta, 0x800 + index * 8, prev);
+        put_u32(&mut meta, 0x804 + index * 8, next);
+    }
+    let mut hashes = ivfc_hashes(&fs, "L3");
+    for (level, (offset, size)) in IVFC_LEVELS.into_iter().enumerate().rev() {
+        put(&mut meta, offset, &hashes);
+        hashes = ivfc_hashes(&meta[offset..offset + size], ["Master", "L1", "L2"][level]);
+    }
+    put(&mut image, master_offset + 0x20, &hashes);
+    put(&mut image, master_offset + 0x40, &hashes);
+    for block in 0..8 {
+        let data = &meta[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE];
+        let (current, stale) = match block == B_BLOCK {
+            true => (0x1200, 0x200),
+            false => (0x200, 0x1200),
+        };
+        put_data(&mut image, current + (block * BLOCK_SIZE) as u64, data);
+        put_data(&mut image, stale + (block * BLOCK_SIZE) as u64, &[0xEE; BLOCK_SIZE]);
+    }
+
+    let layout_hash = Sha256::digest(&image[0x300..0x4000]);
+    put(&mut image, 0x108, &layout_hash);
+    image
+}
+
+#[test]
+pub fn parse_save() {
+    let mut save = SaveReader::parse_reader(Cursor::new(test_save())).unwrap();
+    assert_eq!(save.header.layout.version, 0x50000);
+    assert_eq!(save.header.extra_data.title_id, 0x0100_0000_0000_1000);
+    assert_eq!(save.header.extra_data.save_id, 0x8000_0000_0000_0001);
+    assert_eq!(save.header.save.block_size, BLOCK_SIZE as u64);
+    assert_eq!(save.verify_layout_hash(), Validity::Valid);
+    assert_eq!(save.verify_ivfc(), Validity::Valid);
+    assert_eq!(save.verify_header_cmac(&NcaKeys::default()), Validity::Unchecked);
+
+    assert_eq!(save.directories().unwrap(), ["dir"]);
+    let files: Vec<_> = save.files().unwrap().into_iter().map(|file| (file.path, file.size)).collect();
+    assert_eq!(files, [("root.txt".to_string(), 0x10), ("empty".to_string(), 0), ("dir/a.bin".to_string(), 0x500)]);
+}
+
+#[test]
+pub fn read_save_files() {
+    let mut save = SaveReader::parse_reader(Cursor::new(test_save())).unwrap();
+    let mut read = |path: &str| {
+        let file = save.find_file(path).unwrap().unwrap();
+        let mut data = Vec::new();
+        save.read_file_into(&file, &mut data).unwrap();
+        data
+    };
+    assert_eq!(read("dir/a.bin"), a_bin());
+    assert_eq!(read("/root.txt"), b"root text file!\n");
+    assert!(read("empty").is_empty());
+    assert!(save.find_file("missing").unwrap().is_none());
+
+    let output = std::env::temp_dir().join(format!("hactool-rs-save-{}", std::process::id()));
+    save.extract_to(&output).unwrap();
+    let extracted = std::fs::read(output.join("dir/a.bin")).unwrap();
+    let empty = std::fs::metadata(output.join("empty")).unwrap().len();
+    std::fs::remove_dir_all(&output).unwrap();
+    assert_eq!(extracted, a_bin());
+    assert_eq!(empty, 0);
+}
+
+#[test]
+pub fn detect_modified_save_layout() {
+    let mut image = test_save();
+    image[0x6D8] ^= 1;
+    let save = SaveReader::parse_reader(Cursor::new(image)).unwrap();
+    assert_eq!(save.verify_layout_hash(), Validity::Invalid);
+}
+
+#[test]
+pub fn detect_modified_save_data() {
+    let mut image = test_save();
+    // Last byte of root.txt, in journal block 4 stored at physical block 2
+    put_data(&mut image, 0x2200 + 2 * BLOCK_SIZE as u64 + 0xF, b"?");
+    let mut save = SaveReader::parse_reader(Cursor::new(image)).unwrap();
+    assert_eq!(save.verify_ivfc(), Validity::Invalid);
+}
+
+#[test]
+pub fn reject_invalid_save_header() {
+    // Journal block size, file system block size, data duplex block size and duplex data size
+    let fields: [(usize, &[u8]); 4] = [(0x420, &[0; 8]), (0x618, &[0; 8]), (0x340, &[64, 0, 0, 0]), (0x180, &[0xFF; 8])];
+    for (offset, value) in fields {
+        let mut image = test_save();
+        put(&mut image, offset, value);
+        assert!(SaveReader::parse_reader(Cursor::new(image)).is_err());
+    }
+}
+
+#[test]
+pub fn reject_save_names_outside_of_output() {
+    let mut image = test_save();
+    // Name of dir, in journal block 0 stored at physical block 6
+    put_data(&mut image, 0x2200 + 6 * BLOCK_SIZE as u64 + 3 * 0x60 + 4, b"..\0");
+    let mut save = SaveReader::parse_reader(Cursor::new(image)).unwrap();
+    assert_eq!(save.directories().unwrap(), [".."]);
+
+    let output = std::env::temp_dir().join(format!("hactool-rs-save-escape-{}", std::process::id()));
+    assert!(save.extract_to(output.join("inner")).is_err());
+    let escaped = output.join("a.bin").exists();
+    std::fs::remove_dir_all(&output).unwrap();
+    assert!(!escaped);
+}
+
+#[test]
+pub fn write_save_file() {
+    let path = std::env::temp_dir().join(format!("hactool-rs-save-write-{}.bin", std::process::id()));
+    std::fs::write(&path, test_save()).unwrap();
+    let new_a_bin: Vec<u8> = (0..0x480).map(|i| (i % 13) as u8).collect();
+
+    // Without save_mac_key the save is not opened, so nothing is written that cannot be signed
+    assert!(SaveReader::parse_file_writable(&path, &NcaKeys::default()).is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), test_save());
+
+    let mut save = SaveReader::parse_file_writable(&path, &test_keys()).unwrap();
+    save.write_file("dir/a.bin", &new_a_bin).unwrap();
+    save.write_file("root.txt", b"short").unwrap();
+    assert!(save.write_file("root.txt", &[0u8; BLOCK_SIZE + 1]).is_err());
+    assert!(save.write_file("empty", b"data").is_err());
+    save.commit().unwrap();
+    drop(save);
+
+    let mut save = SaveReader::parse_file(&path).unwrap();
+    assert_eq!(save.header.layout.duplex_index, 1);
+    assert_eq!(save.verify_layout_hash(), Validity::Valid);
+    assert_eq!(save.verify_header_cmac(&test_keys()), Validity::Valid);
+    let other_keys = NcaKeys {
+        console: ConsoleKeys { save_mac_key: [0x67; 0x10], ..Default::default() },
+        ..Default::default()
+    };
+    assert_eq!(save.verify_header_cmac(&other_keys), Validity::Invalid);
+    assert_eq!(save.verify_ivfc(), Validity::Valid);
+    let mut read = |path: &str| {
+        let file = save.find_file(path).unwrap().unwrap();
+        let mut data = Vec::new();
+        save.read_file_into(&file, &mut data).unwrap();
+        data
+    };
+    assert_eq!(read("dir/a.bin"), new_a_bin);
+    assert_eq!(read("root.txt"), b"short");
+
+    // Written but not committed, so the hashes no longer match
+    let mut save = SaveReader::parse_file_writable(&path, &test_keys()).unwrap();
+    save.write_file("root.txt", b"uncommitted").unwrap();
+    assert_eq!(save.verify_ivfc(), Validity::Invalid);
+    drop(save);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+pub fn write_save_file_with_masters_in_header() {
+    let path = std::env::temp_dir().join(format!("hactool-rs-save-header-{}.bin", std::process::id()));
+    std::fs::write(&path, test_save_with_masters_at(0x3E00)).unwrap();
+    let mut save = SaveReader::parse_file(&path).unwrap();
+    assert_eq!(save.verify_layout_hash(), Validity::Valid);
+    assert_eq!(save.verify_ivfc(), Validity::Valid);
+    drop(save);
+
+    let mut save = SaveReader::parse_file_writable(&path, &test_keys()).unwrap();
+    save.write_file("root.txt", b"short").unwrap();
+    save.commit().unwrap();
+    drop(save);
+
+    let image = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    // The new master bitmap went to slot B, inside the header
+    assert_ne!(image[0x3E10..0x3E20], [0xFF; 0x10]);
+    let mut save = SaveReader::parse_reader(Cursor::new(image)).unwrap();
+    assert_eq!(save.header.layout.duplex_index, 1);
+    assert_eq!(save.verify_layout_hash(), Validity::Valid);
+    assert_eq!(save.verify_header_cmac(&test_keys()), Validity::Valid);
+    assert_eq!(save.verify_ivfc(), Validity::Valid);
+    let file = save.find_file("root.txt").unwrap().unwrap();
+    let mut data = Vec::new();
+    save.read_file_into(&file, &mut data).unwrap();
+    assert_eq!(data, b"short");
+}
+
+#[test]
+pub fn reject_writes_to_read_only_save() {
+    let mut save = SaveReader::parse_reader(Cursor::new(test_save())).unwrap();
+    assert!(save.write_file("root.txt", b"data").is_err());
+    assert!(save.commit().is_err());
+}